@@ -1,4 +1,5 @@
-use crate::traits::Writable;
+use crate::traits::{UsesLabels, Writable};
+use crate::writer::{Syntax, Writer};
 use crate::Data;
 use std::io::prelude::*;
 
@@ -31,7 +32,10 @@ pub enum DataEL {
 }
 
 impl Writable for DataEL {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        if file.syntax() == Syntax::Nasm {
+            return self.write_nasm(file);
+        }
         match self {
             Self::Byte(el) => {
                 file.write_all(b"\t.byte  ")?;
@@ -63,11 +67,11 @@ impl Writable for DataEL {
             }
             Self::AddressLong(el) => {
                 file.write_all(b"\t.long  ")?;
-                el.write_in(file)
+                el.write_with(file)
             }
             Self::AddressQuad(el) => {
                 file.write_all(b"\t.quad  ")?;
-                el.write_in(file)
+                el.write_with(file)
             }
             Self::Ascii(str) => {
                 file.write_all(b"\t.ascii \"")?;
@@ -82,6 +86,41 @@ impl Writable for DataEL {
             Self::Space(i) => file.write_all(format!("\t.space {}", i).as_bytes()),
         }
     }
+}
+
+impl DataEL {
+    /// NASM uses `db/dw/dd/dq` and backquoted strings (which accept C escapes)
+    fn write_nasm(&self, file: &mut Writer) -> std::io::Result<()> {
+        match self {
+            Self::Byte(el) => file.write_all(format!("db {}", el).as_bytes()),
+            Self::ByteU(el) => file.write_all(format!("db {}", el).as_bytes()),
+            Self::Word(el) => file.write_all(format!("dw {}", el).as_bytes()),
+            Self::ShortU(el) => file.write_all(format!("dw {}", el).as_bytes()),
+            Self::Long(el) => file.write_all(format!("dd {}", el).as_bytes()),
+            Self::LongU(el) => file.write_all(format!("dd {}", el).as_bytes()),
+            Self::Quad(el) => file.write_all(format!("dq {}", el).as_bytes()),
+            Self::AddressLong(el) => {
+                file.write_all(b"dd ")?;
+                el.write_with(file)
+            }
+            Self::AddressQuad(el) => {
+                file.write_all(b"dq ")?;
+                el.write_with(file)
+            }
+            Self::Ascii(str) => {
+                file.write_all(b"db `")?;
+                file.write_all(str.replace('`', "\\`").as_bytes())?;
+                file.write_all(b"`")
+            }
+            Self::Asciz(str) => {
+                file.write_all(b"db `")?;
+                file.write_all(str.replace('`', "\\`").as_bytes())?;
+                file.write_all(b"`, 0")
+            }
+            Self::Space(i) if file.in_nobits() => file.write_all(format!("resb {}", i).as_bytes()),
+            Self::Space(i) => file.write_all(format!("times {} db 0", i).as_bytes()),
+        }
+    }
 
     // fn to_data_el(self) -> DataEL {
     //     DataEL {
@@ -91,7 +130,16 @@ impl Writable for DataEL {
     // }
 }
 
-/// Structure representing a single element of the data segment
+impl UsesLabels for DataEL {
+    fn used_labels(&self) -> Vec<&super::reg::Label> {
+        match self {
+            Self::AddressLong(label) | Self::AddressQuad(label) => vec![label],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Structure representing a single element of the data segment
 // pub struct DataEL {
 //     info: DataELInner,
 //     comment: Option<String>,
@@ -111,8 +159,8 @@ impl Writable for DataEL {
 // }
 
 // impl Writable for DataEL {
-//     fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-//         self.info.write_in(file)?;
+//     fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+//         self.info.write_with(file)?;
 //         match &self.comment {
 //             None => (),
 //             Some(str) => {
@@ -124,7 +172,7 @@ impl Writable for DataEL {
 //     }
 // }
 
-/// Structure representing the data segment
+// Structure representing the data segment
 /*pub struct Data {
     infos: Vec<DataEL>,
 }
@@ -159,9 +207,9 @@ impl Data {
 
     /// Write data segment in file
     /// Does not add the .data at the beginning!
-    pub fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    pub fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
         for data in &self.infos {
-            data.write_in(file)?;
+            data.write_with(file)?;
         }
         Ok(())
     }
@@ -1,4 +1,4 @@
-///! Accessibility encoding (figure 25 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Accessibility encoding (figure 25 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const PUBLIC: u8 = 0x01;
 pub const PROTECTED: u8 = 0x02;
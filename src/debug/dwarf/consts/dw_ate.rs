@@ -1,4 +1,4 @@
-///! Base type encoding (figure 25 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Base type encoding (figure 25 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const ADDRESS: u8 = 0x01;
 pub const BOOLEAN: u8 = 0x02;
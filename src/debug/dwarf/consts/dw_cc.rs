@@ -1,4 +1,4 @@
-///! Calling convention encoding (figure 33 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Calling convention encoding (figure 33 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const NORMAL: u8 = 0x01;
 pub const PROGRAM: u8 = 0x02;
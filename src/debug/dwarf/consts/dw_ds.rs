@@ -1,4 +1,4 @@
-///! Decimal sign encoding (figure 26 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Decimal sign encoding (figure 26 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const UNSIGNED: u8 = 0x01;
 pub const LEADING_OVERPUNCH: u8 = 0x02;
@@ -1,4 +1,4 @@
-///! Discriminant encoding (figure 35 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Discriminant encoding (figure 35 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const LABEL: u8 = 0x00;
 pub const RANGE: u8 = 0x01;
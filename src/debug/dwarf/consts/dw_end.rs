@@ -1,4 +1,4 @@
-///! Endianity sign encoding (figure 26 [https://dwarfstd.org/doc/DWARF4.pdf])7
+//! Endianity sign encoding (figure 26 [https://dwarfstd.org/doc/DWARF4.pdf])7
 
 pub const DEFAULT: u8 = 0x00;
 pub const BIG: u8 = 0x01;
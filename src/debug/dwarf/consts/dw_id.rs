@@ -1,4 +1,4 @@
-///! Identifier case encoding (figure 32 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Identifier case encoding (figure 32 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const CASE_SENSITIVE: u8 = 0x01;
 pub const UP_CASE: u8 = 0x02;
@@ -1,4 +1,4 @@
-///! Inline encoding (figure 34 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Inline encoding (figure 34 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const NOT_INLINED: u8 = 0x00;
 pub const INLINED: u8 = 0x01;
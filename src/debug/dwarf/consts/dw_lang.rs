@@ -1,4 +1,4 @@
-///! Language encoding (figure 31 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Language encoding (figure 31 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const C89: u16 = 0x0001;
 pub const C: u16 = 0x0002;
@@ -1,4 +1,4 @@
-///! Line Number Extended Opcode encoding (figure 38 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Line Number Extended Opcode encoding (figure 38 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const END_SEQUENCE: u8 = 0x01;
 pub const SET_ADDRESS: u8 = 0x02;
@@ -1,4 +1,4 @@
-///! Line Number Standart Opcode encoding (figure 37 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Line Number Standart Opcode encoding (figure 37 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const COPY: u8 = 0x01;
 pub const ADVANCE_PC: u8 = 0x02;
@@ -1,4 +1,4 @@
-///! Macinfo Type encoding (figure 38 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Macinfo Type encoding (figure 38 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const DEFINE: u8 = 0x01;
 pub const UNDEF: u8 = 0x02;
@@ -1,4 +1,4 @@
-///! Ordering encoding (figure 35 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Ordering encoding (figure 35 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const ROW_MAJOR: u8 = 0x00;
 pub const COL_MAJOR: u8 = 0x01;
@@ -1,4 +1,4 @@
-///! Virtuality encoding (figure 30 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Virtuality encoding (figure 30 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const NONE: u8 = 0x00;
 pub const VIRTUAL: u8 = 0x01;
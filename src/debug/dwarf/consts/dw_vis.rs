@@ -1,4 +1,4 @@
-///! Visibility encoding (figure 29 [https://dwarfstd.org/doc/DWARF4.pdf])
+//! Visibility encoding (figure 29 [https://dwarfstd.org/doc/DWARF4.pdf])
 
 pub const LOCAL: u8 = 0x01;
 pub const EXPORTED: u8 = 0x02;
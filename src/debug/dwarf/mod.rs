@@ -1,5 +1,5 @@
-///! Section will be missing lost of documentation
-///! See [https://dwarfstd.org/doc/DWARF4.pdf] for more informations
+//! Section will be missing lost of documentation
+//! See [https://dwarfstd.org/doc/DWARF4.pdf] for more informations
 use std::collections::HashMap;
 
 use crate::data::*;
@@ -68,7 +68,7 @@ impl Context {
 
     fn to_data(self) -> Data {
         let mut v: Vec<_> = self.hashmap.into_iter().collect();
-        v.sort_by(|a, b| a.1.cmp(&b.1));
+        v.sort_by_key(|a| a.1);
         let mut data = Data::empty();
         let mut i = 0;
         for (el, i2) in v {
@@ -2320,7 +2320,7 @@ impl DwarfDebug {
             }
             + debug.abbrev;
         debug.info = dubyte(id).add_comment("Abbrev id".to_string()) + debug.info;
-        id = id + 1;
+        id += 1;
         for child in self.childrens {
             let (new_id, new_debug) = child.to_data_inner(id, context);
             debug.concat(new_debug);
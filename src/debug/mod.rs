@@ -1,8 +1,10 @@
+use crate::file::Section;
 use crate::Data;
 
 /// Defines the dwarf format
 /// [https://dwarfstd.org/doc/DWARF4.pdf]
 #[allow(missing_docs)]
+#[allow(clippy::wrong_self_convention, clippy::manual_non_exhaustive)]
 pub mod dwarf;
 
 /// Structure representing the output of converting a debug symbol structure to data
@@ -14,3 +16,14 @@ pub struct DebugSegments {
     /// .debug_str segment
     pub debug_str: Data,
 }
+
+impl DebugSegments {
+    /// Sections `.debug_abbrev`, `.debug_info` and `.debug_str` to add to [`crate::file::File::sections`]
+    pub fn into_sections(self) -> Vec<Section> {
+        vec![
+            Section::debug(".debug_abbrev", self.debug_abbrev),
+            Section::debug(".debug_info", self.debug_info),
+            Section::debug(".debug_str", self.debug_str),
+        ]
+    }
+}
@@ -1,6 +1,10 @@
 use std::io::Write;
 
-use crate::{reg::Label, traits::Writable};
+use crate::{
+    reg::Label,
+    traits::{UsesLabels, Writable},
+    writer::Writer,
+};
 
 /// Expressions are not recursive, use .set multiple times to build a recursive expression
 pub enum Expr {
@@ -15,20 +19,29 @@ pub enum Expr {
 }
 
 impl Writable for Expr {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
         match self {
             Expr::Sub(lab1, lab2) => {
-                lab1.write_in(file)?;
+                lab1.write_with(file)?;
                 file.write_all(b"-")?;
-                lab2.write_in(file)
+                lab2.write_with(file)
             }
             Expr::Add(lab1, lab2) => {
-                lab1.write_in(file)?;
+                lab1.write_with(file)?;
                 file.write_all(b"+")?;
-                lab2.write_in(file)
+                lab2.write_with(file)
             }
             Expr::UConst(c) => file.write_all(format!("{c}").as_bytes()),
             Expr::SConst(c) => file.write_all(format!("{c}").as_bytes()),
         }
     }
 }
+
+impl UsesLabels for Expr {
+    fn used_labels(&self) -> Vec<&Label> {
+        match self {
+            Expr::Sub(lab1, lab2) | Expr::Add(lab1, lab2) => vec![lab1, lab2],
+            Expr::UConst(_) | Expr::SConst(_) => Vec::new(),
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use std::io::Write;
 
-use crate::{reg::Label, traits::Writable};
+use crate::{
+    reg::Label,
+    traits::{UsesLabels, Writable},
+    writer::{unsupported, Syntax, Writer},
+};
 
 /// Define expressions (for .set)
 pub mod expr;
@@ -16,7 +20,7 @@ pub enum LocOptions {
 }
 
 impl Writable for LocOptions {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
         match self {
             Self::BasicBloc => file.write_all(b"basic_block"),
             Self::PrologueEnd => file.write_all(b"prologue_end"),
@@ -46,7 +50,10 @@ pub enum Directive {
 }
 
 impl Writable for Directive {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        if file.syntax() == Syntax::Nasm {
+            return self.write_nasm(file);
+        }
         match self {
             Directive::P2Align(i1, opt1, opt2) => {
                 file.write_all(format!(".p2align {:#x}", i1).as_bytes())?;
@@ -72,7 +79,7 @@ impl Writable for Directive {
                 }
                 for opt in options {
                     file.write_all(b" ")?;
-                    opt.write_in(file)?;
+                    opt.write_with(file)?;
                 }
             }
             Directive::LocMarkBlocks(b) => {
@@ -81,15 +88,57 @@ impl Writable for Directive {
 
             Directive::Set(lab, expr) => {
                 file.write_all(b".set ")?;
-                lab.write_in(file)?;
+                lab.write_with(file)?;
                 file.write_all(b", ")?;
-                expr.write_in(file)?;
+                expr.write_with(file)?;
             }
         }
         std::io::Result::Ok(())
     }
 }
 
+impl Directive {
+    fn write_nasm(&self, file: &mut Writer) -> std::io::Result<()> {
+        match self {
+            Directive::P2Align(_, _, Some(_)) => {
+                Err(unsupported(".p2align with a maximum skip", file.syntax()))
+            }
+            Directive::P2Align(i1, fill, None) => {
+                file.write_all(format!("align {}", 1usize << i1).as_bytes())?;
+                if let Some(i) = fill {
+                    file.write_all(format!(", db {}", i).as_bytes())?;
+                }
+                std::io::Result::Ok(())
+            }
+            Directive::File(_, _) => Err(unsupported(".file", file.syntax())),
+            Directive::Loc(_, _, _, _) => Err(unsupported(".loc", file.syntax())),
+            Directive::LocMarkBlocks(_) => Err(unsupported(".loc_mark_blocks", file.syntax())),
+            Directive::Set(lab, expr) => {
+                lab.write_with(file)?;
+                file.write_all(b" equ ")?;
+                expr.write_with(file)
+            }
+        }
+    }
+
+    /// Label defined by the directive
+    pub fn defined_label(&self) -> Option<&Label> {
+        match self {
+            Directive::Set(lab, _) => Some(lab),
+            _ => None,
+        }
+    }
+}
+
+impl UsesLabels for Directive {
+    fn used_labels(&self) -> Vec<&Label> {
+        match self {
+            Directive::Set(_, expr) => expr.used_labels(),
+            _ => Vec::new(),
+        }
+    }
+}
+
 /// .set lab1 lab2-lab3  directive
 pub fn set_sub(lab1: Label, lab2: Label, lab3: Label) -> Directive {
     Directive::Set(lab1, expr::Expr::Sub(lab2, lab3))
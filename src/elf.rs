@@ -0,0 +1,574 @@
+use std::io::Write;
+
+use crate::file::{SectionFlags, SectionType};
+use crate::object::{Object, ObjectSection, ObjectSymbol, RelocationKind, SymbolSection};
+use crate::reg::Label;
+use crate::symbol::{Binding, SymbolType, Visibility};
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOTE: u32 = 7;
+const SHT_NOBITS: u32 = 8;
+const SHT_INIT_ARRAY: u32 = 14;
+const SHT_FINI_ARRAY: u32 = 15;
+const SHT_PREINIT_ARRAY: u32 = 16;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_MERGE: u64 = 0x10;
+const SHF_STRINGS: u64 = 0x20;
+const SHF_INFO_LINK: u64 = 0x40;
+const SHF_TLS: u64 = 0x400;
+
+const SHN_ABS: u16 = 0xfff1;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474e551;
+
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+/// ELF header
+struct Ehdr {
+    kind: u16,
+    entry: u64,
+    phnum: u16,
+    shoff: u64,
+    shnum: u16,
+}
+
+impl Ehdr {
+    fn write(&self, out: &mut Vec<u8>) {
+        // ELF64, little endian, version 1, System V ABI
+        out.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        out.extend_from_slice(&[0; 8]);
+        out.extend_from_slice(&self.kind.to_le_bytes());
+        // EM_X86_64
+        out.extend_from_slice(&62u16.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&self.entry.to_le_bytes());
+        // program headers follow the ELF header
+        let phoff = if self.phnum == 0 { 0 } else { EHDR_SIZE as u64 };
+        out.extend_from_slice(&phoff.to_le_bytes());
+        out.extend_from_slice(&self.shoff.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&self.phnum.to_le_bytes());
+        out.extend_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+        out.extend_from_slice(&self.shnum.to_le_bytes());
+        // .shstrtab is the last section
+        out.extend_from_slice(&self.shnum.saturating_sub(1).to_le_bytes());
+    }
+}
+
+/// ELF number of the relocation
+fn relocation_type(kind: RelocationKind) -> u32 {
+    match kind {
+        RelocationKind::Abs64 => 1,
+        RelocationKind::Pc32 => 2,
+        RelocationKind::Plt32 => 4,
+        RelocationKind::Abs32 => 10,
+        RelocationKind::Abs32S => 11,
+        RelocationKind::Pc8 => 15,
+    }
+}
+
+fn section_type(kind: SectionType) -> u32 {
+    match kind {
+        SectionType::Progbits => SHT_PROGBITS,
+        SectionType::Nobits => SHT_NOBITS,
+        SectionType::Note => SHT_NOTE,
+        SectionType::InitArray => SHT_INIT_ARRAY,
+        SectionType::FiniArray => SHT_FINI_ARRAY,
+        SectionType::PreinitArray => SHT_PREINIT_ARRAY,
+    }
+}
+
+fn section_flags(flags: &SectionFlags) -> u64 {
+    let mut out = 0;
+    for (set, flag) in [
+        (flags.write, SHF_WRITE),
+        (flags.alloc, SHF_ALLOC),
+        (flags.exec, SHF_EXECINSTR),
+        (flags.merge.is_some(), SHF_MERGE),
+        (flags.strings, SHF_STRINGS),
+        (flags.tls, SHF_TLS),
+    ] {
+        if set {
+            out |= flag
+        }
+    }
+    out
+}
+
+/// String table (`.strtab` and `.shstrtab`)
+struct StrTab {
+    bytes: Vec<u8>,
+}
+
+impl StrTab {
+    fn new() -> Self {
+        Self { bytes: vec![0] }
+    }
+
+    fn push(&mut self, str: &str) -> u32 {
+        let index = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(str.as_bytes());
+        self.bytes.push(0);
+        index
+    }
+}
+
+/// Section header and content
+struct Shdr {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+    size: u64,
+    bytes: Vec<u8>,
+}
+
+impl Shdr {
+    fn new(name: u32, kind: u32, bytes: Vec<u8>) -> Self {
+        Self {
+            name,
+            kind,
+            flags: 0,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+            size: bytes.len() as u64,
+            bytes,
+        }
+    }
+
+    fn of_section(name: u32, section: &ObjectSection) -> Self {
+        let mut shdr = Self::new(name, section_type(section.kind), section.bytes.clone());
+        shdr.flags = section_flags(&section.flags);
+        shdr.align = section.align as u64;
+        shdr.entsize = section.flags.merge.unwrap_or(0) as u64;
+        shdr.size = section.size as u64;
+        shdr
+    }
+
+    fn write_header(&self, out: &mut Vec<u8>, offset: usize) {
+        out.extend_from_slice(&self.name.to_le_bytes());
+        out.extend_from_slice(&self.kind.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        // sh_addr
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&(offset as u64).to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.link.to_le_bytes());
+        out.extend_from_slice(&self.info.to_le_bytes());
+        out.extend_from_slice(&self.align.to_le_bytes());
+        out.extend_from_slice(&self.entsize.to_le_bytes());
+    }
+}
+
+fn push_symbol(
+    symtab: &mut Vec<u8>,
+    name: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+) {
+    symtab.extend_from_slice(&name.to_le_bytes());
+    symtab.push(info);
+    symtab.push(other);
+    symtab.extend_from_slice(&shndx.to_le_bytes());
+    symtab.extend_from_slice(&value.to_le_bytes());
+    symtab.extend_from_slice(&size.to_le_bytes());
+}
+
+/// Write an object as an ELF64 relocatable file (like the output of `as`)
+///
+/// Labels starting with `.L` are not written in the symbol table and relocations
+/// to local labels use the symbol of their section.
+/// An empty `.note.GNU-stack` section is added to mark the stack as not executable.
+pub fn write_object(object: &Object, file: &mut dyn Write) -> std::io::Result<()> {
+    let mut shstrtab = StrTab::new();
+    let mut strtab = StrTab::new();
+    // index 0 is the null section, sections of the object come next
+    let mut shdrs = vec![Shdr::new(0, 0, Vec::new())];
+    for section in &object.sections {
+        shdrs.push(Shdr::of_section(shstrtab.push(&section.name), section));
+    }
+    if object.section(".note.GNU-stack").is_none() {
+        shdrs.push(Shdr::new(
+            shstrtab.push(".note.GNU-stack"),
+            SHT_PROGBITS,
+            Vec::new(),
+        ));
+    }
+    let shndx = |index: usize| (index + 1) as u16;
+
+    // local symbols come first: the null symbol, sections and local labels
+    let mut symtab = vec![0; SYM_SIZE];
+    let mut indices: Vec<(&Label, u32)> = Vec::new();
+    for index in 0..object.sections.len() {
+        let info = (STB_LOCAL << 4) | STT_SECTION;
+        push_symbol(&mut symtab, 0, info, 0, shndx(index), 0, 0);
+    }
+    let mut nb_symbols = object.sections.len() as u32 + 1;
+    let mut first_global = 0;
+    for local in [true, false] {
+        if !local {
+            first_global = nb_symbols;
+        }
+        for symbol in &object.symbols {
+            let binding = match symbol.binding {
+                Binding::Local => STB_LOCAL,
+                Binding::Global => STB_GLOBAL,
+                Binding::Weak => STB_WEAK,
+            };
+            let name = symbol.label.name();
+            let section_label = matches!(symbol.section, SymbolSection::Section(_));
+            if (binding == STB_LOCAL) != local || (local && section_label && name.starts_with(".L"))
+            {
+                continue;
+            }
+            let kind = match symbol.kind {
+                None => STT_NOTYPE,
+                Some(SymbolType::Function) => STT_FUNC,
+                Some(SymbolType::Object) => STT_OBJECT,
+            };
+            let visibility = match symbol.visibility {
+                Visibility::Default => 0,
+                Visibility::Hidden => 2,
+                Visibility::Protected => 3,
+            };
+            let section = match symbol.section {
+                SymbolSection::Undefined => 0,
+                SymbolSection::Absolute => SHN_ABS,
+                SymbolSection::Section(index) => shndx(index),
+            };
+            push_symbol(
+                &mut symtab,
+                strtab.push(name),
+                (binding << 4) | kind,
+                visibility,
+                section,
+                symbol.value,
+                symbol.size.unwrap_or(0) as u64,
+            );
+            indices.push((&symbol.label, nb_symbols));
+            nb_symbols += 1;
+        }
+    }
+
+    let relocated = object
+        .sections
+        .iter()
+        .filter(|section| !section.relocations.is_empty())
+        .count();
+    let symtab_index = (shdrs.len() + relocated) as u32;
+    for (index, section) in object.sections.iter().enumerate() {
+        if section.relocations.is_empty() {
+            continue;
+        }
+        let mut rela = Vec::with_capacity(section.relocations.len() * RELA_SIZE);
+        for relocation in &section.relocations {
+            let (sym, addend) = match object.symbol(&relocation.label) {
+                // relocations to local labels use the symbol of their section
+                Some(ObjectSymbol {
+                    binding: Binding::Local,
+                    section: SymbolSection::Section(index),
+                    value,
+                    ..
+                }) => (shndx(*index) as u32, relocation.addend + *value as i64),
+                _ => match indices
+                    .iter()
+                    .find(|(label, _)| *label == &relocation.label)
+                {
+                    Some((_, sym)) => (*sym, relocation.addend),
+                    None => {
+                        return Err(invalid_input(format!(
+                            "no symbol for label {}",
+                            relocation.label.name()
+                        )))
+                    }
+                },
+            };
+            let info = ((sym as u64) << 32) | relocation_type(relocation.kind) as u64;
+            rela.extend_from_slice(&(relocation.offset as u64).to_le_bytes());
+            rela.extend_from_slice(&info.to_le_bytes());
+            rela.extend_from_slice(&addend.to_le_bytes());
+        }
+        let name = shstrtab.push(&format!(".rela{}", section.name));
+        let mut shdr = Shdr::new(name, SHT_RELA, rela);
+        shdr.flags = SHF_INFO_LINK;
+        shdr.link = symtab_index;
+        shdr.info = shndx(index) as u32;
+        shdr.align = 8;
+        shdr.entsize = RELA_SIZE as u64;
+        shdrs.push(shdr);
+    }
+
+    let mut shdr = Shdr::new(shstrtab.push(".symtab"), SHT_SYMTAB, symtab);
+    shdr.link = symtab_index + 1;
+    shdr.info = first_global;
+    shdr.align = 8;
+    shdr.entsize = SYM_SIZE as u64;
+    shdrs.push(shdr);
+    shdrs.push(Shdr::new(
+        shstrtab.push(".strtab"),
+        SHT_STRTAB,
+        strtab.bytes,
+    ));
+    let name = shstrtab.push(".shstrtab");
+    shdrs.push(Shdr::new(name, SHT_STRTAB, shstrtab.bytes));
+
+    // content of the sections follows the ELF header, section headers are at the end
+    let mut content = Vec::new();
+    let mut offsets = Vec::with_capacity(shdrs.len());
+    for shdr in &shdrs {
+        let align = shdr.align.max(1) as usize;
+        let padding = (align - (EHDR_SIZE + content.len()) % align) % align;
+        content.resize(content.len() + padding, 0);
+        offsets.push(EHDR_SIZE + content.len());
+        content.extend_from_slice(&shdr.bytes);
+    }
+    content.resize(content.len() + (8 - (EHDR_SIZE + content.len()) % 8) % 8, 0);
+    let shoff = EHDR_SIZE + content.len();
+
+    let mut out = Vec::with_capacity(shoff + shdrs.len() * SHDR_SIZE);
+    let header = Ehdr {
+        kind: ET_REL,
+        entry: 0,
+        phnum: 0,
+        shoff: shoff as u64,
+        shnum: shdrs.len() as u16,
+    };
+    header.write(&mut out);
+    out.append(&mut content);
+    for (shdr, offset) in shdrs.iter().zip(offsets) {
+        shdr.write_header(&mut out, if shdr.kind == 0 { 0 } else { offset });
+    }
+    file.write_all(&out)
+}
+
+/// Address where executables are loaded
+const BASE_ADDRESS: u64 = 0x40_0000;
+const PAGE_SIZE: usize = 0x1000;
+
+/// Loadable segment of an executable
+struct Phdr {
+    flags: u32,
+    offset: usize,
+    vaddr: u64,
+    filesz: usize,
+    memsz: usize,
+}
+
+impl Phdr {
+    fn write(&self, out: &mut Vec<u8>, kind: u32, align: u64) {
+        out.extend_from_slice(&kind.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        out.extend_from_slice(&(self.offset as u64).to_le_bytes());
+        out.extend_from_slice(&self.vaddr.to_le_bytes());
+        // p_paddr
+        out.extend_from_slice(&self.vaddr.to_le_bytes());
+        out.extend_from_slice(&(self.filesz as u64).to_le_bytes());
+        out.extend_from_slice(&(self.memsz as u64).to_le_bytes());
+        out.extend_from_slice(&align.to_le_bytes());
+    }
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) / align * align
+}
+
+/// Write an object as a static ELF64 executable starting at `entry`
+///
+/// Sections are loaded in three segments (code, read only data and writable data),
+/// every label must be defined in the object as no library is linked (undefined weak
+/// symbols are 0). Sections that are not allocated (like debug sections) are dropped.
+pub fn write_executable(
+    object: &Object,
+    entry: &Label,
+    file: &mut dyn Write,
+) -> std::io::Result<()> {
+    let loaded: Vec<usize> = (0..object.sections.len())
+        .filter(|index| object.sections[*index].flags.alloc)
+        .collect();
+    let group = |index: &usize| {
+        let section = &object.sections[*index];
+        if section.flags.exec {
+            0
+        } else if !section.flags.write {
+            1
+        } else if section.kind != SectionType::Nobits {
+            2
+        } else {
+            3
+        }
+    };
+    let groups: [Vec<usize>; 3] = [
+        loaded.iter().filter(|i| group(i) == 0).copied().collect(),
+        loaded.iter().filter(|i| group(i) == 1).copied().collect(),
+        // .bss is at the end of the segment
+        loaded
+            .iter()
+            .filter(|i| group(i) == 2)
+            .chain(loaded.iter().filter(|i| group(i) == 3))
+            .copied()
+            .collect(),
+    ];
+    // the first segment also contains the headers
+    let phnum = 1 + groups[1..].iter().filter(|group| !group.is_empty()).count();
+    let mut size = EHDR_SIZE + (phnum + 1) * PHDR_SIZE;
+
+    // offsets in the file and addresses are equal modulo the page size
+    let mut phdrs = Vec::new();
+    let mut addresses = vec![0; object.sections.len()];
+    let mut offsets = vec![0; object.sections.len()];
+    for (i, (sections, flags)) in groups
+        .iter()
+        .zip([PF_R | PF_X, PF_R, PF_R | PF_W])
+        .enumerate()
+    {
+        if i > 0 && sections.is_empty() {
+            continue;
+        }
+        let start = if i == 0 { 0 } else { align_up(size, PAGE_SIZE) };
+        let vaddr = BASE_ADDRESS + start as u64;
+        let mut memsz = size.max(start) - start;
+        let mut filesz = memsz;
+        for index in sections {
+            let section = &object.sections[*index];
+            memsz = align_up(memsz, section.align.max(1));
+            offsets[*index] = start + memsz;
+            addresses[*index] = vaddr + memsz as u64;
+            memsz += section.size;
+            if section.kind != SectionType::Nobits {
+                filesz = memsz;
+            }
+        }
+        size = start + filesz;
+        phdrs.push(Phdr {
+            flags,
+            offset: start,
+            vaddr,
+            filesz,
+            memsz,
+        });
+    }
+
+    let address = |label: &Label| match object.symbol(label) {
+        Some(ObjectSymbol {
+            section: SymbolSection::Section(index),
+            value,
+            ..
+        }) => Ok(addresses[*index] + value),
+        Some(ObjectSymbol {
+            section: SymbolSection::Absolute,
+            value,
+            ..
+        }) => Ok(*value),
+        Some(ObjectSymbol {
+            section: SymbolSection::Undefined,
+            binding: Binding::Weak,
+            ..
+        }) => Ok(0),
+        _ => Err(invalid_input(format!(
+            "label {} is not defined",
+            label.name()
+        ))),
+    };
+
+    let mut out = vec![0; size];
+    let mut headers = Vec::with_capacity(EHDR_SIZE + (phnum + 1) * PHDR_SIZE);
+    Ehdr {
+        kind: ET_EXEC,
+        entry: address(entry)?,
+        phnum: phnum as u16 + 1,
+        shoff: 0,
+        shnum: 0,
+    }
+    .write(&mut headers);
+    for phdr in &phdrs {
+        phdr.write(&mut headers, PT_LOAD, PAGE_SIZE as u64);
+    }
+    // the stack is not executable
+    Phdr {
+        flags: PF_R | PF_W,
+        offset: 0,
+        vaddr: 0,
+        filesz: 0,
+        memsz: 0,
+    }
+    .write(&mut headers, PT_GNU_STACK, 16);
+    out[..headers.len()].copy_from_slice(&headers);
+
+    for index in &loaded {
+        let section = &object.sections[*index];
+        if section.kind == SectionType::Nobits {
+            continue;
+        }
+        let bytes = &mut out[offsets[*index]..offsets[*index] + section.size];
+        bytes.copy_from_slice(&section.bytes);
+        for relocation in &section.relocations {
+            let target = address(&relocation.label)? as i64 + relocation.addend;
+            let place = (addresses[*index] + relocation.offset as u64) as i64;
+            let value = match relocation.kind {
+                RelocationKind::Pc8 | RelocationKind::Pc32 | RelocationKind::Plt32 => {
+                    target - place
+                }
+                RelocationKind::Abs32 | RelocationKind::Abs32S | RelocationKind::Abs64 => target,
+            };
+            let fits = match relocation.kind {
+                RelocationKind::Pc8 => i8::try_from(value).is_ok(),
+                RelocationKind::Pc32 | RelocationKind::Plt32 | RelocationKind::Abs32S => {
+                    i32::try_from(value).is_ok()
+                }
+                RelocationKind::Abs32 => u32::try_from(value).is_ok(),
+                RelocationKind::Abs64 => true,
+            };
+            if !fits {
+                return Err(invalid_input(format!(
+                    "relocation to {} does not fit",
+                    relocation.label.name()
+                )));
+            }
+            let size = relocation.kind.size();
+            bytes[relocation.offset..relocation.offset + size]
+                .copy_from_slice(&value.to_le_bytes()[..size]);
+        }
+    }
+    file.write_all(&out)
+}
@@ -1,9 +1,288 @@
-use super::{Data, Text};
-use crate::traits::Writable;
+use super::{Data, NamedSegment, Text};
+use crate::data::DataEL;
+use crate::instr::Instr;
+use crate::reg::Label;
+use crate::symbol::Symbol;
+use crate::traits::{UsesLabels, Writable};
+use crate::writer::{Syntax, Writer};
 use std::fs;
 use std::io::prelude::*;
 
+/// Flags of a section (`"aw"` in `.section .data,"aw",@progbits`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SectionFlags {
+    /// Section is loaded in memory (a)
+    pub alloc: bool,
+    /// Section is writable (w)
+    pub write: bool,
+    /// Section is executable (x)
+    pub exec: bool,
+    /// Section can be merged, the value is the size of the entities (M)
+    pub merge: Option<usize>,
+    /// Section contains zero terminated strings (S)
+    pub strings: bool,
+    /// Section contains thread local data (T)
+    pub tls: bool,
+}
+
+impl SectionFlags {
+    /// Flags of read only data (a)
+    pub fn read_only() -> Self {
+        Self {
+            alloc: true,
+            ..Self::default()
+        }
+    }
+
+    /// Flags of writable data (aw)
+    pub fn writable() -> Self {
+        Self {
+            alloc: true,
+            write: true,
+            ..Self::default()
+        }
+    }
+
+    /// Flags of code (ax)
+    pub fn executable() -> Self {
+        Self {
+            alloc: true,
+            exec: true,
+            ..Self::default()
+        }
+    }
+
+    /// Get flags as written by GNU as
+    pub fn to_gas_string(&self) -> String {
+        let mut str = String::new();
+        if self.alloc {
+            str.push('a')
+        }
+        if self.write {
+            str.push('w')
+        }
+        if self.exec {
+            str.push('x')
+        }
+        if self.merge.is_some() {
+            str.push('M')
+        }
+        if self.strings {
+            str.push('S')
+        }
+        if self.tls {
+            str.push('T')
+        }
+        str
+    }
+
+    /// Get flags as written by NASM (merge and strings have no equivalent and are dropped)
+    pub fn to_nasm_string(&self) -> String {
+        let mut str = String::new();
+        str.push_str(if self.alloc { " alloc" } else { " noalloc" });
+        str.push_str(if self.exec { " exec" } else { " noexec" });
+        str.push_str(if self.write { " write" } else { " nowrite" });
+        if self.tls {
+            str.push_str(" tls")
+        }
+        str
+    }
+}
+
+/// Type of a section (`@progbits` in `.section .data,"aw",@progbits`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionType {
+    /// Section with content
+    Progbits,
+    /// Section without content (only zeros like `.bss`)
+    Nobits,
+    /// Note section
+    Note,
+    /// Array of constructors
+    InitArray,
+    /// Array of destructors
+    FiniArray,
+    /// Array of pre-constructors
+    PreinitArray,
+}
+
+impl SectionType {
+    /// Get type as written by GNU as (without the `@`) and NASM
+    pub fn to_gas_str(&self) -> &'static str {
+        match self {
+            Self::Progbits => "progbits",
+            Self::Nobits => "nobits",
+            Self::Note => "note",
+            Self::InitArray => "init_array",
+            Self::FiniArray => "fini_array",
+            Self::PreinitArray => "preinit_array",
+        }
+    }
+
+    /// Mach-O name of well-known ELF sections, `.debug_*` sections go in the `__DWARF` segment
+    pub fn macho_name(name: &str) -> Option<String> {
+        let name = match name {
+            ".text" => "__TEXT,__text",
+            ".rodata" => "__TEXT,__const",
+            ".data" => "__DATA,__data",
+            ".data.rel.ro" => "__DATA,__const",
+            ".bss" => "__DATA,__bss",
+            ".init_array" => "__DATA,__mod_init_func",
+            ".fini_array" => "__DATA,__mod_term_func",
+            // Mach-O section names are at most 16 bytes
+            _ => {
+                return name
+                    .strip_prefix(".debug_")
+                    .filter(|debug| debug.len() <= 8)
+                    .map(|debug| format!("__DWARF,__debug_{}", debug))
+            }
+        };
+        Some(name.to_string())
+    }
+}
+
+/// Section of a file, containing either code or data
+pub enum Section {
+    /// Section containing instructions
+    Text(NamedSegment<Instr>),
+    /// Section containing data
+    Data(NamedSegment<DataEL>),
+}
+
+impl Section {
+    /// Custom code section (flags "ax", @progbits)
+    pub fn text(name: &str, text: Text) -> Self {
+        Self::Text(
+            text.name(name.to_string())
+                .with_flags(SectionFlags::executable())
+                .with_type(SectionType::Progbits),
+        )
+    }
+
+    /// Custom data section (flags "aw", @progbits)
+    pub fn data(name: &str, data: Data) -> Self {
+        Self::Data(
+            data.name(name.to_string())
+                .with_flags(SectionFlags::writable())
+                .with_type(SectionType::Progbits),
+        )
+    }
+
+    /// Read only data (.rodata)
+    pub fn rodata(data: Data) -> Self {
+        Self::Data(
+            data.name(".rodata".to_string())
+                .with_flags(SectionFlags::read_only())
+                .with_type(SectionType::Progbits),
+        )
+    }
+
+    /// Data initialised to zero (.bss), should only contain labels and [`crate::data::space`]
+    pub fn bss(data: Data) -> Self {
+        Self::Data(
+            data.name(".bss".to_string())
+                .with_flags(SectionFlags::writable())
+                .with_type(SectionType::Nobits),
+        )
+    }
+
+    /// Data read only after relocation (.data.rel.ro)
+    pub fn data_rel_ro(data: Data) -> Self {
+        Self::Data(
+            data.name(".data.rel.ro".to_string())
+                .with_flags(SectionFlags::writable())
+                .with_type(SectionType::Progbits),
+        )
+    }
+
+    /// Addresses of functions called before main (.init_array)
+    pub fn init_array(data: Data) -> Self {
+        Self::Data(
+            data.name(".init_array".to_string())
+                .with_flags(SectionFlags::writable())
+                .with_type(SectionType::InitArray),
+        )
+    }
+
+    /// Addresses of functions called after main (.fini_array)
+    pub fn fini_array(data: Data) -> Self {
+        Self::Data(
+            data.name(".fini_array".to_string())
+                .with_flags(SectionFlags::writable())
+                .with_type(SectionType::FiniArray),
+        )
+    }
+
+    /// Note section (name should start with .note)
+    pub fn note(name: &str, data: Data) -> Self {
+        Self::Data(
+            data.name(name.to_string())
+                .with_flags(SectionFlags::read_only())
+                .with_type(SectionType::Note),
+        )
+    }
+
+    /// Debug section (no flags, @progbits)
+    pub fn debug(name: &str, data: Data) -> Self {
+        Self::Data(
+            data.name(name.to_string())
+                .with_flags(SectionFlags::default())
+                .with_type(SectionType::Progbits),
+        )
+    }
+
+    /// Name of the section
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Text(text) => text.name(),
+            Self::Data(data) => data.name(),
+        }
+    }
+
+    fn defined_labels(&self) -> Vec<&Label> {
+        match self {
+            Self::Text(text) => text.segment().defined_labels(),
+            Self::Data(data) => data.segment().defined_labels(),
+        }
+    }
+}
+
+impl UsesLabels for Section {
+    fn used_labels(&self) -> Vec<&Label> {
+        match self {
+            Self::Text(text) => text.segment().used_labels(),
+            Self::Data(data) => data.segment().used_labels(),
+        }
+    }
+}
+
+impl Writable for Section {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        match self {
+            Self::Text(text) => text.write_with(file),
+            Self::Data(data) => data.write_with(file),
+        }
+    }
+}
+
+impl From<NamedSegment<Instr>> for Section {
+    fn from(text: NamedSegment<Instr>) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<NamedSegment<DataEL>> for Section {
+    fn from(data: NamedSegment<DataEL>) -> Self {
+        Self::Data(data)
+    }
+}
+
 /// Structure representing a file
+///
+/// `text_ss` and `data_ss` are written in `.text` and `.data`, followed by `sections`
+///
+/// Attributes of `symbols` are written at the beginning of the file, their sizes at the end
+#[derive(Default)]
 pub struct File {
     /// Entry point of code
     pub globl: Option<super::reg::Label>,
@@ -13,24 +292,142 @@ pub struct File {
 
     /// Data segment
     pub data_ss: Data,
+
+    /// Other sections
+    pub sections: Vec<Section>,
+
+    /// Attributes of symbols (binding, visibility, type and size)
+    pub symbols: Vec<Symbol>,
 }
 
 impl File {
     /// Method to print assembly file in given file
     pub fn print_in(self, file_name: &str) -> std::io::Result<()> {
         let mut file = fs::File::create(file_name)?;
+        self.write_in(&mut file)
+    }
+
+    /// Assemble the file and write it as an ELF object file (`.o`) without calling `as`
+    pub fn print_object_in(&self, file_name: &str) -> std::io::Result<()> {
+        let object = crate::object::Object::new(self)?;
+        let mut file = fs::File::create(file_name)?;
+        crate::elf::write_object(&object, &mut file)
+    }
+
+    /// Assemble the file and write it as a static executable starting at `globl`
+    ///
+    /// No library is linked: the program cannot call libc and must end with the `exit` syscall
+    pub fn print_executable_in(&self, file_name: &str) -> std::io::Result<()> {
+        let entry = self.globl.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "an executable needs an entry point (globl)",
+            )
+        })?;
+        let object = crate::object::Object::new(self)?;
+        let mut file = fs::File::create(file_name)?;
+        crate::elf::write_executable(&object, entry, &mut file)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(())
+    }
+}
+
+impl File {
+    /// Labels defined in the file
+    pub fn defined_labels(&self) -> Vec<&Label> {
+        let mut defined = self.text_ss.defined_labels();
+        defined.append(&mut self.data_ss.defined_labels());
+        for section in &self.sections {
+            defined.append(&mut section.defined_labels());
+        }
+        defined
+    }
+
+    /// Labels used but not defined in the file
+    pub fn external_labels(&self) -> Vec<&Label> {
+        let defined = self.defined_labels();
+        let mut external: Vec<&Label> = Vec::new();
+        let mut used = self.text_ss.used_labels();
+        used.append(&mut self.data_ss.used_labels());
+        for section in &self.sections {
+            used.append(&mut section.used_labels());
+        }
+        for symbol in &self.symbols {
+            used.append(&mut symbol.used_labels());
+        }
+        for label in used {
+            if !defined.contains(&label) && !external.contains(&label) {
+                external.push(label)
+            }
+        }
+        external
+    }
+
+    fn write_nasm(&self, file: &mut Writer) -> std::io::Result<()> {
+        file.write_all(b"\tsection .text\n")?;
+        if let Some(main) = &self.globl {
+            file.write_all(b"\tglobal ")?;
+            main.write_with(file)?;
+            file.write_all(b"\n")?;
+        }
+        for symbol in &self.symbols {
+            symbol.write_attributes(file)?;
+        }
+        for label in self.external_labels() {
+            file.write_all(b"\textern ")?;
+            label.write_with(file)?;
+            file.write_all(b"\n")?;
+        }
+        self.text_ss.write_with(file)?;
+        file.write_all(b"\tsection .data\n")?;
+        self.data_ss.write_with(file)?;
+        for section in &self.sections {
+            section.write_with(file)?;
+        }
+        std::io::Result::Ok(())
+    }
+}
+
+impl Writable for File {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        if file.syntax() == Syntax::Nasm {
+            return self.write_nasm(file);
+        }
+        if file.syntax() == Syntax::Intel {
+            file.write_all(b"\t.intel_syntax noprefix\n")?;
+        }
         file.write_all(b"\t.text\n")?;
-        match self.globl {
+        match &self.globl {
             None => (),
             Some(main) => {
                 file.write_all(b"\t.globl\t")?;
-                main.write_in(&mut file)?;
+                main.write_with(file)?;
                 file.write_all(b"\n")?;
             }
         }
-        self.text_ss.write_in(&mut file)?;
+        for symbol in &self.symbols {
+            symbol.write_attributes(file)?;
+        }
+        self.text_ss.write_with(file)?;
         file.write_all(b"\t.data\n")?;
 
-        self.data_ss.write_in(&mut file)
+        self.data_ss.write_with(file)?;
+        for section in &self.sections {
+            section.write_with(file)?;
+        }
+        for symbol in &self.symbols {
+            symbol.write_size_directive(file)?;
+        }
+        std::io::Result::Ok(())
+    }
+}
+
+impl std::fmt::Display for File {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::traits::fmt_writable(self, f)
     }
 }
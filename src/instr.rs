@@ -1,7 +1,17 @@
 use std::io::Write;
 
-use crate::reg::{Label, Operand, RegInv, RegQ, Sizes};
-use crate::traits::{Reg, Writable};
+use crate::reg::{Label, Operand, Sizes};
+use crate::traits::{Reg, UsesLabels, Writable};
+use crate::writer::{Syntax, Writer};
+
+/// Encoding of instructions in machine code
+pub mod encode;
+
+/// Assemble a text segment in machine code
+pub mod assembler;
+
+/// Decode machine code into instructions
+pub mod decode;
 
 /// Various conditionals
 ///
@@ -36,10 +46,62 @@ pub enum Cond {
     B,
     /// CF or ZF = below or equal (unsigned lower or equal)
     BE,
+    /// OF = overflow
+    O,
+    /// not(OF) = no overflow
+    NO,
+    /// PF = parity even (unordered after ucomisd)
+    P,
+    /// Same as P
+    PE,
+    /// not(PF) = parity odd (ordered after ucomisd)
+    NP,
+    /// Same as NP
+    PO,
+    /// Same as B, CF = carry
+    C,
+    /// Same as AE
+    NC,
+    /// Same as BE
+    NA,
+    /// Same as B
+    NAE,
+    /// Same as AE
+    NB,
+    /// Same as A
+    NBE,
+    /// Same as LE
+    NG,
+    /// Same as L
+    NGE,
+    /// Same as GE
+    NL,
+    /// Same as G
+    NLE,
+}
+
+/// Comparison of the destination of `cmp` with its source
+///
+/// `cmpq(reg!(RBX), reg!(RAX))` followed by a jump on `Cond::signed(Comparison::Lt)`
+/// jumps if %rax < %rbx
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    /// Equal
+    Eq,
+    /// Not equal
+    Ne,
+    /// Lower
+    Lt,
+    /// Lower or equal
+    Le,
+    /// Greater
+    Gt,
+    /// Greater or equal
+    Ge,
 }
 
 impl Cond {
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
             Self::E => "e",
             Self::Z => "z",
@@ -55,6 +117,230 @@ impl Cond {
             Self::AE => "ae",
             Self::B => "b",
             Self::BE => "be",
+            Self::O => "o",
+            Self::NO => "no",
+            Self::P => "p",
+            Self::PE => "pe",
+            Self::NP => "np",
+            Self::PO => "po",
+            Self::C => "c",
+            Self::NC => "nc",
+            Self::NA => "na",
+            Self::NAE => "nae",
+            Self::NB => "nb",
+            Self::NBE => "nbe",
+            Self::NG => "ng",
+            Self::NGE => "nge",
+            Self::NL => "nl",
+            Self::NLE => "nle",
+        }
+    }
+
+    /// Condition of a comparison of signed integers
+    pub fn signed(cmp: Comparison) -> Self {
+        match cmp {
+            Comparison::Eq => Self::E,
+            Comparison::Ne => Self::NE,
+            Comparison::Lt => Self::L,
+            Comparison::Le => Self::LE,
+            Comparison::Gt => Self::G,
+            Comparison::Ge => Self::GE,
+        }
+    }
+
+    /// Condition of a comparison of unsigned integers (or of floats with `ucomisd`)
+    pub fn unsigned(cmp: Comparison) -> Self {
+        match cmp {
+            Comparison::Eq => Self::E,
+            Comparison::Ne => Self::NE,
+            Comparison::Lt => Self::B,
+            Comparison::Le => Self::BE,
+            Comparison::Gt => Self::A,
+            Comparison::Ge => Self::AE,
+        }
+    }
+
+    /// Condition true when `self` is false
+    pub fn negate(self) -> Self {
+        match self {
+            Self::E => Self::NE,
+            Self::Z => Self::NZ,
+            Self::NE => Self::E,
+            Self::NZ => Self::Z,
+            Self::S => Self::NS,
+            Self::NS => Self::S,
+            Self::G => Self::LE,
+            Self::GE => Self::L,
+            Self::L => Self::GE,
+            Self::LE => Self::G,
+            Self::A => Self::BE,
+            Self::AE => Self::B,
+            Self::B => Self::AE,
+            Self::BE => Self::A,
+            Self::O => Self::NO,
+            Self::NO => Self::O,
+            Self::P => Self::NP,
+            Self::PE => Self::PO,
+            Self::NP => Self::P,
+            Self::PO => Self::PE,
+            Self::C => Self::NC,
+            Self::NC => Self::C,
+            Self::NA => Self::A,
+            Self::NAE => Self::AE,
+            Self::NB => Self::B,
+            Self::NBE => Self::BE,
+            Self::NG => Self::G,
+            Self::NGE => Self::GE,
+            Self::NL => Self::L,
+            Self::NLE => Self::LE,
+        }
+    }
+
+    /// Condition to use when the operands of `cmp` are swapped
+    ///
+    /// Returns `None` for the conditions on the sign, overflow and parity flags,
+    /// they do not only depend on the order of the operands
+    pub fn swap_operands(self) -> Option<Self> {
+        match self {
+            Self::E | Self::Z | Self::NE | Self::NZ => Some(self),
+            Self::G => Some(Self::L),
+            Self::GE => Some(Self::LE),
+            Self::L => Some(Self::G),
+            Self::LE => Some(Self::GE),
+            Self::A => Some(Self::B),
+            Self::AE => Some(Self::BE),
+            Self::B | Self::C => Some(Self::A),
+            Self::BE => Some(Self::AE),
+            Self::NC => Some(Self::BE),
+            Self::NA => Some(Self::NB),
+            Self::NAE => Some(Self::NBE),
+            Self::NB => Some(Self::NA),
+            Self::NBE => Some(Self::NAE),
+            Self::NG => Some(Self::NL),
+            Self::NGE => Some(Self::NLE),
+            Self::NL => Some(Self::NG),
+            Self::NLE => Some(Self::NGE),
+            Self::S | Self::NS | Self::O | Self::NO | Self::P | Self::PE | Self::NP | Self::PO => {
+                None
+            }
+        }
+    }
+
+    /// Condition code used in the opcodes of jcc, setcc and cmovcc
+    fn code(self) -> u8 {
+        match self {
+            Self::O => 0x0,
+            Self::NO => 0x1,
+            Self::B | Self::C | Self::NAE => 0x2,
+            Self::AE | Self::NC | Self::NB => 0x3,
+            Self::E | Self::Z => 0x4,
+            Self::NE | Self::NZ => 0x5,
+            Self::BE | Self::NA => 0x6,
+            Self::A | Self::NBE => 0x7,
+            Self::S => 0x8,
+            Self::NS => 0x9,
+            Self::P | Self::PE => 0xa,
+            Self::NP | Self::PO => 0xb,
+            Self::L | Self::NGE => 0xc,
+            Self::GE | Self::NL => 0xd,
+            Self::LE | Self::NG => 0xe,
+            Self::G | Self::NLE => 0xf,
+        }
+    }
+}
+
+/// Prefixes of the lock and repeat group, an instruction has at most one of them
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Prefix {
+    /// Repeat a string instruction %rcx times (`movs`, `stos` and `lods`)
+    Rep,
+    /// Repeat a string comparison %rcx times while the elements are equal (`cmps` and `scas`)
+    Repe,
+    /// Repeat a string comparison %rcx times while the elements are different (`cmps` and `scas`)
+    Repne,
+    /// Atomic read-modify-write of a memory destination
+    Lock,
+}
+
+impl Prefix {
+    fn to_str(self) -> &'static str {
+        match self {
+            Self::Rep => "rep",
+            Self::Repe => "repe",
+            Self::Repne => "repne",
+            Self::Lock => "lock",
+        }
+    }
+
+    /// Byte written before the instruction
+    fn code(self) -> u8 {
+        match self {
+            Self::Rep | Self::Repe => 0xf3,
+            Self::Repne => 0xf2,
+            Self::Lock => 0xf0,
+        }
+    }
+}
+
+/// Segment override prefixes, the memory operand of the instruction (or the (%rsi) source of
+/// `movs`, `lods` and `cmps`) is relative to the base of the segment (thread local storage
+/// uses %fs on Linux and %gs on macOS)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SegmentOverride {
+    /// `%fs` segment
+    Fs,
+    /// `%gs` segment
+    Gs,
+}
+
+impl SegmentOverride {
+    fn to_str(self) -> &'static str {
+        match self {
+            Self::Fs => "fs",
+            Self::Gs => "gs",
+        }
+    }
+
+    /// Byte written before the instruction
+    fn code(self) -> u8 {
+        match self {
+            Self::Fs => 0x64,
+            Self::Gs => 0x65,
+        }
+    }
+}
+
+/// Precision of floating point instructions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Precision {
+    /// 4-bytes floats (`ss` and `ps` suffixes)
+    Single,
+    /// 8-bytes floats (`sd` and `pd` suffixes)
+    Double,
+}
+
+/// Suffix of packed integer instructions for elements of the given size
+fn element_suffix(size: Sizes) -> &'static str {
+    match size {
+        Sizes::Byte => "b",
+        Sizes::Word => "w",
+        Sizes::Long => "d",
+        _ => "q",
+    }
+}
+
+impl Precision {
+    fn scalar(self) -> &'static str {
+        match self {
+            Self::Single => "ss",
+            Self::Double => "sd",
+        }
+    }
+
+    fn packed(self) -> &'static str {
+        match self {
+            Self::Single => "ps",
+            Self::Double => "pd",
         }
     }
 }
@@ -64,6 +350,8 @@ impl Cond {
 pub enum InstrName {
     /// Move operation
     Move,
+    /// Move of a 64-bits immediate or between %rax and a 64-bits absolute address
+    Movabs,
     /// Add operation
     Add,
     /// Add with carry
@@ -84,6 +372,14 @@ pub enum InstrName {
     Shr,
     /// Arithmetic right shift
     Sar,
+    /// Left rotation
+    Rol,
+    /// Right rotation
+    Ror,
+    /// Left rotation through the carry flag
+    Rcl,
+    /// Right rotation through the carry flag
+    Rcr,
     /// Compare (set flags based on Sub instr)
     Cmp,
     /// Test  (set flags based on And instr)
@@ -92,15 +388,31 @@ pub enum InstrName {
     Lea,
     /// Signed multiplication
     IMul,
+    /// Signed multiplication of the second operand by an immediate, stored in the third one
+    IMulImm,
 
     /// Sign extend move
     Movs,
     /// Fill with zeros move
     Movz,
-    /// Logical shift left
+    /// Logical shift left by %cl
     ShlC,
-    /// Logical shift right
+    /// Logical shift right by %cl
     ShrC,
+    /// Arithmetic shift right by %cl
+    SarC,
+    /// Left rotation by %cl
+    RolC,
+    /// Right rotation by %cl
+    RorC,
+    /// Left rotation through the carry flag by %cl
+    RclC,
+    /// Right rotation through the carry flag by %cl
+    RcrC,
+    /// Left shift of the destination by %cl, filled with the high bits of the second operand
+    ShldC,
+    /// Right shift of the destination by %cl, filled with the low bits of the second operand
+    ShrdC,
 
     /// Increment value
     Inc,
@@ -118,6 +430,12 @@ pub enum InstrName {
     UnsignedDiv,
     /// Signed division (beware of edx/rdc register)
     SignedDiv,
+    /// Unsigned multiplication by the accumulator, the result is stored in %rdx:%rax
+    /// (%edx:%eax, %dx:%ax or %ax for smaller operands)
+    UnsignedMul,
+    /// Signed multiplication by the accumulator, the result is stored in %rdx:%rax
+    /// (%edx:%eax, %dx:%ax or %ax for smaller operands)
+    SignedMul,
 
     /// Equivalent to popq rip
     Ret,
@@ -143,16 +461,196 @@ pub enum InstrName {
     CondJump(Cond, Label),
     /// Jump to label
     Jump(Label),
+    /// Jump to a close label if %rcx is 0
+    Jrcxz(Label),
+    /// Decrement %rcx and jump to a close label if it is not 0
+    Loop(Label),
+    /// Decrement %rcx and jump to a close label if it is not 0 and ZF is set
+    Loope(Label),
+    /// Decrement %rcx and jump to a close label if it is not 0 and ZF is not set
+    Loopne(Label),
     /// Jump to address
     JumpStar,
     /// Set operand to 0 or 1 based on the condition
     Set(Cond),
+
+    /// Left shift of the destination by an immediate, filled with the high bits of the second operand
+    Shld,
+    /// Right shift of the destination by an immediate, filled with the low bits of the second operand
+    Shrd,
+
+    /// Bit test (CF is set to the bit of the second operand at the index given by the first one)
+    Bt,
+    /// Bit test and set
+    Bts,
+    /// Bit test and reset
+    Btr,
+    /// Bit test and complement
+    Btc,
+    /// Index of the lowest set bit (the destination is undefined for 0)
+    Bsf,
+    /// Index of the highest set bit (the destination is undefined for 0)
+    Bsr,
+    /// Number of set bits
+    Popcnt,
+    /// Number of leading zeros
+    Lzcnt,
+    /// Number of trailing zeros
+    Tzcnt,
+    /// And with the complement of the second operand (BMI1)
+    Andn,
+    /// Extract the lowest set bit (BMI1)
+    Blsi,
+    /// Reset the lowest set bit (BMI1)
+    Blsr,
+    /// Zero the bits starting at the index given by the first operand (BMI2)
+    Bzhi,
+    /// Left shift by the first operand without changing the flags (BMI2)
+    Shlx,
+    /// Logical right shift by the first operand without changing the flags (BMI2)
+    Shrx,
+    /// Arithmetic right shift by the first operand without changing the flags (BMI2)
+    Sarx,
+    /// Scatter the lowest bits in the positions of the bits set in the mask (BMI2)
+    Pdep,
+    /// Gather the bits at the positions of the bits set in the mask (BMI2)
+    Pext,
+    /// Right rotation by an immediate without changing the flags (BMI2)
+    Rorx,
+    /// Unsigned multiplication of %rdx by the first operand without changing the flags,
+    /// the low half is stored in the second operand and the high half in the third (BMI2)
+    Mulx,
+
+    /// Copy the element at (%rsi) to (%rdi) and advance both registers (movs)
+    MovString,
+    /// Store the accumulator (%al, %ax, %eax or %rax) at (%rdi) and advance %rdi (stos)
+    StoreString,
+    /// Load the element at (%rsi) in the accumulator and advance %rsi (lods)
+    LoadString,
+    /// Compare the element at (%rsi) with the one at (%rdi) and advance both registers (cmps)
+    CmpString,
+    /// Compare the accumulator with the element at (%rdi) and advance %rdi (scas)
+    ScanString,
+
+    /// Exchange the operands (atomic when one of them is in memory)
+    Xchg,
+    /// Compare the accumulator with the destination, if equal the destination is set
+    /// to the source, otherwise the accumulator is set to the destination
+    Cmpxchg,
+    /// Compare %edx:%eax with 8 bytes in memory, if equal they are set to %ecx:%ebx,
+    /// otherwise %edx:%eax is set to the memory
+    Cmpxchg8b,
+    /// Compare %rdx:%rax with 16 bytes in memory (aligned on 16 bytes), if equal they
+    /// are set to %rcx:%rbx, otherwise %rdx:%rax is set to the memory
+    Cmpxchg16b,
+    /// Exchange then add, the destination is set to the sum and the source to the old destination
+    Xadd,
+    /// Serialize loads and stores
+    Mfence,
+    /// Serialize loads
+    Lfence,
+    /// Serialize stores
+    Sfence,
+
+    /// Move of a scalar float (movss and movsd)
+    MovFloat(Precision),
+    /// Move of 16 bytes aligned on 16 bytes in memory (movaps and movapd)
+    MovAligned(Precision),
+    /// Move of 16 bytes (movups and movupd)
+    MovUnaligned(Precision),
+    /// Floating point addition
+    AddFloat(Precision),
+    /// Floating point substraction
+    SubFloat(Precision),
+    /// Floating point multiplication
+    MulFloat(Precision),
+    /// Floating point division
+    DivFloat(Precision),
+    /// Square root
+    SqrtFloat(Precision),
+    /// Unordered compare of floats (set ZF, PF and CF, PF is set if an operand is NaN)
+    Ucomis(Precision),
+    /// Compare of floats (same as Ucomis but signals quiet NaNs)
+    Comis(Precision),
+    /// Conversion of a signed integer to a float
+    CvtIntToFloat(Precision),
+    /// Conversion of a float to a signed integer rounded toward zero
+    CvtFloatToInt(Precision),
+    /// Conversion between floats, the precision is the one of the result
+    CvtFloat(Precision),
+    /// Bitwise And of floats (packed)
+    AndFloat(Precision),
+    /// Bitwise Xor of floats (packed)
+    XorFloat(Precision),
+    /// Move between a 4-bytes register and a XMM register
+    MovdXmm,
+    /// Move between a 8-bytes register and a XMM register
+    MovqXmm,
+
+    /// AVX packed floating point addition
+    VAdd(Precision),
+    /// AVX packed floating point substraction
+    VSub(Precision),
+    /// AVX packed floating point multiplication
+    VMul(Precision),
+    /// AVX packed floating point division
+    VDiv(Precision),
+    /// AVX bitwise Xor of floats
+    VXor(Precision),
+    /// Fused multiply-add, the destination is multiplied by the first operand and the second one is added
+    VFmadd132(Precision),
+    /// Fused multiply-add, the second operand is multiplied by the destination and the first one is added
+    VFmadd213(Precision),
+    /// Fused multiply-add, the product of the operands is added to the destination
+    VFmadd231(Precision),
+    /// AVX move of floats aligned in memory (vmovaps and vmovapd)
+    VMovAligned(Precision),
+    /// AVX move of floats (vmovups and vmovupd)
+    VMovUnaligned(Precision),
+    /// AVX move of integers aligned in memory
+    VMovdqa,
+    /// AVX move of integers
+    VMovdqu,
+    /// Packed integer addition, the size is the one of the elements
+    VPAdd(Sizes),
+    /// Packed integer substraction, the size is the one of the elements
+    VPSub(Sizes),
+    /// Packed integer comparison, equal elements are set to -1 and others to 0
+    VPCmpEq(Sizes),
+    /// AVX bitwise And of integers
+    VPAnd,
+    /// AVX bitwise Or of integers
+    VPOr,
+    /// AVX bitwise Xor of integers
+    VPXor,
+    /// Mask made of the highest bit of each byte
+    VPMovMskB,
+    /// Copy of a float in all the elements (vbroadcastss and vbroadcastsd)
+    VBroadcast(Precision),
+    /// Clear the upper half of the YMM registers (avoids penalties before SSE code)
+    VZeroUpper,
 }
 
 impl Writable for InstrName {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        if file.syntax().is_intel_like() {
+            match self {
+                // NASM chooses the 64-bits encoding of mov by itself
+                InstrName::Movabs if file.syntax() == Syntax::Nasm => {
+                    return file.write_all(b"mov")
+                }
+                InstrName::Movs => return file.write_all(b"movsx"),
+                InstrName::Movz => return file.write_all(b"movzx"),
+                InstrName::Cltd => return file.write_all(b"cdq"),
+                InstrName::Cqto => return file.write_all(b"cqo"),
+                InstrName::CallStar => return file.write_all(b"call"),
+                InstrName::JumpStar => return file.write_all(b"jmp"),
+                _ => (),
+            }
+        }
         match self {
             InstrName::Move => file.write_all(b"mov"),
+            InstrName::Movabs => file.write_all(b"movabs"),
             InstrName::Add => file.write_all(b"add"),
             InstrName::Adc => file.write_all(b"adc"),
             InstrName::Sub => file.write_all(b"sub"),
@@ -163,14 +661,25 @@ impl Writable for InstrName {
             InstrName::Shl => file.write_all(b"shl"),
             InstrName::Shr => file.write_all(b"shr"),
             InstrName::Sar => file.write_all(b"sar"),
+            InstrName::Rol => file.write_all(b"rol"),
+            InstrName::Ror => file.write_all(b"ror"),
+            InstrName::Rcl => file.write_all(b"rcl"),
+            InstrName::Rcr => file.write_all(b"rcr"),
             InstrName::Cmp => file.write_all(b"cmp"),
             InstrName::Test => file.write_all(b"test"),
             InstrName::Lea => file.write_all(b"lea"),
-            InstrName::IMul => file.write_all(b"imul"),
+            InstrName::IMul | InstrName::IMulImm => file.write_all(b"imul"),
             InstrName::Movs => file.write_all(b"movs"),
             InstrName::Movz => file.write_all(b"movz"),
             InstrName::ShlC => file.write_all(b"shl"),
             InstrName::ShrC => file.write_all(b"shr"),
+            InstrName::SarC => file.write_all(b"sar"),
+            InstrName::RolC => file.write_all(b"rol"),
+            InstrName::RorC => file.write_all(b"ror"),
+            InstrName::RclC => file.write_all(b"rcl"),
+            InstrName::RcrC => file.write_all(b"rcr"),
+            InstrName::Shld | InstrName::ShldC => file.write_all(b"shld"),
+            InstrName::Shrd | InstrName::ShrdC => file.write_all(b"shrd"),
             InstrName::Inc => file.write_all(b"inc"),
             InstrName::Dec => file.write_all(b"dec"),
             InstrName::Neg => file.write_all(b"neg"),
@@ -179,6 +688,8 @@ impl Writable for InstrName {
             InstrName::Pop => file.write_all(b"pop"),
             InstrName::UnsignedDiv => file.write_all(b"div"),
             InstrName::SignedDiv => file.write_all(b"idiv"),
+            InstrName::UnsignedMul => file.write_all(b"mul"),
+            InstrName::SignedMul => file.write_all(b"imul"),
             InstrName::Ret => file.write_all(b"ret"),
             InstrName::Leave => file.write_all(b"leave"),
             InstrName::Syscall => file.write_all(b"syscall"),
@@ -192,32 +703,135 @@ impl Writable for InstrName {
             }
             InstrName::Call(label) => {
                 file.write_all(b"call ")?;
-                label.write_in(file)
+                label.write_with(file)
             }
             InstrName::CallStar => file.write_all(b"call *"),
             InstrName::CondJump(cond, label) => {
                 file.write_all(b"j")?;
                 file.write_all(cond.to_str().as_bytes())?;
                 file.write_all(b" ")?;
-                label.write_in(file)
+                label.write_with(file)
             }
             InstrName::Jump(label) => {
                 file.write_all(b"jmp ")?;
-                label.write_in(file)
+                label.write_with(file)
+            }
+            InstrName::Jrcxz(label) => {
+                file.write_all(b"jrcxz ")?;
+                label.write_with(file)
+            }
+            InstrName::Loop(label) => {
+                file.write_all(b"loop ")?;
+                label.write_with(file)
+            }
+            InstrName::Loope(label) => {
+                file.write_all(b"loope ")?;
+                label.write_with(file)
+            }
+            InstrName::Loopne(label) => {
+                file.write_all(b"loopne ")?;
+                label.write_with(file)
             }
             InstrName::JumpStar => file.write_all(b"jmp *"),
             InstrName::Set(cond) => {
                 file.write_all(b"set")?;
                 file.write_all(cond.to_str().as_bytes())
             }
+            InstrName::Bt => file.write_all(b"bt"),
+            InstrName::Bts => file.write_all(b"bts"),
+            InstrName::Btr => file.write_all(b"btr"),
+            InstrName::Btc => file.write_all(b"btc"),
+            InstrName::Bsf => file.write_all(b"bsf"),
+            InstrName::Bsr => file.write_all(b"bsr"),
+            InstrName::Popcnt => file.write_all(b"popcnt"),
+            InstrName::Lzcnt => file.write_all(b"lzcnt"),
+            InstrName::Tzcnt => file.write_all(b"tzcnt"),
+            InstrName::Andn => file.write_all(b"andn"),
+            InstrName::Blsi => file.write_all(b"blsi"),
+            InstrName::Blsr => file.write_all(b"blsr"),
+            InstrName::Bzhi => file.write_all(b"bzhi"),
+            InstrName::Shlx => file.write_all(b"shlx"),
+            InstrName::Shrx => file.write_all(b"shrx"),
+            InstrName::Sarx => file.write_all(b"sarx"),
+            InstrName::Pdep => file.write_all(b"pdep"),
+            InstrName::Pext => file.write_all(b"pext"),
+            InstrName::Rorx => file.write_all(b"rorx"),
+            InstrName::Mulx => file.write_all(b"mulx"),
+            InstrName::MovString => file.write_all(b"movs"),
+            InstrName::StoreString => file.write_all(b"stos"),
+            InstrName::LoadString => file.write_all(b"lods"),
+            InstrName::CmpString => file.write_all(b"cmps"),
+            InstrName::ScanString => file.write_all(b"scas"),
+            InstrName::Xchg => file.write_all(b"xchg"),
+            InstrName::Cmpxchg => file.write_all(b"cmpxchg"),
+            InstrName::Cmpxchg8b => file.write_all(b"cmpxchg8b"),
+            InstrName::Cmpxchg16b => file.write_all(b"cmpxchg16b"),
+            InstrName::Xadd => file.write_all(b"xadd"),
+            InstrName::Mfence => file.write_all(b"mfence"),
+            InstrName::Lfence => file.write_all(b"lfence"),
+            InstrName::Sfence => file.write_all(b"sfence"),
+            InstrName::MovFloat(p) => write!(file, "mov{}", p.scalar()),
+            InstrName::MovAligned(p) => write!(file, "mova{}", p.packed()),
+            InstrName::MovUnaligned(p) => write!(file, "movu{}", p.packed()),
+            InstrName::AddFloat(p) => write!(file, "add{}", p.scalar()),
+            InstrName::SubFloat(p) => write!(file, "sub{}", p.scalar()),
+            InstrName::MulFloat(p) => write!(file, "mul{}", p.scalar()),
+            InstrName::DivFloat(p) => write!(file, "div{}", p.scalar()),
+            InstrName::SqrtFloat(p) => write!(file, "sqrt{}", p.scalar()),
+            InstrName::Ucomis(p) => write!(file, "ucomi{}", p.scalar()),
+            InstrName::Comis(p) => write!(file, "comi{}", p.scalar()),
+            InstrName::CvtIntToFloat(p) => write!(file, "cvtsi2{}", p.scalar()),
+            InstrName::CvtFloatToInt(p) => write!(file, "cvtt{}2si", p.scalar()),
+            InstrName::CvtFloat(Precision::Single) => file.write_all(b"cvtsd2ss"),
+            InstrName::CvtFloat(Precision::Double) => file.write_all(b"cvtss2sd"),
+            InstrName::AndFloat(p) => write!(file, "and{}", p.packed()),
+            InstrName::XorFloat(p) => write!(file, "xor{}", p.packed()),
+            InstrName::MovdXmm => file.write_all(b"movd"),
+            InstrName::MovqXmm => file.write_all(b"movq"),
+            InstrName::VAdd(p) => write!(file, "vadd{}", p.packed()),
+            InstrName::VSub(p) => write!(file, "vsub{}", p.packed()),
+            InstrName::VMul(p) => write!(file, "vmul{}", p.packed()),
+            InstrName::VDiv(p) => write!(file, "vdiv{}", p.packed()),
+            InstrName::VXor(p) => write!(file, "vxor{}", p.packed()),
+            InstrName::VFmadd132(p) => write!(file, "vfmadd132{}", p.packed()),
+            InstrName::VFmadd213(p) => write!(file, "vfmadd213{}", p.packed()),
+            InstrName::VFmadd231(p) => write!(file, "vfmadd231{}", p.packed()),
+            InstrName::VMovAligned(p) => write!(file, "vmova{}", p.packed()),
+            InstrName::VMovUnaligned(p) => write!(file, "vmovu{}", p.packed()),
+            InstrName::VMovdqa => file.write_all(b"vmovdqa"),
+            InstrName::VMovdqu => file.write_all(b"vmovdqu"),
+            InstrName::VPAdd(size) => write!(file, "vpadd{}", element_suffix(*size)),
+            InstrName::VPSub(size) => write!(file, "vpsub{}", element_suffix(*size)),
+            InstrName::VPCmpEq(size) => write!(file, "vpcmpeq{}", element_suffix(*size)),
+            InstrName::VPAnd => file.write_all(b"vpand"),
+            InstrName::VPOr => file.write_all(b"vpor"),
+            InstrName::VPXor => file.write_all(b"vpxor"),
+            InstrName::VPMovMskB => file.write_all(b"vpmovmskb"),
+            InstrName::VBroadcast(p) => write!(file, "vbroadcast{}", p.scalar()),
+            InstrName::VZeroUpper => file.write_all(b"vzeroupper"),
         }
     }
 }
 
 impl InstrName {
+    /// Label used by the instruction name
+    pub fn label(&self) -> Option<&Label> {
+        match self {
+            InstrName::Call(label)
+            | InstrName::CondJump(_, label)
+            | InstrName::Jump(label)
+            | InstrName::Jrcxz(label)
+            | InstrName::Loop(label)
+            | InstrName::Loope(label)
+            | InstrName::Loopne(label) => Some(label),
+            _ => None,
+        }
+    }
+
     fn nb_args(&self) -> usize {
         match self {
             InstrName::Move
+            | InstrName::Movabs
             | InstrName::Add
             | InstrName::Adc
             | InstrName::Sub
@@ -228,11 +842,24 @@ impl InstrName {
             | InstrName::Shl
             | InstrName::Shr
             | InstrName::Sar
+            | InstrName::Rol
+            | InstrName::Ror
+            | InstrName::Rcl
+            | InstrName::Rcr
             | InstrName::Cmp
             | InstrName::Test
             | InstrName::Lea
             | InstrName::IMul => 2,
-            InstrName::Movs | InstrName::Movz | InstrName::ShlC | InstrName::ShrC => 2,
+            InstrName::IMulImm => 3,
+            InstrName::Movs | InstrName::Movz => 2,
+            InstrName::ShlC
+            | InstrName::ShrC
+            | InstrName::SarC
+            | InstrName::RolC
+            | InstrName::RorC
+            | InstrName::RclC
+            | InstrName::RcrC => 2,
+            InstrName::ShldC | InstrName::ShrdC => 3,
             InstrName::Inc
             | InstrName::Dec
             | InstrName::Neg
@@ -240,7 +867,9 @@ impl InstrName {
             | InstrName::Push
             | InstrName::Pop
             | InstrName::UnsignedDiv
-            | InstrName::SignedDiv => 1,
+            | InstrName::SignedDiv
+            | InstrName::UnsignedMul
+            | InstrName::SignedMul => 1,
             InstrName::Ret
             | InstrName::Leave
             | InstrName::Syscall
@@ -253,14 +882,86 @@ impl InstrName {
             InstrName::CallStar => 1,
             InstrName::CondJump(_, _) => 0,
             InstrName::Jump(_) => 0,
+            InstrName::Jrcxz(_)
+            | InstrName::Loop(_)
+            | InstrName::Loope(_)
+            | InstrName::Loopne(_) => 0,
             InstrName::JumpStar => 1,
             InstrName::Set(_) => 1,
+            InstrName::Shld | InstrName::Shrd => 3,
+            InstrName::Bt
+            | InstrName::Bts
+            | InstrName::Btr
+            | InstrName::Btc
+            | InstrName::Bsf
+            | InstrName::Bsr
+            | InstrName::Popcnt
+            | InstrName::Lzcnt
+            | InstrName::Tzcnt
+            | InstrName::Blsi
+            | InstrName::Blsr => 2,
+            InstrName::Andn
+            | InstrName::Bzhi
+            | InstrName::Shlx
+            | InstrName::Shrx
+            | InstrName::Sarx
+            | InstrName::Pdep
+            | InstrName::Pext
+            | InstrName::Rorx
+            | InstrName::Mulx => 3,
+            InstrName::MovString
+            | InstrName::StoreString
+            | InstrName::LoadString
+            | InstrName::CmpString
+            | InstrName::ScanString => 0,
+            InstrName::Xchg | InstrName::Cmpxchg | InstrName::Xadd => 2,
+            InstrName::Cmpxchg8b | InstrName::Cmpxchg16b => 1,
+            InstrName::Mfence | InstrName::Lfence | InstrName::Sfence => 0,
+            InstrName::MovFloat(_)
+            | InstrName::MovAligned(_)
+            | InstrName::MovUnaligned(_)
+            | InstrName::AddFloat(_)
+            | InstrName::SubFloat(_)
+            | InstrName::MulFloat(_)
+            | InstrName::DivFloat(_)
+            | InstrName::SqrtFloat(_)
+            | InstrName::Ucomis(_)
+            | InstrName::Comis(_)
+            | InstrName::CvtIntToFloat(_)
+            | InstrName::CvtFloatToInt(_)
+            | InstrName::CvtFloat(_)
+            | InstrName::AndFloat(_)
+            | InstrName::XorFloat(_)
+            | InstrName::MovdXmm
+            | InstrName::MovqXmm => 2,
+            InstrName::VAdd(_)
+            | InstrName::VSub(_)
+            | InstrName::VMul(_)
+            | InstrName::VDiv(_)
+            | InstrName::VXor(_)
+            | InstrName::VFmadd132(_)
+            | InstrName::VFmadd213(_)
+            | InstrName::VFmadd231(_)
+            | InstrName::VPAdd(_)
+            | InstrName::VPSub(_)
+            | InstrName::VPCmpEq(_)
+            | InstrName::VPAnd
+            | InstrName::VPOr
+            | InstrName::VPXor => 3,
+            InstrName::VMovAligned(_)
+            | InstrName::VMovUnaligned(_)
+            | InstrName::VMovdqa
+            | InstrName::VMovdqu
+            | InstrName::VPMovMskB
+            | InstrName::VBroadcast(_) => 2,
+            InstrName::VZeroUpper => 0,
         }
     }
 
     fn print_size_1(&self) -> bool {
         match self {
             InstrName::Move
+            | InstrName::Movabs
             | InstrName::Add
             | InstrName::Adc
             | InstrName::Sub
@@ -271,12 +972,25 @@ impl InstrName {
             | InstrName::Shl
             | InstrName::Shr
             | InstrName::Sar
+            | InstrName::Rol
+            | InstrName::Ror
+            | InstrName::Rcl
+            | InstrName::Rcr
             | InstrName::Cmp
             | InstrName::Test
             | InstrName::Lea
             | InstrName::IMul => false,
+            InstrName::IMulImm => false,
             InstrName::Movs | InstrName::Movz => true,
-            InstrName::ShlC | InstrName::ShrC => false,
+            InstrName::ShlC
+            | InstrName::ShrC
+            | InstrName::SarC
+            | InstrName::RolC
+            | InstrName::RorC
+            | InstrName::RclC
+            | InstrName::RcrC
+            | InstrName::ShldC
+            | InstrName::ShrdC => false,
             InstrName::Inc
             | InstrName::Dec
             | InstrName::Neg
@@ -284,7 +998,9 @@ impl InstrName {
             | InstrName::Push
             | InstrName::Pop
             | InstrName::UnsignedDiv
-            | InstrName::SignedDiv => true,
+            | InstrName::SignedDiv
+            | InstrName::UnsignedMul
+            | InstrName::SignedMul => true,
             InstrName::Ret
             | InstrName::Leave
             | InstrName::Syscall
@@ -297,14 +1013,91 @@ impl InstrName {
             InstrName::CallStar => false,
             InstrName::CondJump(_, _) => false,
             InstrName::Jump(_) => false,
+            InstrName::Jrcxz(_)
+            | InstrName::Loop(_)
+            | InstrName::Loope(_)
+            | InstrName::Loopne(_) => false,
             InstrName::JumpStar => false,
             InstrName::Set(_) => false,
+            InstrName::Shld | InstrName::Shrd => false,
+            InstrName::Bt
+            | InstrName::Bts
+            | InstrName::Btr
+            | InstrName::Btc
+            | InstrName::Bsf
+            | InstrName::Bsr
+            | InstrName::Popcnt
+            | InstrName::Lzcnt
+            | InstrName::Tzcnt
+            | InstrName::Blsi
+            | InstrName::Blsr
+            | InstrName::Andn
+            | InstrName::Bzhi
+            | InstrName::Shlx
+            | InstrName::Shrx
+            | InstrName::Sarx
+            | InstrName::Pdep
+            | InstrName::Pext
+            | InstrName::Rorx
+            | InstrName::Mulx => false,
+            InstrName::MovString
+            | InstrName::StoreString
+            | InstrName::LoadString
+            | InstrName::CmpString
+            | InstrName::ScanString => true,
+            InstrName::Xchg
+            | InstrName::Cmpxchg
+            | InstrName::Xadd
+            | InstrName::Cmpxchg8b
+            | InstrName::Cmpxchg16b
+            | InstrName::Mfence
+            | InstrName::Lfence
+            | InstrName::Sfence => false,
+            InstrName::CvtIntToFloat(_) => true,
+            InstrName::MovFloat(_)
+            | InstrName::MovAligned(_)
+            | InstrName::MovUnaligned(_)
+            | InstrName::AddFloat(_)
+            | InstrName::SubFloat(_)
+            | InstrName::MulFloat(_)
+            | InstrName::DivFloat(_)
+            | InstrName::SqrtFloat(_)
+            | InstrName::Ucomis(_)
+            | InstrName::Comis(_)
+            | InstrName::CvtFloatToInt(_)
+            | InstrName::CvtFloat(_)
+            | InstrName::AndFloat(_)
+            | InstrName::XorFloat(_)
+            | InstrName::MovdXmm
+            | InstrName::MovqXmm => false,
+            InstrName::VAdd(_)
+            | InstrName::VSub(_)
+            | InstrName::VMul(_)
+            | InstrName::VDiv(_)
+            | InstrName::VXor(_)
+            | InstrName::VFmadd132(_)
+            | InstrName::VFmadd213(_)
+            | InstrName::VFmadd231(_)
+            | InstrName::VPAdd(_)
+            | InstrName::VPSub(_)
+            | InstrName::VPCmpEq(_)
+            | InstrName::VPAnd
+            | InstrName::VPOr
+            | InstrName::VPXor
+            | InstrName::VMovAligned(_)
+            | InstrName::VMovUnaligned(_)
+            | InstrName::VMovdqa
+            | InstrName::VMovdqu
+            | InstrName::VPMovMskB
+            | InstrName::VBroadcast(_)
+            | InstrName::VZeroUpper => false,
         }
     }
 
     fn print_size_2(&self) -> bool {
         match self {
             InstrName::Move
+            | InstrName::Movabs
             | InstrName::Add
             | InstrName::Adc
             | InstrName::Sub
@@ -315,12 +1108,25 @@ impl InstrName {
             | InstrName::Shl
             | InstrName::Shr
             | InstrName::Sar
+            | InstrName::Rol
+            | InstrName::Ror
+            | InstrName::Rcl
+            | InstrName::Rcr
             | InstrName::Cmp
             | InstrName::Test
             | InstrName::Lea
             | InstrName::IMul => true,
+            InstrName::IMulImm => true,
             InstrName::Movs | InstrName::Movz => true,
-            InstrName::ShlC | InstrName::ShrC => true,
+            InstrName::ShlC
+            | InstrName::ShrC
+            | InstrName::SarC
+            | InstrName::RolC
+            | InstrName::RorC
+            | InstrName::RclC
+            | InstrName::RcrC
+            | InstrName::ShldC
+            | InstrName::ShrdC => true,
             InstrName::Inc
             | InstrName::Dec
             | InstrName::Neg
@@ -328,7 +1134,9 @@ impl InstrName {
             | InstrName::Push
             | InstrName::Pop
             | InstrName::UnsignedDiv
-            | InstrName::SignedDiv => false,
+            | InstrName::SignedDiv
+            | InstrName::UnsignedMul
+            | InstrName::SignedMul => false,
             InstrName::Ret
             | InstrName::Leave
             | InstrName::Syscall
@@ -341,544 +1149,332 @@ impl InstrName {
             InstrName::CallStar => false,
             InstrName::CondJump(_, _) => false,
             InstrName::Jump(_) => false,
+            InstrName::Jrcxz(_)
+            | InstrName::Loop(_)
+            | InstrName::Loope(_)
+            | InstrName::Loopne(_) => false,
             InstrName::JumpStar => false,
             InstrName::Set(_) => false,
+            InstrName::Shld | InstrName::Shrd => true,
+            InstrName::Bt
+            | InstrName::Bts
+            | InstrName::Btr
+            | InstrName::Btc
+            | InstrName::Bsf
+            | InstrName::Bsr
+            | InstrName::Popcnt
+            | InstrName::Lzcnt
+            | InstrName::Tzcnt
+            | InstrName::Blsi
+            | InstrName::Blsr
+            | InstrName::Andn
+            | InstrName::Bzhi
+            | InstrName::Shlx
+            | InstrName::Shrx
+            | InstrName::Sarx
+            | InstrName::Pdep
+            | InstrName::Pext
+            | InstrName::Rorx
+            | InstrName::Mulx => true,
+            InstrName::MovString
+            | InstrName::StoreString
+            | InstrName::LoadString
+            | InstrName::CmpString
+            | InstrName::ScanString => false,
+            InstrName::Xchg | InstrName::Cmpxchg | InstrName::Xadd => true,
+            InstrName::Cmpxchg8b
+            | InstrName::Cmpxchg16b
+            | InstrName::Mfence
+            | InstrName::Lfence
+            | InstrName::Sfence => false,
+            InstrName::MovFloat(_)
+            | InstrName::MovAligned(_)
+            | InstrName::MovUnaligned(_)
+            | InstrName::AddFloat(_)
+            | InstrName::SubFloat(_)
+            | InstrName::MulFloat(_)
+            | InstrName::DivFloat(_)
+            | InstrName::SqrtFloat(_)
+            | InstrName::Ucomis(_)
+            | InstrName::Comis(_)
+            | InstrName::CvtIntToFloat(_)
+            | InstrName::CvtFloatToInt(_)
+            | InstrName::CvtFloat(_)
+            | InstrName::AndFloat(_)
+            | InstrName::XorFloat(_)
+            | InstrName::MovdXmm
+            | InstrName::MovqXmm => false,
+            InstrName::VAdd(_)
+            | InstrName::VSub(_)
+            | InstrName::VMul(_)
+            | InstrName::VDiv(_)
+            | InstrName::VXor(_)
+            | InstrName::VFmadd132(_)
+            | InstrName::VFmadd213(_)
+            | InstrName::VFmadd231(_)
+            | InstrName::VPAdd(_)
+            | InstrName::VPSub(_)
+            | InstrName::VPCmpEq(_)
+            | InstrName::VPAnd
+            | InstrName::VPOr
+            | InstrName::VPXor
+            | InstrName::VMovAligned(_)
+            | InstrName::VMovUnaligned(_)
+            | InstrName::VMovdqa
+            | InstrName::VMovdqu
+            | InstrName::VPMovMskB
+            | InstrName::VBroadcast(_)
+            | InstrName::VZeroUpper => false,
         }
     }
 
     fn add_space(&self) -> bool {
-        match self {
-            InstrName::CallStar | InstrName::JumpStar => false,
-            _ => true,
+        !matches!(self, InstrName::CallStar | InstrName::JumpStar)
+    }
+
+    /// Tests if the prefix can be applied to the instruction
+    fn accepts(&self, prefix: Prefix) -> bool {
+        match prefix {
+            Prefix::Rep => matches!(
+                self,
+                InstrName::MovString | InstrName::StoreString | InstrName::LoadString
+            ),
+            Prefix::Repe | Prefix::Repne => {
+                matches!(self, InstrName::CmpString | InstrName::ScanString)
+            }
+            Prefix::Lock => matches!(
+                self,
+                InstrName::Add
+                    | InstrName::Adc
+                    | InstrName::Sub
+                    | InstrName::Sbb
+                    | InstrName::And
+                    | InstrName::Or
+                    | InstrName::Xor
+                    | InstrName::Inc
+                    | InstrName::Dec
+                    | InstrName::Neg
+                    | InstrName::Not
+                    | InstrName::Bts
+                    | InstrName::Btr
+                    | InstrName::Btc
+                    | InstrName::Xchg
+                    | InstrName::Cmpxchg
+                    | InstrName::Cmpxchg8b
+                    | InstrName::Cmpxchg16b
+                    | InstrName::Xadd
+            ),
         }
     }
+
+    fn is_string(&self) -> bool {
+        self.accepts(Prefix::Rep) || self.accepts(Prefix::Repe)
+    }
+
+    /// Tests if the string instruction reads the element at (%rsi), whose segment can be
+    /// overridden (the one of (%rdi) is always %es)
+    fn reads_rsi(&self) -> bool {
+        matches!(
+            self,
+            InstrName::MovString | InstrName::LoadString | InstrName::CmpString
+        )
+    }
+
+    /// Tests if the size of memory operands is written in Intel syntaxes (lea only
+    /// computes the address and the 16 bytes of cmpxchg16b have no keyword in GNU as)
+    fn sized_memory(&self) -> bool {
+        !matches!(self, InstrName::Lea | InstrName::Cmpxchg16b)
+    }
 }
 
-struct REX {
-    w: bool,
-    r: bool,
-    x: bool,
-    b: bool,
+/// Structure storing the instruction name and a most 3 operands.
+/// To type with less than 2 operands use the type RegInv which can never be used for real operands
+pub struct Instruction<S1: Reg = crate::reg::RegInv, S2: Reg = crate::reg::RegInv> {
+    /// Instruction name
+    pub instr: InstrName,
+    /// First operand if exists
+    pub reg1: Option<Operand<S1>>,
+    /// Second operand if exists
+    pub reg2: Option<Operand<S2>>,
+    /// Third operand if exists (destination of AVX instructions), it has the type of the second one
+    pub reg3: Option<Operand<S2>>,
+    /// Lock or repeat prefix written before the instruction name
+    pub lock_rep: Option<Prefix>,
+    /// Segment of the memory operand
+    pub segment: Option<SegmentOverride>,
 }
 
-impl REX {
-    fn new() -> Self {
-        Self {
-            w: false,
-            /// extends MODRM.reg
-            r: false,
-            /// extends SIB.index
-            x: false,
-            /// extends MODRM.rm or the SIB.base
-            b: false,
-        }
+/// Trait to wrap around all instructions types
+pub trait InstrTrait {
+    /// Write the instruction in a file
+    fn write_instr(&self, file: &mut Writer) -> std::io::Result<()>;
+
+    /// Labels referred to by the instruction
+    fn used_labels(&self) -> Vec<&Label>;
+
+    /// Encode the instruction in machine code (shortest encoding)
+    fn encode(&self) -> Result<encode::MachineCode, encode::EncodeError> {
+        self.encode_with(encode::Encoding::Shortest)
     }
 
-    fn needed(&self) -> bool {
-        self.w || self.r || self.x || self.b
+    /// Encode the instruction in machine code, [`encode::Encoding::Fixed`] gives code that can be patched
+    fn encode_with(
+        &self,
+        encoding: encode::Encoding,
+    ) -> Result<encode::MachineCode, encode::EncodeError>;
+
+    /// Shorter encoding of jumps, only valid if the label is at most 128 bytes away
+    fn encode_short(&self) -> Option<encode::MachineCode>;
+
+    /// Add a prefix to the instruction, panics if the instruction does not accept it
+    fn set_prefix(&mut self, prefix: Prefix);
+
+    /// Access the memory operand (or the (%rsi) source of `movs`, `lods` and `cmps`) in the given
+    /// segment, panics if the instruction has no such operand
+    fn set_segment(&mut self, segment: SegmentOverride);
+}
+
+impl<S1: Reg, S2: Reg> Instruction<S1, S2> {
+    fn labels(&self) -> Vec<&Label> {
+        let mut labels: Vec<&Label> = self.instr.label().into_iter().collect();
+        labels.extend(self.reg1.as_ref().and_then(Operand::label));
+        labels.extend(self.reg2.as_ref().and_then(Operand::label));
+        labels.extend(self.reg3.as_ref().and_then(Operand::label));
+        labels
     }
 
-    fn as_byte(&self) -> u8 {
-        let mut out = 0x40;
-        if self.w {
-            out |= 0x08;
+    fn check_args(&self) {
+        if self.instr.nb_args() < 1 && self.reg1.is_some() {
+            panic!(
+                "Instruction {:?} expects 0 arguments but received a least 1",
+                self.instr
+            )
         }
-        if self.r {
-            out |= 0x4;
+        if self.instr.nb_args() < 2 && self.reg2.is_some() {
+            panic!(
+                "Instruction {:?} expects at most 1 arguments but received a second argument",
+                self.instr
+            )
         }
-        if self.x {
-            out |= 0x2;
+        if self.instr.nb_args() < 3 && self.reg3.is_some() {
+            panic!(
+                "Instruction {:?} expects at most 2 arguments but received a third argument",
+                self.instr
+            )
         }
-        if self.b {
-            out |= 0x1;
+        if let Some(prefix) = self.lock_rep {
+            self.check_prefix(prefix)
+        }
+        if self.segment.is_some() {
+            self.check_segment()
         }
-        out
     }
-}
-
-enum Imm {
-    NoImm(u8),
-    I8(i8, u8),
-    I16(i16, u8),
-    I32(i32, u8),
-    I64(i64, u8),
-}
 
-enum RegOrQ<R = RegInv> {
-    R(R),
-    Q(RegQ),
-}
+    fn check_prefix(&self, prefix: Prefix) {
+        if !self.instr.accepts(prefix) {
+            panic!(
+                "Instruction {:?} does not accept the prefix {:?}",
+                self.instr, prefix
+            )
+        }
+        if prefix == Prefix::Lock && !self.memory_destination() {
+            panic!(
+                "Instruction {:?} can only be locked with a memory destination",
+                self.instr
+            )
+        }
+    }
 
-impl<R: Reg> RegOrQ<R> {
-    fn to_bits(&self) -> (bool, u8) {
-        match self {
-            Self::R(r) => r.to_bits(),
-            Self::Q(r) => r.to_bits(),
+    fn check_segment(&self) {
+        if !self.accepts_segment() {
+            panic!(
+                "Instruction {:?} has no memory operand for a segment override",
+                self.instr
+            )
         }
     }
-}
 
-struct ByteCode<R1: Reg = RegInv, R2: Reg = RegInv> {
-    small_reg_flag: bool,
-    prefix: Option<u8>,
-    op_code: u8,
-    rex: REX,
-    reg: Option<R1>,
-    rm: RegOrQ<R2>,
-    addr: Option<(i64, Option<(RegQ, u8)>)>,
-    imm: Imm,
-}
+    /// Tests if a segment override applies to the memory operand of the instruction or to
+    /// the (%rsi) source of string instructions
+    fn accepts_segment(&self) -> bool {
+        self.memory_operand().is_some() || self.instr.reads_rsi()
+    }
 
-impl<R2: Reg> ByteCode<RegInv, R2> {
-    fn only_rm(rex: REX, rm: &Operand<R2>) -> Self {
-        let mut addr = None;
-        let rm = match rm {
-            Operand::Addr(offset, base, None, 0) => {
-                addr = Some((*offset, None));
-                RegOrQ::Q(base.clone())
-            }
-            Operand::Addr(offset, base, Some(ind), scale) => {
-                addr = Some((*offset, Some((ind.clone(), *scale))));
-                RegOrQ::Q(base.clone())
-            }
-            Operand::Reg(rm) => RegOrQ::R(rm.clone()),
-            _ => panic!("Should not happen"),
-        };
-        Self {
-            small_reg_flag: false,
-            prefix: None,
-            op_code: 0,
-            rex,
-            reg: None,
-            rm,
-            addr,
-            imm: Imm::NoImm(0),
+    /// Position of the memory operand (1, 2 or 3), the string instructions have none
+    /// as their operands are implicit
+    fn memory_operand(&self) -> Option<u8> {
+        if self.reg1.as_ref().map_or(false, Operand::is_memory) {
+            Some(1)
+        } else if self.reg2.as_ref().map_or(false, Operand::is_memory) {
+            Some(2)
+        } else if self.reg3.as_ref().map_or(false, Operand::is_memory) {
+            Some(3)
+        } else {
+            None
         }
     }
-}
 
-impl<R1: Reg, R2: Reg> ByteCode<R1, R2> {
-    fn new(rex: REX, op_code: u8, reg: R1, rm: &Operand<R2>) -> Self {
-        let mut addr = None;
-        let rm = match rm {
-            Operand::Addr(offset, base, None, 0) => {
-                addr = Some((*offset, None));
-                RegOrQ::Q(base.clone())
-            }
-            Operand::Addr(offset, base, Some(ind), scale) => {
-                addr = Some((*offset, Some((ind.clone(), *scale))));
-                RegOrQ::Q(base.clone())
-            }
-            Operand::Reg(rm) => RegOrQ::R(rm.clone()),
-            _ => panic!("Should not happen"),
-        };
-        Self {
-            small_reg_flag: false,
-            prefix: None,
-            op_code,
-            rex,
-            reg: Some(reg),
-            rm,
-            addr,
-            imm: Imm::NoImm(0),
-        }
-    }
-
-    fn as_bytes(&mut self) -> Vec<u8> {
-        let mut vec = Vec::new();
-        if self.small_reg_flag {
-            vec.push(0x66)
-        }
-        let (r, reg) = match &self.reg {
-            None => match &self.imm {
-                Imm::NoImm(code) => (false, *code),
-                Imm::I8(_, o) | Imm::I16(_, o) | Imm::I32(_, o) | Imm::I64(_, o) => (false, *o),
-            },
-            Some(reg) => reg.to_bits(),
-        };
-        let (b, base) = self.rm.to_bits();
-        self.rex.b = b;
-        self.rex.r = r;
-        if self.rex.needed() {
-            vec.push(self.rex.as_byte());
-        }
-        if let Some(pref) = self.prefix {
-            vec.push(pref)
-        }
-        vec.push(self.op_code);
-        match &self.addr {
-            None => {
-                assert!(matches!(self.rm, RegOrQ::R(_)));
-                vec.push(0b11_000_000 | (reg << 3) | base)
-            }
-            Some((offset, None)) => {
-                assert!(matches!(self.rm, RegOrQ::Q(_)));
-                if *offset == 0 && base != 4 && base != 5 {
-                    vec.push(0b00_000_000 | (reg << 3) | base)
-                } else if base == 4 {
-                    if i8::MIN as i64 <= *offset && *offset <= i8::MAX as i64 {
-                        vec.push(0b01_000_000 | (reg << 3) | base);
-                        vec.push((0 << 6) | (0b100 << 3) | base);
-                        vec.push(*offset as u8);
-                    } else {
-                        vec.push(0b10_000_000 | (reg << 3) | base);
-                        vec.push((0 << 6) | (0b100 << 3) | base);
-                        vec.push((*offset & 255) as u8);
-                        vec.push(((*offset >> 8) & 255) as u8);
-                        vec.push(((*offset >> 16) & 255) as u8);
-                        vec.push(((*offset >> 24) & 255) as u8);
-                    }
-                } else if i8::MIN as i64 <= *offset && *offset <= i8::MAX as i64 {
-                    vec.push(0b01_000_000 | (reg << 3) | base);
-                    vec.push(*offset as u8);
-                } else if i32::MIN as i64 <= *offset && *offset <= i32::MAX as i64 {
-                    vec.push(0b10_000_000 | (reg << 3) | base);
-                    vec.push((*offset & 255) as u8);
-                    vec.push(((*offset >> 8) & 255) as u8);
-                    vec.push(((*offset >> 16) & 255) as u8);
-                    vec.push(((*offset >> 24) & 255) as u8);
-                } else {
-                    todo!()
-                }
-            }
-            Some((offset, Some((ind, scale)))) => {
-                let scale = match *scale {
-                    1 => 0,
-                    2 => 1,
-                    4 => 2,
-                    8 => 3,
-                    _ => panic!("Invalid scale {}", scale),
-                };
-                assert!(ind.to_bits() != (false, 0b100));
-                let (x, ind) = ind.to_bits();
-                if x {
-                    self.rex.x = x;
-                    panic!("Not yet handled, please only use register of the first half (not r8->r15) as index");
-                }
-                vec.push(0b10_000_000 | (reg << 3) | base);
-                vec.push((scale << 6) | (ind << 3) | base);
-                vec.push((*offset & 255) as u8);
-                vec.push(((*offset >> 8) & 255) as u8);
-                vec.push(((*offset >> 16) & 255) as u8);
-                vec.push(((*offset >> 24) & 255) as u8);
-            }
+    /// Tests if the last operand is in memory
+    fn memory_destination(&self) -> bool {
+        let reg1 = self.reg1.as_ref().map_or(false, Operand::is_memory);
+        let reg2 = self.reg2.as_ref().map_or(false, Operand::is_memory);
+        match self.instr.nb_args() {
+            1 => reg1,
+            2 => reg2,
+            _ => false,
         }
-        match self.imm {
-            Imm::NoImm(_) => (),
-            Imm::I8(i, _) => vec.push(i as u8),
-            Imm::I16(i, _) => {
-                vec.push((i & 255) as u8);
-                vec.push(((i >> 8) & 255) as u8);
-            }
-            Imm::I32(i, _) => {
-                vec.push((i & 255) as u8);
-                vec.push(((i >> 8) & 255) as u8);
-                vec.push(((i >> 16) & 255) as u8);
-                vec.push(((i >> 24) & 255) as u8);
-            }
-            Imm::I64(i, _) => {
-                vec.push((i & 255) as u8);
-                vec.push(((i >> 8) & 255) as u8);
-                vec.push(((i >> 16) & 255) as u8);
-                vec.push(((i >> 24) & 255) as u8);
-                vec.push(((i >> 32) & 255) as u8);
-                vec.push(((i >> 40) & 255) as u8);
-                vec.push(((i >> 48) & 255) as u8);
-                vec.push(((i >> 56) & 255) as u8);
-            }
-        }
-        vec
     }
-}
-
-#[allow(dead_code)]
-impl<R1: Reg, R2: Reg> Instruction<R1, R2> {
-    fn to_bin(&self) -> Option<Vec<u8>> {
-        match self.instr {
-            InstrName::Move => {
-                let op1 = self.reg1.as_ref().unwrap();
-                let op2 = self.reg2.as_ref().unwrap();
-                let mut rex = REX::new();
-                match (R1::SIZE, R2::SIZE, op1, op2) {
-                    (Sizes::Word, Sizes::Word, Operand::Reg(reg), rm)
-                    | (Sizes::Long, Sizes::Long, Operand::Reg(reg), rm)
-                    | (Sizes::Quad, Sizes::Quad, Operand::Reg(reg), rm) if rm.is_rm() => {
-                        rex.w = R1::SIZE == Sizes::Quad;
-                        let mut op = ByteCode::new(rex, 0x89, reg.clone(), rm);
-                        op.small_reg_flag = R1::SIZE == Sizes::Word;
-                        Some(op.as_bytes())
-                    },
-                    (Sizes::Word, Sizes::Word, rm, Operand::Reg(reg))
-                    | (Sizes::Long, Sizes::Long, rm, Operand::Reg(reg))
-                    | (Sizes::Quad, Sizes::Quad, rm, Operand::Reg(reg)) if rm.is_rm() => {
-                        rex.w = R1::SIZE == Sizes::Quad;
-                        let mut op = ByteCode::new(rex, 0x8b, reg.clone(), rm);
-                        op.small_reg_flag = R1::SIZE == Sizes::Word;
-                        Some(op.as_bytes())
-                    },
-                    (Sizes::Word, Sizes::Word, Operand::Imm(imm), rm) => {
-                        rex.w = R1::SIZE == Sizes::Quad;
-                        let mut op = ByteCode::only_rm(rex, rm);
-                        assert!(*imm <= i16::MAX as i64 && *imm >= i16::MIN as i64);
-                        op.op_code = 0xc7;
-                        op.imm = Imm::I16(*imm as i16, 0);
-                        op.small_reg_flag = true;
-                        Some(op.as_bytes())
-                    },
-
-                    (Sizes::Long, Sizes::Long, Operand::Imm(imm), rm)
-                    | (Sizes::Quad, Sizes::Quad, Operand::Imm(imm), rm) => {
-                        rex.w = R1::SIZE == Sizes::Quad;
-                        let mut op = ByteCode::only_rm(rex, rm);
-                        if *imm <= i32::MAX as i64 && *imm >= i32::MIN as i64 {
-                            op.op_code = 0xc7;
-                            op.imm = Imm::I32(*imm as i32, 0);
-                            Some(op.as_bytes())
-                        } else {
-                            None
-                            // todo!()
-                        }
-                        // Some(op.as_bytes())
-                    },
-                    _ => None,
-                }
 
+    fn write_prefix(&self, file: &mut Writer) -> std::io::Result<()> {
+        // the implicit source of string instructions has no operand to write the segment on
+        match self.segment {
+            Some(segment) if self.memory_operand().is_none() => {
+                file.write_all(segment.to_str().as_bytes())?;
+                file.write_all(b" ")?;
             }
-            InstrName::Add | InstrName::Or
-            | InstrName::Adc | InstrName::Sbb
-            | InstrName::And | InstrName::Sub
-            | InstrName::Xor | InstrName::Cmp => { // https://www.felixcloutier.com/x86/add
-                let op_index = match self.instr {
-                    InstrName::Add => 0,
-                    InstrName::Or  => 1,
-                    InstrName::Adc => 2,
-                    InstrName::Sbb => 3,
-                    InstrName::And => 4,
-                    InstrName::Sub => 5,
-                    InstrName::Xor => 6,
-                    InstrName::Cmp => 7,
-                    _ => panic!("Should never happen"),
-                };
-                let _imm_ra8 = op_index * 8 + 4;
-                let _imm_ra  = op_index * 8 + 5;
-                let r_rm8   = op_index * 8 + 0;
-                let r_rm    = op_index * 8 + 1;
-                let rm_r8   = op_index * 8 + 2;
-                let rm_r    = op_index * 8 + 3;
-                let op1 = self.reg1.as_ref().unwrap();
-                let op2 = self.reg2.as_ref().unwrap();
-                let mut rex = REX::new();
-                match (R1::SIZE, R2::SIZE, op1, op2) {
-                    (Sizes::Byte, Sizes::Byte, rm, Operand::Reg(reg)) if rm.is_rm() => {
-                        let mut op = ByteCode::new(rex, rm_r8, reg.clone(), rm);
-                        Some(op.as_bytes())
-                    }
-                    (Sizes::Byte, Sizes::Byte, Operand::Reg(reg), rm) if rm.is_rm() => {
-                        let mut op = ByteCode::new(rex, r_rm8, reg.clone(), rm);
-                        Some(op.as_bytes())
-                    }
-                    (Sizes::Long, Sizes::Long, rm, Operand::Reg(reg))
-                    | (Sizes::Quad, Sizes::Quad, rm, Operand::Reg(reg)) if rm.is_rm() => {
-                        rex.w = R1::SIZE == Sizes::Quad;
-                        let mut op = ByteCode::new(rex, rm_r, reg.clone(), rm);
-                        Some(op.as_bytes())
-                    }
-                    (Sizes::Long, Sizes::Long, Operand::Reg(reg), rm)
-                    | (Sizes::Quad, Sizes::Quad, Operand::Reg(reg), rm) if rm.is_rm() => {
-                        rex.w = R1::SIZE == Sizes::Quad;
-                        let mut op = ByteCode::new(rex, r_rm, reg.clone(), rm);
-                        Some(op.as_bytes())
-                    }
-                    (Sizes::Long, Sizes::Long, Operand::Imm(imm), rm)
-                    | (Sizes::Quad, Sizes::Quad, Operand::Imm(imm), rm) => {
-                            rex.w = R1::SIZE == Sizes::Quad;
-                        let mut op = ByteCode::only_rm(rex, rm);
-                        op.op_code = 0x81;
-                        assert!(i32::MIN as i64 <= *imm && *imm <= i32::MAX as i64);
-                        op.imm = Imm::I32(*imm as i32, op_index);
-                        Some(op.as_bytes())
-                    }
-                    (Sizes::Quad, Sizes::Quad, Operand::LabRelAddr(_), Operand::Reg(_)) => {
-                        None
-                    }
-                    // (Sizes::Quad, Sizes::Quad, _, _) => panic!("{:?} {:?}", self.reg1, self.reg2),
-                    _ => None,
-                }
+            _ => (),
+        }
+        if let Some(prefix) = self.lock_rep {
+            file.write_all(prefix.to_str().as_bytes())?;
+            file.write_all(b" ")?;
+        }
+        Ok(())
+    }
 
-            }
-            InstrName::Shl =>  None,
-            InstrName::Shr =>  None,
-            InstrName::Sar =>  None,
-            InstrName::Test => None,
-            InstrName::Lea =>  None,
-            InstrName::IMul => None,
-            InstrName::Movs => None,
-            InstrName::Movz => None,
-            InstrName::ShlC => None,
-            InstrName::ShrC => None,
-            InstrName::Inc =>  None,
-            InstrName::Dec =>  None,
-            InstrName::Push => {
-                let reg1 = self.reg1.as_ref().unwrap();
-                let rex = REX::new();
-                assert!(self.reg2.is_none());
-                match (R1::SIZE, reg1) {
-                    (Sizes::Byte, Operand::Imm(imm)) => {
-                        assert!(i8::MIN as i64 <= *imm && *imm <= i8::MAX as i64);
-                        Some(vec![0x6A, *imm as u8])
-                    },
-                    (Sizes::Word, Operand::Imm(imm)) => {
-                        assert!(i16::MIN as i64 <= *imm && *imm <= i16::MAX as i64);
-                        Some(vec![0x66, 0x68, (*imm & 255) as u8, (*imm >> 8) as u8])
-                    }
-                    (Sizes::Long, Operand::Imm(imm)) => {
-                        assert!(i32::MIN as i64 <= *imm && *imm <= i32::MAX as i64);
-                        Some(vec![0x68, (*imm & 255) as u8, ((*imm >> 8) & 255) as u8,
-                                ((*imm >> 16) & 255) as u8, ((*imm >> 24) & 255) as u8])
-                    }
-                    (Sizes::Word, rm) | (Sizes::Long, rm)
-                    | (Sizes::Quad, rm) if rm.is_rm() => {
-                        assert!(R1::SIZE == Sizes::Quad);
-                        let mut op = ByteCode::only_rm(rex, rm);
-                        op.op_code = 0xff;
-                        // op.small_reg_flag = R1::SIZE == Sizes::Word;
-                        op.imm = Imm::NoImm(6);
-                        Some(op.as_bytes())
-                        // None
-                    },
-                    // Operand::LabRelAddr(_) => todo!(),
-                    // Operand::LabAbsAddr(_) => todo!(),
-                    // Operand::LabVal(_) => todo!(),
-                    _ => None,
-                }
-            }
-            InstrName::Pop => {
-                let rm = self.reg1.as_ref().unwrap();
-                assert!(rm.is_rm());
-                assert!(self.reg2.is_none());
-                assert!(R1::SIZE == Sizes::Quad);
-                let mut op = ByteCode::only_rm(REX::new(), rm);
-                op.op_code = 0x8F;
-                Some(op.as_bytes())
-            }
-            InstrName::Not | InstrName::Neg // | InstrName::Mul
-            | InstrName::UnsignedDiv | InstrName::SignedDiv => {
-                let rm = self.reg1.as_ref().unwrap();
-                assert!(rm.is_rm());
-                assert!(self.reg2.is_none());
-                let mut rex = REX::new();
-                rex.w = R1::SIZE == Sizes::Quad;
-                let mut op = ByteCode::only_rm(rex, rm);
-                op.small_reg_flag = R1::SIZE == Sizes::Word;
-                op.imm = Imm::NoImm(match self.instr {
-                    InstrName::Not => 2,
-                    InstrName::Neg => 3,
-                    // InstrName::Mul => 4,
-                    InstrName::UnsignedDiv => 6,
-                    InstrName::SignedDiv => 7,
-                    _ => panic!("Should not happen")
-                });
-                if R1::SIZE == Sizes::Byte {
-                    op.op_code = 0xf6;
-                } else {
-                    op.op_code = 0xf7;
+    /// Write the operand at `position`, with its segment if it is the memory operand
+    fn write_operand<S: Reg>(
+        &self,
+        operand: &Operand<S>,
+        position: u8,
+        file: &mut Writer,
+        size: bool,
+    ) -> std::io::Result<()> {
+        let segment = match self.segment {
+            Some(segment) if self.memory_operand() == Some(position) => Some(segment.to_str()),
+            _ => None,
+        };
+        match file.syntax() {
+            Syntax::Att => {
+                if let Some(segment) = segment {
+                    file.write_all(format!("%{}:", segment).as_bytes())?;
                 }
-                Some(op.as_bytes())
-            }
-            InstrName::Ret => Some(vec![0xc3]),
-            InstrName::Leave => Some(vec![0xc9]),
-            InstrName::Syscall => Some(vec![0x0f, 0x05]),
-            InstrName::Hlt => Some(vec![0xf4]),
-            InstrName::Cltd => Some(vec![0x99]),
-            InstrName::Cqto => {
-                let mut rex = REX::new();
-                rex.w = true;
-                Some(vec![rex.as_byte(), 0x99])
-            },
-            InstrName::Nop => Some(vec![0x90]),
-            InstrName::Cmov(cond) => {
-                assert_eq!(R1::SIZE, R2::SIZE);
-                assert_ne!(R1::SIZE, Sizes::Byte);
-                let rm = self.reg1.as_ref().unwrap();
-                assert!(rm.is_rm());
-                let reg = match self.reg2.as_ref().unwrap() {
-                    Operand::Reg(reg) => reg,
-                    _ => panic!("Not allowed"),
-                };
-                let op_code = match cond {
-                    Cond::E | Cond::Z =>  0x44,
-                    Cond::NE | Cond::NZ => 0x45,
-                    Cond::S =>  0x48,
-                    Cond::NS => 0x49,
-                    Cond::G =>  0x4f,
-                    Cond::GE => 0x4d,
-                    Cond::L =>  0x4c,
-                    Cond::LE => 0x4e,
-                    Cond::A =>  0x47,
-                    Cond::AE => 0x43,
-                    Cond::B =>  0x42,
-                    Cond::BE => 0x46,
-                };
-                let mut rex = REX::new();
-                rex.w = R1::SIZE == Sizes::Quad;
-                let mut op = ByteCode::new(rex, op_code, reg.clone(), rm);
-                op.prefix = Some(0x0f);
-                op.small_reg_flag = R1::SIZE == Sizes::Word;
-                Some(op.as_bytes())
+                operand.write_with(file)
             }
-            InstrName::Call(_) => None,
-            InstrName::CallStar => None,
-            InstrName::CondJump(_, _) => None,
-            InstrName::Jump(_) => None,
-            InstrName::JumpStar => None,
-            InstrName::Set(cond) => {
-                assert_eq!(R1::SIZE, Sizes::Byte);
-                let rm = self.reg1.as_ref().unwrap();
-                assert!(rm.is_rm());
-                assert!(self.reg2.is_none());
-                let rex = REX::new();
-                let mut op = ByteCode::only_rm(rex, rm);
-                op.prefix = Some(0x0f);
-                op.op_code = match cond {
-                    Cond::E | Cond::Z =>  0x94,
-                    Cond::NE | Cond::NZ => 0x95,
-                    Cond::S =>  0x98,
-                    Cond::NS => 0x99,
-                    Cond::G =>  0x9f,
-                    Cond::GE => 0x9d,
-                    Cond::L =>  0x9c,
-                    Cond::LE => 0x9e,
-                    Cond::A =>  0x97,
-                    Cond::AE => 0x93,
-                    Cond::B =>  0x92,
-                    Cond::BE => 0x96,
-                };
-                Some(op.as_bytes())
-            },
+            Syntax::Intel | Syntax::Nasm => operand.write_intel(file, size, segment),
         }
     }
-}
-
-/// Structure storing the instruction name and a most 2 operands.
-/// To type with less than 2 operands use the type RegInv which can never be used for real operands
-pub struct Instruction<S1: Reg = crate::reg::RegInv, S2: Reg = crate::reg::RegInv> {
-    /// Instruction name
-    pub instr: InstrName,
-    /// First operand if exists
-    pub reg1: Option<Operand<S1>>,
-    /// Second operand if exists
-    pub reg2: Option<Operand<S2>>,
-}
 
-/// Trait to wrap around all instructions types
-pub trait InstrTrait {
-    /// Write the instruction in a file
-    fn write_instr(&self, file: &mut std::fs::File) -> std::io::Result<()>;
-}
+    fn default_writer(&self, file: &mut Writer) -> std::io::Result<()> {
+        self.check_args();
+        match file.syntax() {
+            Syntax::Att => self.att_writer(file),
+            Syntax::Intel | Syntax::Nasm => self.intel_writer(file),
+        }
+    }
 
-impl<S1: Reg, S2: Reg> Instruction<S1, S2> {
-    fn default_writer(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        self.instr.write_in(file)?;
+    fn att_writer(&self, file: &mut Writer) -> std::io::Result<()> {
+        self.write_prefix(file)?;
+        self.instr.write_with(file)?;
         if self.instr.print_size_1() {
             file.write_all(&[S1::SIZE.to_char() as u8])?;
         }
@@ -889,59 +1485,107 @@ impl<S1: Reg, S2: Reg> Instruction<S1, S2> {
             if self.instr.add_space() {
                 file.write_all(b" ")?
             };
-            self.reg1.as_ref().unwrap().write_in(file)?;
-        } else {
-            if self.reg1.is_some() {
-                panic!(
-                    "Instruction {:?} expects 0 arguments but received a least 1",
-                    self.instr
-                )
-            }
+            self.write_operand(self.reg1.as_ref().unwrap(), 1, file, true)?;
         }
         if self.instr.nb_args() >= 2 {
             file.write_all(b", ")?;
-            self.reg2.as_ref().unwrap().write_in(file)?;
-        } else {
-            if self.reg2.is_some() {
-                panic!(
-                    "Instruction {:?} expects at most 1 arguments but received a second argument",
-                    self.instr
-                )
-            }
+            self.write_operand(self.reg2.as_ref().unwrap(), 2, file, true)?;
+        }
+        if self.instr.nb_args() >= 3 {
+            file.write_all(b", ")?;
+            self.write_operand(self.reg3.as_ref().unwrap(), 3, file, true)?;
         }
         std::io::Result::Ok(())
     }
-}
 
-#[cfg(feature = "gen_binary")]
-impl<S1: Reg, S2: Reg> InstrTrait for Instruction<S1, S2> {
-    fn write_instr(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        match self.to_bin() {
-            Some(v) => {
-                // file.write_all("##".as_bytes())?;
-                // self.default_writer(file)?;
-                for b in v {
-                    file.write_all(format!("\n\t.byte 0x{b:x}").as_bytes())?;
-                }
-                std::io::Result::Ok(())
+    /// Intel syntaxes have no size suffixes and put the destination first
+    fn intel_writer(&self, file: &mut Writer) -> std::io::Result<()> {
+        self.write_prefix(file)?;
+        match (&self.instr, S1::SIZE) {
+            (InstrName::Movs, Sizes::Long) => file.write_all(b"movsxd")?,
+            // string instructions keep the size of their implicit operands
+            (name, size) if name.is_string() => {
+                name.write_with(file)?;
+                file.write_all(element_suffix(size).as_bytes())?
             }
-            None => self.default_writer(file),
+            _ => self.instr.write_with(file)?,
         }
+        if self.instr.nb_args() >= 3 {
+            file.write_all(b" ")?;
+            self.write_operand(self.reg3.as_ref().unwrap(), 3, file, true)?;
+            file.write_all(b", ")?;
+            self.write_operand(self.reg2.as_ref().unwrap(), 2, file, true)?;
+            file.write_all(b", ")?;
+            self.write_operand(self.reg1.as_ref().unwrap(), 1, file, true)?;
+        } else if self.instr.nb_args() >= 2 {
+            file.write_all(b" ")?;
+            self.write_operand(self.reg2.as_ref().unwrap(), 2, file, true)?;
+            file.write_all(b", ")?;
+            let size = self.instr.sized_memory();
+            self.write_operand(self.reg1.as_ref().unwrap(), 1, file, size)?;
+        } else if self.instr.nb_args() == 1 {
+            file.write_all(b" ")?;
+            let size = self.instr.sized_memory();
+            self.write_operand(self.reg1.as_ref().unwrap(), 1, file, size)?;
+        }
+        std::io::Result::Ok(())
     }
 }
 
-#[cfg(not(feature = "gen_binary"))]
 impl<S1: Reg, S2: Reg> InstrTrait for Instruction<S1, S2> {
-    fn write_instr(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    /// With the feature `gen_binary` instructions are written as machine code (after
+    /// a comment with the instruction), it is an error to write an instruction that cannot be encoded
+    fn write_instr(&self, file: &mut Writer) -> std::io::Result<()> {
+        #[cfg(feature = "gen_binary")]
+        {
+            let code = self.to_bin(encode::Encoding::Shortest)?;
+            file.write_all(file.syntax().comment_marker().as_bytes())?;
+            file.write_all(b" ")?;
+            self.default_writer(file)?;
+            file.write_all(b"\n\t")?;
+            code.write_with(file)
+        }
+        #[cfg(not(feature = "gen_binary"))]
         self.default_writer(file)
     }
+
+    fn used_labels(&self) -> Vec<&Label> {
+        self.labels()
+    }
+
+    fn encode_with(
+        &self,
+        encoding: encode::Encoding,
+    ) -> Result<encode::MachineCode, encode::EncodeError> {
+        self.to_bin(encoding)
+    }
+
+    fn encode_short(&self) -> Option<encode::MachineCode> {
+        self.to_bin_short()
+    }
+
+    fn set_prefix(&mut self, prefix: Prefix) {
+        self.check_prefix(prefix);
+        self.lock_rep = Some(prefix);
+    }
+
+    fn set_segment(&mut self, segment: SegmentOverride) {
+        self.check_segment();
+        self.segment = Some(segment);
+    }
 }
 
 /// Type representing an instruction
 pub type Instr = Box<dyn InstrTrait>;
 
 impl Writable for Instr {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
         self.write_instr(file)
     }
 }
+
+impl UsesLabels for Instr {
+    fn used_labels(&self) -> Vec<&Label> {
+        InstrTrait::used_labels(self.as_ref())
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet};
+
+use super::encode::{fits_i8, EncodeError, Fixup, FixupKind, MachineCode};
+use crate::directives::Directive;
+use crate::reg::Label;
+use crate::{SegmentEL, Text};
+
+/// Text segment assembled in machine code
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Assembly {
+    /// Machine code of the segment, fixups are the ones that could not be resolved
+    /// (labels not defined in the segment and absolute addresses)
+    pub code: MachineCode,
+    /// Offset of the labels defined in the segment
+    pub labels: HashMap<Label, usize>,
+}
+
+enum Item<'a> {
+    Label(&'a Label),
+    Code {
+        code: MachineCode,
+        // encoding with a 8-bits displacement
+        short: Option<MachineCode>,
+    },
+    Align {
+        align: usize,
+        // multi-byte nops if there is no fill value
+        fill: Option<u8>,
+        max: Option<usize>,
+    },
+}
+
+impl<'a> Item<'a> {
+    fn new(el: &'a SegmentEL<super::Instr>) -> Result<Option<Self>, EncodeError> {
+        match el {
+            SegmentEL::Label(label) => Ok(Some(Self::Label(label))),
+            SegmentEL::Data(instr) => Ok(Some(Self::Code {
+                code: instr.encode()?,
+                short: instr.encode_short(),
+            })),
+            SegmentEL::Directive(Directive::P2Align(pow, fill, max)) => {
+                let fill = match fill {
+                    None => None,
+                    Some(fill) => Some(u8::try_from(*fill).map_err(|_| {
+                        EncodeError::new(format!("invalid fill value {} in .p2align", fill))
+                    })?),
+                };
+                Ok(Some(Self::Align {
+                    align: 1 << pow,
+                    fill,
+                    max: *max,
+                }))
+            }
+            SegmentEL::Directive(
+                Directive::File(..) | Directive::Loc(..) | Directive::LocMarkBlocks(_),
+            ) => Err(EncodeError::new(
+                "line information (.file, .loc) cannot be assembled as .debug_line is not generated"
+                    .to_string(),
+            )),
+            // .set takes no space, its value is computed when writing an object
+            SegmentEL::Directive(Directive::Set(..)) | SegmentEL::Comment(_) => Ok(None),
+            SegmentEL::Inline(_) => Err(EncodeError::new(
+                "inlined assembly cannot be assembled".to_string(),
+            )),
+        }
+    }
+
+    fn code(&self, long: bool) -> Option<&MachineCode> {
+        match self {
+            Self::Code {
+                short: Some(short), ..
+            } if !long => Some(short),
+            Self::Code { code, .. } => Some(code),
+            Self::Label(_) | Self::Align { .. } => None,
+        }
+    }
+
+    fn padding(align: usize, max: Option<usize>, offset: usize) -> usize {
+        let padding = (align - offset % align) % align;
+        match max {
+            Some(max) if padding > max => 0,
+            _ => padding,
+        }
+    }
+
+    fn size(&self, long: bool, offset: usize) -> usize {
+        match self {
+            Self::Label(_) => 0,
+            Self::Code { .. } => self.code(long).unwrap().bytes.len(),
+            Self::Align { align, max, .. } => Self::padding(*align, *max, offset),
+        }
+    }
+}
+
+/// Offset of each item and of each label
+fn layout(items: &[Item], long: &[bool]) -> (Vec<usize>, HashMap<Label, usize>) {
+    let mut offsets = Vec::with_capacity(items.len());
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+    for (item, long) in items.iter().zip(long) {
+        offsets.push(offset);
+        if let Item::Label(label) = item {
+            labels.insert((*label).clone(), offset);
+        }
+        offset += item.size(*long, offset);
+    }
+    (offsets, labels)
+}
+
+/// Value of a relative fixup if its label is defined in the segment
+fn resolve(
+    fixup: &Fixup,
+    pos: usize,
+    labels: &HashMap<Label, usize>,
+    globals: &[&Label],
+) -> Option<i64> {
+    match fixup.kind {
+        FixupKind::Abs32 | FixupKind::Abs32S | FixupKind::Abs64 => None,
+        _ if globals.contains(&&fixup.label) => None,
+        FixupKind::Rel8 | FixupKind::Rel32 | FixupKind::Plt32 => labels
+            .get(&fixup.label)
+            .map(|target| *target as i64 + fixup.addend - pos as i64),
+    }
+}
+
+/// Multi-byte nops used by GNU as for padding
+const NOPS: [&[u8]; 11] = [
+    &[0x90],
+    &[0x66, 0x90],
+    &[0x0f, 0x1f, 0x00],
+    &[0x0f, 0x1f, 0x40, 0x00],
+    &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x80, 0x00, 0x00, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x66, 0x2e, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[
+        0x66, 0x66, 0x2e, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ],
+];
+
+/// Padding of `size` bytes made of the longest nops
+pub(crate) fn nops(mut size: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(size);
+    while size > 0 {
+        let nop = NOPS[size.min(NOPS.len()) - 1];
+        bytes.extend_from_slice(nop);
+        size -= nop.len();
+    }
+    bytes
+}
+
+/// Assemble a text segment
+///
+/// Labels defined in the segment are resolved, jumps use a 8-bits displacement when
+/// their label is close enough (starting from short jumps, jumps are made longer until
+/// every displacement fits) and `.p2align` directives insert padding (nops by default).
+/// Labels defined elsewhere and absolute addresses are left as fixups.
+/// Line information (`.file`, `.loc`) is an error as `.debug_line` is not generated.
+///
+/// ```
+/// use write_x86_64::*;
+/// use write_x86_64::instr::assembler::assemble;
+///
+/// let text = Segment::label(new_label("loop"))
+///     + decq(reg!(RDI))
+///     + jnz(new_label("loop"))
+///     + ret();
+/// let assembly = assemble(&text).unwrap();
+/// assert_eq!(assembly.code.bytes, [0x48, 0xff, 0xcf, 0x75, 0xfb, 0xc3]);
+/// assert_eq!(assembly.labels[&new_label("loop")], 0);
+/// ```
+pub fn assemble(text: &Text) -> Result<Assembly, EncodeError> {
+    assemble_with_globals(text, &[])
+}
+
+/// Assemble a text segment without resolving references to `globals`
+///
+/// Like GNU as on ELF, references to global symbols are left as fixups as the
+/// symbols can be overridden when linking a shared library.
+pub fn assemble_with_globals(text: &Text, globals: &[&Label]) -> Result<Assembly, EncodeError> {
+    let mut items = Vec::new();
+    for el in &text.data {
+        if let Some(item) = Item::new(&el.el)? {
+            items.push(item)
+        }
+    }
+    let mut defined = HashSet::new();
+    for item in &items {
+        if let Item::Label(label) = item {
+            if !defined.insert(*label) {
+                return Err(EncodeError::new(format!(
+                    "label {:?} is defined twice",
+                    label
+                )));
+            }
+        }
+    }
+
+    // like gas, the layout is updated as soon as a jump gets longer
+    // and jumps never get shorter so this terminates
+    let mut long = vec![false; items.len()];
+    let (mut offsets, mut labels) = layout(&items, &long);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, item) in items.iter().enumerate() {
+            if let Item::Code {
+                short: Some(short), ..
+            } = item
+            {
+                let fixup = &short.fixups[0];
+                let fits = resolve(fixup, offsets[i] + fixup.offset, &labels, globals)
+                    .map_or(false, fits_i8);
+                if !long[i] && !fits {
+                    long[i] = true;
+                    changed = true;
+                    let (new_offsets, new_labels) = layout(&items, &long);
+                    offsets = new_offsets;
+                    labels = new_labels;
+                }
+            }
+        }
+    }
+
+    let mut code = MachineCode::default();
+    for (i, item) in items.iter().enumerate() {
+        match item.code(long[i]) {
+            Some(instr) => {
+                code.bytes.extend_from_slice(&instr.bytes);
+                for fixup in &instr.fixups {
+                    let pos = offsets[i] + fixup.offset;
+                    match resolve(fixup, pos, &labels, globals) {
+                        Some(value) => patch(&mut code.bytes[pos..], fixup, value)?,
+                        None => code.fixups.push(Fixup {
+                            offset: pos,
+                            ..fixup.clone()
+                        }),
+                    }
+                }
+            }
+            None => {
+                if let Item::Align { fill, .. } = item {
+                    let size = item.size(long[i], offsets[i]);
+                    match fill {
+                        None => code.bytes.append(&mut nops(size)),
+                        Some(fill) => code.bytes.resize(code.bytes.len() + size, *fill),
+                    }
+                }
+            }
+        }
+    }
+    Ok(Assembly { code, labels })
+}
+
+fn patch(bytes: &mut [u8], fixup: &Fixup, value: i64) -> Result<(), EncodeError> {
+    match fixup.kind {
+        FixupKind::Rel8 => {
+            let value = i8::try_from(value).map_err(|_| {
+                EncodeError::new(format!(
+                    "label {:?} is too far ({} bytes)",
+                    fixup.label, value
+                ))
+            })?;
+            bytes[0] = value as u8
+        }
+        FixupKind::Rel32 | FixupKind::Plt32 | FixupKind::Abs32 | FixupKind::Abs32S => {
+            let value = i32::try_from(value).map_err(|_| {
+                EncodeError::new(format!(
+                    "label {:?} is too far ({} bytes)",
+                    fixup.label, value
+                ))
+            })?;
+            bytes[..4].copy_from_slice(&value.to_le_bytes())
+        }
+        FixupKind::Abs64 => bytes[..8].copy_from_slice(&value.to_le_bytes()),
+    }
+    Ok(())
+}
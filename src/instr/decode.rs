@@ -0,0 +1,1551 @@
+use std::collections::HashMap;
+
+use super::encode::{Fixup, MachineCode};
+use super::{Cond, InstrName, Instruction, Precision, Prefix, SegmentOverride};
+use crate::reg::{Label, Operand, RegB, RegL, RegQ, RegW, RegX, RegY, Sizes};
+use crate::traits::{Reg, Writable};
+use crate::writer::Writer;
+use crate::{Segment, Text};
+
+/// Instruction decoded from machine code, the variant gives the type of the operands
+pub enum Decoded {
+    /// Instruction without register operands (`ret`, `call label`, ...)
+    Nullary(Instruction),
+    /// Instruction with a 1-byte operand
+    B(Instruction<RegB>),
+    /// Instruction with a 2-bytes operand
+    W(Instruction<RegW>),
+    /// Instruction with a 4-bytes operand
+    L(Instruction<RegL>),
+    /// Instruction with a 8-bytes operand
+    Q(Instruction<RegQ>),
+    /// Instruction between 1-byte operands
+    BB(Instruction<RegB, RegB>),
+    /// Instruction between 2-bytes operands
+    WW(Instruction<RegW, RegW>),
+    /// Instruction between 4-bytes operands
+    LL(Instruction<RegL, RegL>),
+    /// Instruction between 8-bytes operands
+    QQ(Instruction<RegQ, RegQ>),
+    /// Extension from 1 to 2 bytes (or shift of 2 bytes by %cl)
+    BW(Instruction<RegB, RegW>),
+    /// Extension from 1 to 4 bytes (or shift of 4 bytes by %cl)
+    BL(Instruction<RegB, RegL>),
+    /// Extension from 1 to 8 bytes (or shift of 8 bytes by %cl)
+    BQ(Instruction<RegB, RegQ>),
+    /// Extension from 2 to 4 bytes
+    WL(Instruction<RegW, RegL>),
+    /// Extension from 2 to 8 bytes
+    WQ(Instruction<RegW, RegQ>),
+    /// Extension from 4 to 8 bytes
+    LQ(Instruction<RegL, RegQ>),
+    /// Floating point instruction between XMM registers
+    XX(Instruction<RegX, RegX>),
+    /// Conversion or move from a 4-bytes operand to a XMM register
+    LX(Instruction<RegL, RegX>),
+    /// Conversion or move from a 8-bytes operand to a XMM register
+    QX(Instruction<RegQ, RegX>),
+    /// Conversion or move from a XMM register to a 4-bytes operand
+    XL(Instruction<RegX, RegL>),
+    /// Conversion or move from a XMM register to a 8-bytes operand
+    XQ(Instruction<RegX, RegQ>),
+    /// AVX instruction on YMM registers
+    YY(Instruction<RegY, RegY>),
+    /// Broadcast of a float in a YMM register
+    XY(Instruction<RegX, RegY>),
+    /// Mask of the bytes of a YMM register
+    YL(Instruction<RegY, RegL>),
+}
+
+macro_rules! dispatch {
+    ($self:expr, $instr:ident => $e:expr) => {
+        match $self {
+            Decoded::Nullary($instr) => $e,
+            Decoded::B($instr) => $e,
+            Decoded::W($instr) => $e,
+            Decoded::L($instr) => $e,
+            Decoded::Q($instr) => $e,
+            Decoded::BB($instr) => $e,
+            Decoded::WW($instr) => $e,
+            Decoded::LL($instr) => $e,
+            Decoded::QQ($instr) => $e,
+            Decoded::BW($instr) => $e,
+            Decoded::BL($instr) => $e,
+            Decoded::BQ($instr) => $e,
+            Decoded::WL($instr) => $e,
+            Decoded::WQ($instr) => $e,
+            Decoded::LQ($instr) => $e,
+            Decoded::XX($instr) => $e,
+            Decoded::LX($instr) => $e,
+            Decoded::QX($instr) => $e,
+            Decoded::XL($instr) => $e,
+            Decoded::XQ($instr) => $e,
+            Decoded::YY($instr) => $e,
+            Decoded::XY($instr) => $e,
+            Decoded::YL($instr) => $e,
+        }
+    };
+}
+
+impl Decoded {
+    /// Name of the instruction
+    pub fn instr(&self) -> &InstrName {
+        dispatch!(self, instr => &instr.instr)
+    }
+
+    /// Text segment containing only the instruction
+    pub fn into_text(self) -> Text {
+        dispatch!(self, instr => Segment::new(Box::new(instr)))
+    }
+
+    /// Add the lock prefix, fails if the instruction cannot be locked
+    fn lock(&mut self) -> bool {
+        dispatch!(self, instr => {
+            let valid = instr.instr.accepts(Prefix::Lock) && instr.memory_destination();
+            if valid {
+                instr.lock_rep = Some(Prefix::Lock);
+            }
+            valid
+        })
+    }
+
+    /// Add a segment override, fails if the instruction has no memory operand nor (%rsi) source
+    fn segment(&mut self, segment: SegmentOverride) -> bool {
+        dispatch!(self, instr => {
+            let valid = instr.accepts_segment();
+            if valid {
+                instr.segment = Some(segment);
+            }
+            valid
+        })
+    }
+}
+
+impl Writable for Decoded {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        dispatch!(self, instr => instr.default_writer(file))
+    }
+}
+
+impl std::fmt::Display for Decoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::traits::fmt_writable(self, f)
+    }
+}
+
+/// Error raised when bytes are not a supported instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    offset: usize,
+    message: String,
+}
+
+impl DecodeError {
+    fn new(offset: usize, message: String) -> Self {
+        Self { offset, message }
+    }
+
+    /// Offset of the instruction that could not be decoded
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Description of the error
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cannot decode instruction at offset {}: {}",
+            self.offset, self.message
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for std::io::Error {
+    fn from(err: DecodeError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Registers that can be decoded from their number (with REX.R, REX.X or REX.B)
+trait DecodeReg: Reg {
+    fn decode(num: u8, rex: bool) -> Self;
+}
+
+impl DecodeReg for RegQ {
+    fn decode(num: u8, _: bool) -> Self {
+        [
+            Self::Rax,
+            Self::Rcx,
+            Self::Rdx,
+            Self::Rbx,
+            Self::Rsp,
+            Self::Rbp,
+            Self::Rsi,
+            Self::Rdi,
+            Self::R8,
+            Self::R9,
+            Self::R10,
+            Self::R11,
+            Self::R12,
+            Self::R13,
+            Self::R14,
+            Self::R15,
+        ][num as usize]
+    }
+}
+
+impl DecodeReg for RegL {
+    fn decode(num: u8, _: bool) -> Self {
+        [
+            Self::Eax,
+            Self::Ecx,
+            Self::Edx,
+            Self::Ebx,
+            Self::Esp,
+            Self::Ebp,
+            Self::Esi,
+            Self::Edi,
+            Self::R8d,
+            Self::R9d,
+            Self::R10d,
+            Self::R11d,
+            Self::R12d,
+            Self::R13d,
+            Self::R14d,
+            Self::R15d,
+        ][num as usize]
+    }
+}
+
+impl DecodeReg for RegW {
+    fn decode(num: u8, _: bool) -> Self {
+        [
+            Self::Ax,
+            Self::Cx,
+            Self::Dx,
+            Self::Bx,
+            Self::Sp,
+            Self::Bp,
+            Self::Si,
+            Self::Di,
+            Self::R8w,
+            Self::R9w,
+            Self::R10w,
+            Self::R11w,
+            Self::R12w,
+            Self::R13w,
+            Self::R14w,
+            Self::R15w,
+        ][num as usize]
+    }
+}
+
+impl DecodeReg for RegB {
+    fn decode(num: u8, rex: bool) -> Self {
+        // without REX, 4 to 7 are the high bytes of the first registers
+        if !rex && (4..8).contains(&num) {
+            return [Self::Ah, Self::Ch, Self::Dh, Self::Bh][num as usize - 4];
+        }
+        [
+            Self::Al,
+            Self::Cl,
+            Self::Dl,
+            Self::Bl,
+            Self::Spl,
+            Self::Bpl,
+            Self::Sil,
+            Self::Dil,
+            Self::R8b,
+            Self::R9b,
+            Self::R10b,
+            Self::R11b,
+            Self::R12b,
+            Self::R13b,
+            Self::R14b,
+            Self::R15b,
+        ][num as usize]
+    }
+}
+
+impl DecodeReg for RegX {
+    fn decode(num: u8, _: bool) -> Self {
+        [
+            Self::Xmm0,
+            Self::Xmm1,
+            Self::Xmm2,
+            Self::Xmm3,
+            Self::Xmm4,
+            Self::Xmm5,
+            Self::Xmm6,
+            Self::Xmm7,
+            Self::Xmm8,
+            Self::Xmm9,
+            Self::Xmm10,
+            Self::Xmm11,
+            Self::Xmm12,
+            Self::Xmm13,
+            Self::Xmm14,
+            Self::Xmm15,
+        ][num as usize]
+    }
+}
+
+impl DecodeReg for RegY {
+    fn decode(num: u8, _: bool) -> Self {
+        [
+            Self::Ymm0,
+            Self::Ymm1,
+            Self::Ymm2,
+            Self::Ymm3,
+            Self::Ymm4,
+            Self::Ymm5,
+            Self::Ymm6,
+            Self::Ymm7,
+            Self::Ymm8,
+            Self::Ymm9,
+            Self::Ymm10,
+            Self::Ymm11,
+            Self::Ymm12,
+            Self::Ymm13,
+            Self::Ymm14,
+            Self::Ymm15,
+        ][num as usize]
+    }
+}
+
+fn cond(code: u8) -> Cond {
+    [
+        Cond::O,
+        Cond::NO,
+        Cond::B,
+        Cond::AE,
+        Cond::E,
+        Cond::NE,
+        Cond::BE,
+        Cond::A,
+        Cond::S,
+        Cond::NS,
+        Cond::P,
+        Cond::NP,
+        Cond::L,
+        Cond::GE,
+        Cond::LE,
+        Cond::G,
+    ][(code & 0xf) as usize]
+}
+
+fn instr<S1: Reg, S2: Reg>(
+    instr: InstrName,
+    reg1: Option<Operand<S1>>,
+    reg2: Option<Operand<S2>>,
+) -> Instruction<S1, S2> {
+    Instruction {
+        instr,
+        reg1,
+        reg2,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }
+}
+
+fn instr3<S1: Reg, S2: Reg>(
+    instr: InstrName,
+    reg1: Operand<S1>,
+    reg2: Operand<S2>,
+    reg3: Operand<S2>,
+) -> Instruction<S1, S2> {
+    Instruction {
+        instr,
+        reg1: Some(reg1),
+        reg2: Some(reg2),
+        reg3: Some(reg3),
+        lock_rep: None,
+        segment: None,
+    }
+}
+
+/// Wrap an instruction with one operand of the given size
+macro_rules! one {
+    ($size:expr, $e:expr) => {
+        match $size {
+            Sizes::Byte => Decoded::B($e),
+            Sizes::Word => Decoded::W($e),
+            Sizes::Long => Decoded::L($e),
+            _ => Decoded::Q($e),
+        }
+    };
+}
+
+/// Wrap an instruction with two operands of the given size
+macro_rules! two {
+    ($size:expr, $e:expr) => {
+        match $size {
+            Sizes::Byte => Decoded::BB($e),
+            Sizes::Word => Decoded::WW($e),
+            Sizes::Long => Decoded::LL($e),
+            _ => Decoded::QQ($e),
+        }
+    };
+}
+
+/// Memory operand of a ModRM byte
+enum Mem {
+    Addr(i64, RegQ, Option<RegQ>, u8),
+    Index(i64, RegQ, u8),
+    // displacement relative to the end of the instruction
+    Rip { disp: i64, at: usize },
+    Abs { disp: i64, at: usize },
+}
+
+enum Rm {
+    Reg(u8),
+    Mem(Mem),
+}
+
+struct ModRm {
+    // register number (with REX.R)
+    reg: u8,
+    // 3 bits of the reg field, used as an opcode extension
+    ext: u8,
+    rm: Rm,
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    fixups: &'a HashMap<usize, &'a Fixup>,
+    start: usize,
+    pos: usize,
+    rex: Option<u8>,
+    opsize: bool,
+    // 0xf2 or 0xf3, selecting scalar floating point instructions
+    rep: Option<u8>,
+    // targets of jumps and %rip relative accesses without fixups
+    targets: Vec<usize>,
+}
+
+impl<'a> Cursor<'a> {
+    fn error(&self, message: String) -> DecodeError {
+        DecodeError::new(self.start, message)
+    }
+
+    fn unsupported(&self) -> DecodeError {
+        let end = self.bytes.len().min(self.pos.max(self.start + 1));
+        self.error(format!(
+            "unsupported instruction {:02x?}",
+            &self.bytes[self.start..end]
+        ))
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        match self.bytes.get(self.pos) {
+            Some(byte) => {
+                self.pos += 1;
+                Ok(*byte)
+            }
+            None => Err(self.error("truncated instruction".to_string())),
+        }
+    }
+
+    fn le_bytes<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut bytes = [0; N];
+        for byte in &mut bytes {
+            *byte = self.byte()?;
+        }
+        Ok(bytes)
+    }
+
+    /// Signed immediate of `size` bytes
+    fn signed(&mut self, size: usize) -> Result<i64, DecodeError> {
+        Ok(match size {
+            1 => self.byte()? as i8 as i64,
+            2 => i16::from_le_bytes(self.le_bytes()?) as i64,
+            4 => i32::from_le_bytes(self.le_bytes()?) as i64,
+            _ => i64::from_le_bytes(self.le_bytes()?),
+        })
+    }
+
+    fn rex_bit(&self, bit: u8) -> u8 {
+        match self.rex {
+            Some(rex) if rex & bit != 0 => 8,
+            _ => 0,
+        }
+    }
+
+    fn rex_w(&self) -> bool {
+        self.rex_bit(0x8) != 0
+    }
+
+    /// Size of operands of an opcode that has a 1-byte form
+    fn size(&self, byte: bool) -> Sizes {
+        if byte {
+            Sizes::Byte
+        } else if self.rex_w() {
+            Sizes::Quad
+        } else if self.opsize {
+            Sizes::Word
+        } else {
+            Sizes::Long
+        }
+    }
+
+    fn modrm(&mut self) -> Result<ModRm, DecodeError> {
+        let modrm = self.byte()?;
+        let mode = modrm >> 6;
+        let ext = (modrm >> 3) & 0b111;
+        let reg = ext + self.rex_bit(0x4);
+        let rm = modrm & 0b111;
+        if mode == 0b11 {
+            return Ok(ModRm {
+                reg,
+                ext,
+                rm: Rm::Reg(rm + self.rex_bit(0x1)),
+            });
+        }
+        if mode == 0b00 && rm == 0b101 {
+            let at = self.pos;
+            let disp = self.signed(4)?;
+            return Ok(ModRm {
+                reg,
+                ext,
+                rm: Rm::Mem(Mem::Rip { disp, at }),
+            });
+        }
+        let mut index = None;
+        let mut scale = 0;
+        let mut base = Some(rm + self.rex_bit(0x1));
+        if rm == 0b100 {
+            let sib = self.byte()?;
+            let index_num = ((sib >> 3) & 0b111) + self.rex_bit(0x2);
+            // %rsp cannot be an index, it means no index
+            if index_num != 0b100 {
+                index = Some(RegQ::decode(index_num, true));
+                scale = 1 << (sib >> 6);
+            }
+            base = if mode == 0b00 && sib & 0b111 == 0b101 {
+                None
+            } else {
+                Some((sib & 0b111) + self.rex_bit(0x1))
+            };
+        }
+        let at = self.pos;
+        let disp = match (mode, base) {
+            (0b01, _) => self.signed(1)?,
+            (0b10, _) | (_, None) => self.signed(4)?,
+            _ => 0,
+        };
+        let mem = match (base, index) {
+            (Some(base), index) => Mem::Addr(disp, RegQ::decode(base, true), index, scale),
+            (None, Some(index)) => Mem::Index(disp, index, scale),
+            (None, None) => Mem::Abs { disp, at },
+        };
+        Ok(ModRm {
+            reg,
+            ext,
+            rm: Rm::Mem(mem),
+        })
+    }
+
+    fn reg<R: DecodeReg>(&self, num: u8) -> Operand<R> {
+        Operand::Reg(R::decode(num, self.rex.is_some()))
+    }
+
+    /// Label at `target` (an offset in the decoded bytes) or of the fixup at `at`
+    fn label(&mut self, at: usize, target: i64) -> Label {
+        match self.fixups.get(&at) {
+            Some(fixup) => fixup.label.clone(),
+            None => {
+                if target >= 0 {
+                    self.targets.push(target as usize);
+                }
+                Label::from_str(format!(".L{}", target))
+            }
+        }
+    }
+
+    /// Operand of the r/m field, must be called after reading the immediate
+    /// as %rip relative addresses depend on the end of the instruction
+    fn rm<R: DecodeReg>(&mut self, rm: &Rm) -> Operand<R> {
+        match rm {
+            Rm::Reg(num) => self.reg(*num),
+            Rm::Mem(Mem::Addr(disp, base, index, scale)) => {
+                Operand::Addr(*disp, *base, *index, *scale)
+            }
+            Rm::Mem(Mem::Index(disp, index, scale)) => Operand::Index(*disp, *index, *scale),
+            Rm::Mem(Mem::Rip { disp, at }) => {
+                let target = self.pos as i64 + disp;
+                Operand::LabRelAddr(self.label(*at, target))
+            }
+            Rm::Mem(Mem::Abs { disp, at }) => Operand::LabAbsAddr(match self.fixups.get(at) {
+                Some(fixup) => fixup.label.clone(),
+                None => Label::from_str(disp.to_string()),
+            }),
+        }
+    }
+
+    /// Memory operand of the r/m field
+    fn mem<R: DecodeReg>(&mut self, rm: &Rm) -> Result<Operand<R>, DecodeError> {
+        match rm {
+            Rm::Reg(_) => Err(self.unsupported()),
+            Rm::Mem(_) => Ok(self.rm(rm)),
+        }
+    }
+
+    /// Immediate of an operation of the given size (at most 4 bytes), or label value
+    fn imm_of(&mut self, size: Sizes) -> Result<ImmOperand, DecodeError> {
+        let at = self.pos;
+        let imm = match size {
+            Sizes::Byte => self.signed(1)?,
+            Sizes::Word => self.signed(2)?,
+            _ => self.signed(4)?,
+        };
+        Ok(match self.fixups.get(&at) {
+            Some(fixup) => ImmOperand::Label(fixup.label.clone()),
+            None => ImmOperand::Imm(imm),
+        })
+    }
+
+    /// Label of a jump with a displacement of `size` bytes
+    fn target(&mut self, size: usize) -> Result<Label, DecodeError> {
+        let at = self.pos;
+        let disp = self.signed(size)?;
+        Ok(self.label(at, self.pos as i64 + disp))
+    }
+
+    fn decode(&mut self) -> Result<Decoded, DecodeError> {
+        let mut cs = false;
+        let mut lock = false;
+        let mut segment = None;
+        let mut op = self.byte()?;
+        loop {
+            match op {
+                0x66 => self.opsize = true,
+                0xf2 | 0xf3 => self.rep = Some(op),
+                0xf0 => lock = true,
+                0x64 => segment = Some(SegmentOverride::Fs),
+                0x65 => segment = Some(SegmentOverride::Gs),
+                // used by GNU as in multi-byte nops
+                0x2e => cs = true,
+                _ => break,
+            }
+            op = self.byte()?;
+        }
+        let mut decoded = self.decode_op(op, cs)?;
+        if lock && !decoded.lock() {
+            return Err(self.unsupported());
+        }
+        if let Some(segment) = segment {
+            if !decoded.segment(segment) {
+                return Err(self.unsupported());
+            }
+        }
+        Ok(decoded)
+    }
+
+    fn decode_op(&mut self, mut op: u8, cs: bool) -> Result<Decoded, DecodeError> {
+        if op == 0xc4 || op == 0xc5 {
+            if cs || self.opsize || self.rep.is_some() {
+                return Err(self.unsupported());
+            }
+            return self.decode_vex(op);
+        }
+        if op & 0xf0 == 0x40 {
+            self.rex = Some(op);
+            op = self.byte()?;
+        }
+        let string = matches!(op, 0xa4..=0xa7 | 0xaa..=0xaf);
+        if (cs && op != 0x0f) || (self.rep.is_some() && op != 0x0f && !string) {
+            return Err(self.unsupported());
+        }
+        match op {
+            0x0f => self.decode_0f(cs),
+            // add, or, adc, sbb, and, sub, xor and cmp
+            _ if op < 0x40 && op & 0b111 < 6 => {
+                let name = match op >> 3 {
+                    0 => InstrName::Add,
+                    1 => InstrName::Or,
+                    2 => InstrName::Adc,
+                    3 => InstrName::Sbb,
+                    4 => InstrName::And,
+                    5 => InstrName::Sub,
+                    6 => InstrName::Xor,
+                    _ => InstrName::Cmp,
+                };
+                let size = self.size(op & 1 == 0);
+                match op & 0b111 {
+                    0 | 1 => self.reg_rm(name, size),
+                    2 | 3 => self.rm_reg(name, size),
+                    _ => {
+                        let imm = self.imm_of(size)?;
+                        Ok(two!(
+                            size,
+                            instr(name, Some(imm.clone().into()), Some(self.reg(0)))
+                        ))
+                    }
+                }
+            }
+            0x50..=0x5f if !self.opsize => {
+                let name = if op < 0x58 {
+                    InstrName::Push
+                } else {
+                    InstrName::Pop
+                };
+                let reg = self.reg((op & 0b111) + self.rex_bit(0x1));
+                Ok(Decoded::Q(instr(name, Some(reg), None)))
+            }
+            0x63 if self.rex_w() => {
+                let modrm = self.modrm()?;
+                let src = self.rm(&modrm.rm);
+                Ok(Decoded::LQ(instr(
+                    InstrName::Movs,
+                    Some(src),
+                    Some(self.reg(modrm.reg)),
+                )))
+            }
+            0x68 | 0x6a => {
+                let imm = self.imm_of(if op == 0x68 { Sizes::Long } else { Sizes::Byte })?;
+                Ok(Decoded::Q(instr(InstrName::Push, Some(imm.into()), None)))
+            }
+            0x69 | 0x6b => {
+                let size = self.size(false);
+                let modrm = self.modrm()?;
+                let imm_size = if op == 0x6b { Sizes::Byte } else { size };
+                let imm = self.imm_of(imm_size)?;
+                match modrm.rm {
+                    // short form with the same source and destination
+                    Rm::Reg(num) if num == modrm.reg => Ok(two!(
+                        size,
+                        instr(
+                            InstrName::IMul,
+                            Some(imm.clone().into()),
+                            Some(self.reg(num))
+                        )
+                    )),
+                    _ => Ok(two!(
+                        size,
+                        instr3(
+                            InstrName::IMulImm,
+                            imm.clone().into(),
+                            self.rm(&modrm.rm),
+                            self.reg(modrm.reg)
+                        )
+                    )),
+                }
+            }
+            0x70..=0x7f => {
+                let cond = cond(op);
+                let label = self.target(1)?;
+                Ok(Decoded::Nullary(instr(
+                    InstrName::CondJump(cond, label),
+                    None,
+                    None,
+                )))
+            }
+            0x80 | 0x81 | 0x83 => {
+                let size = self.size(op == 0x80);
+                let modrm = self.modrm()?;
+                let imm = self.imm_of(if op == 0x83 { Sizes::Byte } else { size })?;
+                let name = match modrm.ext {
+                    0 => InstrName::Add,
+                    1 => InstrName::Or,
+                    2 => InstrName::Adc,
+                    3 => InstrName::Sbb,
+                    4 => InstrName::And,
+                    5 => InstrName::Sub,
+                    6 => InstrName::Xor,
+                    _ => InstrName::Cmp,
+                };
+                Ok(two!(
+                    size,
+                    instr(name, Some(imm.clone().into()), Some(self.rm(&modrm.rm)))
+                ))
+            }
+            0x84 | 0x85 => self.reg_rm(InstrName::Test, self.size(op == 0x84)),
+            0x88 | 0x89 => self.reg_rm(InstrName::Move, self.size(op == 0x88)),
+            0x8a | 0x8b => self.rm_reg(InstrName::Move, self.size(op == 0x8a)),
+            0x8d => {
+                let size = self.size(false);
+                let modrm = self.modrm()?;
+                Ok(two!(
+                    size,
+                    instr(
+                        InstrName::Lea,
+                        Some(self.mem(&modrm.rm)?),
+                        Some(self.reg(modrm.reg))
+                    )
+                ))
+            }
+            0x8f => {
+                let modrm = self.modrm()?;
+                if modrm.ext != 0 || self.opsize {
+                    return Err(self.unsupported());
+                }
+                let op = self.rm(&modrm.rm);
+                Ok(Decoded::Q(instr(InstrName::Pop, Some(op), None)))
+            }
+            0x86 | 0x87 => self.reg_rm(InstrName::Xchg, self.size(op == 0x86)),
+            // nop, also xchg %rax, %rax (or %ax, %ax with 0x66)
+            0x90 if self.rex_bit(0x1) == 0 => {
+                Ok(Decoded::Nullary(instr(InstrName::Nop, None, None)))
+            }
+            // xchg with the accumulator
+            0x90..=0x97 => {
+                let num = (op & 0b111) + self.rex_bit(0x1);
+                Ok(two!(
+                    self.size(false),
+                    instr(InstrName::Xchg, Some(self.reg(0)), Some(self.reg(num)))
+                ))
+            }
+            0x99 if !self.opsize => {
+                let name = if self.rex_w() {
+                    InstrName::Cqto
+                } else {
+                    InstrName::Cltd
+                };
+                Ok(Decoded::Nullary(instr(name, None, None)))
+            }
+            0xa4..=0xa7 | 0xaa..=0xaf => {
+                let name = match op >> 1 {
+                    0x52 => InstrName::MovString,
+                    0x53 => InstrName::CmpString,
+                    0x55 => InstrName::StoreString,
+                    0x56 => InstrName::LoadString,
+                    _ => InstrName::ScanString,
+                };
+                let lock_rep = match (self.rep, name.accepts(Prefix::Rep)) {
+                    (None, _) => None,
+                    (Some(0xf3), true) => Some(Prefix::Rep),
+                    (Some(0xf3), false) => Some(Prefix::Repe),
+                    (Some(_), false) => Some(Prefix::Repne),
+                    (Some(_), true) => return Err(self.unsupported()),
+                };
+                Ok(one!(
+                    self.size(op & 1 == 0),
+                    Instruction {
+                        instr: name,
+                        reg1: None,
+                        reg2: None,
+                        reg3: None,
+                        lock_rep,
+                        segment: None,
+                    }
+                ))
+            }
+            0xa8 | 0xa9 => {
+                let size = self.size(op == 0xa8);
+                let imm = self.imm_of(size)?;
+                Ok(two!(
+                    size,
+                    instr(InstrName::Test, Some(imm.clone().into()), Some(self.reg(0)))
+                ))
+            }
+            // movabs between %rax and a 64-bits absolute address
+            0xa1 | 0xa3 if self.rex_w() && !self.opsize => {
+                let at = self.pos;
+                let addr = self.signed(8)?;
+                let addr = Operand::LabAbsAddr(match self.fixups.get(&at) {
+                    Some(fixup) => fixup.label.clone(),
+                    None => Label::from_str(addr.to_string()),
+                });
+                let (src, dst) = if op == 0xa1 {
+                    (addr, self.reg(0))
+                } else {
+                    (self.reg(0), addr)
+                };
+                Ok(Decoded::QQ(instr(InstrName::Movabs, Some(src), Some(dst))))
+            }
+            0xb0..=0xbf => {
+                let size = self.size(op < 0xb8);
+                let reg = (op & 0b111) + self.rex_bit(0x1);
+                let (name, imm) = if size == Sizes::Quad {
+                    let at = self.pos;
+                    let imm = self.signed(8)?;
+                    let imm = match self.fixups.get(&at) {
+                        Some(fixup) => ImmOperand::Label(fixup.label.clone()),
+                        None => ImmOperand::Imm(imm),
+                    };
+                    (InstrName::Movabs, imm)
+                } else {
+                    (InstrName::Move, self.imm_of(size)?)
+                };
+                Ok(two!(
+                    size,
+                    instr(name, Some(imm.clone().into()), Some(self.reg(reg)))
+                ))
+            }
+            0xc0 | 0xc1 | 0xd0 | 0xd1 | 0xd2 | 0xd3 => self.decode_shift(op),
+            0xc3 => Ok(Decoded::Nullary(instr(InstrName::Ret, None, None))),
+            0xc6 | 0xc7 => {
+                let size = self.size(op == 0xc6);
+                let modrm = self.modrm()?;
+                if modrm.ext != 0 {
+                    return Err(self.unsupported());
+                }
+                let imm = self.imm_of(size)?;
+                Ok(two!(
+                    size,
+                    instr(
+                        InstrName::Move,
+                        Some(imm.clone().into()),
+                        Some(self.rm(&modrm.rm))
+                    )
+                ))
+            }
+            0xc9 => Ok(Decoded::Nullary(instr(InstrName::Leave, None, None))),
+            0xe0..=0xe3 => {
+                let label = self.target(1)?;
+                let name = match op {
+                    0xe0 => InstrName::Loopne(label),
+                    0xe1 => InstrName::Loope(label),
+                    0xe2 => InstrName::Loop(label),
+                    _ => InstrName::Jrcxz(label),
+                };
+                Ok(Decoded::Nullary(instr(name, None, None)))
+            }
+            0xe8 | 0xe9 | 0xeb => {
+                let label = self.target(if op == 0xeb { 1 } else { 4 })?;
+                let name = if op == 0xe8 {
+                    InstrName::Call(label)
+                } else {
+                    InstrName::Jump(label)
+                };
+                Ok(Decoded::Nullary(instr(name, None, None)))
+            }
+            0xf4 => Ok(Decoded::Nullary(instr(InstrName::Hlt, None, None))),
+            0xf6 | 0xf7 => {
+                let size = self.size(op == 0xf6);
+                let modrm = self.modrm()?;
+                let name = match modrm.ext {
+                    0 => {
+                        let imm = self.imm_of(size)?;
+                        return Ok(two!(
+                            size,
+                            instr(
+                                InstrName::Test,
+                                Some(imm.clone().into()),
+                                Some(self.rm(&modrm.rm))
+                            )
+                        ));
+                    }
+                    2 => InstrName::Not,
+                    3 => InstrName::Neg,
+                    4 => InstrName::UnsignedMul,
+                    5 => InstrName::SignedMul,
+                    6 => InstrName::UnsignedDiv,
+                    7 => InstrName::SignedDiv,
+                    _ => return Err(self.unsupported()),
+                };
+                Ok(one!(size, instr(name, Some(self.rm(&modrm.rm)), None)))
+            }
+            0xfe | 0xff => {
+                let size = self.size(op == 0xfe);
+                let modrm = self.modrm()?;
+                let name = match modrm.ext {
+                    0 => InstrName::Inc,
+                    1 => InstrName::Dec,
+                    2 | 4 | 6 if op == 0xff && !self.opsize => {
+                        let name = match modrm.ext {
+                            2 => InstrName::CallStar,
+                            4 => InstrName::JumpStar,
+                            _ => InstrName::Push,
+                        };
+                        let op = self.rm(&modrm.rm);
+                        return Ok(Decoded::Q(instr(name, Some(op), None)));
+                    }
+                    _ => return Err(self.unsupported()),
+                };
+                Ok(one!(size, instr(name, Some(self.rm(&modrm.rm)), None)))
+            }
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    /// Opcodes starting with 0x0f
+    fn decode_0f(&mut self, cs: bool) -> Result<Decoded, DecodeError> {
+        let op = self.byte()?;
+        if cs && op != 0x1f {
+            return Err(self.unsupported());
+        }
+        if let 0x10 | 0x11 | 0x28..=0x2f | 0x51..=0x5f | 0x6e | 0x7e = op {
+            return self.decode_sse(op);
+        }
+        if let (Some(0xf3), 0xb8 | 0xbc | 0xbd) = (self.rep, op) {
+            let name = match op {
+                0xb8 => InstrName::Popcnt,
+                0xbc => InstrName::Tzcnt,
+                _ => InstrName::Lzcnt,
+            };
+            return self.rm_reg(name, self.size(false));
+        }
+        if self.rep.is_some() {
+            return Err(self.unsupported());
+        }
+        match op {
+            0x05 => Ok(Decoded::Nullary(instr(InstrName::Syscall, None, None))),
+            // multi-byte nop
+            0x1f => {
+                let modrm = self.modrm()?;
+                if modrm.ext != 0 {
+                    return Err(self.unsupported());
+                }
+                Ok(Decoded::Nullary(instr(InstrName::Nop, None, None)))
+            }
+            0x40..=0x4f => {
+                let cond = cond(op);
+                self.rm_reg(InstrName::Cmov(cond), self.size(false))
+            }
+            0x80..=0x8f => {
+                let cond = cond(op);
+                let label = self.target(4)?;
+                Ok(Decoded::Nullary(instr(
+                    InstrName::CondJump(cond, label),
+                    None,
+                    None,
+                )))
+            }
+            0x90..=0x9f => {
+                let cond = cond(op);
+                let modrm = self.modrm()?;
+                let op = self.rm(&modrm.rm);
+                Ok(Decoded::B(instr(InstrName::Set(cond), Some(op), None)))
+            }
+            0xa3 => self.reg_rm(InstrName::Bt, self.size(false)),
+            0xab => self.reg_rm(InstrName::Bts, self.size(false)),
+            0xb3 => self.reg_rm(InstrName::Btr, self.size(false)),
+            0xbb => self.reg_rm(InstrName::Btc, self.size(false)),
+            0xba => {
+                let size = self.size(false);
+                let modrm = self.modrm()?;
+                let name = match modrm.ext {
+                    4 => InstrName::Bt,
+                    5 => InstrName::Bts,
+                    6 => InstrName::Btr,
+                    7 => InstrName::Btc,
+                    _ => return Err(self.unsupported()),
+                };
+                let index = ImmOperand::Imm(self.byte()? as i64);
+                Ok(two!(
+                    size,
+                    instr(name, Some(index.clone().into()), Some(self.rm(&modrm.rm)))
+                ))
+            }
+            0xb0 | 0xb1 => self.reg_rm(InstrName::Cmpxchg, self.size(op == 0xb0)),
+            0xc0 | 0xc1 => self.reg_rm(InstrName::Xadd, self.size(op == 0xc0)),
+            0xc7 => {
+                let modrm = self.modrm()?;
+                if modrm.ext != 1 || self.opsize {
+                    return Err(self.unsupported());
+                }
+                let name = if self.rex_w() {
+                    InstrName::Cmpxchg16b
+                } else {
+                    InstrName::Cmpxchg8b
+                };
+                let mem = self.mem(&modrm.rm)?;
+                Ok(Decoded::Q(instr(name, Some(mem), None)))
+            }
+            0xae => {
+                let name = match self.byte()? {
+                    0xe8 => InstrName::Lfence,
+                    0xf0 => InstrName::Mfence,
+                    0xf8 => InstrName::Sfence,
+                    _ => return Err(self.unsupported()),
+                };
+                Ok(Decoded::Nullary(instr(name, None, None)))
+            }
+            0xa4 | 0xa5 | 0xac | 0xad => self.decode_double_shift(op),
+            0xbc => self.rm_reg(InstrName::Bsf, self.size(false)),
+            0xbd => self.rm_reg(InstrName::Bsr, self.size(false)),
+            0xaf => self.rm_reg(InstrName::IMul, self.size(false)),
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let name = if op < 0xb8 {
+                    InstrName::Movz
+                } else {
+                    InstrName::Movs
+                };
+                let modrm = self.modrm()?;
+                let src = &modrm.rm;
+                let dst = modrm.reg;
+                match (op & 1 == 0, self.size(false)) {
+                    (true, Sizes::Word) => Ok(Decoded::BW(self.movx(name, src, dst))),
+                    (true, Sizes::Long) => Ok(Decoded::BL(self.movx(name, src, dst))),
+                    (true, _) => Ok(Decoded::BQ(self.movx(name, src, dst))),
+                    (false, Sizes::Long) => Ok(Decoded::WL(self.movx(name, src, dst))),
+                    (false, Sizes::Quad) => Ok(Decoded::WQ(self.movx(name, src, dst))),
+                    (false, _) => Err(self.unsupported()),
+                }
+            }
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    /// SSE instructions, the precision is given by the 0x66, 0xf2 and 0xf3 prefixes
+    fn decode_sse(&mut self, op: u8) -> Result<Decoded, DecodeError> {
+        let (scalar, packed) = match (self.rep, self.opsize) {
+            (Some(0xf3), false) => (Some(Precision::Single), None),
+            (Some(_), false) => (Some(Precision::Double), None),
+            (None, false) => (None, Some(Precision::Single)),
+            (None, true) => (None, Some(Precision::Double)),
+            _ => return Err(self.unsupported()),
+        };
+        let modrm = self.modrm()?;
+        let name = match (op, scalar, packed) {
+            (0x10 | 0x11, Some(p), _) => InstrName::MovFloat(p),
+            (0x10 | 0x11, _, Some(p)) => InstrName::MovUnaligned(p),
+            (0x28 | 0x29, _, Some(p)) => InstrName::MovAligned(p),
+            (0x2e, _, Some(p)) => InstrName::Ucomis(p),
+            (0x2f, _, Some(p)) => InstrName::Comis(p),
+            (0x51, Some(p), _) => InstrName::SqrtFloat(p),
+            (0x54, _, Some(p)) => InstrName::AndFloat(p),
+            (0x57, _, Some(p)) => InstrName::XorFloat(p),
+            (0x58, Some(p), _) => InstrName::AddFloat(p),
+            (0x59, Some(p), _) => InstrName::MulFloat(p),
+            // the prefix is the one of the source
+            (0x5a, Some(Precision::Single), _) => InstrName::CvtFloat(Precision::Double),
+            (0x5a, Some(Precision::Double), _) => InstrName::CvtFloat(Precision::Single),
+            (0x5c, Some(p), _) => InstrName::SubFloat(p),
+            (0x5e, Some(p), _) => InstrName::DivFloat(p),
+            (0x2a, Some(p), _) => {
+                let name = InstrName::CvtIntToFloat(p);
+                return Ok(if self.rex_w() {
+                    Decoded::QX(self.movx(name, &modrm.rm, modrm.reg))
+                } else {
+                    Decoded::LX(self.movx(name, &modrm.rm, modrm.reg))
+                });
+            }
+            (0x2c, Some(p), _) => {
+                let name = InstrName::CvtFloatToInt(p);
+                return Ok(if self.rex_w() {
+                    Decoded::XQ(self.movx(name, &modrm.rm, modrm.reg))
+                } else {
+                    Decoded::XL(self.movx(name, &modrm.rm, modrm.reg))
+                });
+            }
+            (0x6e, _, Some(Precision::Double)) => {
+                return Ok(if self.rex_w() {
+                    Decoded::QX(self.movx(InstrName::MovqXmm, &modrm.rm, modrm.reg))
+                } else {
+                    Decoded::LX(self.movx(InstrName::MovdXmm, &modrm.rm, modrm.reg))
+                });
+            }
+            // the XMM register is in the reg field
+            (0x7e, _, Some(Precision::Double)) => {
+                let src = Some(self.reg(modrm.reg));
+                return Ok(if self.rex_w() {
+                    Decoded::XQ(instr(InstrName::MovqXmm, src, Some(self.rm(&modrm.rm))))
+                } else {
+                    Decoded::XL(instr(InstrName::MovdXmm, src, Some(self.rm(&modrm.rm))))
+                });
+            }
+            _ => return Err(self.unsupported()),
+        };
+        Ok(Decoded::XX(match op {
+            0x11 | 0x29 => instr(name, Some(self.reg(modrm.reg)), Some(self.rm(&modrm.rm))),
+            _ => instr(name, Some(self.rm(&modrm.rm)), Some(self.reg(modrm.reg))),
+        }))
+    }
+
+    /// AVX instructions, the VEX prefix gives the bits of REX, the mandatory prefix,
+    /// the opcode map and the register of the second source
+    fn decode_vex(&mut self, vex: u8) -> Result<Decoded, DecodeError> {
+        let byte = self.byte()?;
+        // R, X and B are inverted, the 2-bytes form has only R
+        let (rxb, map, last) = if vex == 0xc5 {
+            ((byte >> 5) | 0b011, 1, byte)
+        } else {
+            (byte >> 5, byte & 0x1f, self.byte()?)
+        };
+        let w = last >> 7;
+        self.rex = Some(0x40 | (w << 3) | (!rxb & 0b111));
+        let vvvv = (!last >> 3) & 0xf;
+        let ymm = last & 0b100 != 0;
+        let pp = last & 0b11;
+        let op = self.byte()?;
+        if let (2, 0xf2 | 0xf3 | 0xf5 | 0xf6 | 0xf7) | (3, 0xf0) = (map, op) {
+            return self.decode_bmi(map, pp, op, vvvv, ymm);
+        }
+        if (map, pp, op) == (1, 0, 0x77) && vvvv == 0 && !ymm {
+            return Ok(Decoded::Nullary(instr(InstrName::VZeroUpper, None, None)));
+        }
+        let float = match pp {
+            0 => Some(Precision::Single),
+            1 => Some(Precision::Double),
+            _ => None,
+        };
+        let fma = if w == 0 {
+            Precision::Single
+        } else {
+            Precision::Double
+        };
+        let sizes = [Sizes::Byte, Sizes::Word, Sizes::Long, Sizes::Quad];
+        let name = match (map, pp, op, float) {
+            (1, _, 0x58, Some(p)) => InstrName::VAdd(p),
+            (1, _, 0x59, Some(p)) => InstrName::VMul(p),
+            (1, _, 0x5c, Some(p)) => InstrName::VSub(p),
+            (1, _, 0x5e, Some(p)) => InstrName::VDiv(p),
+            (1, _, 0x57, Some(p)) => InstrName::VXor(p),
+            (2, 1, 0x98, _) => InstrName::VFmadd132(fma),
+            (2, 1, 0xa8, _) => InstrName::VFmadd213(fma),
+            (2, 1, 0xb8, _) => InstrName::VFmadd231(fma),
+            (1, 1, 0xfc..=0xfe, _) => InstrName::VPAdd(sizes[(op - 0xfc) as usize]),
+            (1, 1, 0xd4, _) => InstrName::VPAdd(Sizes::Quad),
+            (1, 1, 0xf8..=0xfb, _) => InstrName::VPSub(sizes[(op - 0xf8) as usize]),
+            (1, 1, 0x74..=0x76, _) => InstrName::VPCmpEq(sizes[(op - 0x74) as usize]),
+            (2, 1, 0x29, _) => InstrName::VPCmpEq(Sizes::Quad),
+            (1, 1, 0xdb, _) => InstrName::VPAnd,
+            (1, 1, 0xeb, _) => InstrName::VPOr,
+            (1, 1, 0xef, _) => InstrName::VPXor,
+            _ => return self.decode_vex_move(map, pp, op, float, vvvv, ymm),
+        };
+        let modrm = self.modrm()?;
+        Ok(if ymm {
+            Decoded::YY(self.vex3(name, &modrm, vvvv))
+        } else {
+            Decoded::XX(self.vex3(name, &modrm, vvvv))
+        })
+    }
+
+    /// BMI instructions on general purpose registers, the size is given by VEX.W
+    fn decode_bmi(
+        &mut self,
+        map: u8,
+        pp: u8,
+        op: u8,
+        vvvv: u8,
+        l: bool,
+    ) -> Result<Decoded, DecodeError> {
+        if l {
+            return Err(self.unsupported());
+        }
+        let size = self.size(false);
+        let modrm = self.modrm()?;
+        let name = match (map, pp, op) {
+            (2, 0, 0xf3) => {
+                let name = match modrm.ext {
+                    1 => InstrName::Blsr,
+                    3 => InstrName::Blsi,
+                    _ => return Err(self.unsupported()),
+                };
+                return Ok(two!(
+                    size,
+                    instr(name, Some(self.rm(&modrm.rm)), Some(self.reg(vvvv)))
+                ));
+            }
+            (3, 3, 0xf0) if vvvv == 0 => {
+                let imm = ImmOperand::Imm(self.byte()? as i64);
+                return Ok(two!(
+                    size,
+                    instr3(
+                        InstrName::Rorx,
+                        imm.clone().into(),
+                        self.rm(&modrm.rm),
+                        self.reg(modrm.reg)
+                    )
+                ));
+            }
+            (2, 0, 0xf2) => InstrName::Andn,
+            (2, 3, 0xf5) => InstrName::Pdep,
+            (2, 2, 0xf5) => InstrName::Pext,
+            (2, 3, 0xf6) => InstrName::Mulx,
+            // the first operand is VEX.vvvv
+            (2, _, 0xf5 | 0xf7) => {
+                let name = match (pp, op) {
+                    (0, 0xf5) => InstrName::Bzhi,
+                    (1, 0xf7) => InstrName::Shlx,
+                    (2, 0xf7) => InstrName::Sarx,
+                    (3, 0xf7) => InstrName::Shrx,
+                    _ => return Err(self.unsupported()),
+                };
+                return Ok(two!(
+                    size,
+                    instr3(
+                        name,
+                        self.reg(vvvv),
+                        self.rm(&modrm.rm),
+                        self.reg(modrm.reg)
+                    )
+                ));
+            }
+            _ => return Err(self.unsupported()),
+        };
+        Ok(two!(
+            size,
+            instr3(
+                name,
+                self.rm(&modrm.rm),
+                self.reg(vvvv),
+                self.reg(modrm.reg)
+            )
+        ))
+    }
+
+    /// AVX instructions with 2 operands
+    fn decode_vex_move(
+        &mut self,
+        map: u8,
+        pp: u8,
+        op: u8,
+        float: Option<Precision>,
+        vvvv: u8,
+        ymm: bool,
+    ) -> Result<Decoded, DecodeError> {
+        if vvvv != 0 {
+            return Err(self.unsupported());
+        }
+        let name = match (map, pp, op, float) {
+            (1, _, 0x28 | 0x29, Some(p)) => InstrName::VMovAligned(p),
+            (1, _, 0x10 | 0x11, Some(p)) => InstrName::VMovUnaligned(p),
+            (1, 1, 0x6f | 0x7f, _) => InstrName::VMovdqa,
+            (1, 2, 0x6f | 0x7f, _) => InstrName::VMovdqu,
+            (1, 1, 0xd7, _) => InstrName::VPMovMskB,
+            (2, 1, 0x18, _) => InstrName::VBroadcast(Precision::Single),
+            (2, 1, 0x19, _) if ymm => InstrName::VBroadcast(Precision::Double),
+            _ => return Err(self.unsupported()),
+        };
+        let modrm = self.modrm()?;
+        let (src, dst) = (&modrm.rm, modrm.reg);
+        Ok(match (op, ymm) {
+            (0xd7, _) if matches!(src, Rm::Mem(_)) => return Err(self.unsupported()),
+            (0xd7, true) => Decoded::YL(self.movx(name, src, dst)),
+            (0xd7, false) => Decoded::XL(self.movx(name, src, dst)),
+            (0x18 | 0x19, true) => Decoded::XY(self.movx(name, src, dst)),
+            (0x18 | 0x19, false) => Decoded::XX(self.movx(name, src, dst)),
+            // the register is stored in memory (or in the r/m register)
+            (0x11 | 0x29 | 0x7f, true) => {
+                Decoded::YY(instr(name, Some(self.reg(dst)), Some(self.rm(src))))
+            }
+            (0x11 | 0x29 | 0x7f, false) => {
+                Decoded::XX(instr(name, Some(self.reg(dst)), Some(self.rm(src))))
+            }
+            (_, true) => Decoded::YY(self.movx(name, src, dst)),
+            (_, false) => Decoded::XX(self.movx(name, src, dst)),
+        })
+    }
+
+    /// AVX instruction with 3 operands: MODRM.rm, VEX.vvvv and MODRM.reg
+    fn vex3<R: DecodeReg>(
+        &mut self,
+        name: InstrName,
+        modrm: &ModRm,
+        vvvv: u8,
+    ) -> Instruction<R, R> {
+        let src = self.reg(vvvv);
+        let dst = self.reg(modrm.reg);
+        instr3(name, self.rm(&modrm.rm), src, dst)
+    }
+
+    /// Shl, Shr and Sar by 1, an immediate or %cl
+    fn decode_shift(&mut self, op: u8) -> Result<Decoded, DecodeError> {
+        let size = self.size(op & 1 == 0);
+        let modrm = self.modrm()?;
+        let cl = op & 0xfe == 0xd2;
+        let name = match (modrm.ext, cl) {
+            (0, false) => InstrName::Rol,
+            (1, false) => InstrName::Ror,
+            (2, false) => InstrName::Rcl,
+            (3, false) => InstrName::Rcr,
+            (4, false) => InstrName::Shl,
+            (5, false) => InstrName::Shr,
+            (7, false) => InstrName::Sar,
+            (0, true) => InstrName::RolC,
+            (1, true) => InstrName::RorC,
+            (2, true) => InstrName::RclC,
+            (3, true) => InstrName::RcrC,
+            (4, true) => InstrName::ShlC,
+            (5, true) => InstrName::ShrC,
+            (7, true) => InstrName::SarC,
+            _ => return Err(self.unsupported()),
+        };
+        let count = match op {
+            0xd0 | 0xd1 => 1,
+            0xc0 | 0xc1 => self.byte()? as i64,
+            _ => {
+                let cl = Some(Operand::Reg(RegB::Cl));
+                let rm = &modrm.rm;
+                return Ok(match size {
+                    Sizes::Byte => Decoded::BB(instr(name, cl, Some(self.rm(rm)))),
+                    Sizes::Word => Decoded::BW(instr(name, cl, Some(self.rm(rm)))),
+                    Sizes::Long => Decoded::BL(instr(name, cl, Some(self.rm(rm)))),
+                    _ => Decoded::BQ(instr(name, cl, Some(self.rm(rm)))),
+                });
+            }
+        };
+        Ok(two!(
+            size,
+            instr(name, Some(Operand::Imm(count)), Some(self.rm(&modrm.rm)))
+        ))
+    }
+
+    /// shld and shrd, the count is an immediate or %cl
+    fn decode_double_shift(&mut self, op: u8) -> Result<Decoded, DecodeError> {
+        let size = self.size(false);
+        let modrm = self.modrm()?;
+        let (src, dst) = (modrm.reg, &modrm.rm);
+        if op & 1 == 0 {
+            let name = if op == 0xa4 {
+                InstrName::Shld
+            } else {
+                InstrName::Shrd
+            };
+            let count = self.byte()? as i64;
+            return Ok(two!(
+                size,
+                instr3(name, Operand::Imm(count), self.reg(src), self.rm(dst))
+            ));
+        }
+        let name = if op == 0xa5 {
+            InstrName::ShldC
+        } else {
+            InstrName::ShrdC
+        };
+        let cl = Operand::Reg(RegB::Cl);
+        Ok(match size {
+            Sizes::Word => Decoded::BW(instr3(name, cl, self.reg(src), self.rm(dst))),
+            Sizes::Long => Decoded::BL(instr3(name, cl, self.reg(src), self.rm(dst))),
+            _ => Decoded::BQ(instr3(name, cl, self.reg(src), self.rm(dst))),
+        })
+    }
+
+    /// Instruction from the reg field to the r/m field
+    fn reg_rm(&mut self, name: InstrName, size: Sizes) -> Result<Decoded, DecodeError> {
+        let modrm = self.modrm()?;
+        Ok(two!(
+            size,
+            instr(name, Some(self.reg(modrm.reg)), Some(self.rm(&modrm.rm)))
+        ))
+    }
+
+    /// Instruction from the r/m field to the reg field
+    fn rm_reg(&mut self, name: InstrName, size: Sizes) -> Result<Decoded, DecodeError> {
+        let modrm = self.modrm()?;
+        Ok(two!(
+            size,
+            instr(name, Some(self.rm(&modrm.rm)), Some(self.reg(modrm.reg)))
+        ))
+    }
+
+    fn movx<S: DecodeReg, D: DecodeReg>(
+        &mut self,
+        name: InstrName,
+        src: &Rm,
+        dst: u8,
+    ) -> Instruction<S, D> {
+        instr(name, Some(self.rm(src)), Some(self.reg(dst)))
+    }
+}
+
+/// Immediate operand, decoded before the type of the registers is known
+#[derive(Clone)]
+enum ImmOperand {
+    Imm(i64),
+    Label(Label),
+}
+
+impl<R: Reg> From<ImmOperand> for Operand<R> {
+    fn from(imm: ImmOperand) -> Self {
+        match imm {
+            ImmOperand::Imm(imm) => Operand::Imm(imm),
+            ImmOperand::Label(label) => Operand::LabVal(label),
+        }
+    }
+}
+
+/// Decode the instruction at `offset` in `bytes`, returns it with its length
+fn decode_at(
+    bytes: &[u8],
+    fixups: &HashMap<usize, &Fixup>,
+    offset: usize,
+    targets: &mut Vec<usize>,
+) -> Result<(Decoded, usize), DecodeError> {
+    let mut cursor = Cursor {
+        bytes,
+        fixups,
+        start: offset,
+        pos: offset,
+        rex: None,
+        opsize: false,
+        rep: None,
+        targets: Vec::new(),
+    };
+    let decoded = cursor.decode()?;
+    targets.append(&mut cursor.targets);
+    Ok((decoded, cursor.pos - offset))
+}
+
+fn fixups_by_offset(code: &MachineCode) -> HashMap<usize, &Fixup> {
+    code.fixups
+        .iter()
+        .map(|fixup| (fixup.offset, fixup))
+        .collect()
+}
+
+/// Decode the instruction at `offset` in `code`, returns it with its length
+///
+/// Labels of the fixups are used for the operands they patch, other jumps and
+/// %rip relative addresses use labels named after their target (`.L12` for the offset 12)
+pub fn decode_instr(code: &MachineCode, offset: usize) -> Result<(Decoded, usize), DecodeError> {
+    decode_at(
+        &code.bytes,
+        &fixups_by_offset(code),
+        offset,
+        &mut Vec::new(),
+    )
+}
+
+/// Decode all the instructions of `code` with their offset
+///
+/// ```
+/// use write_x86_64::*;
+/// use write_x86_64::instr::assembler::assemble;
+/// use write_x86_64::instr::decode::decode;
+///
+/// let text = movq(immq(-42), reg!(RAX)) + call(new_label("f")) + ret();
+/// let code = assemble(&text).unwrap().code;
+/// let decoded = decode(&code).unwrap();
+/// assert_eq!(decoded[0].1.to_string(), "movq $-42, %rax");
+/// assert_eq!(decoded[1].1.to_string(), "call f");
+/// assert_eq!(decoded[2].0, 12);
+/// ```
+pub fn decode(code: &MachineCode) -> Result<Vec<(usize, Decoded)>, DecodeError> {
+    let fixups = fixups_by_offset(code);
+    let mut instrs = Vec::new();
+    let mut offset = 0;
+    while offset < code.bytes.len() {
+        let (decoded, len) = decode_at(&code.bytes, &fixups, offset, &mut Vec::new())?;
+        instrs.push((offset, decoded));
+        offset += len;
+    }
+    Ok(instrs)
+}
+
+/// Disassemble machine code in a text segment
+///
+/// Targets of jumps and %rip relative addresses are labelled `.L<offset>`, so the
+/// segment can be assembled again.
+pub fn disassemble(bytes: &[u8]) -> Result<Text, DecodeError> {
+    let fixups = HashMap::new();
+    let mut targets = Vec::new();
+    let mut instrs = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let (decoded, len) = decode_at(bytes, &fixups, offset, &mut targets)?;
+        instrs.push((offset, decoded));
+        offset += len;
+    }
+    let mut text = Text::empty();
+    for (offset, decoded) in instrs {
+        if targets.contains(&offset) {
+            text += Segment::label(Label::from_str(format!(".L{}", offset)));
+        }
+        text += decoded.into_text();
+    }
+    Ok(text)
+}
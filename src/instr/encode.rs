@@ -0,0 +1,1671 @@
+use std::io::Write;
+
+use super::{InstrName, Instruction, Precision, Prefix};
+use crate::reg::{Label, Operand, RegInv, RegQ, Sizes};
+use crate::target::Os;
+use crate::traits::{Reg, Writable};
+use crate::writer::{Syntax, Writer};
+
+/// How the value of a [`Fixup`] is computed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FixupKind {
+    /// 32-bits displacement relative to the fixup (`label + addend - .`)
+    Rel32,
+    /// Same as `Rel32` for calls, the label can be a function of a shared library
+    Plt32,
+    /// 32-bits absolute address (`label + addend`)
+    Abs32,
+    /// 32-bits absolute address sign extended to 64 bits (`label + addend`)
+    Abs32S,
+    /// 64-bits absolute address (`label + addend`) of `movabs`
+    Abs64,
+    /// 8-bits displacement relative to the fixup (`label + addend - .`) of short jumps,
+    /// `jrcxz` and `loop`
+    Rel8,
+}
+
+impl FixupKind {
+    /// Number of bytes patched
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Rel8 => 1,
+            Self::Rel32 | Self::Plt32 | Self::Abs32 | Self::Abs32S => 4,
+            Self::Abs64 => 8,
+        }
+    }
+}
+
+/// Bytes of machine code depending on the address of a label
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fixup {
+    /// Position of the bytes to patch
+    pub offset: usize,
+    /// Label referred to
+    pub label: Label,
+    /// Constant added to the address of the label
+    pub addend: i64,
+    /// How the value is computed
+    pub kind: FixupKind,
+}
+
+/// Machine code of an instruction
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MachineCode {
+    /// Encoded bytes, bytes of fixups are set to 0
+    pub bytes: Vec<u8>,
+    /// Labels to resolve
+    pub fixups: Vec<Fixup>,
+}
+
+impl MachineCode {
+    /// Insert a prefix byte at `pos`, the fixups after it are moved
+    fn insert_prefix(&mut self, pos: usize, prefix: u8) {
+        self.bytes.insert(pos, prefix);
+        for fixup in &mut self.fixups {
+            fixup.offset += 1;
+        }
+    }
+
+    fn push_fixup(&mut self, label: Label, kind: FixupKind, addend: i64) {
+        self.fixups.push(Fixup {
+            offset: self.bytes.len(),
+            label,
+            addend,
+            kind,
+        });
+        self.bytes.resize(self.bytes.len() + kind.size(), 0);
+    }
+
+    fn write_bytes(
+        &self,
+        file: &mut Writer,
+        bytes: &[u8],
+        first: &mut bool,
+    ) -> std::io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        new_line(file, first)?;
+        file.write_all(match file.syntax() {
+            Syntax::Att | Syntax::Intel => b".byte ",
+            Syntax::Nasm => b"db ",
+        })?;
+        for (i, b) in bytes.iter().enumerate() {
+            if i > 0 {
+                file.write_all(b", ")?;
+            }
+            file.write_all(format!("0x{b:02x}").as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn new_line(file: &mut Writer, first: &mut bool) -> std::io::Result<()> {
+    if !*first {
+        file.write_all(b"\n\t")?;
+    }
+    *first = false;
+    Ok(())
+}
+
+/// Machine code is written as `.byte` directives, labels as `.long` expressions
+/// (or `.reloc` for calls on ELF to allow calling shared libraries)
+impl Writable for MachineCode {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        let mut first = true;
+        let mut pos = 0;
+        for fixup in &self.fixups {
+            self.write_bytes(file, &self.bytes[pos..fixup.offset], &mut first)?;
+            new_line(file, &mut first)?;
+            fixup.write_with(file)?;
+            pos = fixup.offset + fixup.kind.size();
+        }
+        self.write_bytes(file, &self.bytes[pos..], &mut first)
+    }
+}
+
+impl Writable for Fixup {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        let nasm = file.syntax() == Syntax::Nasm;
+        let elf_reloc = match self.kind {
+            FixupKind::Plt32 => Some("R_X86_64_PLT32"),
+            FixupKind::Abs32S => Some("R_X86_64_32S"),
+            _ => None,
+        };
+        if let Some(reloc) = elf_reloc.filter(|_| !nasm && file.target().os == Os::Linux) {
+            file.write_all(format!(".reloc ., {}, ", reloc).as_bytes())?;
+            self.label.write_with(file)?;
+            return file.write_all(format!("{:+}\n\t.long 0", self.addend).as_bytes());
+        }
+        file.write_all(match (self.kind, nasm) {
+            (FixupKind::Rel8, false) => b".byte ",
+            (FixupKind::Rel8, true) => b"db ",
+            (FixupKind::Abs64, false) => b".quad ",
+            (FixupKind::Abs64, true) => b"dq ",
+            (_, false) => b".long ",
+            (_, true) => b"dd ",
+        })?;
+        self.label.write_with(file)?;
+        match self.kind {
+            FixupKind::Rel32 | FixupKind::Plt32 | FixupKind::Rel8 => {
+                let here = if nasm { "$" } else { "." };
+                file.write_all(format!("{:+}-{}", self.addend, here).as_bytes())
+            }
+            FixupKind::Abs32 | FixupKind::Abs32S | FixupKind::Abs64 if self.addend == 0 => Ok(()),
+            FixupKind::Abs32 | FixupKind::Abs32S | FixupKind::Abs64 => {
+                file.write_all(format!("{:+}", self.addend).as_bytes())
+            }
+        }
+    }
+}
+
+/// Error returned when an instruction has no machine code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodeError {
+    message: String,
+}
+
+impl EncodeError {
+    pub(crate) fn new(message: String) -> Self {
+        Self { message }
+    }
+
+    /// Reason of the error
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cannot encode instruction: {}", self.message)
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl From<EncodeError> for std::io::Error {
+    fn from(err: EncodeError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// Choice between the encodings of an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Shortest encoding for the values of immediates and displacements, like GNU as with `-O`
+    /// (plain as keeps the 7-bytes `movq $imm, %reg` where this uses the 5-bytes `movl`)
+    Shortest,
+    /// Encoding whose size does not depend on the values of immediates and displacements
+    /// (32-bits immediates and displacements, 64-bits immediates for moves to 8-bytes registers)
+    /// so that they can be patched
+    Fixed,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::Shortest
+    }
+}
+
+struct Rex {
+    w: bool,
+    r: bool,
+    x: bool,
+    b: bool,
+}
+
+impl Rex {
+    fn new() -> Self {
+        Self {
+            w: false,
+            // extends MODRM.reg
+            r: false,
+            // extends SIB.index
+            x: false,
+            // extends MODRM.rm or the SIB.base
+            b: false,
+        }
+    }
+
+    fn needed(&self) -> bool {
+        self.w || self.r || self.x || self.b
+    }
+
+    fn as_byte(&self) -> u8 {
+        let mut out = 0x40;
+        if self.w {
+            out |= 0x08;
+        }
+        if self.r {
+            out |= 0x4;
+        }
+        if self.x {
+            out |= 0x2;
+        }
+        if self.b {
+            out |= 0x1;
+        }
+        out
+    }
+}
+
+enum Imm {
+    Empty,
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    /// Absolute address of label on 32 bits
+    Lab32(Label),
+    /// Absolute address of label on 32 bits sign extended to 64 bits
+    Lab32S(Label),
+    /// Absolute address of label on 64 bits
+    Lab64(Label),
+}
+
+impl Imm {
+    fn len(&self) -> i64 {
+        match self {
+            Self::Empty => 0,
+            Self::I8(_) => 1,
+            Self::I16(_) => 2,
+            Self::I32(_) | Self::Lab32(_) | Self::Lab32S(_) => 4,
+            Self::I64(_) | Self::Lab64(_) => 8,
+        }
+    }
+
+    fn push_in(&self, code: &mut MachineCode) {
+        match self {
+            Self::Empty => (),
+            Self::I8(i) => code.bytes.push(*i as u8),
+            Self::I16(i) => code.bytes.extend_from_slice(&i.to_le_bytes()),
+            Self::I32(i) => code.bytes.extend_from_slice(&i.to_le_bytes()),
+            Self::I64(i) => code.bytes.extend_from_slice(&i.to_le_bytes()),
+            Self::Lab32(label) => code.push_fixup(label.clone(), FixupKind::Abs32, 0),
+            Self::Lab32S(label) => code.push_fixup(label.clone(), FixupKind::Abs32S, 0),
+            Self::Lab64(label) => code.push_fixup(label.clone(), FixupKind::Abs64, 0),
+        }
+    }
+}
+
+/// Register or memory operand (ModRM.rm)
+enum Rm<R> {
+    Reg(R),
+    /// Offset, base and index with scale
+    Addr(i64, Option<RegQ>, Option<(RegQ, u8)>),
+    RipRel(Label),
+    Abs(Label),
+}
+
+impl<R: Reg> Rm<R> {
+    fn new(op: &Operand<R>) -> Result<Self, EncodeError> {
+        match op {
+            Operand::Reg(reg) => Ok(Self::Reg(reg.clone())),
+            Operand::Addr(offset, base, index, scale) => Ok(Self::Addr(
+                *offset,
+                Some(*base),
+                index.map(|index| (index, *scale)),
+            )),
+            Operand::Index(offset, index, scale) => {
+                Ok(Self::Addr(*offset, None, Some((*index, *scale))))
+            }
+            Operand::LabRelAddr(label) => Ok(Self::RipRel(label.clone())),
+            Operand::LabAbsAddr(label) => Ok(Self::Abs(label.clone())),
+            Operand::Imm(_) | Operand::LabVal(_) => Err(EncodeError::new(format!(
+                "{:?} is not a register nor a memory operand",
+                op
+            ))),
+        }
+    }
+
+    fn reg(&self) -> Option<&R> {
+        match self {
+            Self::Reg(reg) => Some(reg),
+            _ => None,
+        }
+    }
+}
+
+/// Displacement of a memory operand
+enum Disp {
+    Zero,
+    I8(i8),
+    I32(i32),
+}
+
+impl Disp {
+    /// `base_bits` are the low bits of the base register (0b101 for %rbp and %r13 which always have a displacement)
+    fn new(offset: i64, base_bits: u8, encoding: Encoding) -> Result<Self, EncodeError> {
+        if encoding == Encoding::Fixed {
+            Ok(Self::I32(disp32(offset)?))
+        } else if offset == 0 && base_bits != 0b101 {
+            Ok(Self::Zero)
+        } else if fits_i8(offset) {
+            Ok(Self::I8(offset as i8))
+        } else {
+            Ok(Self::I32(disp32(offset)?))
+        }
+    }
+
+    /// MODRM.mod of the displacement
+    fn mode(&self) -> u8 {
+        match self {
+            Self::Zero => 0b00_000_000,
+            Self::I8(_) => 0b01_000_000,
+            Self::I32(_) => 0b10_000_000,
+        }
+    }
+
+    fn push_in(&self, vec: &mut Vec<u8>) {
+        match self {
+            Self::Zero => (),
+            Self::I8(d) => vec.push(*d as u8),
+            Self::I32(d) => vec.extend_from_slice(&d.to_le_bytes()),
+        }
+    }
+}
+
+fn disp32(offset: i64) -> Result<i32, EncodeError> {
+    i32::try_from(offset)
+        .map_err(|_| EncodeError::new(format!("displacement {} does not fit in 32 bits", offset)))
+}
+
+/// Index register and scale of a SIB byte
+fn sib_index((index, scale): (RegQ, u8)) -> Result<(bool, u8, u8), EncodeError> {
+    let scale = match scale {
+        1 => 0,
+        2 => 1,
+        4 => 2,
+        8 => 3,
+        _ => return Err(EncodeError::new(format!("invalid scale {}", scale))),
+    };
+    if index == RegQ::Rsp {
+        return Err(EncodeError::new("%rsp cannot be an index".to_string()));
+    }
+    let (x, index) = index.to_bits();
+    Ok((x, index, scale))
+}
+
+/// Checks the use of %ah, %bh, %ch and %dh (no REX prefix) and %sil, %dil, %spl and %bpl (REX prefix)
+fn check_rex(rex: &Rex, requires: bool, forbids: bool) -> Result<bool, EncodeError> {
+    if forbids && (requires || rex.needed()) {
+        Err(EncodeError::new(
+            "%ah, %bh, %ch and %dh cannot be used in an instruction requiring a REX prefix"
+                .to_string(),
+        ))
+    } else {
+        Ok(requires || rex.needed())
+    }
+}
+
+struct ByteCode<R1: Reg = RegInv, R2: Reg = RegInv> {
+    small_reg_flag: bool,
+    // mandatory prefix of SSE instructions (0x66, 0xf2 or 0xf3), written before REX
+    sse_prefix: Option<u8>,
+    // replaces the prefixes and REX for AVX instructions
+    vex: Option<Vex>,
+    prefix: Option<u8>,
+    op_code: u8,
+    rex: Rex,
+    reg: Option<R1>,
+    // opcode extension stored in MODRM.reg when there is no register
+    ext: u8,
+    rm: Rm<R2>,
+    imm: Imm,
+}
+
+impl<R2: Reg> ByteCode<RegInv, R2> {
+    fn only_rm(op_code: u8, ext: u8, rm: &Operand<R2>) -> Result<Self, EncodeError> {
+        Ok(Self {
+            small_reg_flag: false,
+            sse_prefix: None,
+            vex: None,
+            prefix: None,
+            op_code,
+            rex: Rex::new(),
+            reg: None,
+            ext,
+            rm: Rm::new(rm)?,
+            imm: Imm::Empty,
+        })
+    }
+}
+
+impl<R1: Reg, R2: Reg> ByteCode<R1, R2> {
+    fn new(op_code: u8, reg: R1, rm: &Operand<R2>) -> Result<Self, EncodeError> {
+        Ok(Self {
+            small_reg_flag: false,
+            sse_prefix: None,
+            vex: None,
+            prefix: None,
+            op_code,
+            rex: Rex::new(),
+            reg: Some(reg),
+            ext: 0,
+            rm: Rm::new(rm)?,
+            imm: Imm::Empty,
+        })
+    }
+
+    /// Set operand size (0x66 prefix for 2 bytes, REX.W for 8 bytes)
+    fn sized(mut self, size: Sizes) -> Self {
+        self.small_reg_flag = size == Sizes::Word;
+        self.rex.w = size == Sizes::Quad;
+        self
+    }
+
+    fn with_prefix(mut self, prefix: u8) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    fn with_imm(mut self, imm: Imm) -> Self {
+        self.imm = imm;
+        self
+    }
+
+    fn encode(mut self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let (r, reg) = match &self.reg {
+            None => (false, self.ext),
+            Some(reg) => reg.to_bits(),
+        };
+        self.rex.r = r;
+        // MODRM, SIB and displacement
+        let mut vec = Vec::new();
+        let mut disp_label = None;
+        match &self.rm {
+            Rm::Reg(rm) => {
+                let (b, base) = rm.to_bits();
+                self.rex.b = b;
+                vec.push(0b11_000_000 | (reg << 3) | base)
+            }
+            Rm::Addr(offset, Some(base), None) if base.to_bits().1 != 0b100 => {
+                let (b, base) = base.to_bits();
+                self.rex.b = b;
+                let disp = Disp::new(*offset, base, encoding)?;
+                vec.push(disp.mode() | (reg << 3) | base);
+                disp.push_in(&mut vec);
+            }
+            Rm::Addr(offset, Some(base), index) => {
+                // %rsp and %r12 as base or an index require a SIB byte
+                let (x, index, scale) = match index {
+                    None => (false, 0b100, 0),
+                    Some(index) => sib_index(*index)?,
+                };
+                let (b, base) = base.to_bits();
+                self.rex.x = x;
+                self.rex.b = b;
+                let disp = Disp::new(*offset, base, encoding)?;
+                vec.push(disp.mode() | (reg << 3) | 0b100);
+                vec.push((scale << 6) | (index << 3) | base);
+                disp.push_in(&mut vec);
+            }
+            Rm::Addr(offset, None, index) => {
+                // without base the displacement is always on 32 bits
+                let (x, index, scale) = match index {
+                    None => (false, 0b100, 0),
+                    Some(index) => sib_index(*index)?,
+                };
+                self.rex.x = x;
+                vec.push((reg << 3) | 0b100);
+                vec.push((scale << 6) | (index << 3) | 0b101);
+                vec.extend_from_slice(&disp32(*offset)?.to_le_bytes());
+            }
+            Rm::RipRel(label) => {
+                vec.push((reg << 3) | 0b101);
+                disp_label = Some((label, FixupKind::Rel32));
+            }
+            Rm::Abs(label) => {
+                vec.push((reg << 3) | 0b100);
+                vec.push(0b00_100_101);
+                disp_label = Some((label, FixupKind::Abs32S));
+            }
+        }
+        let requires = self.reg.as_ref().map_or(false, Reg::requires_rex)
+            || self.rm.reg().map_or(false, Reg::requires_rex);
+        let forbids = self.reg.as_ref().map_or(false, Reg::forbids_rex)
+            || self.rm.reg().map_or(false, Reg::forbids_rex);
+        let mut code = MachineCode::default();
+        if let Some(vex) = &self.vex {
+            vex.push_in(&self.rex, &mut code.bytes);
+        } else {
+            if self.small_reg_flag {
+                code.bytes.push(0x66)
+            }
+            if let Some(pref) = self.sse_prefix {
+                code.bytes.push(pref)
+            }
+            if check_rex(&self.rex, requires, forbids)? {
+                code.bytes.push(self.rex.as_byte());
+            }
+            if let Some(pref) = self.prefix {
+                code.bytes.push(pref)
+            }
+        }
+        code.bytes.push(self.op_code);
+        code.bytes.append(&mut vec);
+        if let Some((label, kind)) = disp_label {
+            // RIP points after the immediate
+            let addend = match kind {
+                FixupKind::Abs32S => 0,
+                _ => -4 - self.imm.len(),
+            };
+            code.push_fixup(label.clone(), kind, addend);
+        }
+        self.imm.push_in(&mut code);
+        Ok(code)
+    }
+}
+
+/// Encoding with the register in the opcode (like `push %rax` = 0x50 + rax)
+fn plus_reg<R: Reg>(
+    size: Sizes,
+    op_code: u8,
+    reg: &R,
+    imm: Imm,
+) -> Result<MachineCode, EncodeError> {
+    let (b, bits) = reg.to_bits();
+    let mut code = MachineCode::default();
+    let mut rex = Rex::new();
+    rex.w = size == Sizes::Quad;
+    rex.b = b;
+    if size == Sizes::Word {
+        code.bytes.push(0x66);
+    }
+    if check_rex(&rex, reg.requires_rex(), reg.forbids_rex())? {
+        code.bytes.push(rex.as_byte());
+    }
+    code.bytes.push(op_code + bits);
+    imm.push_in(&mut code);
+    Ok(code)
+}
+
+/// SSE encoding after 0x0f with its mandatory prefix, `size` is the size of the integer operand
+fn sse<R1: Reg, R2: Reg>(
+    code: ByteCode<R1, R2>,
+    prefix: Option<u8>,
+    size: Sizes,
+    encoding: Encoding,
+) -> Result<MachineCode, EncodeError> {
+    let mut code = code.with_prefix(0x0f).sized(size);
+    code.sse_prefix = prefix;
+    code.encode(encoding)
+}
+
+/// VEX prefix of AVX instructions, it contains the bits of REX, the mandatory prefix
+/// and the escape bytes of the opcode
+struct Vex {
+    // 1 for 0x0f, 2 for 0x0f 0x38 and 3 for 0x0f 0x3a
+    map: u8,
+    // mandatory prefix: 0 for none, 1 for 0x66, 2 for 0xf3 and 3 for 0xf2
+    pp: u8,
+    // 32 bytes registers
+    l: bool,
+    // register of the second source, 0 if unused
+    vvvv: u8,
+}
+
+impl Vex {
+    fn new(map: u8, pp: u8, l: bool) -> Self {
+        Self {
+            map,
+            pp,
+            l,
+            vvvv: 0,
+        }
+    }
+
+    fn push_in(&self, rex: &Rex, bytes: &mut Vec<u8>) {
+        // register numbers and the extension bits are inverted
+        let last = ((!self.vvvv & 0xf) << 3) | ((self.l as u8) << 2) | self.pp;
+        if self.map == 1 && !rex.w && !rex.x && !rex.b {
+            bytes.push(0xc5);
+            bytes.push(((!rex.r as u8) << 7) | last);
+        } else {
+            bytes.push(0xc4);
+            bytes.push(
+                ((!rex.r as u8) << 7) | ((!rex.x as u8) << 6) | ((!rex.b as u8) << 5) | self.map,
+            );
+            bytes.push(((rex.w as u8) << 7) | last);
+        }
+    }
+}
+
+fn vex<R1: Reg, R2: Reg>(
+    mut code: ByteCode<R1, R2>,
+    vex: Vex,
+    w: bool,
+    encoding: Encoding,
+) -> Result<MachineCode, EncodeError> {
+    code.rex.w = w;
+    code.vex = Some(vex);
+    code.encode(encoding)
+}
+
+/// Register number on 4 bits
+fn reg_num<R: Reg>(reg: &R) -> u8 {
+    let (ext, num) = reg.to_bits();
+    ((ext as u8) << 3) | num
+}
+
+/// Encoding with only an opcode and an immediate (like `add $1000, %eax` = 0x05 imm32)
+fn op_imm(size: Sizes, op_code: u8, imm: Imm) -> MachineCode {
+    let mut code = MachineCode::default();
+    if size == Sizes::Word {
+        code.bytes.push(0x66);
+    }
+    if size == Sizes::Quad {
+        let mut rex = Rex::new();
+        rex.w = true;
+        code.bytes.push(rex.as_byte());
+    }
+    code.bytes.push(op_code);
+    imm.push_in(&mut code);
+    code
+}
+
+/// Encoding without operand
+fn only_op(op_codes: &[u8]) -> MachineCode {
+    MachineCode {
+        bytes: op_codes.to_vec(),
+        fixups: Vec::new(),
+    }
+}
+
+/// Encoding followed by a 32-bits displacement to a label
+fn with_label(op_codes: &[u8], label: &Label, kind: FixupKind) -> MachineCode {
+    let mut code = only_op(op_codes);
+    code.push_fixup(label.clone(), kind, -(kind.size() as i64));
+    code
+}
+
+pub(crate) fn fits_i8(imm: i64) -> bool {
+    i8::MIN as i64 <= imm && imm <= i8::MAX as i64
+}
+
+fn fits_i32(imm: i64) -> bool {
+    i32::MIN as i64 <= imm && imm <= i32::MAX as i64
+}
+
+/// Immediate of the size of the operation (4 bytes sign extended for 8 bytes operations)
+fn imm_of_size(size: Sizes, imm: i64) -> Result<Imm, EncodeError> {
+    match size {
+        Sizes::Byte if i8::MIN as i64 <= imm && imm <= u8::MAX as i64 => Ok(Imm::I8(imm as i8)),
+        Sizes::Word if i16::MIN as i64 <= imm && imm <= u16::MAX as i64 => Ok(Imm::I16(imm as i16)),
+        Sizes::Long if i32::MIN as i64 <= imm && imm <= u32::MAX as i64 => Ok(Imm::I32(imm as i32)),
+        Sizes::Quad if fits_i32(imm) => Ok(Imm::I32(imm as i32)),
+        _ => Err(EncodeError::new(format!(
+            "immediate {} does not fit in a {:?} operation",
+            imm, size
+        ))),
+    }
+}
+
+fn is_accumulator<R: Reg>(op: &Operand<R>) -> bool {
+    matches!(op, Operand::Reg(reg) if reg.to_bits() == (false, 0))
+}
+
+impl<R1: Reg, R2: Reg> Instruction<R1, R2> {
+    fn invalid(&self) -> EncodeError {
+        let nb_args = self.instr.nb_args();
+        let mut buffer = Vec::new();
+        if (nb_args < 1 || self.reg1.is_some())
+            && (nb_args < 2 || self.reg2.is_some())
+            && (nb_args < 3 || self.reg3.is_some())
+            && self.att_writer(&mut Writer::new(&mut buffer)).is_ok()
+        {
+            EncodeError::new(format!(
+                "`{}` has no encoding",
+                String::from_utf8_lossy(&buffer)
+            ))
+        } else {
+            EncodeError::new(format!("{:?} has invalid operands", self.instr))
+        }
+    }
+
+    fn operands(&self) -> Result<(&Operand<R1>, &Operand<R2>), EncodeError> {
+        match (&self.reg1, &self.reg2) {
+            (Some(op1), Some(op2)) => Ok((op1, op2)),
+            _ => Err(EncodeError::new(format!(
+                "{:?} expects 2 operands",
+                self.instr
+            ))),
+        }
+    }
+
+    fn operand(&self) -> Result<&Operand<R1>, EncodeError> {
+        match &self.reg1 {
+            Some(op) => Ok(op),
+            None => Err(EncodeError::new(format!(
+                "{:?} expects an operand",
+                self.instr
+            ))),
+        }
+    }
+
+    /// Size of operands, both operands must have the same size
+    fn same_size(&self) -> Result<Sizes, EncodeError> {
+        if R1::SIZE == R2::SIZE {
+            Ok(R1::SIZE)
+        } else {
+            Err(self.invalid())
+        }
+    }
+
+    /// Immediate (or label address) of an operation of the given size
+    fn imm(&self, op: &Operand<R1>, size: Sizes) -> Result<Imm, EncodeError> {
+        match op {
+            Operand::Imm(imm) => imm_of_size(size, *imm),
+            Operand::LabVal(label) if size == Sizes::Long => Ok(Imm::Lab32(label.clone())),
+            Operand::LabVal(label) if size == Sizes::Quad => Ok(Imm::Lab32S(label.clone())),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Encode instruction in machine code, the size of immediates and displacements depends on `encoding`
+    pub(crate) fn to_bin(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        match self.lock_rep {
+            Some(prefix) if !self.instr.accepts(prefix) => return Err(self.invalid()),
+            Some(Prefix::Lock) if !self.memory_destination() => return Err(self.invalid()),
+            _ => (),
+        }
+        if self.segment.is_some() && !self.accepts_segment() {
+            return Err(self.invalid());
+        }
+        let mut code = self.encode_instr(encoding)?;
+        // like GNU as, the segment override comes first and the lock or repeat prefix
+        // after the operand size prefix (`lock addw %ax, %fs:(%rbx)` = 64 66 f0 01 03)
+        if let Some(prefix) = self.lock_rep {
+            let pos = usize::from(code.bytes.first() == Some(&0x66));
+            code.insert_prefix(pos, prefix.code());
+        }
+        if let Some(segment) = self.segment {
+            code.insert_prefix(0, segment.code());
+        }
+        Ok(code)
+    }
+
+    fn encode_instr(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        match &self.instr {
+            InstrName::Move => self.encode_mov(encoding),
+            InstrName::Movabs => self.encode_movabs(),
+            InstrName::Add => self.encode_arith(0, encoding),
+            InstrName::Or => self.encode_arith(1, encoding),
+            InstrName::Adc => self.encode_arith(2, encoding),
+            InstrName::Sbb => self.encode_arith(3, encoding),
+            InstrName::And => self.encode_arith(4, encoding),
+            InstrName::Sub => self.encode_arith(5, encoding),
+            InstrName::Xor => self.encode_arith(6, encoding),
+            InstrName::Cmp => self.encode_arith(7, encoding),
+            InstrName::Shl | InstrName::ShlC => self.encode_shift(4, encoding),
+            InstrName::Shr | InstrName::ShrC => self.encode_shift(5, encoding),
+            InstrName::Sar | InstrName::SarC => self.encode_shift(7, encoding),
+            InstrName::Rol | InstrName::RolC => self.encode_shift(0, encoding),
+            InstrName::Ror | InstrName::RorC => self.encode_shift(1, encoding),
+            InstrName::Rcl | InstrName::RclC => self.encode_shift(2, encoding),
+            InstrName::Rcr | InstrName::RcrC => self.encode_shift(3, encoding),
+            InstrName::Shld | InstrName::ShldC => self.encode_double_shift(0xa4, encoding),
+            InstrName::Shrd | InstrName::ShrdC => self.encode_double_shift(0xac, encoding),
+            InstrName::Test => self.encode_test(encoding),
+            InstrName::Lea => {
+                let (src, dst) = self.operands()?;
+                match dst {
+                    Operand::Reg(reg) if src.is_memory() && R2::SIZE != Sizes::Byte => {
+                        ByteCode::new(0x8d, reg.clone(), src)?
+                            .sized(R2::SIZE)
+                            .encode(encoding)
+                    }
+                    _ => Err(self.invalid()),
+                }
+            }
+            InstrName::IMul => self.encode_imul(encoding),
+            InstrName::IMulImm => match (self.operands()?, &self.reg3) {
+                ((Operand::Imm(imm), rm), Some(Operand::Reg(reg))) => {
+                    self.encode_imul_imm(*imm, rm, reg, encoding)
+                }
+                _ => Err(self.invalid()),
+            },
+            InstrName::Movs | InstrName::Movz => self.encode_movx(encoding),
+            InstrName::Inc | InstrName::Dec => {
+                let op = self.operand()?;
+                let ext = if matches!(self.instr, InstrName::Inc) {
+                    0
+                } else {
+                    1
+                };
+                let op_code = if R1::SIZE == Sizes::Byte { 0xfe } else { 0xff };
+                ByteCode::only_rm(op_code, ext, op)?
+                    .sized(R1::SIZE)
+                    .encode(encoding)
+            }
+            InstrName::Not
+            | InstrName::Neg
+            | InstrName::UnsignedMul
+            | InstrName::SignedMul
+            | InstrName::UnsignedDiv
+            | InstrName::SignedDiv => {
+                let op = self.operand()?;
+                let ext = match self.instr {
+                    InstrName::Not => 2,
+                    InstrName::Neg => 3,
+                    InstrName::UnsignedMul => 4,
+                    InstrName::SignedMul => 5,
+                    InstrName::UnsignedDiv => 6,
+                    _ => 7,
+                };
+                let op_code = if R1::SIZE == Sizes::Byte { 0xf6 } else { 0xf7 };
+                ByteCode::only_rm(op_code, ext, op)?
+                    .sized(R1::SIZE)
+                    .encode(encoding)
+            }
+            InstrName::Push => {
+                if R1::SIZE != Sizes::Quad {
+                    return Err(self.invalid());
+                }
+                // push and pop use 8 bytes operands without REX.W
+                match self.operand()? {
+                    Operand::Imm(imm) if encoding == Encoding::Shortest && fits_i8(*imm) => {
+                        Ok(only_op(&[0x6a, *imm as u8]))
+                    }
+                    Operand::Imm(imm) if fits_i32(*imm) => {
+                        Ok(op_imm(Sizes::Long, 0x68, Imm::I32(*imm as i32)))
+                    }
+                    Operand::LabVal(label) => {
+                        Ok(op_imm(Sizes::Long, 0x68, Imm::Lab32S(label.clone())))
+                    }
+                    Operand::Reg(reg) => plus_reg(Sizes::Long, 0x50, reg, Imm::Empty),
+                    op => ByteCode::only_rm(0xff, 6, op)?.encode(encoding),
+                }
+            }
+            InstrName::Pop => {
+                if R1::SIZE != Sizes::Quad {
+                    return Err(self.invalid());
+                }
+                match self.operand()? {
+                    Operand::Reg(reg) => plus_reg(Sizes::Long, 0x58, reg, Imm::Empty),
+                    op => ByteCode::only_rm(0x8f, 0, op)?.encode(encoding),
+                }
+            }
+            InstrName::Ret => Ok(only_op(&[0xc3])),
+            InstrName::Leave => Ok(only_op(&[0xc9])),
+            InstrName::Syscall => Ok(only_op(&[0x0f, 0x05])),
+            InstrName::Hlt => Ok(only_op(&[0xf4])),
+            InstrName::Cltd => Ok(only_op(&[0x99])),
+            InstrName::Cqto => {
+                let mut rex = Rex::new();
+                rex.w = true;
+                Ok(only_op(&[rex.as_byte(), 0x99]))
+            }
+            InstrName::Nop => Ok(only_op(&[0x90])),
+            InstrName::Cmov(cond) => {
+                let size = self.same_size()?;
+                let (rm, reg) = self.operands()?;
+                match reg {
+                    Operand::Reg(reg) if size != Sizes::Byte => {
+                        ByteCode::new(0x40 + cond.code(), reg.clone(), rm)?
+                            .with_prefix(0x0f)
+                            .sized(size)
+                            .encode(encoding)
+                    }
+                    _ => Err(self.invalid()),
+                }
+            }
+            InstrName::Call(label) => Ok(with_label(&[0xe8], label, FixupKind::Plt32)),
+            InstrName::CallStar | InstrName::JumpStar => {
+                if R1::SIZE != Sizes::Quad {
+                    return Err(self.invalid());
+                }
+                let ext = if matches!(self.instr, InstrName::CallStar) {
+                    2
+                } else {
+                    4
+                };
+                ByteCode::only_rm(0xff, ext, self.operand()?)?.encode(encoding)
+            }
+            InstrName::CondJump(cond, label) => Ok(with_label(
+                &[0x0f, 0x80 + cond.code()],
+                label,
+                FixupKind::Rel32,
+            )),
+            InstrName::Jump(label) => Ok(with_label(&[0xe9], label, FixupKind::Rel32)),
+            // only a 8-bits displacement
+            InstrName::Jrcxz(label) => Ok(with_label(&[0xe3], label, FixupKind::Rel8)),
+            InstrName::Loop(label) => Ok(with_label(&[0xe2], label, FixupKind::Rel8)),
+            InstrName::Loope(label) => Ok(with_label(&[0xe1], label, FixupKind::Rel8)),
+            InstrName::Loopne(label) => Ok(with_label(&[0xe0], label, FixupKind::Rel8)),
+            InstrName::Set(cond) => {
+                if R1::SIZE != Sizes::Byte {
+                    return Err(self.invalid());
+                }
+                ByteCode::only_rm(0x90 + cond.code(), 0, self.operand()?)?
+                    .with_prefix(0x0f)
+                    .encode(encoding)
+            }
+            InstrName::Bt => self.encode_bt(4, encoding),
+            InstrName::Bts => self.encode_bt(5, encoding),
+            InstrName::Btr => self.encode_bt(6, encoding),
+            InstrName::Btc => self.encode_bt(7, encoding),
+            InstrName::Bsf => self.encode_bit_count(None, 0xbc, encoding),
+            InstrName::Bsr => self.encode_bit_count(None, 0xbd, encoding),
+            InstrName::Popcnt => self.encode_bit_count(Some(0xf3), 0xb8, encoding),
+            InstrName::Lzcnt => self.encode_bit_count(Some(0xf3), 0xbd, encoding),
+            InstrName::Tzcnt => self.encode_bit_count(Some(0xf3), 0xbc, encoding),
+            InstrName::Andn
+            | InstrName::Blsi
+            | InstrName::Blsr
+            | InstrName::Bzhi
+            | InstrName::Shlx
+            | InstrName::Shrx
+            | InstrName::Sarx
+            | InstrName::Pdep
+            | InstrName::Pext
+            | InstrName::Rorx
+            | InstrName::Mulx => self.encode_bmi(encoding),
+            InstrName::MovString => self.encode_string(0xa4),
+            InstrName::CmpString => self.encode_string(0xa6),
+            InstrName::StoreString => self.encode_string(0xaa),
+            InstrName::LoadString => self.encode_string(0xac),
+            InstrName::ScanString => self.encode_string(0xae),
+            InstrName::Xchg => self.encode_xchg(encoding),
+            InstrName::Cmpxchg => self.encode_exchange(0xb0, encoding),
+            InstrName::Xadd => self.encode_exchange(0xc0, encoding),
+            InstrName::Cmpxchg8b | InstrName::Cmpxchg16b => match self.operand()? {
+                op if op.is_memory() && R1::SIZE == Sizes::Quad => {
+                    let size = if matches!(self.instr, InstrName::Cmpxchg16b) {
+                        Sizes::Quad
+                    } else {
+                        Sizes::Long
+                    };
+                    ByteCode::only_rm(0xc7, 1, op)?
+                        .with_prefix(0x0f)
+                        .sized(size)
+                        .encode(encoding)
+                }
+                _ => Err(self.invalid()),
+            },
+            InstrName::Mfence => Ok(only_op(&[0x0f, 0xae, 0xf0])),
+            InstrName::Lfence => Ok(only_op(&[0x0f, 0xae, 0xe8])),
+            InstrName::Sfence => Ok(only_op(&[0x0f, 0xae, 0xf8])),
+            InstrName::MovFloat(_)
+            | InstrName::MovAligned(_)
+            | InstrName::MovUnaligned(_)
+            | InstrName::AddFloat(_)
+            | InstrName::SubFloat(_)
+            | InstrName::MulFloat(_)
+            | InstrName::DivFloat(_)
+            | InstrName::SqrtFloat(_)
+            | InstrName::Ucomis(_)
+            | InstrName::Comis(_)
+            | InstrName::CvtIntToFloat(_)
+            | InstrName::CvtFloatToInt(_)
+            | InstrName::CvtFloat(_)
+            | InstrName::AndFloat(_)
+            | InstrName::XorFloat(_)
+            | InstrName::MovdXmm
+            | InstrName::MovqXmm => self.encode_sse(encoding),
+            InstrName::VAdd(_)
+            | InstrName::VSub(_)
+            | InstrName::VMul(_)
+            | InstrName::VDiv(_)
+            | InstrName::VXor(_)
+            | InstrName::VFmadd132(_)
+            | InstrName::VFmadd213(_)
+            | InstrName::VFmadd231(_)
+            | InstrName::VPAdd(_)
+            | InstrName::VPSub(_)
+            | InstrName::VPCmpEq(_)
+            | InstrName::VPAnd
+            | InstrName::VPOr
+            | InstrName::VPXor => self.encode_avx3(encoding),
+            InstrName::VMovAligned(_)
+            | InstrName::VMovUnaligned(_)
+            | InstrName::VMovdqa
+            | InstrName::VMovdqu
+            | InstrName::VPMovMskB
+            | InstrName::VBroadcast(_) => self.encode_avx2(encoding),
+            InstrName::VZeroUpper => Ok(only_op(&[0xc5, 0xf8, 0x77])),
+        }
+    }
+
+    /// Jumps with a 8-bits displacement (`jmp` and `jcc` have a shorter encoding for close labels)
+    pub(crate) fn to_bin_short(&self) -> Option<MachineCode> {
+        match &self.instr {
+            InstrName::Jump(label) => Some(with_label(&[0xeb], label, FixupKind::Rel8)),
+            InstrName::CondJump(cond, label) => {
+                Some(with_label(&[0x70 + cond.code()], label, FixupKind::Rel8))
+            }
+            _ => None,
+        }
+    }
+
+    fn encode_mov(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        let byte = size == Sizes::Byte;
+        match self.operands()? {
+            // writing a 4-bytes register clears the upper half of the 8-bytes register
+            (Operand::Imm(imm), Operand::Reg(reg))
+                if size == Sizes::Quad
+                    && encoding == Encoding::Shortest
+                    && 0 <= *imm
+                    && *imm <= u32::MAX as i64 =>
+            {
+                plus_reg(Sizes::Long, 0xb8, reg, Imm::I32(*imm as u32 as i32))
+            }
+            (Operand::Imm(imm), Operand::Reg(reg))
+                if size != Sizes::Quad || encoding == Encoding::Fixed || !fits_i32(*imm) =>
+            {
+                let imm = if size == Sizes::Quad {
+                    Imm::I64(*imm)
+                } else {
+                    imm_of_size(size, *imm)?
+                };
+                let op_code = if byte { 0xb0 } else { 0xb8 };
+                plus_reg(size, op_code, reg, imm)
+            }
+            (Operand::LabVal(label), Operand::Reg(reg)) if size == Sizes::Long => {
+                plus_reg(size, 0xb8, reg, Imm::Lab32(label.clone()))
+            }
+            (src @ (Operand::Imm(_) | Operand::LabVal(_)), rm) => {
+                let op_code = if byte { 0xc6 } else { 0xc7 };
+                ByteCode::only_rm(op_code, 0, rm)?
+                    .sized(size)
+                    .with_imm(self.imm(src, size)?)
+                    .encode(encoding)
+            }
+            (Operand::Reg(reg), rm) => {
+                let op_code = if byte { 0x88 } else { 0x89 };
+                ByteCode::new(op_code, reg.clone(), rm)?
+                    .sized(size)
+                    .encode(encoding)
+            }
+            (rm, Operand::Reg(reg)) => {
+                let op_code = if byte { 0x8a } else { 0x8b };
+                ByteCode::new(op_code, reg.clone(), rm)?
+                    .sized(size)
+                    .encode(encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Moves of 64-bits immediates and of %rax from or to 64-bits absolute addresses
+    fn encode_movabs(&self) -> Result<MachineCode, EncodeError> {
+        if self.same_size()? != Sizes::Quad {
+            return Err(self.invalid());
+        }
+        match self.operands()? {
+            (Operand::Imm(imm), Operand::Reg(reg)) => {
+                plus_reg(Sizes::Quad, 0xb8, reg, Imm::I64(*imm))
+            }
+            (Operand::LabVal(label), Operand::Reg(reg)) => {
+                plus_reg(Sizes::Quad, 0xb8, reg, Imm::Lab64(label.clone()))
+            }
+            (Operand::LabAbsAddr(label), reg) if is_accumulator(reg) => {
+                Ok(op_imm(Sizes::Quad, 0xa1, Imm::Lab64(label.clone())))
+            }
+            (reg, Operand::LabAbsAddr(label)) if is_accumulator(reg) => {
+                Ok(op_imm(Sizes::Quad, 0xa3, Imm::Lab64(label.clone())))
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Add, Or, Adc, Sbb, And, Sub, Xor and Cmp, see <https://www.felixcloutier.com/x86/add>
+    fn encode_arith(&self, op_index: u8, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        let byte = size == Sizes::Byte;
+        match self.operands()? {
+            (Operand::Imm(imm), rm) if !byte && encoding == Encoding::Shortest && fits_i8(*imm) => {
+                ByteCode::only_rm(0x83, op_index, rm)?
+                    .sized(size)
+                    .with_imm(Imm::I8(*imm as i8))
+                    .encode(encoding)
+            }
+            (src @ (Operand::Imm(_) | Operand::LabVal(_)), rm) => {
+                let imm = self.imm(src, size)?;
+                if is_accumulator(rm) {
+                    let op_code = op_index * 8 + if byte { 4 } else { 5 };
+                    Ok(op_imm(size, op_code, imm))
+                } else {
+                    let op_code = if byte { 0x80 } else { 0x81 };
+                    ByteCode::only_rm(op_code, op_index, rm)?
+                        .sized(size)
+                        .with_imm(imm)
+                        .encode(encoding)
+                }
+            }
+            (Operand::Reg(reg), rm) => {
+                let op_code = op_index * 8 + if byte { 0 } else { 1 };
+                ByteCode::new(op_code, reg.clone(), rm)?
+                    .sized(size)
+                    .encode(encoding)
+            }
+            (rm, Operand::Reg(reg)) => {
+                let op_code = op_index * 8 + if byte { 2 } else { 3 };
+                ByteCode::new(op_code, reg.clone(), rm)?
+                    .sized(size)
+                    .encode(encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Shl, Shr and Sar, the first operand is the count (an immediate or %cl)
+    fn encode_shift(&self, ext: u8, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = R2::SIZE;
+        let byte = size == Sizes::Byte;
+        let (count, rm) = self.operands()?;
+        match count {
+            Operand::Imm(1) if encoding == Encoding::Shortest => {
+                ByteCode::only_rm(if byte { 0xd0 } else { 0xd1 }, ext, rm)?
+                    .sized(size)
+                    .encode(encoding)
+            }
+            Operand::Imm(imm) if 0 <= *imm && *imm <= u8::MAX as i64 => {
+                ByteCode::only_rm(if byte { 0xc0 } else { 0xc1 }, ext, rm)?
+                    .sized(size)
+                    .with_imm(Imm::I8(*imm as i8))
+                    .encode(encoding)
+            }
+            Operand::Reg(reg) if R1::SIZE == Sizes::Byte && reg.to_bits() == (false, 0b001) => {
+                ByteCode::only_rm(if byte { 0xd2 } else { 0xd3 }, ext, rm)?
+                    .sized(size)
+                    .encode(encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// shld and shrd, the count is an immediate (op_code) or %cl (op_code + 1)
+    fn encode_double_shift(
+        &self,
+        op_code: u8,
+        encoding: Encoding,
+    ) -> Result<MachineCode, EncodeError> {
+        let size = R2::SIZE;
+        if size == Sizes::Byte {
+            return Err(self.invalid());
+        }
+        match (self.operands()?, &self.reg3) {
+            ((Operand::Imm(imm), Operand::Reg(src)), Some(rm))
+                if 0 <= *imm && *imm <= u8::MAX as i64 =>
+            {
+                ByteCode::new(op_code, src.clone(), rm)?
+                    .with_prefix(0x0f)
+                    .sized(size)
+                    .with_imm(Imm::I8(*imm as i8))
+                    .encode(encoding)
+            }
+            ((Operand::Reg(cl), Operand::Reg(src)), Some(rm))
+                if R1::SIZE == Sizes::Byte && cl.to_bits() == (false, 0b001) =>
+            {
+                ByteCode::new(op_code + 1, src.clone(), rm)?
+                    .with_prefix(0x0f)
+                    .sized(size)
+                    .encode(encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn encode_test(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        let byte = size == Sizes::Byte;
+        match self.operands()? {
+            (src @ (Operand::Imm(_) | Operand::LabVal(_)), rm) => {
+                let imm = self.imm(src, size)?;
+                if is_accumulator(rm) {
+                    Ok(op_imm(size, if byte { 0xa8 } else { 0xa9 }, imm))
+                } else {
+                    ByteCode::only_rm(if byte { 0xf6 } else { 0xf7 }, 0, rm)?
+                        .sized(size)
+                        .with_imm(imm)
+                        .encode(encoding)
+                }
+            }
+            (Operand::Reg(reg), rm) => {
+                ByteCode::new(if byte { 0x84 } else { 0x85 }, reg.clone(), rm)?
+                    .sized(size)
+                    .encode(encoding)
+            }
+            (rm, Operand::Reg(reg)) => {
+                ByteCode::new(if byte { 0x84 } else { 0x85 }, reg.clone(), rm)?
+                    .sized(size)
+                    .encode(encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn encode_imul(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        match self.operands()? {
+            _ if size == Sizes::Byte => Err(self.invalid()),
+            // short form of imul $imm, %reg, %reg
+            (Operand::Imm(imm), Operand::Reg(reg)) => {
+                self.encode_imul_imm(*imm, &Operand::Reg(reg.clone()), reg, encoding)
+            }
+            (rm, Operand::Reg(reg)) => ByteCode::new(0xaf, reg.clone(), rm)?
+                .with_prefix(0x0f)
+                .sized(size)
+                .encode(encoding),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Product of `rm` by an immediate stored in `reg`
+    fn encode_imul_imm(
+        &self,
+        imm: i64,
+        rm: &Operand<R2>,
+        reg: &R2,
+        encoding: Encoding,
+    ) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        if size == Sizes::Byte {
+            return Err(self.invalid());
+        }
+        if encoding == Encoding::Shortest && fits_i8(imm) {
+            ByteCode::new(0x6b, reg.clone(), rm)?
+                .sized(size)
+                .with_imm(Imm::I8(imm as i8))
+                .encode(encoding)
+        } else {
+            ByteCode::new(0x69, reg.clone(), rm)?
+                .sized(size)
+                .with_imm(imm_of_size(size, imm)?)
+                .encode(encoding)
+        }
+    }
+
+    /// SSE instructions, see <https://www.felixcloutier.com/x86/addsd>
+    fn encode_sse(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let scalar = |p: &Precision| match p {
+            Precision::Single => Some(0xf3),
+            Precision::Double => Some(0xf2),
+        };
+        let packed = |p: &Precision| match p {
+            Precision::Single => None,
+            Precision::Double => Some(0x66),
+        };
+        let xmm = (Sizes::Xmm, Sizes::Xmm);
+        // prefix, opcode (after 0x0f), opcode storing the XMM register in memory, operand sizes
+        let (prefix, op_code, store, sizes) = match &self.instr {
+            InstrName::MovFloat(p) => (scalar(p), 0x10, Some(0x11), xmm),
+            InstrName::MovAligned(p) => (packed(p), 0x28, Some(0x29), xmm),
+            InstrName::MovUnaligned(p) => (packed(p), 0x10, Some(0x11), xmm),
+            InstrName::AddFloat(p) => (scalar(p), 0x58, None, xmm),
+            InstrName::MulFloat(p) => (scalar(p), 0x59, None, xmm),
+            InstrName::SubFloat(p) => (scalar(p), 0x5c, None, xmm),
+            InstrName::DivFloat(p) => (scalar(p), 0x5e, None, xmm),
+            InstrName::SqrtFloat(p) => (scalar(p), 0x51, None, xmm),
+            InstrName::Ucomis(p) => (packed(p), 0x2e, None, xmm),
+            InstrName::Comis(p) => (packed(p), 0x2f, None, xmm),
+            InstrName::CvtIntToFloat(p) => (scalar(p), 0x2a, None, (R1::SIZE, Sizes::Xmm)),
+            InstrName::CvtFloatToInt(p) => (scalar(p), 0x2c, None, (Sizes::Xmm, R2::SIZE)),
+            // the prefix is the one of the source
+            InstrName::CvtFloat(Precision::Single) => (Some(0xf2), 0x5a, None, xmm),
+            InstrName::CvtFloat(Precision::Double) => (Some(0xf3), 0x5a, None, xmm),
+            InstrName::AndFloat(p) => (packed(p), 0x54, None, xmm),
+            InstrName::XorFloat(p) => (packed(p), 0x57, None, xmm),
+            // 0x7e moves from the XMM register to the other one
+            InstrName::MovdXmm if R1::SIZE == Sizes::Xmm => {
+                (Some(0x66), 0x7e, None, (Sizes::Xmm, Sizes::Long))
+            }
+            InstrName::MovdXmm => (Some(0x66), 0x6e, None, (Sizes::Long, Sizes::Xmm)),
+            InstrName::MovqXmm if R1::SIZE == Sizes::Xmm => {
+                (Some(0x66), 0x7e, None, (Sizes::Xmm, Sizes::Quad))
+            }
+            InstrName::MovqXmm => (Some(0x66), 0x6e, None, (Sizes::Quad, Sizes::Xmm)),
+            _ => return Err(self.invalid()),
+        };
+        let valid = |size| matches!(size, Sizes::Long | Sizes::Quad | Sizes::Xmm);
+        if (R1::SIZE, R2::SIZE) != sizes || !valid(sizes.0) || !valid(sizes.1) {
+            return Err(self.invalid());
+        }
+        // size of the integer operand (REX.W for 8 bytes)
+        let int_size = if sizes.0 == Sizes::Xmm {
+            sizes.1
+        } else {
+            sizes.0
+        };
+        match (self.operands()?, op_code) {
+            // the XMM register is always in MODRM.reg
+            ((Operand::Reg(xmm), rm), 0x7e) => sse(
+                ByteCode::new(op_code, xmm.clone(), rm)?,
+                prefix,
+                int_size,
+                encoding,
+            ),
+            (_, 0x7e) => Err(self.invalid()),
+            ((rm, Operand::Reg(reg)), _) => sse(
+                ByteCode::new(op_code, reg.clone(), rm)?,
+                prefix,
+                int_size,
+                encoding,
+            ),
+            ((Operand::Reg(reg), rm), _) if store.is_some() && rm.is_memory() => sse(
+                ByteCode::new(store.unwrap(), reg.clone(), rm)?,
+                prefix,
+                int_size,
+                encoding,
+            ),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Bt, Bts, Btr and Btc (`ext` is the opcode extension of the immediate form)
+    fn encode_bt(&self, ext: u8, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        if !matches!(size, Sizes::Word | Sizes::Long | Sizes::Quad) {
+            return Err(self.invalid());
+        }
+        match self.operands()? {
+            (Operand::Reg(index), rm) => ByteCode::new(0xa3 + 8 * (ext - 4), index.clone(), rm)?
+                .with_prefix(0x0f)
+                .sized(size)
+                .encode(encoding),
+            (Operand::Imm(imm), rm) if 0 <= *imm && *imm <= u8::MAX as i64 => {
+                ByteCode::only_rm(0xba, ext, rm)?
+                    .with_prefix(0x0f)
+                    .sized(size)
+                    .with_imm(Imm::I8(*imm as i8))
+                    .encode(encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Bsf, Bsr, Popcnt, Lzcnt and Tzcnt (the last ones have a mandatory 0xf3 prefix)
+    fn encode_bit_count(
+        &self,
+        prefix: Option<u8>,
+        op_code: u8,
+        encoding: Encoding,
+    ) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        if !matches!(size, Sizes::Word | Sizes::Long | Sizes::Quad) {
+            return Err(self.invalid());
+        }
+        match self.operands()? {
+            (rm, Operand::Reg(reg)) => sse(
+                ByteCode::new(op_code, reg.clone(), rm)?,
+                prefix,
+                size,
+                encoding,
+            ),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// BMI instructions, encoded with a VEX prefix where VEX.vvvv is a general purpose register
+    fn encode_bmi(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        if !matches!(size, Sizes::Long | Sizes::Quad) {
+            return Err(self.invalid());
+        }
+        let w = size == Sizes::Quad;
+        let prefix = |map, pp, vvvv| {
+            let mut prefix = Vex::new(map, pp, false);
+            prefix.vvvv = vvvv;
+            prefix
+        };
+        match (&self.instr, self.operands()?, &self.reg3) {
+            // the first operand is MODRM.rm, the second one VEX.vvvv
+            (
+                InstrName::Andn | InstrName::Pdep | InstrName::Pext | InstrName::Mulx,
+                (rm, Operand::Reg(src)),
+                Some(Operand::Reg(dst)),
+            ) => {
+                let (pp, op_code) = match self.instr {
+                    InstrName::Andn => (0, 0xf2),
+                    InstrName::Pdep => (3, 0xf5),
+                    InstrName::Mulx => (3, 0xf6),
+                    _ => (2, 0xf5),
+                };
+                let code = ByteCode::new(op_code, dst.clone(), rm)?;
+                vex(code, prefix(2, pp, reg_num(src)), w, encoding)
+            }
+            // the destination is VEX.vvvv
+            (InstrName::Blsi | InstrName::Blsr, (rm, Operand::Reg(dst)), None) => {
+                let ext = if matches!(self.instr, InstrName::Blsi) {
+                    3
+                } else {
+                    1
+                };
+                let code = ByteCode::only_rm(0xf3, ext, rm)?;
+                vex(code, prefix(2, 0, reg_num(dst)), w, encoding)
+            }
+            // the index or the count is VEX.vvvv
+            (
+                InstrName::Bzhi | InstrName::Shlx | InstrName::Shrx | InstrName::Sarx,
+                (Operand::Reg(index), rm),
+                Some(Operand::Reg(dst)),
+            ) => {
+                let (pp, op_code) = match self.instr {
+                    InstrName::Bzhi => (0, 0xf5),
+                    InstrName::Shlx => (1, 0xf7),
+                    InstrName::Sarx => (2, 0xf7),
+                    _ => (3, 0xf7),
+                };
+                let code = ByteCode::new(op_code, dst.clone(), rm)?;
+                vex(code, prefix(2, pp, reg_num(index)), w, encoding)
+            }
+            (InstrName::Rorx, (Operand::Imm(imm), rm), Some(Operand::Reg(dst)))
+                if 0 <= *imm && *imm <= u8::MAX as i64 =>
+            {
+                let code = ByteCode::new(0xf0, dst.clone(), rm)?.with_imm(Imm::I8(*imm as i8));
+                vex(code, prefix(3, 3, 0), w, encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// String instructions have only implicit operands, the `rep` prefixes are added by
+    /// [`Instruction::to_bin`] before REX (`rep movsq` = f3 48 a5)
+    fn encode_string(&self, op_code: u8) -> Result<MachineCode, EncodeError> {
+        let size = R1::SIZE;
+        if !matches!(size, Sizes::Byte | Sizes::Word | Sizes::Long | Sizes::Quad) {
+            return Err(self.invalid());
+        }
+        let mut code = MachineCode::default();
+        if size == Sizes::Word {
+            code.bytes.push(0x66);
+        }
+        if size == Sizes::Quad {
+            let mut rex = Rex::new();
+            rex.w = true;
+            code.bytes.push(rex.as_byte());
+        }
+        code.bytes.push(op_code + (size != Sizes::Byte) as u8);
+        Ok(code)
+    }
+
+    /// xchg has a shorter encoding between the accumulator and another register (`xchg %rax, %rbx` = 48 93),
+    /// `xchg %eax, %eax` keeps the long one as it clears the upper half of %rax and 90 is a nop
+    fn encode_xchg(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        let op_code = if size == Sizes::Byte { 0x86 } else { 0x87 };
+        let short = |bits: (bool, u8)| {
+            size == Sizes::Word
+                || size == Sizes::Quad
+                || (size == Sizes::Long && bits != (false, 0))
+        };
+        match self.operands()? {
+            (acc, other) if size == Sizes::Quad && is_accumulator(acc) && is_accumulator(other) => {
+                Ok(only_op(&[0x90]))
+            }
+            (acc, Operand::Reg(reg)) if is_accumulator(acc) && short(reg.to_bits()) => {
+                plus_reg(size, 0x90, reg, Imm::Empty)
+            }
+            (Operand::Reg(reg), acc) if is_accumulator(acc) && short(reg.to_bits()) => {
+                plus_reg(size, 0x90, reg, Imm::Empty)
+            }
+            (Operand::Reg(reg), rm) => ByteCode::new(op_code, reg.clone(), rm)?
+                .sized(size)
+                .encode(encoding),
+            (rm, Operand::Reg(reg)) if rm.is_memory() => ByteCode::new(op_code, reg.clone(), rm)?
+                .sized(size)
+                .encode(encoding),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// cmpxchg and xadd, the source is a register and the destination a register or memory
+    fn encode_exchange(&self, op_code: u8, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let size = self.same_size()?;
+        match self.operands()? {
+            (Operand::Reg(reg), rm) if rm.is_rm() => {
+                ByteCode::new(op_code + (size != Sizes::Byte) as u8, reg.clone(), rm)?
+                    .with_prefix(0x0f)
+                    .sized(size)
+                    .encode(encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// AVX instructions with 3 operands, the first one is MODRM.rm, the second one VEX.vvvv
+    /// and the destination MODRM.reg
+    fn encode_avx3(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let pp = |p: &Precision| match p {
+            Precision::Single => 0,
+            Precision::Double => 1,
+        };
+        // map, mandatory prefix, REX.W and opcode
+        let (map, pp, w, op_code) = match &self.instr {
+            InstrName::VAdd(p) => (1, pp(p), false, 0x58),
+            InstrName::VMul(p) => (1, pp(p), false, 0x59),
+            InstrName::VSub(p) => (1, pp(p), false, 0x5c),
+            InstrName::VDiv(p) => (1, pp(p), false, 0x5e),
+            InstrName::VXor(p) => (1, pp(p), false, 0x57),
+            // the precision is given by REX.W
+            InstrName::VFmadd132(p) => (2, 1, *p == Precision::Double, 0x98),
+            InstrName::VFmadd213(p) => (2, 1, *p == Precision::Double, 0xa8),
+            InstrName::VFmadd231(p) => (2, 1, *p == Precision::Double, 0xb8),
+            InstrName::VPAdd(Sizes::Byte) => (1, 1, false, 0xfc),
+            InstrName::VPAdd(Sizes::Word) => (1, 1, false, 0xfd),
+            InstrName::VPAdd(Sizes::Long) => (1, 1, false, 0xfe),
+            InstrName::VPAdd(Sizes::Quad) => (1, 1, false, 0xd4),
+            InstrName::VPSub(Sizes::Byte) => (1, 1, false, 0xf8),
+            InstrName::VPSub(Sizes::Word) => (1, 1, false, 0xf9),
+            InstrName::VPSub(Sizes::Long) => (1, 1, false, 0xfa),
+            InstrName::VPSub(Sizes::Quad) => (1, 1, false, 0xfb),
+            InstrName::VPCmpEq(Sizes::Byte) => (1, 1, false, 0x74),
+            InstrName::VPCmpEq(Sizes::Word) => (1, 1, false, 0x75),
+            InstrName::VPCmpEq(Sizes::Long) => (1, 1, false, 0x76),
+            InstrName::VPCmpEq(Sizes::Quad) => (2, 1, false, 0x29),
+            InstrName::VPAnd => (1, 1, false, 0xdb),
+            InstrName::VPOr => (1, 1, false, 0xeb),
+            InstrName::VPXor => (1, 1, false, 0xef),
+            _ => return Err(self.invalid()),
+        };
+        let size = self.same_size()?;
+        if !matches!(size, Sizes::Xmm | Sizes::Ymm) {
+            return Err(self.invalid());
+        }
+        let (rm, src) = self.operands()?;
+        match (src, &self.reg3) {
+            (Operand::Reg(src), Some(Operand::Reg(dst))) => {
+                let mut prefix = Vex::new(map, pp, size == Sizes::Ymm);
+                prefix.vvvv = reg_num(src);
+                vex(
+                    ByteCode::new(op_code, dst.clone(), rm)?,
+                    prefix,
+                    w,
+                    encoding,
+                )
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// AVX instructions with 2 operands
+    fn encode_avx2(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let pp = |p: &Precision| match p {
+            Precision::Single => 0,
+            Precision::Double => 1,
+        };
+        // map, mandatory prefix, opcode, opcode storing the register in memory, operand sizes
+        let (map, pp, op_code, store, sizes) = match &self.instr {
+            InstrName::VMovAligned(p) => (1, pp(p), 0x28, Some(0x29), (R2::SIZE, R2::SIZE)),
+            InstrName::VMovUnaligned(p) => (1, pp(p), 0x10, Some(0x11), (R2::SIZE, R2::SIZE)),
+            InstrName::VMovdqa => (1, 1, 0x6f, Some(0x7f), (R2::SIZE, R2::SIZE)),
+            InstrName::VMovdqu => (1, 2, 0x6f, Some(0x7f), (R2::SIZE, R2::SIZE)),
+            InstrName::VPMovMskB => (1, 1, 0xd7, None, (R1::SIZE, Sizes::Long)),
+            InstrName::VBroadcast(Precision::Single) => (2, 1, 0x18, None, (Sizes::Xmm, R2::SIZE)),
+            InstrName::VBroadcast(Precision::Double) => {
+                (2, 1, 0x19, None, (Sizes::Xmm, Sizes::Ymm))
+            }
+            _ => return Err(self.invalid()),
+        };
+        let vector = if sizes.1 == Sizes::Long {
+            sizes.0
+        } else {
+            sizes.1
+        };
+        if (R1::SIZE, R2::SIZE) != sizes || !matches!(vector, Sizes::Xmm | Sizes::Ymm) {
+            return Err(self.invalid());
+        }
+        let prefix = Vex::new(map, pp, vector == Sizes::Ymm);
+        match self.operands()? {
+            // the mask is stored in MODRM.reg and the source must be a register
+            (src @ Operand::Reg(_), Operand::Reg(dst)) if sizes.1 == Sizes::Long => vex(
+                ByteCode::new(op_code, dst.clone(), src)?,
+                prefix,
+                false,
+                encoding,
+            ),
+            _ if sizes.1 == Sizes::Long => Err(self.invalid()),
+            // like GNU as, a move between registers uses the store opcode when it avoids
+            // the 3-bytes VEX prefix (when only the source requires an extension bit)
+            (Operand::Reg(src), Operand::Reg(dst))
+                if store.is_some() && src.to_bits().0 && !dst.to_bits().0 =>
+            {
+                vex(
+                    ByteCode::new(store.unwrap(), src.clone(), &Operand::Reg(dst.clone()))?,
+                    prefix,
+                    false,
+                    encoding,
+                )
+            }
+            (rm, Operand::Reg(reg)) => vex(
+                ByteCode::new(op_code, reg.clone(), rm)?,
+                prefix,
+                false,
+                encoding,
+            ),
+            (Operand::Reg(reg), rm) if store.is_some() && rm.is_memory() => vex(
+                ByteCode::new(store.unwrap(), reg.clone(), rm)?,
+                prefix,
+                false,
+                encoding,
+            ),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// Movs and Movz, sizes are given by the operands
+    fn encode_movx(&self, encoding: Encoding) -> Result<MachineCode, EncodeError> {
+        let sign = matches!(self.instr, InstrName::Movs);
+        let (prefix, op_code) = match (R1::SIZE, R2::SIZE) {
+            (Sizes::Byte, Sizes::Word | Sizes::Long | Sizes::Quad) => {
+                (Some(0x0f), if sign { 0xbe } else { 0xb6 })
+            }
+            (Sizes::Word, Sizes::Long | Sizes::Quad) => {
+                (Some(0x0f), if sign { 0xbf } else { 0xb7 })
+            }
+            (Sizes::Long, Sizes::Quad) if sign => (None, 0x63),
+            _ => return Err(self.invalid()),
+        };
+        match self.operands()? {
+            (rm, Operand::Reg(reg)) => {
+                let mut op = ByteCode::new(op_code, reg.clone(), rm)?.sized(R2::SIZE);
+                op.prefix = prefix;
+                op.encode(encoding)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::ffi::{c_void, CString};
+use std::os::raw::{c_char, c_int};
+
+use crate::instr::assembler::{assemble, Assembly};
+use crate::instr::encode::{Fixup, FixupKind};
+use crate::reg::Label;
+use crate::Text;
+
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const PROT_EXEC: c_int = 0x4;
+
+const MAP_PRIVATE: c_int = 0x02;
+#[cfg(target_os = "macos")]
+const MAP_ANONYMOUS: c_int = 0x1000;
+#[cfg(not(target_os = "macos"))]
+const MAP_ANONYMOUS: c_int = 0x20;
+
+#[cfg(target_os = "macos")]
+const RTLD_DEFAULT: *mut c_void = -2isize as *mut c_void;
+#[cfg(not(target_os = "macos"))]
+const RTLD_DEFAULT: *mut c_void = std::ptr::null_mut();
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+// dlsym is in libdl before glibc 2.34 (in libc since then, libdl is kept empty),
+// it is in the C library of musl and macOS
+#[cfg_attr(all(target_os = "linux", target_env = "gnu"), link(name = "dl"))]
+extern "C" {
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+/// Size of a stub jumping to an external function (`jmp *0(%rip)` followed by the address)
+const STUB_SIZE: usize = 14;
+
+fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+/// Tests if the fixup is the target of a call or a jump, which can go through a stub
+///
+/// Relative data accesses have a MODRM byte before their displacement, it is never
+/// the opcode of `jmp` (e9) or of `jcc` (0f 80 to 0f 8f)
+fn is_branch(bytes: &[u8], fixup: &Fixup) -> bool {
+    match fixup.kind {
+        FixupKind::Plt32 | FixupKind::Rel8 => true,
+        FixupKind::Rel32 => matches!(
+            bytes[..fixup.offset],
+            [.., 0xe8] | [.., 0xe9] | [.., 0x0f, 0x80..=0x8f]
+        ),
+        FixupKind::Abs32 | FixupKind::Abs32S | FixupKind::Abs64 => false,
+    }
+}
+
+/// Compiler of text segments in executable memory
+///
+/// Labels not defined in a segment are looked up in the symbols given to
+/// [`Jit::define`] and then in the symbols of the running program (like `printf`).
+///
+/// ```
+/// use write_x86_64::*;
+/// use write_x86_64::jit::Jit;
+///
+/// let text = Segment::label(new_label("add"))
+///     + movq(reg!(RDI), reg!(RAX))
+///     + addq(reg!(RSI), reg!(RAX))
+///     + ret();
+/// let function = Jit::new().compile(&text, &new_label("add")).unwrap();
+/// let add: extern "C" fn(i64, i64) -> i64 = unsafe { function.as_fn() };
+/// assert_eq!(add(40, 2), 42);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Jit {
+    symbols: HashMap<Label, usize>,
+}
+
+impl Jit {
+    /// Compiler resolving external labels in the running program
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve `label` to `address` in the segments compiled afterwards
+    ///
+    /// It can be the address of a previously compiled function, which must not be freed while
+    /// the code calling it is alive
+    pub fn define(&mut self, label: Label, address: usize) {
+        self.symbols.insert(label, address);
+    }
+
+    /// Address of `label` in the symbols given by the user or in the running program
+    fn lookup(&self, label: &Label) -> Option<usize> {
+        if let Some(address) = self.symbols.get(label) {
+            return Some(*address);
+        }
+        let name = CString::new(label.name()).ok()?;
+        let address = unsafe { dlsym(RTLD_DEFAULT, name.as_ptr()) };
+        if address.is_null() {
+            None
+        } else {
+            Some(address as usize)
+        }
+    }
+
+    /// Assemble `text` in executable memory, the function starts at `entry`
+    pub fn compile(&self, text: &Text, entry: &Label) -> std::io::Result<JitFunction> {
+        let Assembly { code, labels } = assemble(text)?;
+        let entry = *labels
+            .get(entry)
+            .ok_or_else(|| invalid_input(format!("label {} is not defined", entry.name())))?;
+
+        // calls and jumps to other labels go through stubs as they may be far away,
+        // data must be less than 2 GiB away from the code to be accessed relatively to %rip
+        let mut externals: Vec<(&Label, usize)> = Vec::new();
+        for fixup in &code.fixups {
+            if labels.contains_key(&fixup.label)
+                || externals.iter().any(|(label, _)| *label == &fixup.label)
+            {
+                continue;
+            }
+            let address = self.lookup(&fixup.label).ok_or_else(|| {
+                invalid_input(format!("label {} is not defined", fixup.label.name()))
+            })?;
+            externals.push((&fixup.label, address));
+        }
+        let stubs = (code.bytes.len() + 15) / 16 * 16;
+        let len = stubs + externals.len() * STUB_SIZE;
+        let mut function = JitFunction::new(len.max(1), labels.clone())?;
+        let base = function.ptr as usize;
+
+        let mut bytes = code.bytes.clone();
+        bytes.resize(stubs, 0xcc);
+        for (_, address) in &externals {
+            bytes.extend_from_slice(&[0xff, 0x25, 0, 0, 0, 0]);
+            bytes.extend_from_slice(&(*address as u64).to_le_bytes());
+        }
+        for fixup in &code.fixups {
+            let external = externals
+                .iter()
+                .position(|(label, _)| *label == &fixup.label);
+            let target = match (labels.get(&fixup.label), external) {
+                (Some(offset), _) => base + offset,
+                (None, Some(i)) if is_branch(&code.bytes, fixup) => base + stubs + i * STUB_SIZE,
+                (None, Some(i)) => externals[i].1,
+                (None, None) => unreachable!(),
+            } as i64
+                + fixup.addend;
+            let place = (base + fixup.offset) as i64;
+            let (value, fits) = match fixup.kind {
+                FixupKind::Rel8 => (target - place, i8::try_from(target - place).is_ok()),
+                FixupKind::Rel32 | FixupKind::Plt32 => {
+                    (target - place, i32::try_from(target - place).is_ok())
+                }
+                FixupKind::Abs32 => (target, u32::try_from(target).is_ok()),
+                FixupKind::Abs32S => (target, i32::try_from(target).is_ok()),
+                FixupKind::Abs64 => (target, true),
+            };
+            if !fits {
+                let hint = match fixup.kind {
+                    FixupKind::Abs32 | FixupKind::Abs32S => "use addresses relative to %rip",
+                    _ => "more than 2 GiB away from the compiled code",
+                };
+                return Err(invalid_input(format!(
+                    "label {} is too far ({})",
+                    fixup.label.name(),
+                    hint
+                )));
+            }
+            let size = fixup.kind.size();
+            bytes[fixup.offset..fixup.offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), function.ptr, bytes.len());
+            // memory is never writable and executable at the same time
+            if mprotect(
+                function.ptr as *mut c_void,
+                function.len,
+                PROT_READ | PROT_EXEC,
+            ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        function.entry = entry;
+        function.code_len = code.bytes.len();
+        Ok(function)
+    }
+}
+
+/// Code compiled by [`Jit::compile`], the memory is freed when it is dropped
+#[derive(Debug)]
+pub struct JitFunction {
+    ptr: *mut u8,
+    len: usize,
+    // the stubs of external functions follow the code
+    code_len: usize,
+    entry: usize,
+    labels: HashMap<Label, usize>,
+}
+
+impl JitFunction {
+    fn new(len: usize, labels: HashMap<Label, usize>) -> std::io::Result<Self> {
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        // MAP_FAILED
+        if ptr as isize == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+            code_len: 0,
+            entry: 0,
+            labels,
+        })
+    }
+
+    /// Compiled machine code (without the stubs calling external functions),
+    /// see [`crate::instr::decode::disassemble`] to read it
+    pub fn code(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.code_len) }
+    }
+
+    /// Address of the entry point
+    pub fn entry(&self) -> usize {
+        self.ptr as usize + self.entry
+    }
+
+    /// Address of a label defined in the compiled segment
+    pub fn address(&self, label: &Label) -> Option<usize> {
+        self.labels
+            .get(label)
+            .map(|offset| self.ptr as usize + offset)
+    }
+
+    /// Get the entry point as a function pointer (like `extern "C" fn(i64) -> i64`)
+    ///
+    /// # Safety
+    ///
+    /// `F` must be a function pointer type matching the compiled code
+    /// and it must not be called after `self` is dropped
+    pub unsafe fn as_fn<F: Copy>(&self) -> F {
+        assert_eq!(
+            std::mem::size_of::<F>(),
+            std::mem::size_of::<usize>(),
+            "F should be a function pointer"
+        );
+        let entry = self.entry();
+        std::mem::transmute_copy(&entry)
+    }
+}
+
+impl Drop for JitFunction {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.ptr as *mut c_void, self.len);
+        }
+    }
+}
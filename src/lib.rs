@@ -8,6 +8,8 @@
 //!
 //! [`data::Data`], [`file::File`] and [Text] data structures
 //!
+//! AT&T, Intel or NASM syntax can be produced, see [`writer::Writer`]
+//!
 //! Registers %rax -> %r15 are all accessible for 8, 16, 32 and 64 bits.
 //! Write name in capital letters to access them
 //!
@@ -17,15 +19,29 @@
 //!
 //! Transfert instruction : [`movq`]
 //!
-//! Arithmetic : [`leaq`], [`incq`], [`decq`], [`negq`], [`addq`], [`subq`], [`imulq`], [`cqto`], [`idivq`], [`divq`]
+//! Arithmetic : [`leaq`], [`incq`], [`decq`], [`negq`], [`addq`], [`subq`], [`imulq`], [`imulq_imm`], [`mulq`], [`imulq_wide`], [`mulxq`], [`cqto`], [`idivq`], [`divq`]
 //!
 //! Logic : [`notq`], [`andq`], [`orq`], [`xorq`]
 //!
-//! Shifts : [`shlq`], [`shrq`], [`sarq`]
+//! Shifts : [`shlq`], [`shrq`], [`sarq`], [`rolq`], [`rorq`], [`rclq`], [`rcrq`], [`shlq_reg`], [`sarq_reg`], [`shldq`], [`shrdq_reg`]
+//!
+//! Bit manipulation : [`btq`], [`bsfq`], [`popcntq`], [`lzcntq`], [`tzcntq`], [`andnq`], [`blsrq`], [`bzhiq`], [`shlxq`], [`pdepq`], [`pextq`], [`rorxq`]
+//!
+//! Strings : [`movsb`], [`stosq`], [`lodsb`], [`cmpsb`], [`scasb`], [`rep_movsb`], [`rep_stosq`], [`repe_cmpsb`], [`repne_scasb`] with the implicit operands of [`reg::implicit`]
+//!
+//! Atomics : [`lock_addq`], [`lock_incq`], [`xchgq`], [`lock_cmpxchgq`], [`lock_cmpxchg16b`], [`lock_xaddq`], [`mfence`] with [`mem!`] destinations and [`reg::RegImm`] sources
+//!
+//! Segment overrides : [`fs`], [`gs`]
+//!
+//! Floating point : [`movsd`], [`addsd`], [`ucomisd`], [`cvtsi2sdq`], [`cvttsd2siq`], [`xorpd`], [`movq_to_xmm`]
+//!
+//! AVX : [`vmovups`], [`vaddps`], [`vfmadd231pd`], [`vpaddd`], [`vpcmpeqb`], [`vpmovmskb`], [`vbroadcastss`], [`vzeroupper`]
 //!
-//! Jumps : [`call`], [`call_star`], [`leave`], [`ret`], [`jmp`], [`jmp_star`], [`jcc`]
+//! Jumps : [`call`], [`call_star`], [`leave`], [`ret`], [`jmp`], [`jmp_star`], [`jcc`], [`jrcxz`], [`loop_`]
 //!
-//! Conditions : [`cmpb`], [`testq`], [`set`], [`cmovq`]
+//! Conditions : [`cmpb`], [`testq`], [`set`], [`cmovq`], [`instr::Cond::signed`], [`instr::Cond::negate`]
+//!
+//! Each condition has its helpers : [`jl`], [`setl`], [`cmovlq`], [`jo`], [`setp`]...
 //!
 //! Stack : [`pushq`], [`popq`]
 //!
@@ -57,6 +73,25 @@ pub mod debug;
 /// Defines directives
 pub mod directives;
 
+/// Defines the writer and the output syntaxes
+pub mod writer;
+
+/// Defines the targeted platforms
+pub mod target;
+
+/// Defines symbol attributes
+pub mod symbol;
+
+/// Assemble files in memory
+pub mod object;
+
+/// Write ELF object files
+pub mod elf;
+
+/// Run code in memory
+#[cfg(all(unix, target_arch = "x86_64"))]
+pub mod jit;
+
 #[macro_use]
 mod macros;
 
@@ -65,6 +100,7 @@ mod tests;
 
 use std::io::prelude::*;
 use std::ops::{Add, AddAssign};
+use writer::Writer;
 
 // Segments
 
@@ -87,24 +123,25 @@ pub enum SegmentEL<T> {
 }
 
 impl<T: traits::Writable> traits::Writable for SegmentEL<T> {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
         match self {
             Self::Label(lab) => {
-                lab.write_in(file)?;
+                lab.write_with(file)?;
                 file.write_all(b":")
             }
             Self::Inline(str) => file.write_all(str.as_bytes()),
             Self::Comment(str) => {
-                file.write_all(b"## ")?;
+                file.write_all(file.syntax().comment_marker().as_bytes())?;
+                file.write_all(b" ")?;
                 file.write_all(str.as_bytes())
             }
             Self::Data(el) => {
                 file.write_all(b"\t")?;
-                el.write_in(file)
+                el.write_with(file)
             }
             Self::Directive(d) => {
                 file.write_all(b"\t")?;
-                d.write_in(file)
+                d.write_with(file)
             }
         }
     }
@@ -140,10 +177,12 @@ impl<T> SegmentELWrapper<T> {
 }
 
 impl<T: traits::Writable> traits::Writable for SegmentELWrapper<T> {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        self.el.write_in(file)?;
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        self.el.write_with(file)?;
         if let Some(str) = &self.comment {
-            file.write_all(b" ## ")?;
+            file.write_all(b" ")?;
+            file.write_all(file.syntax().comment_marker().as_bytes())?;
+            file.write_all(b" ")?;
             file.write_all(str.as_bytes())?;
         };
         file.write_all(b"\n")
@@ -209,6 +248,43 @@ impl<T> Segment<T> {
     pub fn empty() -> Self {
         Self { data: Vec::new() }
     }
+
+    /// Tests if the segment is empty
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Labels defined in the segment
+    pub fn defined_labels(&self) -> Vec<&reg::Label> {
+        self.data
+            .iter()
+            .filter_map(|el| match &el.el {
+                SegmentEL::Label(lab) => Some(lab),
+                SegmentEL::Directive(d) => d.defined_label(),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl<T: traits::UsesLabels> traits::UsesLabels for Segment<T> {
+    fn used_labels(&self) -> Vec<&reg::Label> {
+        let mut labels = Vec::new();
+        for el in &self.data {
+            match &el.el {
+                SegmentEL::Data(el) => labels.append(&mut el.used_labels()),
+                SegmentEL::Directive(d) => labels.append(&mut d.used_labels()),
+                _ => (),
+            }
+        }
+        labels
+    }
+}
+
+impl<T> Default for Segment<T> {
+    fn default() -> Self {
+        Self::empty()
+    }
 }
 
 impl<T> Add for Segment<T> {
@@ -227,33 +303,163 @@ impl<T> AddAssign for Segment<T> {
 }
 
 impl<T: traits::Writable> traits::Writable for Segment<T> {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
         for el in &self.data {
-            el.write_in(file)?;
+            el.write_with(file)?;
         }
         std::io::Result::Ok(())
     }
 }
 
+impl<T: traits::Writable> std::fmt::Display for Segment<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        traits::fmt_writable(self, f)
+    }
+}
+
 /// Named Segment
 pub struct NamedSegment<T> {
     name: String,
+    flags: Option<file::SectionFlags>,
+    kind: Option<file::SectionType>,
     data: Segment<T>,
 }
 
 impl<T> NamedSegment<T> {
     /// Create a named segment from a Segment
     pub fn new(name: String, data: Segment<T>) -> Self {
-        Self { name, data }
+        Self {
+            name,
+            flags: None,
+            kind: None,
+            data,
+        }
+    }
+
+    /// Set the flags of the section
+    pub fn with_flags(mut self, flags: file::SectionFlags) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
+    /// Set the type of the section
+    pub fn with_type(mut self, kind: file::SectionType) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Name of the section
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Flags of the section if specified
+    pub fn flags(&self) -> Option<file::SectionFlags> {
+        self.flags
+    }
+
+    /// Type of the section if specified
+    pub fn kind(&self) -> Option<file::SectionType> {
+        self.kind
+    }
+
+    /// Content of the section
+    pub fn segment(&self) -> &Segment<T> {
+        &self.data
     }
 }
 
-impl<T: traits::Writable> traits::Writable for NamedSegment<T> {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+impl<T: traits::Writable> NamedSegment<T> {
+    /// ELF sections are written with their flags and type (`.section .bss,"aw",@nobits`)
+    fn write_gas_header(&self, file: &mut Writer) -> std::io::Result<()> {
+        if file.target().os == target::Os::MacOs {
+            return self.write_macho_header(file);
+        }
         file.write_all(b"\t.section ")?;
         file.write_all(self.name.as_bytes())?;
-        file.write_all(b"\n")?;
-        self.data.write_in(file)
+        if self.flags.is_some() || self.kind.is_some() {
+            let flags = self.flags.unwrap_or_default();
+            file.write_all(format!(",\"{}\"", flags.to_gas_string()).as_bytes())?;
+            if let Some(kind) = self.kind {
+                file.write_all(format!(",@{}", kind.to_gas_str()).as_bytes())?;
+            } else if flags.merge.is_some() {
+                file.write_all(b",@progbits")?;
+            }
+            if let Some(size) = flags.merge {
+                file.write_all(format!(",{}", size).as_bytes())?;
+            }
+        }
+        file.write_all(b"\n")
+    }
+
+    /// Mach-O sections are written with their type and attributes
+    /// (`.section __DATA,__mod_init_func,mod_init_funcs`), names already in the `segment,section`
+    /// form are kept
+    fn write_macho_header(&self, file: &mut Writer) -> std::io::Result<()> {
+        let unsupported =
+            || writer::unsupported(&format!("section {} on macOS", self.name), file.syntax());
+        let name = if self.name.contains(',') {
+            Some(self.name.clone())
+        } else {
+            file::SectionType::macho_name(&self.name)
+        };
+        let name = name.ok_or_else(unsupported)?;
+        let flags = self.flags.unwrap_or_default();
+        let kind = match (self.kind, flags.tls) {
+            // `.section` does not accept the zerofill type, `.zerofill` declares the section first
+            (Some(file::SectionType::Nobits), false) => {
+                return file
+                    .write_all(format!("\t.zerofill {}\n\t.section {}\n", name, name).as_bytes())
+            }
+            (Some(file::SectionType::Nobits), true) => "thread_local_zerofill",
+            (Some(file::SectionType::InitArray), _) => "mod_init_funcs",
+            (Some(file::SectionType::FiniArray), _) => "mod_term_funcs",
+            (Some(file::SectionType::Note | file::SectionType::PreinitArray), _) => {
+                return Err(unsupported())
+            }
+            (_, true) => "thread_local_regular",
+            _ => "regular",
+        };
+        file.write_all(format!("\t.section {},{}", name, kind).as_bytes())?;
+        if flags.exec {
+            file.write_all(b",pure_instructions")?;
+        } else if name.starts_with("__DWARF,") {
+            file.write_all(b",debug")?;
+        }
+        file.write_all(b"\n")
+    }
+
+    /// NASM sections use keywords (`section .bss nobits alloc noexec write`)
+    fn write_nasm_header(&self, file: &mut Writer) -> std::io::Result<()> {
+        file.write_all(b"\tsection ")?;
+        file.write_all(self.name.as_bytes())?;
+        if let Some(kind) = self.kind {
+            file.write_all(b" ")?;
+            file.write_all(kind.to_gas_str().as_bytes())?;
+        }
+        if let Some(flags) = self.flags {
+            file.write_all(flags.to_nasm_string().as_bytes())?;
+        }
+        file.write_all(b"\n")
+    }
+}
+
+impl<T: traits::Writable> traits::Writable for NamedSegment<T> {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        match file.syntax() {
+            writer::Syntax::Att | writer::Syntax::Intel => self.write_gas_header(file)?,
+            writer::Syntax::Nasm => self.write_nasm_header(file)?,
+        }
+        file.set_nobits(self.kind == Some(file::SectionType::Nobits));
+        let res = self.data.write_with(file);
+        file.set_nobits(false);
+        res
+    }
+}
+
+impl<T: traits::Writable> std::fmt::Display for NamedSegment<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        traits::fmt_writable(self, f)
     }
 }
 
@@ -269,6 +475,9 @@ pub fn nop() -> Text {
         instr: instr::InstrName::Nop,
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -277,7 +486,7 @@ pub fn inline(str: String) -> Text {
     Text::inline(str)
 }
 
-//// Registers
+// Registers
 
 def_regq!(RAX, Rax);
 def_regq!(RBX, Rbx);
@@ -351,7 +560,41 @@ def_regb!(R13B, R13b);
 def_regb!(R14B, R14b);
 def_regb!(R15B, R15b);
 
-/// Operands
+def_regx!(XMM0, Xmm0);
+def_regx!(XMM1, Xmm1);
+def_regx!(XMM2, Xmm2);
+def_regx!(XMM3, Xmm3);
+def_regx!(XMM4, Xmm4);
+def_regx!(XMM5, Xmm5);
+def_regx!(XMM6, Xmm6);
+def_regx!(XMM7, Xmm7);
+def_regx!(XMM8, Xmm8);
+def_regx!(XMM9, Xmm9);
+def_regx!(XMM10, Xmm10);
+def_regx!(XMM11, Xmm11);
+def_regx!(XMM12, Xmm12);
+def_regx!(XMM13, Xmm13);
+def_regx!(XMM14, Xmm14);
+def_regx!(XMM15, Xmm15);
+
+def_regy!(YMM0, Ymm0);
+def_regy!(YMM1, Ymm1);
+def_regy!(YMM2, Ymm2);
+def_regy!(YMM3, Ymm3);
+def_regy!(YMM4, Ymm4);
+def_regy!(YMM5, Ymm5);
+def_regy!(YMM6, Ymm6);
+def_regy!(YMM7, Ymm7);
+def_regy!(YMM8, Ymm8);
+def_regy!(YMM9, Ymm9);
+def_regy!(YMM10, Ymm10);
+def_regy!(YMM11, Ymm11);
+def_regy!(YMM12, Ymm12);
+def_regy!(YMM13, Ymm13);
+def_regy!(YMM14, Ymm14);
+def_regy!(YMM15, Ymm15);
+
+// Operands
 
 /// Immediate operand for 64-bits instructions
 pub fn immq(imm: i64) -> reg::Operand<reg::RegQ> {
@@ -391,6 +634,8 @@ macro_rules! reg {
 /// addr!(offset, rbp, rax) => offset(%rbp, %rax, 1)
 ///
 /// addr!(offset, rbp, rax, scale) => offset(%rbp, %rax, scale)
+///
+/// addr!(offset, , rax, scale) => offset(, %rax, scale)
 #[macro_export]
 macro_rules! addr {
     ($reg:expr) => {
@@ -402,26 +647,51 @@ macro_rules! addr {
     ($offset:expr, $reg:expr, $reg2:expr) => {
         $crate::reg::Operand::Addr($offset, $reg, Some($reg2), 1)
     };
+    ($offset:expr, , $reg2:expr, $scale:expr) => {
+        $crate::reg::Operand::Index($offset, $reg2, $scale)
+    };
     ($offset:expr, $reg:expr, $reg2:expr, $scale:expr) => {
-        $crate::reg::Operand::Addr($offset, $reg, Some($reg2), scale)
+        $crate::reg::Operand::Addr($offset, $reg, Some($reg2), $scale)
     };
 }
 
-#[cfg(target_os = "macos")]
+/// Create a [`reg::Memory`] operand (destination of atomic instructions), it takes
+/// the same arguments as [`addr!`]
+///
+/// mem!(offset, rdi) => offset(%rdi)
 #[macro_export]
-/// lab operator from <https://www.lri.fr/~filliatr/ens/compil/lib/x86_64.ml.html>
-macro_rules! lab {
-    ($label:expr) => {
-        $crate::reg::Operand::LabRelAddr($label)
+macro_rules! mem {
+    ($reg:expr) => {
+        $crate::reg::Memory::Addr(0, $reg, None, 0)
+    };
+    ($offset:expr, $reg:expr) => {
+        $crate::reg::Memory::Addr($offset, $reg, None, 0)
+    };
+    ($offset:expr, $reg:expr, $reg2:expr) => {
+        $crate::reg::Memory::Addr($offset, $reg, Some($reg2), 1)
+    };
+    ($offset:expr, , $reg2:expr, $scale:expr) => {
+        $crate::reg::Memory::Index($offset, $reg2, $scale)
+    };
+    ($offset:expr, $reg:expr, $reg2:expr, $scale:expr) => {
+        $crate::reg::Memory::Addr($offset, $reg, Some($reg2), $scale)
     };
 }
 
-#[cfg(target_os = "linux")]
 #[macro_export]
 /// lab operator from <https://www.lri.fr/~filliatr/ens/compil/lib/x86_64.ml.html>
+///
+/// lab!(label) => access label for the host target, relatively to %rip as executables
+/// are position independent by default (also on Linux where previous versions used the
+/// absolute address, use `lab!(Target::linux().with_pie(false), label)` to get it)
+///
+/// lab!(target, label) => access label for the given [`target::Target`]
 macro_rules! lab {
     ($label:expr) => {
-        $crate::reg::Operand::LabAbsAddr($label)
+        $crate::target::Target::host().lab($label)
+    };
+    ($target:expr, $label:expr) => {
+        $target.lab($label)
     };
 }
 
@@ -433,11 +703,56 @@ macro_rules! ilab {
     };
 }
 
-//// Instructions
+// Instructions
 
 // Data transfer
 
-build_instr_op_op!(Move, movb, movw, movl, movq);
+// movq is written by hand to move large immediates with movabsq
+build_instr_op_op!(Move, movb, movw, movl, _);
+
+/// Instructions between 8-bytes operands
+///
+/// Immediates that fit neither in 32 bits signed nor in 32 bits unsigned (moved with `movl`
+/// which clears the upper half of the register) are moved with [`movabsq`],
+/// it panics if such an immediate is moved to memory
+pub fn movq(reg1: reg::Operand<reg::RegQ>, reg2: reg::Operand<reg::RegQ>) -> Text {
+    match (&reg1, &reg2) {
+        (reg::Operand::Imm(imm), reg::Operand::Reg(_))
+            if i32::try_from(*imm).is_err() && u32::try_from(*imm).is_err() =>
+        {
+            movabsq(reg1, reg2)
+        }
+        (reg::Operand::Imm(imm), _)
+            if i32::try_from(*imm).is_err() && !matches!(reg2, reg::Operand::Reg(_)) =>
+        {
+            panic!(
+                "immediate {} does not fit in 32 bits, it can only be moved to a register",
+                imm
+            )
+        }
+        _ => Text::new(Box::new(instr::Instruction {
+            instr: instr::InstrName::Move,
+            reg1: Some(reg1),
+            reg2: Some(reg2),
+            reg3: None,
+            lock_rep: None,
+            segment: None,
+        })),
+    }
+}
+
+/// Move of a 64-bits immediate (or label value) to a register, or
+/// between %rax and a 64-bits absolute address ([`reg::Operand::LabAbsAddr`])
+pub fn movabsq(reg1: reg::Operand<reg::RegQ>, reg2: reg::Operand<reg::RegQ>) -> Text {
+    Text::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::Movabs,
+        reg1: Some(reg1),
+        reg2: Some(reg2),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
 
 /// Sign extend for 1-byte to 2-bytes
 pub fn movsbw(reg1: reg::Operand<reg::RegB>, reg2: reg::RegW) -> Text {
@@ -445,6 +760,9 @@ pub fn movsbw(reg1: reg::Operand<reg::RegB>, reg2: reg::RegW) -> Text {
         instr: instr::InstrName::Movs,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -454,6 +772,9 @@ pub fn movsbl(reg1: reg::Operand<reg::RegB>, reg2: reg::RegL) -> Text {
         instr: instr::InstrName::Movs,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -463,6 +784,9 @@ pub fn movsbq(reg1: reg::Operand<reg::RegB>, reg2: reg::RegQ) -> Text {
         instr: instr::InstrName::Movs,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -472,6 +796,9 @@ pub fn movswl(reg1: reg::Operand<reg::RegW>, reg2: reg::RegL) -> Text {
         instr: instr::InstrName::Movs,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -481,6 +808,9 @@ pub fn movswq(reg1: reg::Operand<reg::RegW>, reg2: reg::RegQ) -> Text {
         instr: instr::InstrName::Movs,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -490,59 +820,75 @@ pub fn movslq(reg1: reg::Operand<reg::RegL>, reg2: reg::RegQ) -> Text {
         instr: instr::InstrName::Movs,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
 /// Extension with zeros for 1-byte to 2-bytes
 pub fn movzbw(reg1: reg::Operand<reg::RegB>, reg2: reg::RegW) -> Text {
     Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Movs,
+        instr: instr::InstrName::Movz,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
 /// Extension with zeros for 1-byte to 4-bytes
 pub fn movzbl(reg1: reg::Operand<reg::RegB>, reg2: reg::RegL) -> Text {
     Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Movs,
+        instr: instr::InstrName::Movz,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
 /// Extension with zeros for 1-byte to 8-bytes
 pub fn movzbq(reg1: reg::Operand<reg::RegB>, reg2: reg::RegQ) -> Text {
     Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Movs,
+        instr: instr::InstrName::Movz,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
 /// Extension with zeros for 2-byte to 4-bytes
 pub fn movzwl(reg1: reg::Operand<reg::RegW>, reg2: reg::RegL) -> Text {
     Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Movs,
+        instr: instr::InstrName::Movz,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
 /// Extension with zeros for 2-byte to 8-bytes
 pub fn movzwq(reg1: reg::Operand<reg::RegW>, reg2: reg::RegQ) -> Text {
     Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Movs,
+        instr: instr::InstrName::Movz,
         reg1: Some(reg1),
         reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
 // Move between different sizes not implemented
 
-// movabsq not implemented
-
-//// Arithmetic
+// Arithmetic
 
 build_instr_op_reg!(Lea, leab, leaw, leal, leaq);
 
@@ -558,12 +904,34 @@ build_instr_op_op!(Sub, subb, subw, subl, subq);
 
 build_instr_op_op!(IMul, imulw, imull, imulq);
 
+// imul $imm, src, dst
+build_instr_imm_op_reg!(IMulImm, imulw_imm, imull_imm, imulq_imm);
+
+// Multiplications with a result twice as large as the operands, the accumulator is multiplied
+build_instr_widening!(
+    UnsignedMul,
+    mulb: "Unsigned multiplication of %al by the operand, reads %al and writes %ax",
+    mulw: "Unsigned multiplication of %ax by the operand, reads %ax and writes %dx:%ax",
+    mull: "Unsigned multiplication of %eax by the operand, reads %eax and writes %edx:%eax",
+    mulq: "Unsigned multiplication of %rax by the operand, reads %rax and writes %rdx:%rax"
+);
+build_instr_widening!(
+    SignedMul,
+    imulb_wide: "Signed multiplication of %al by the operand, reads %al and writes %ax",
+    imulw_wide: "Signed multiplication of %ax by the operand, reads %ax and writes %dx:%ax",
+    imull_wide: "Signed multiplication of %eax by the operand, reads %eax and writes %edx:%eax",
+    imulq_wide: "Signed multiplication of %rax by the operand, reads %rax and writes %rdx:%rax"
+);
+
 /// sign extend EAX into EDX::EAX
 pub fn cltd() -> Text {
     Text::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
         instr: instr::InstrName::Cltd,
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -573,6 +941,9 @@ pub fn cqto() -> Text {
         instr: instr::InstrName::Cqto,
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -580,7 +951,7 @@ build_instr_op!(SignedDiv, idivl, idivq);
 
 build_instr_op!(UnsignedDiv, divl, divq);
 
-///// Logic operations
+// Logic operations
 // Those operations are bitwise operations
 
 build_instr_op!(Not, notb, notw, notl, notq);
@@ -591,85 +962,328 @@ build_instr_op_op!(Or, orb, orw, orl, orq);
 
 build_instr_op_op!(Xor, xorb, xorw, xorl, xorq);
 
-//// Shifts
+// Shifts
 
 build_instr_op_op!(Shl, shlb, shlw, shll, shlq);
 build_instr_op_op!(Shr, shrb, shrw, shrl, shrq);
 build_instr_op_op!(Sar, sarb, sarw, sarl, sarq);
 
-/// logical shift of register by value in CL
-pub fn shlb_reg(reg: reg::Operand<reg::RegB>) -> Text {
+// Rotations, rcl and rcr rotate through the carry flag
+
+build_instr_op_op!(Rol, rolb, rolw, roll, rolq);
+build_instr_op_op!(Ror, rorb, rorw, rorl, rorq);
+build_instr_op_op!(Rcl, rclb, rclw, rcll, rclq);
+build_instr_op_op!(Rcr, rcrb, rcrw, rcrl, rcrq);
+
+// Shifts and rotations by the value in %cl
+
+build_instr_shift_cl!(ShlC, shlb_reg, shlw_reg, shll_reg, shlq_reg);
+build_instr_shift_cl!(ShrC, shrb_reg, shrw_reg, shrl_reg, shrq_reg);
+build_instr_shift_cl!(SarC, sarb_reg, sarw_reg, sarl_reg, sarq_reg);
+build_instr_shift_cl!(RolC, rolb_reg, rolw_reg, roll_reg, rolq_reg);
+build_instr_shift_cl!(RorC, rorb_reg, rorw_reg, rorl_reg, rorq_reg);
+build_instr_shift_cl!(RclC, rclb_reg, rclw_reg, rcll_reg, rclq_reg);
+build_instr_shift_cl!(RcrC, rcrb_reg, rcrw_reg, rcrl_reg, rcrq_reg);
+
+// Double shifts, the bits shifted in the destination come from the second operand
+
+build_instr_double_shift!(Shld, ShldC, shldw, shldl, shldq, shldw_reg, shldl_reg, shldq_reg);
+build_instr_double_shift!(Shrd, ShrdC, shrdw, shrdl, shrdq, shrdw_reg, shrdl_reg, shrdq_reg);
+
+// Bit manipulation
+// the index of bt is taken modulo the size for registers, it can address any bit in memory
+
+build_instr_op_op!(Bt, btw, btl, btq);
+build_instr_op_op!(Bts, btsw, btsl, btsq);
+build_instr_op_op!(Btr, btrw, btrl, btrq);
+build_instr_op_op!(Btc, btcw, btcl, btcq);
+
+build_instr_op_reg!(Bsf, bsfw, bsfl, bsfq);
+build_instr_op_reg!(Bsr, bsrw, bsrl, bsrq);
+build_instr_op_reg!(Popcnt, popcntw, popcntl, popcntq);
+build_instr_op_reg!(Lzcnt, lzcntw, lzcntl, lzcntq);
+build_instr_op_reg!(Tzcnt, tzcntw, tzcntl, tzcntq);
+
+// BMI1 and BMI2, the operands are in AT&T order (`andnq(reg!(RCX), RBX, RAX)` computes %rax = !%rbx & %rcx)
+build_instr_op_reg!(Blsi, blsil, blsiq);
+build_instr_op_reg!(Blsr, blsrl, blsrq);
+build_instr_op_reg_reg!(Andn, andnl, andnq);
+build_instr_op_reg_reg!(Pdep, pdepl, pdepq);
+build_instr_op_reg_reg!(Pext, pextl, pextq);
+// the first operand is the index of bzhi or the count of shifts
+build_instr_reg_op_reg!(Bzhi, bzhil, bzhiq);
+build_instr_reg_op_reg!(Shlx, shlxl, shlxq);
+build_instr_reg_op_reg!(Shrx, shrxl, shrxq);
+build_instr_reg_op_reg!(Sarx, sarxl, sarxq);
+// %rdx is multiplied, `mulxq(reg!(RCX), RBX, RAX)` stores the low half in %rbx and the high half in %rax
+build_instr_op_reg_reg!(Mulx, mulxl, mulxq);
+
+/// Right rotation of a 4-bytes operand by an immediate
+pub fn rorxl(imm: reg::Operand<reg::RegL>, reg2: reg::Operand<reg::RegL>, reg3: reg::RegL) -> Text {
     Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Shl,
-        reg1: Some(reg!(CL)),
-        reg2: Some(reg),
+        instr: instr::InstrName::Rorx,
+        reg1: Some(imm),
+        reg2: Some(reg2),
+        reg3: Some(reg!(reg3)),
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-/// logical shift of register by value in CL
-pub fn shlw_reg(reg: reg::Operand<reg::RegW>) -> Text {
+/// Right rotation of a 8-bytes operand by an immediate
+pub fn rorxq(imm: reg::Operand<reg::RegQ>, reg2: reg::Operand<reg::RegQ>, reg3: reg::RegQ) -> Text {
     Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Shl,
-        reg1: Some(reg!(CL)),
-        reg2: Some(reg),
+        instr: instr::InstrName::Rorx,
+        reg1: Some(imm),
+        reg2: Some(reg2),
+        reg3: Some(reg!(reg3)),
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-/// logical shift of register by value in CL
-pub fn shll_reg(reg: reg::Operand<reg::RegL>) -> Text {
-    Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Shl,
-        reg1: Some(reg!(CL)),
-        reg2: Some(reg),
+// String instructions, the direction flag is assumed cleared so %rsi and %rdi are incremented,
+// the operands are in the AT&T order
+
+build_instr_string!(
+    MovString,
+    "Copy the element at (%rsi) to (%rdi), %rsi and %rdi are advanced",
+    Rsi,
+    Rdi,
+    movsb,
+    movsw,
+    movsl,
+    movsq
+);
+build_instr_string!(
+    StoreString,
+    "Store the accumulator (%al, %ax, %eax or %rax) at (%rdi), %rdi is advanced",
+    acc,
+    Rdi,
+    stosb,
+    stosw,
+    stosl,
+    stosq
+);
+build_instr_string!(
+    LoadString,
+    "Load the element at (%rsi) in the accumulator (%al, %ax, %eax or %rax), %rsi is advanced",
+    Rsi,
+    acc,
+    lodsb,
+    lodsw,
+    lodsl,
+    lodsq
+);
+build_instr_string!(
+    CmpString,
+    "Compare the element at (%rsi) with the one at (%rdi), %rsi and %rdi are advanced",
+    Rdi,
+    Rsi,
+    cmpsb,
+    cmpsw,
+    cmpsl,
+    cmpsq
+);
+build_instr_string!(
+    ScanString,
+    "Compare the accumulator (%al, %ax, %eax or %rax) with the element at (%rdi), %rdi is advanced",
+    Rdi,
+    acc,
+    scasb,
+    scasw,
+    scasl,
+    scasq
+);
+
+// `rep` repeats an instruction %rcx times, `repe` and `repne` stop earlier when the
+// comparison is different or equal (%rcx is decremented at each step)
+
+build_instr_string!(
+    rep Rep MovString,
+    "Copy %rcx elements from (%rsi) to (%rdi) (`memcpy`)",
+    Rsi,
+    Rdi,
+    rep_movsb,
+    rep_movsw,
+    rep_movsl,
+    rep_movsq
+);
+build_instr_string!(
+    rep Rep StoreString,
+    "Store the accumulator (%al, %ax, %eax or %rax) in %rcx elements from (%rdi) (`memset`)",
+    acc,
+    Rdi,
+    rep_stosb,
+    rep_stosw,
+    rep_stosl,
+    rep_stosq
+);
+build_instr_string!(
+    rep Rep LoadString,
+    "Load %rcx elements from (%rsi) in the accumulator (%al, %ax, %eax or %rax), only the last one is kept",
+    Rsi,
+    acc,
+    rep_lodsb,
+    rep_lodsw,
+    rep_lodsl,
+    rep_lodsq
+);
+build_instr_string!(
+    rep Repe CmpString,
+    "Compare the elements from (%rsi) and (%rdi) while they are equal, at most %rcx times (`memcmp`)",
+    Rdi,
+    Rsi,
+    repe_cmpsb,
+    repe_cmpsw,
+    repe_cmpsl,
+    repe_cmpsq
+);
+build_instr_string!(
+    rep Repne CmpString,
+    "Compare the elements from (%rsi) and (%rdi) while they are different, at most %rcx times",
+    Rdi,
+    Rsi,
+    repne_cmpsb,
+    repne_cmpsw,
+    repne_cmpsl,
+    repne_cmpsq
+);
+build_instr_string!(
+    rep Repe ScanString,
+    "Compare the accumulator (%al, %ax, %eax or %rax) with the elements from (%rdi) while they are equal, at most %rcx times",
+    Rdi,
+    acc,
+    repe_scasb,
+    repe_scasw,
+    repe_scasl,
+    repe_scasq
+);
+build_instr_string!(
+    rep Repne ScanString,
+    "Compare the accumulator (%al, %ax, %eax or %rax) with the elements from (%rdi) while they are different, at most %rcx times (`strlen`)",
+    Rdi,
+    acc,
+    repne_scasb,
+    repne_scasw,
+    repne_scasl,
+    repne_scasq
+);
+
+// Atomics, xchg with memory is always atomic, the other instructions need the lock prefix
+
+build_instr_op_op!(Xchg, xchgb, xchgw, xchgl, xchgq);
+build_instr_reg_op!(Cmpxchg, cmpxchgb, cmpxchgw, cmpxchgl, cmpxchgq);
+build_instr_reg_op!(Xadd, xaddb, xaddw, xaddl, xaddq);
+
+/// Compare and exchange of 8 bytes in memory with %edx:%eax and %ecx:%ebx
+pub fn cmpxchg8b(mem: reg::Memory) -> Text {
+    Text::new(Box::new(instr::Instruction::<reg::RegQ, reg::RegInv> {
+        instr: instr::InstrName::Cmpxchg8b,
+        reg1: Some(mem.into()),
+        reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-/// logical shift of register by value in CL
-pub fn shlq_reg(reg: reg::Operand<reg::RegQ>) -> Text {
-    Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Shl,
-        reg1: Some(reg!(CL)),
-        reg2: Some(reg),
+/// Compare and exchange of 16 bytes in memory with %rdx:%rax and %rcx:%rbx
+pub fn cmpxchg16b(mem: reg::Memory) -> Text {
+    Text::new(Box::new(instr::Instruction::<reg::RegQ, reg::RegInv> {
+        instr: instr::InstrName::Cmpxchg16b,
+        reg1: Some(mem.into()),
+        reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-/// logical shift of register by value in CL
-pub fn shrb_reg(reg: reg::Operand<reg::RegB>) -> Text {
-    Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Shr,
-        reg1: Some(reg!(CL)),
-        reg2: Some(reg),
+build_instr_lock!(Add, lock_addb, lock_addw, lock_addl, lock_addq);
+build_instr_lock!(Sub, lock_subb, lock_subw, lock_subl, lock_subq);
+build_instr_lock!(And, lock_andb, lock_andw, lock_andl, lock_andq);
+build_instr_lock!(Or, lock_orb, lock_orw, lock_orl, lock_orq);
+build_instr_lock!(Xor, lock_xorb, lock_xorw, lock_xorl, lock_xorq);
+build_instr_lock!(Adc, lock_adcb, lock_adcw, lock_adcl, lock_adcq);
+build_instr_lock!(Sbb, lock_sbbb, lock_sbbw, lock_sbbl, lock_sbbq);
+build_instr_lock!(unary Inc, lock_incb, lock_incw, lock_incl, lock_incq);
+build_instr_lock!(unary Dec, lock_decb, lock_decw, lock_decl, lock_decq);
+build_instr_lock!(unary Neg, lock_negb, lock_negw, lock_negl, lock_negq);
+build_instr_lock!(unary Not, lock_notb, lock_notw, lock_notl, lock_notq);
+build_instr_lock!(bit Bts, lock_btsw, lock_btsl, lock_btsq);
+build_instr_lock!(bit Btr, lock_btrw, lock_btrl, lock_btrq);
+build_instr_lock!(bit Btc, lock_btcw, lock_btcl, lock_btcq);
+build_instr_lock!(
+    reg Cmpxchg,
+    lock_cmpxchgb,
+    lock_cmpxchgw,
+    lock_cmpxchgl,
+    lock_cmpxchgq
+);
+build_instr_lock!(reg Xadd, lock_xaddb, lock_xaddw, lock_xaddl, lock_xaddq);
+
+/// Atomic compare and exchange of 8 bytes in memory with %edx:%eax and %ecx:%ebx
+pub fn lock_cmpxchg8b(mem: reg::Memory) -> Text {
+    Text::new(Box::new(instr::Instruction::<reg::RegQ, reg::RegInv> {
+        instr: instr::InstrName::Cmpxchg8b,
+        reg1: Some(mem.into()),
+        reg2: None,
+        reg3: None,
+        lock_rep: Some(instr::Prefix::Lock),
+        segment: None,
     }))
 }
 
-/// logical shift of register by value in CL
-pub fn shrw_reg(reg: reg::Operand<reg::RegW>) -> Text {
-    Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Shr,
-        reg1: Some(reg!(CL)),
-        reg2: Some(reg),
+/// Atomic compare and exchange of 16 bytes in memory with %rdx:%rax and %rcx:%rbx
+pub fn lock_cmpxchg16b(mem: reg::Memory) -> Text {
+    Text::new(Box::new(instr::Instruction::<reg::RegQ, reg::RegInv> {
+        instr: instr::InstrName::Cmpxchg16b,
+        reg1: Some(mem.into()),
+        reg2: None,
+        reg3: None,
+        lock_rep: Some(instr::Prefix::Lock),
+        segment: None,
     }))
 }
 
-/// logical shift of register by value in CL
-pub fn shrl_reg(reg: reg::Operand<reg::RegL>) -> Text {
-    Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Shr,
-        reg1: Some(reg!(CL)),
-        reg2: Some(reg),
+/// Memory barrier, loads and stores before it are visible before the ones after it
+pub fn mfence() -> Text {
+    Text::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
+        instr: instr::InstrName::Mfence,
+        reg1: None,
+        reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-/// logical shift of register by value in CL
-pub fn shrq_reg(reg: reg::Operand<reg::RegQ>) -> Text {
-    Text::new(Box::new(instr::Instruction {
-        instr: instr::InstrName::Shr,
-        reg1: Some(reg!(CL)),
-        reg2: Some(reg),
+/// Load barrier
+pub fn lfence() -> Text {
+    Text::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
+        instr: instr::InstrName::Lfence,
+        reg1: None,
+        reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-//// Jumps
+/// Store barrier
+pub fn sfence() -> Text {
+    Text::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
+        instr: instr::InstrName::Sfence,
+        reg1: None,
+        reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+// Jumps
 
 // Function calls and return
 
@@ -679,6 +1293,9 @@ pub fn call(label: reg::Label) -> Text {
         instr: instr::InstrName::Call(label),
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -688,6 +1305,9 @@ pub fn call_star(op: reg::Operand<reg::RegQ>) -> Text {
         instr: instr::InstrName::CallStar,
         reg1: Some(op),
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -697,6 +1317,9 @@ pub fn leave() -> Text {
         instr: instr::InstrName::Leave,
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -706,6 +1329,9 @@ pub fn syscall() -> Text {
         instr: instr::InstrName::Syscall,
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -715,6 +1341,9 @@ pub fn ret() -> Text {
         instr: instr::InstrName::Ret,
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -724,6 +1353,9 @@ pub fn jmp(label: reg::Label) -> Text {
         instr: instr::InstrName::Jump(label),
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -733,10 +1365,13 @@ pub fn jmp_star(op: reg::Operand<reg::RegQ>) -> Text {
         instr: instr::InstrName::JumpStar,
         reg1: Some(op),
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-////// Conditional jumps
+// Conditional jumps
 
 /// Conditional jump
 pub fn jcc(cond: instr::Cond, label: reg::Label) -> Text {
@@ -744,37 +1379,63 @@ pub fn jcc(cond: instr::Cond, label: reg::Label) -> Text {
         instr: instr::InstrName::CondJump(cond, label),
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-/// Conditional jump if zero
-pub fn jz(label: reg::Label) -> Text {
+// jrcxz and loop only have a 8-bits displacement, the label must be close
+
+/// Jump if %rcx is 0
+pub fn jrcxz(label: reg::Label) -> Text {
     Text::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
-        instr: instr::InstrName::CondJump(instr::Cond::Z, label),
+        instr: instr::InstrName::Jrcxz(label),
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-/// Conditional jump if not zero
-pub fn jnz(label: reg::Label) -> Text {
+/// Decrement %rcx and jump if it is not 0
+pub fn loop_(label: reg::Label) -> Text {
     Text::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
-        instr: instr::InstrName::CondJump(instr::Cond::NZ, label),
+        instr: instr::InstrName::Loop(label),
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-/// Conditional jump if above equal
-pub fn jae(label: reg::Label) -> Text {
+/// Decrement %rcx and jump if it is not 0 and the last comparison was equal
+pub fn loope(label: reg::Label) -> Text {
     Text::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
-        instr: instr::InstrName::CondJump(instr::Cond::AE, label),
+        instr: instr::InstrName::Loope(label),
         reg1: None,
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-//// Conditions
+/// Decrement %rcx and jump if it is not 0 and the last comparison was not equal
+pub fn loopne(label: reg::Label) -> Text {
+    Text::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
+        instr: instr::InstrName::Loopne(label),
+        reg1: None,
+        reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+// Conditions
 
 build_instr_op_op!(Cmp, cmpb, cmpw, cmpl, cmpq);
 
@@ -786,10 +1447,102 @@ pub fn set(cond: instr::Cond, reg: reg::Operand<reg::RegB>) -> Text {
         instr: instr::InstrName::Set(cond),
         reg1: Some(reg),
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-//// Stack handling
+// Jump, set and conditional moves for each condition, see [`instr::Cond`]
+
+build_instr_cond!(E, "equal", je, sete, cmovew, cmovel, cmoveq);
+build_instr_cond!(Z, "zero", jz, setz, cmovzw, cmovzl, cmovzq);
+build_instr_cond!(NE, "not equal", jne, setne, cmovnew, cmovnel, cmovneq);
+build_instr_cond!(NZ, "not zero", jnz, setnz, cmovnzw, cmovnzl, cmovnzq);
+build_instr_cond!(S, "negative", js, sets, cmovsw, cmovsl, cmovsq);
+build_instr_cond!(NS, "non-negative", jns, setns, cmovnsw, cmovnsl, cmovnsq);
+build_instr_cond!(G, "greater", jg, setg, cmovgw, cmovgl, cmovgq);
+build_instr_cond!(
+    GE,
+    "greater or equal",
+    jge,
+    setge,
+    cmovgew,
+    cmovgel,
+    cmovgeq
+);
+build_instr_cond!(L, "lower", jl, setl, cmovlw, cmovll, cmovlq);
+build_instr_cond!(LE, "lower or equal", jle, setle, cmovlew, cmovlel, cmovleq);
+build_instr_cond!(
+    A,
+    "above (unsigned greater)",
+    ja,
+    seta,
+    cmovaw,
+    cmoval,
+    cmovaq
+);
+build_instr_cond!(AE, "above or equal", jae, setae, cmovaew, cmovael, cmovaeq);
+build_instr_cond!(
+    B,
+    "below (unsigned lower)",
+    jb,
+    setb,
+    cmovbw,
+    cmovbl,
+    cmovbq
+);
+build_instr_cond!(BE, "below or equal", jbe, setbe, cmovbew, cmovbel, cmovbeq);
+build_instr_cond!(O, "overflow", jo, seto, cmovow, cmovol, cmovoq);
+build_instr_cond!(NO, "no overflow", jno, setno, cmovnow, cmovnol, cmovnoq);
+build_instr_cond!(P, "parity even", jp, setp, cmovpw, cmovpl, cmovpq);
+build_instr_cond!(PE, "parity even", jpe, setpe, cmovpew, cmovpel, cmovpeq);
+build_instr_cond!(NP, "parity odd", jnp, setnp, cmovnpw, cmovnpl, cmovnpq);
+build_instr_cond!(PO, "parity odd", jpo, setpo, cmovpow, cmovpol, cmovpoq);
+build_instr_cond!(C, "carry", jc, setc, cmovcw, cmovcl, cmovcq);
+build_instr_cond!(NC, "no carry", jnc, setnc, cmovncw, cmovncl, cmovncq);
+build_instr_cond!(NA, "not above", jna, setna, cmovnaw, cmovnal, cmovnaq);
+build_instr_cond!(
+    NAE,
+    "not above or equal",
+    jnae,
+    setnae,
+    cmovnaew,
+    cmovnael,
+    cmovnaeq
+);
+build_instr_cond!(NB, "not below", jnb, setnb, cmovnbw, cmovnbl, cmovnbq);
+build_instr_cond!(
+    NBE,
+    "not below or equal",
+    jnbe,
+    setnbe,
+    cmovnbew,
+    cmovnbel,
+    cmovnbeq
+);
+build_instr_cond!(NG, "not greater", jng, setng, cmovngw, cmovngl, cmovngq);
+build_instr_cond!(
+    NGE,
+    "not greater or equal",
+    jnge,
+    setnge,
+    cmovngew,
+    cmovngel,
+    cmovngeq
+);
+build_instr_cond!(NL, "not lower", jnl, setnl, cmovnlw, cmovnll, cmovnlq);
+build_instr_cond!(
+    NLE,
+    "not lower or equal",
+    jnle,
+    setnle,
+    cmovnlew,
+    cmovnlel,
+    cmovnleq
+);
+
+// Stack handling
 
 /// Push 8-bytes on stack
 pub fn pushq(op: reg::Operand<reg::RegQ>) -> Text {
@@ -797,6 +1550,9 @@ pub fn pushq(op: reg::Operand<reg::RegQ>) -> Text {
         instr: instr::InstrName::Push,
         reg1: Some(op),
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -806,42 +1562,209 @@ pub fn popq(reg: reg::RegQ) -> Text {
         instr: instr::InstrName::Pop,
         reg1: Some(reg!(reg)),
         reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
-//// Various others
+// Floating point (SSE)
+// ss and sd suffixes are for scalars, ps and pd for 16 bytes of floats
+
+build_instr_float!(move MovFloat, movss, movsd);
+// memory must be aligned on 16 bytes
+build_instr_float!(move MovAligned, movaps, movapd);
+build_instr_float!(move MovUnaligned, movups, movupd);
+
+build_instr_float!(AddFloat, addss, addsd);
+build_instr_float!(SubFloat, subss, subsd);
+build_instr_float!(MulFloat, mulss, mulsd);
+build_instr_float!(DivFloat, divss, divsd);
+build_instr_float!(SqrtFloat, sqrtss, sqrtsd);
+
+// set ZF, PF and CF like an unsigned comparison, PF is set if a float is NaN
+build_instr_float!(Ucomis, ucomiss, ucomisd);
+build_instr_float!(Comis, comiss, comisd);
+
+// conversions to integers are rounded toward zero
+build_instr_float!(CvtIntToFloat, cvtsi2ssl, cvtsi2sdl, reg::RegL, reg::RegX);
+build_instr_float!(CvtIntToFloat, cvtsi2ssq, cvtsi2sdq, reg::RegQ, reg::RegX);
+build_instr_float!(CvtFloatToInt, cvttss2sil, cvttsd2sil, reg::RegX, reg::RegL);
+build_instr_float!(CvtFloatToInt, cvttss2siq, cvttsd2siq, reg::RegX, reg::RegQ);
+build_instr_float!(CvtFloat, cvtsd2ss, cvtss2sd);
+
+// bitwise operations (xorpd %xmm0, %xmm0 sets %xmm0 to 0)
+build_instr_float!(AndFloat, andps, andpd);
+build_instr_float!(XorFloat, xorps, xorpd);
+
+/// Move of a 4-bytes register to the lowest bytes of a XMM register (the other ones are set to 0)
+pub fn movd_to_xmm(reg1: reg::RegL, reg2: reg::RegX) -> Text {
+    Text::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::MovdXmm,
+        reg1: Some(reg!(reg1)),
+        reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+/// Move of the lowest 4 bytes of a XMM register to a 4-bytes register
+pub fn movd_from_xmm(reg1: reg::RegX, reg2: reg::RegL) -> Text {
+    Text::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::MovdXmm,
+        reg1: Some(reg!(reg1)),
+        reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+/// Move of a 8-bytes register to the lowest bytes of a XMM register (the other ones are set to 0)
+pub fn movq_to_xmm(reg1: reg::RegQ, reg2: reg::RegX) -> Text {
+    Text::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::MovqXmm,
+        reg1: Some(reg!(reg1)),
+        reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+/// Move of the lowest 8 bytes of a XMM register to a 8-bytes register
+pub fn movq_from_xmm(reg1: reg::RegX, reg2: reg::RegQ) -> Text {
+    Text::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::MovqXmm,
+        reg1: Some(reg!(reg1)),
+        reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+// AVX (VEX encoding), ps and pd suffixes are for 8 floats or 4 doubles
+
+build_instr_avx!(move VMovAligned, vmovaps, vmovapd);
+build_instr_avx!(move VMovUnaligned, vmovups, vmovupd);
+// memory must be aligned on 32 bytes
+build_instr_avx!(move VMovdqa, vmovdqa);
+build_instr_avx!(move VMovdqu, vmovdqu);
+
+build_instr_avx!(VAdd, vaddps, vaddpd);
+build_instr_avx!(VSub, vsubps, vsubpd);
+build_instr_avx!(VMul, vmulps, vmulpd);
+build_instr_avx!(VDiv, vdivps, vdivpd);
+build_instr_avx!(VXor, vxorps, vxorpd);
+
+// FMA, the numbers give the order of the operands (from the last one): 231 computes reg3 = reg2 * reg1 + reg3
+build_instr_avx!(VFmadd132, vfmadd132ps, vfmadd132pd);
+build_instr_avx!(VFmadd213, vfmadd213ps, vfmadd213pd);
+build_instr_avx!(VFmadd231, vfmadd231ps, vfmadd231pd);
+
+build_instr_avx!(packed VPAdd, vpaddb, vpaddw, vpaddd, vpaddq);
+build_instr_avx!(packed VPSub, vpsubb, vpsubw, vpsubd, vpsubq);
+build_instr_avx!(packed VPCmpEq, vpcmpeqb, vpcmpeqw, vpcmpeqd, vpcmpeqq);
+build_instr_avx!(VPAnd, vpand);
+build_instr_avx!(VPOr, vpor);
+build_instr_avx!(VPXor, vpxor);
+
+/// Mask of the highest bit of each of the 32 bytes of a YMM register
+pub fn vpmovmskb(reg1: reg::RegY, reg2: reg::RegL) -> Text {
+    Text::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::VPMovMskB,
+        reg1: Some(reg!(reg1)),
+        reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+/// Copy of a 4-bytes float (lowest bytes of a XMM register or in memory) in the 8 floats of a YMM register
+pub fn vbroadcastss(reg1: reg::Operand<reg::RegX>, reg2: reg::RegY) -> Text {
+    Text::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::VBroadcast(instr::Precision::Single),
+        reg1: Some(reg1),
+        reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+/// Copy of a 8-bytes float (lowest bytes of a XMM register or in memory) in the 4 floats of a YMM register
+pub fn vbroadcastsd(reg1: reg::Operand<reg::RegX>, reg2: reg::RegY) -> Text {
+    Text::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::VBroadcast(instr::Precision::Double),
+        reg1: Some(reg1),
+        reg2: Some(reg!(reg2)),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+/// Clear the upper half of all YMM registers, should be used before calling SSE code
+pub fn vzeroupper() -> Text {
+    Segment::new(Box::new(instr::Instruction::<reg::RegInv, reg::RegInv> {
+        instr: instr::InstrName::VZeroUpper,
+        reg1: None,
+        reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: None,
+    }))
+}
+
+// Various others
 
 /// Add comment to Assembly (should not contain de line break!)
 pub fn comment(s: String) -> Text {
     Text::comment(s)
 }
 
-#[cfg(target_os = "linux")]
-/// Move address of label in register (implementation is OS dependant)
+impl Text {
+    fn in_segment(mut self, segment: instr::SegmentOverride) -> Self {
+        match self.data.as_mut_slice() {
+            [SegmentELWrapper {
+                el: SegmentEL::Data(instr),
+                ..
+            }] => instr.set_segment(segment),
+            _ => panic!("The segment {:?} expects a single instruction", segment),
+        }
+        self
+    }
+}
+
+/// Access the memory operand of the instruction (or the (%rsi) source of `movs`, `lods` and
+/// `cmps`) in the %fs segment, thread local storage on Linux: `fs(movq(addr!(8, RAX), reg!(RDX)))`
 ///
-/// Usefull to get address to string before calling printf
+/// Panics if `text` is not a single instruction with such an operand
+pub fn fs(text: Text) -> Text {
+    text.in_segment(instr::SegmentOverride::Fs)
+}
+
+/// Access the memory operand of the instruction (or the (%rsi) source of `movs`, `lods` and
+/// `cmps`) in the %gs segment, thread local storage on macOS
 ///
-/// Not sure it works on linux, needs to test!
-pub fn deplq(l: reg::Label, reg: reg::RegQ) -> Text {
-    Text::new(Box::new(instr::Instruction::<reg::RegQ, reg::RegQ> {
-        instr: instr::InstrName::Move,
-        reg1: Some(reg::Operand::LabAbsAddr(l)),
-        reg2: Some(reg!(reg)),
-    }))
+/// Panics if `text` is not a single instruction with such an operand
+pub fn gs(text: Text) -> Text {
+    text.in_segment(instr::SegmentOverride::Gs)
 }
 
-#[cfg(target_os = "macos")]
-/// Move address of label in register (implementation is OS dependant)
+/// Move address of label in register for the host target
 ///
 /// Usefull to get address to string before calling printf
 ///
-/// Not sure it works on linux, needs to test!
+/// It is `leaq label(%rip), %reg` on every host (previous versions wrote
+/// `movq label, %reg` on Linux, which reads the memory at the label)
+///
+/// See [`target::Target::deplq`] to choose the target
 pub fn deplq(l: reg::Label, reg: reg::RegQ) -> Text {
-    Text::new(Box::new(instr::Instruction::<reg::RegQ, reg::RegQ> {
-        instr: instr::InstrName::Lea,
-        reg1: Some(reg::Operand::LabAbsAddr(l)),
-        reg2: Some(reg!(reg)),
-    }))
+    target::Target::host().deplq(l, reg)
 }
 
 // cmovb is not valid
@@ -856,6 +1779,9 @@ pub fn cmovw(
         instr: instr::InstrName::Cmov(cond),
         reg1: Some(reg1),
         reg2: Some(reg2),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -869,6 +1795,9 @@ pub fn cmovl(
         instr: instr::InstrName::Cmov(cond),
         reg1: Some(reg1),
         reg2: Some(reg2),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -882,6 +1811,9 @@ pub fn cmovq(
         instr: instr::InstrName::Cmov(cond),
         reg1: Some(reg1),
         reg2: Some(reg2),
+        reg3: None,
+        lock_rep: None,
+        segment: None,
     }))
 }
 
@@ -26,105 +26,249 @@ macro_rules! def_regb {
     };
 }
 
+macro_rules! def_regx {
+    ($name1:ident, $name2:ident) => {
+        /// 128-bits registers of floating point instructions (16 bytes)
+        pub const $name1: reg::RegX = reg::RegX::$name2;
+    };
+}
+
+macro_rules! def_regy {
+    ($name1:ident, $name2:ident) => {
+        /// 256-bits registers of AVX instructions (32 bytes)
+        pub const $name1: reg::RegY = reg::RegY::$name2;
+    };
+}
+
 macro_rules! build_instr_op_op {
     ($op:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
-        /// Instructions between 1-bytes operands
-        pub fn $nameb(reg1: reg::Operand<reg::RegB>, reg2: reg::Operand<reg::RegB>) -> Text {
+        build_instr_op_op!(@ $op, reg::RegB, "1-bytes", $nameb);
+        build_instr_op_op!($op, $namew, $namel, $nameq);
+    };
+
+    // without the 8-bytes instruction, when it is written by hand
+    ($op:ident, $nameb:ident, $namew:ident, $namel:ident, _) => {
+        build_instr_op_op!(@ $op, reg::RegB, "1-bytes", $nameb);
+        build_instr_op_op!(@ $op, reg::RegW, "2-bytes", $namew);
+        build_instr_op_op!(@ $op, reg::RegL, "4-bytes", $namel);
+    };
+
+    ($op:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_op_op!(@ $op, reg::RegW, "2-bytes", $namew);
+        build_instr_op_op!($op, $namel, $nameq);
+    };
+
+    ($op:ident, $namel:ident, $nameq:ident) => {
+        build_instr_op_op!(@ $op, reg::RegL, "4-bytes", $namel);
+        build_instr_op_op!($op, $nameq);
+    };
+
+    ($op:ident, $nameq:ident) => {
+        build_instr_op_op!(@ $op, reg::RegQ, "8-bytes", $nameq);
+    };
+
+    (@ $op:ident, $size:ty, $bytes:literal, $name:ident) => {
+        #[doc = concat!("Instructions between ", $bytes, " operands")]
+        pub fn $name(reg1: reg::Operand<$size>, reg2: reg::Operand<$size>) -> Text {
             Text::new(Box::new(instr::Instruction {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg1),
                 reg2: Some(reg2),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
+    };
+}
 
-        build_instr_op_op!($op, $namew, $namel, $nameq);
+macro_rules! build_instr_op_reg {
+    ($op:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        /// Instructions between 1-bytes operands
+        pub fn $nameb(reg1: reg::Operand<reg::RegB>, reg2: reg::RegB) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg1),
+                reg2: Some(reg!(reg2)),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+
+        build_instr_op_reg!($op, $namew, $namel, $nameq);
     };
 
     ($op:ident, $namew:ident, $namel:ident, $nameq:ident) => {
         /// Instructions between 2-bytes operands
-        pub fn $namew(reg1: reg::Operand<reg::RegW>, reg2: reg::Operand<reg::RegW>) -> Text {
+        pub fn $namew(reg1: reg::Operand<reg::RegW>, reg2: reg::RegW) -> Text {
             Text::new(Box::new(instr::Instruction {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg1),
-                reg2: Some(reg2),
+                reg2: Some(reg!(reg2)),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
 
-        build_instr_op_op!($op, $namel, $nameq);
+        build_instr_op_reg!($op, $namel, $nameq);
     };
 
     ($op:ident, $namel:ident, $nameq:ident) => {
         /// Instructions between 4-bytes operands
-        pub fn $namel(reg1: reg::Operand<reg::RegL>, reg2: reg::Operand<reg::RegL>) -> Text {
+        pub fn $namel(reg1: reg::Operand<reg::RegL>, reg2: reg::RegL) -> Text {
             Text::new(Box::new(instr::Instruction {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg1),
-                reg2: Some(reg2),
+                reg2: Some(reg!(reg2)),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
 
-        build_instr_op_op!($op, $nameq);
+        build_instr_op_reg!($op, $nameq);
     };
 
     ($op:ident, $nameq:ident) => {
         /// Instructions between 8-bytes operands
-        pub fn $nameq(reg1: reg::Operand<reg::RegQ>, reg2: reg::Operand<reg::RegQ>) -> Text {
+        pub fn $nameq(reg1: reg::Operand<reg::RegQ>, reg2: reg::RegQ) -> Text {
             Text::new(Box::new(instr::Instruction {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg1),
-                reg2: Some(reg2),
+                reg2: Some(reg!(reg2)),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
     };
 }
 
-macro_rules! build_instr_op_reg {
+macro_rules! build_instr_reg_op {
     ($op:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
-        /// Instructions between 1-bytes operands
-        pub fn $nameb(reg1: reg::Operand<reg::RegB>, reg2: reg::RegB) -> Text {
+        /// Instructions between 1-bytes operands, the source is a register
+        pub fn $nameb(reg1: reg::RegB, reg2: reg::Operand<reg::RegB>) -> Text {
             Text::new(Box::new(instr::Instruction {
                 instr: instr::InstrName::$op,
-                reg1: Some(reg1),
-                reg2: Some(reg!(reg2)),
+                reg1: Some(reg!(reg1)),
+                reg2: Some(reg2),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
 
-        build_instr_op_reg!($op, $namew, $namel, $nameq);
+        build_instr_reg_op!($op, $namew, $namel, $nameq);
     };
 
     ($op:ident, $namew:ident, $namel:ident, $nameq:ident) => {
-        /// Instructions between 2-bytes operands
-        pub fn $namew(reg1: reg::Operand<reg::RegW>, reg2: reg::RegW) -> Text {
+        /// Instructions between 2-bytes operands, the source is a register
+        pub fn $namew(reg1: reg::RegW, reg2: reg::Operand<reg::RegW>) -> Text {
             Text::new(Box::new(instr::Instruction {
                 instr: instr::InstrName::$op,
-                reg1: Some(reg1),
-                reg2: Some(reg!(reg2)),
+                reg1: Some(reg!(reg1)),
+                reg2: Some(reg2),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
 
-        build_instr_op_reg!($op, $namel, $nameq);
+        build_instr_reg_op!($op, $namel, $nameq);
     };
 
     ($op:ident, $namel:ident, $nameq:ident) => {
-        /// Instructions between 4-bytes operands
-        pub fn $namel(reg1: reg::Operand<reg::RegL>, reg2: reg::RegL) -> Text {
+        /// Instructions between 4-bytes operands, the source is a register
+        pub fn $namel(reg1: reg::RegL, reg2: reg::Operand<reg::RegL>) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg!(reg1)),
+                reg2: Some(reg2),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+
+        build_instr_reg_op!($op, $nameq);
+    };
+
+    ($op:ident, $nameq:ident) => {
+        /// Instructions between 8-bytes operands, the source is a register
+        pub fn $nameq(reg1: reg::RegQ, reg2: reg::Operand<reg::RegQ>) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg!(reg1)),
+                reg2: Some(reg2),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+}
+
+macro_rules! build_instr_op_reg_reg {
+    ($op:ident, $namel:ident, $nameq:ident) => {
+        /// Instructions between 4-bytes operands, the result is stored in the last one
+        pub fn $namel(reg1: reg::Operand<reg::RegL>, reg2: reg::RegL, reg3: reg::RegL) -> Text {
             Text::new(Box::new(instr::Instruction {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg1),
                 reg2: Some(reg!(reg2)),
+                reg3: Some(reg!(reg3)),
+                lock_rep: None,
+                segment: None,
             }))
         }
 
-        build_instr_op_reg!($op, $nameq);
+        build_instr_op_reg_reg!($op, $nameq);
     };
 
     ($op:ident, $nameq:ident) => {
-        /// Instructions between 8-bytes operands
-        pub fn $nameq(reg1: reg::Operand<reg::RegQ>, reg2: reg::RegQ) -> Text {
+        /// Instructions between 8-bytes operands, the result is stored in the last one
+        pub fn $nameq(reg1: reg::Operand<reg::RegQ>, reg2: reg::RegQ, reg3: reg::RegQ) -> Text {
             Text::new(Box::new(instr::Instruction {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg1),
                 reg2: Some(reg!(reg2)),
+                reg3: Some(reg!(reg3)),
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+}
+
+macro_rules! build_instr_reg_op_reg {
+    ($op:ident, $namel:ident, $nameq:ident) => {
+        /// Instructions between 4-bytes operands, the result is stored in the last one
+        pub fn $namel(reg1: reg::RegL, reg2: reg::Operand<reg::RegL>, reg3: reg::RegL) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg!(reg1)),
+                reg2: Some(reg2),
+                reg3: Some(reg!(reg3)),
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+
+        build_instr_reg_op_reg!($op, $nameq);
+    };
+
+    ($op:ident, $nameq:ident) => {
+        /// Instructions between 8-bytes operands, the result is stored in the last one
+        pub fn $nameq(reg1: reg::RegQ, reg2: reg::Operand<reg::RegQ>, reg3: reg::RegQ) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg!(reg1)),
+                reg2: Some(reg2),
+                reg3: Some(reg!(reg3)),
+                lock_rep: None,
+                segment: None,
             }))
         }
     };
@@ -138,6 +282,9 @@ macro_rules! build_instr_op {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg),
                 reg2: None,
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
 
@@ -151,6 +298,9 @@ macro_rules! build_instr_op {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg),
                 reg2: None,
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
         build_instr_op!($op, $namel, $nameq);
@@ -163,6 +313,9 @@ macro_rules! build_instr_op {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg),
                 reg2: None,
+                reg3: None,
+                lock_rep: None,
+                segment: None,
             }))
         }
 
@@ -176,6 +329,394 @@ macro_rules! build_instr_op {
                 instr: instr::InstrName::$op,
                 reg1: Some(reg),
                 reg2: None,
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+}
+
+macro_rules! build_instr_shift_cl {
+    ($op:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_shift_cl!(@ $op, reg::RegB, "1-bytes", $nameb);
+        build_instr_shift_cl!(@ $op, reg::RegW, "2-bytes", $namew);
+        build_instr_shift_cl!(@ $op, reg::RegL, "4-bytes", $namel);
+        build_instr_shift_cl!(@ $op, reg::RegQ, "8-bytes", $nameq);
+    };
+
+    (@ $op:ident, $size:ty, $bytes:literal, $name:ident) => {
+        #[doc = concat!("Instructions on ", $bytes, " operands, the count is in %cl")]
+        pub fn $name(reg: reg::Operand<$size>) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg!(CL)),
+                reg2: Some(reg),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+}
+
+// double shifts by an immediate (`$op`) or by %cl (`$opc`), the last operand is the destination
+macro_rules! build_instr_double_shift {
+    ($op:ident, $opc:ident, $namew:ident, $namel:ident, $nameq:ident, $namew_reg:ident, $namel_reg:ident, $nameq_reg:ident) => {
+        build_instr_double_shift!(@ $op, $opc, reg::RegW, "2-bytes", $namew, $namew_reg);
+        build_instr_double_shift!(@ $op, $opc, reg::RegL, "4-bytes", $namel, $namel_reg);
+        build_instr_double_shift!(@ $op, $opc, reg::RegQ, "8-bytes", $nameq, $nameq_reg);
+    };
+
+    (@ $op:ident, $opc:ident, $size:ty, $bytes:literal, $name:ident, $name_reg:ident) => {
+        #[doc = concat!("Instructions on ", $bytes, " operands, the count is an immediate")]
+        pub fn $name(count: reg::Operand<$size>, reg2: $size, reg3: reg::Operand<$size>) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op,
+                reg1: Some(count),
+                reg2: Some(reg!(reg2)),
+                reg3: Some(reg3),
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+
+        #[doc = concat!("Instructions on ", $bytes, " operands, the count is in %cl")]
+        pub fn $name_reg(reg2: $size, reg3: reg::Operand<$size>) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$opc,
+                reg1: Some(reg!(CL)),
+                reg2: Some(reg!(reg2)),
+                reg3: Some(reg3),
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+}
+
+macro_rules! build_instr_float {
+    (move $op:ident, $names:ident, $named:ident) => {
+        /// Move of 4-bytes floats between XMM registers or with memory
+        pub fn $names(reg1: reg::Operand<reg::RegX>, reg2: reg::Operand<reg::RegX>) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op(instr::Precision::Single),
+                reg1: Some(reg1),
+                reg2: Some(reg2),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+
+        /// Move of 8-bytes floats between XMM registers or with memory
+        pub fn $named(reg1: reg::Operand<reg::RegX>, reg2: reg::Operand<reg::RegX>) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op(instr::Precision::Double),
+                reg1: Some(reg1),
+                reg2: Some(reg2),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+
+    ($op:ident, $names:ident, $named:ident, $src:ty, $dst:ty) => {
+        /// Instructions on 4-bytes floats
+        pub fn $names(reg1: reg::Operand<$src>, reg2: $dst) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op(instr::Precision::Single),
+                reg1: Some(reg1),
+                reg2: Some(reg!(reg2)),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+
+        /// Instructions on 8-bytes floats
+        pub fn $named(reg1: reg::Operand<$src>, reg2: $dst) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op(instr::Precision::Double),
+                reg1: Some(reg1),
+                reg2: Some(reg!(reg2)),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+
+    ($op:ident, $names:ident, $named:ident) => {
+        build_instr_float!($op, $names, $named, reg::RegX, reg::RegX);
+    };
+}
+
+macro_rules! build_instr_avx {
+    (move $op:ident, $names:ident, $named:ident) => {
+        build_instr_avx!(@move $op(instr::Precision::Single), $names);
+        build_instr_avx!(@move $op(instr::Precision::Double), $named);
+    };
+
+    (move $op:ident, $name:ident) => {
+        build_instr_avx!(@move $op, $name);
+    };
+
+    (packed $op:ident, $nameb:ident, $namew:ident, $named:ident, $nameq:ident) => {
+        build_instr_avx!(@ $op(reg::Sizes::Byte), $nameb);
+        build_instr_avx!(@ $op(reg::Sizes::Word), $namew);
+        build_instr_avx!(@ $op(reg::Sizes::Long), $named);
+        build_instr_avx!(@ $op(reg::Sizes::Quad), $nameq);
+    };
+
+    ($op:ident, $names:ident, $named:ident) => {
+        build_instr_avx!(@ $op(instr::Precision::Single), $names);
+        build_instr_avx!(@ $op(instr::Precision::Double), $named);
+    };
+
+    ($op:ident, $name:ident) => {
+        build_instr_avx!(@ $op, $name);
+    };
+
+    (@move $op:ident $(($arg:path))?, $name:ident) => {
+        /// Move of 32 bytes between YMM registers or with memory
+        pub fn $name(reg1: reg::Operand<reg::RegY>, reg2: reg::Operand<reg::RegY>) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op $(($arg))?,
+                reg1: Some(reg1),
+                reg2: Some(reg2),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+
+    (@ $op:ident $(($arg:path))?, $name:ident) => {
+        /// Instructions on 32 bytes registers, only the first operand can be in memory
+        /// and the result is stored in the last one
+        pub fn $name(reg1: reg::Operand<reg::RegY>, reg2: reg::RegY, reg3: reg::RegY) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op $(($arg))?,
+                reg1: Some(reg1),
+                reg2: Some(reg!(reg2)),
+                reg3: Some(reg!(reg3)),
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+}
+
+// the operands of string instructions are implicit, their types only show the registers used
+// (`acc` is the accumulator of the size of the elements)
+macro_rules! build_instr_string {
+    (rep $prefix:ident $op:ident, $doc:expr, $src:ident, $dst:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_string!(@rep $prefix $op, $doc, reg::RegB, "1-byte", Al, $src, $dst, $nameb);
+        build_instr_string!(@rep $prefix $op, $doc, reg::RegW, "2-bytes", Ax, $src, $dst, $namew);
+        build_instr_string!(@rep $prefix $op, $doc, reg::RegL, "4-bytes", Eax, $src, $dst, $namel);
+        build_instr_string!(@rep $prefix $op, $doc, reg::RegQ, "8-bytes", Rax, $src, $dst, $nameq);
+    };
+
+    ($op:ident, $doc:expr, $src:ident, $dst:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_string!(@ $op, $doc, reg::RegB, "1-byte", Al, $src, $dst, $nameb);
+        build_instr_string!(@ $op, $doc, reg::RegW, "2-bytes", Ax, $src, $dst, $namew);
+        build_instr_string!(@ $op, $doc, reg::RegL, "4-bytes", Eax, $src, $dst, $namel);
+        build_instr_string!(@ $op, $doc, reg::RegQ, "8-bytes", Rax, $src, $dst, $nameq);
+    };
+
+    (@rep $prefix:ident $op:ident, $doc:expr, $size:ty, $bytes:literal, $acc:ident, $src:ident, $dst:ident, $name:ident) => {
+        #[doc = $doc]
+        ///
+        #[doc = concat!("On ", $bytes, " elements")]
+        pub fn $name(
+            _: reg::implicit::Rcx,
+            _: build_instr_string!(@reg $acc, $src),
+            _: build_instr_string!(@reg $acc, $dst),
+        ) -> Text {
+            build_instr_string!(@instr $op, $size, Some(instr::Prefix::$prefix))
+        }
+    };
+
+    (@ $op:ident, $doc:expr, $size:ty, $bytes:literal, $acc:ident, $src:ident, $dst:ident, $name:ident) => {
+        #[doc = $doc]
+        ///
+        #[doc = concat!("On ", $bytes, " elements")]
+        pub fn $name(
+            _: build_instr_string!(@reg $acc, $src),
+            _: build_instr_string!(@reg $acc, $dst),
+        ) -> Text {
+            build_instr_string!(@instr $op, $size, None)
+        }
+    };
+
+    (@reg $acc:ident, acc) => {
+        reg::implicit::$acc
+    };
+
+    (@reg $acc:ident, $reg:ident) => {
+        reg::implicit::$reg
+    };
+
+    (@instr $op:ident, $size:ty, $prefix:expr) => {
+        Text::new(Box::new(instr::Instruction::<$size, reg::RegInv> {
+            instr: instr::InstrName::$op,
+            reg1: None,
+            reg2: None,
+            reg3: None,
+            lock_rep: $prefix,
+            segment: None,
+        }))
+    };
+}
+
+// locked instructions only take a memory destination, `lock` cannot be applied to a register
+macro_rules! build_instr_lock {
+    ($op:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_lock!(@op $op, reg::RegB, "1-bytes", $nameb);
+        build_instr_lock!(@op $op, reg::RegW, "2-bytes", $namew);
+        build_instr_lock!(@op $op, reg::RegL, "4-bytes", $namel);
+        build_instr_lock!(@op $op, reg::RegQ, "8-bytes", $nameq);
+    };
+
+    (reg $op:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_lock!(@reg $op, reg::RegB, "1-bytes", $nameb);
+        build_instr_lock!(@reg $op, reg::RegW, "2-bytes", $namew);
+        build_instr_lock!(@reg $op, reg::RegL, "4-bytes", $namel);
+        build_instr_lock!(@reg $op, reg::RegQ, "8-bytes", $nameq);
+    };
+
+    (bit $op:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_lock!(@op $op, reg::RegW, "2-bytes", $namew);
+        build_instr_lock!(@op $op, reg::RegL, "4-bytes", $namel);
+        build_instr_lock!(@op $op, reg::RegQ, "8-bytes", $nameq);
+    };
+
+    (unary $op:ident, $nameb:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_lock!(@unary $op, reg::RegB, "1-bytes", $nameb);
+        build_instr_lock!(@unary $op, reg::RegW, "2-bytes", $namew);
+        build_instr_lock!(@unary $op, reg::RegL, "4-bytes", $namel);
+        build_instr_lock!(@unary $op, reg::RegQ, "8-bytes", $nameq);
+    };
+
+    (@op $op:ident, $size:ty, $bytes:literal, $name:ident) => {
+        #[doc = concat!("Atomic instructions between ", $bytes, " operands")]
+        pub fn $name(reg1: reg::RegImm<$size>, reg2: reg::Memory) -> Text {
+            Text::new(Box::new(instr::Instruction::<$size, $size> {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg1.into()),
+                reg2: Some(reg2.into()),
+                reg3: None,
+                lock_rep: Some(instr::Prefix::Lock),
+                segment: None,
+            }))
+        }
+    };
+
+    (@reg $op:ident, $size:ty, $bytes:literal, $name:ident) => {
+        #[doc = concat!("Atomic instructions between ", $bytes, " operands, the source is a register")]
+        pub fn $name(reg1: $size, reg2: reg::Memory) -> Text {
+            Text::new(Box::new(instr::Instruction::<$size, $size> {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg!(reg1)),
+                reg2: Some(reg2.into()),
+                reg3: None,
+                lock_rep: Some(instr::Prefix::Lock),
+                segment: None,
+            }))
+        }
+    };
+
+    (@unary $op:ident, $size:ty, $bytes:literal, $name:ident) => {
+        #[doc = concat!("Atomic instructions on ", $bytes, " operands")]
+        pub fn $name(reg: reg::Memory) -> Text {
+            Text::new(Box::new(instr::Instruction::<$size, reg::RegInv> {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg.into()),
+                reg2: None,
+                reg3: None,
+                lock_rep: Some(instr::Prefix::Lock),
+                segment: None,
+            }))
+        }
+    };
+}
+
+macro_rules! build_instr_cond {
+    ($cond:ident, $meaning:literal, $jump:ident, $set:ident, $cmovw:ident, $cmovl:ident, $cmovq:ident) => {
+        #[doc = concat!("Conditional jump if ", $meaning)]
+        pub fn $jump(label: reg::Label) -> Text {
+            jcc(instr::Cond::$cond, label)
+        }
+
+        #[doc = concat!("Set to 1 if ", $meaning, ", to 0 otherwise")]
+        pub fn $set(reg: reg::Operand<reg::RegB>) -> Text {
+            set(instr::Cond::$cond, reg)
+        }
+
+        #[doc = concat!("Conditional move of 2-bytes operands if ", $meaning)]
+        pub fn $cmovw(reg1: reg::Operand<reg::RegW>, reg2: reg::Operand<reg::RegW>) -> Text {
+            cmovw(instr::Cond::$cond, reg1, reg2)
+        }
+
+        #[doc = concat!("Conditional move of 4-bytes operands if ", $meaning)]
+        pub fn $cmovl(reg1: reg::Operand<reg::RegL>, reg2: reg::Operand<reg::RegL>) -> Text {
+            cmovl(instr::Cond::$cond, reg1, reg2)
+        }
+
+        #[doc = concat!("Conditional move of 8-bytes operands if ", $meaning)]
+        pub fn $cmovq(reg1: reg::Operand<reg::RegQ>, reg2: reg::Operand<reg::RegQ>) -> Text {
+            cmovq(instr::Cond::$cond, reg1, reg2)
+        }
+    };
+}
+
+// the accumulator is an implicit input and the result an implicit output, given by their types
+macro_rules! build_instr_widening {
+    ($op:ident, $nameb:ident: $docb:literal, $namew:ident: $docw:literal, $namel:ident: $docl:literal, $nameq:ident: $docq:literal) => {
+        build_instr_widening!(@ $op, reg::RegB, Al, Ax, $docb, $nameb);
+        build_instr_widening!(@ $op, reg::RegW, Ax, DxAx, $docw, $namew);
+        build_instr_widening!(@ $op, reg::RegL, Eax, EdxEax, $docl, $namel);
+        build_instr_widening!(@ $op, reg::RegQ, Rax, RdxRax, $docq, $nameq);
+    };
+
+    (@ $op:ident, $size:ty, $acc:ident, $result:ident, $doc:literal, $name:ident) => {
+        #[doc = $doc]
+        pub fn $name(
+            reg: reg::Operand<$size>,
+            _: reg::implicit::$acc,
+            _: reg::implicit::$result,
+        ) -> Text {
+            Text::new(Box::new(instr::Instruction::<_, reg::RegInv> {
+                instr: instr::InstrName::$op,
+                reg1: Some(reg),
+                reg2: None,
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    };
+}
+
+macro_rules! build_instr_imm_op_reg {
+    ($op:ident, $namew:ident, $namel:ident, $nameq:ident) => {
+        build_instr_imm_op_reg!(@ $op, reg::RegW, "2-bytes", $namew);
+        build_instr_imm_op_reg!(@ $op, reg::RegL, "4-bytes", $namel);
+        build_instr_imm_op_reg!(@ $op, reg::RegQ, "8-bytes", $nameq);
+    };
+
+    (@ $op:ident, $size:ty, $bytes:literal, $name:ident) => {
+        #[doc = concat!("Instructions between an immediate and a ", $bytes, " operand, the result is stored in the register")]
+        pub fn $name(imm: reg::Operand<$size>, reg2: reg::Operand<$size>, reg3: $size) -> Text {
+            Text::new(Box::new(instr::Instruction {
+                instr: instr::InstrName::$op,
+                reg1: Some(imm),
+                reg2: Some(reg2),
+                reg3: Some(reg!(reg3)),
+                lock_rep: None,
+                segment: None,
             }))
         }
     };
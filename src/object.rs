@@ -0,0 +1,549 @@
+use crate::data::DataEL;
+use crate::directives::{expr::Expr, Directive};
+use crate::file::{File, Section, SectionFlags, SectionType};
+use crate::instr::assembler::{assemble_with_globals, nops};
+use crate::instr::encode::{EncodeError, FixupKind};
+use crate::reg::Label;
+use crate::symbol::{Binding, SymbolSize, SymbolType, Visibility};
+use crate::traits::UsesLabels;
+use crate::{Data, SegmentEL, Text};
+
+/// How the value of a relocation is computed (named after the ELF relocations)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocationKind {
+    /// 8-bits displacement (`label + addend - .`)
+    Pc8,
+    /// 32-bits displacement (`label + addend - .`)
+    Pc32,
+    /// 32-bits displacement to a function, possibly through the PLT
+    Plt32,
+    /// 32-bits absolute address (`label + addend`)
+    Abs32,
+    /// 32-bits absolute address sign extended to 64 bits
+    Abs32S,
+    /// 64-bits absolute address
+    Abs64,
+}
+
+impl RelocationKind {
+    fn of_fixup(kind: FixupKind) -> Self {
+        match kind {
+            FixupKind::Rel8 => Self::Pc8,
+            FixupKind::Rel32 => Self::Pc32,
+            FixupKind::Plt32 => Self::Plt32,
+            FixupKind::Abs32 => Self::Abs32,
+            FixupKind::Abs32S => Self::Abs32S,
+            FixupKind::Abs64 => Self::Abs64,
+        }
+    }
+
+    /// Number of bytes patched
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Pc8 => 1,
+            Self::Pc32 | Self::Plt32 | Self::Abs32 | Self::Abs32S => 4,
+            Self::Abs64 => 8,
+        }
+    }
+}
+
+/// Bytes of a section depending on the address of a label
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    /// Position of the bytes to patch in the section
+    pub offset: usize,
+    /// Label referred to
+    pub label: Label,
+    /// Constant added to the address of the label
+    pub addend: i64,
+    /// How the value is computed
+    pub kind: RelocationKind,
+}
+
+/// Section of an object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectSection {
+    /// Name of the section
+    pub name: String,
+    /// Flags of the section
+    pub flags: SectionFlags,
+    /// Type of the section
+    pub kind: SectionType,
+    /// Alignment of the section (in bytes)
+    pub align: usize,
+    /// Content of the section (empty for `@nobits` sections)
+    pub bytes: Vec<u8>,
+    /// Size of the section
+    pub size: usize,
+    /// Relocations of the section
+    pub relocations: Vec<Relocation>,
+}
+
+impl ObjectSection {
+    fn new(name: &str, flags: SectionFlags, kind: SectionType) -> Self {
+        Self {
+            name: name.to_string(),
+            flags,
+            kind,
+            align: 1,
+            bytes: Vec::new(),
+            size: 0,
+            relocations: Vec::new(),
+        }
+    }
+
+    fn nobits(&self) -> bool {
+        self.kind == SectionType::Nobits
+    }
+
+    /// Padding needed to reach a multiple of `align`
+    fn padding(&self, align: usize) -> usize {
+        (align - self.size % align) % align
+    }
+
+    fn fill(&mut self, size: usize, fill: u8) {
+        if !self.nobits() {
+            self.bytes.resize(self.bytes.len() + size, fill);
+        }
+        self.size += size;
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<(), EncodeError> {
+        if self.nobits() && bytes.iter().any(|b| *b != 0) {
+            return Err(EncodeError::new(format!(
+                "section {} can only contain zeros",
+                self.name
+            )));
+        }
+        if !self.nobits() {
+            self.bytes.extend_from_slice(bytes);
+        }
+        self.size += bytes.len();
+        Ok(())
+    }
+
+    fn push_relocation(&mut self, label: &Label, kind: RelocationKind) -> Result<(), EncodeError> {
+        self.relocations.push(Relocation {
+            offset: self.size,
+            label: label.clone(),
+            addend: 0,
+            kind,
+        });
+        self.push(&vec![0; kind.size()])
+    }
+}
+
+/// Section where a symbol is defined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolSection {
+    /// Symbol defined in another file
+    Undefined,
+    /// Constant defined with `.set`
+    Absolute,
+    /// Index of the section in [`Object::sections`]
+    Section(usize),
+}
+
+/// Symbol of an object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectSymbol {
+    /// Label of the symbol
+    pub label: Label,
+    /// Section of the symbol
+    pub section: SymbolSection,
+    /// Offset in the section (or value of absolute symbols)
+    pub value: u64,
+    /// Binding of the symbol
+    pub binding: Binding,
+    /// Visibility of the symbol
+    pub visibility: Visibility,
+    /// Type of the symbol
+    pub kind: Option<SymbolType>,
+    /// Size of the symbol
+    pub size: Option<usize>,
+}
+
+impl ObjectSymbol {
+    fn new(label: Label, section: SymbolSection, value: u64) -> Self {
+        Self {
+            label,
+            section,
+            value,
+            binding: Binding::Local,
+            visibility: Visibility::Default,
+            kind: None,
+            size: None,
+        }
+    }
+}
+
+/// File assembled in memory: bytes of sections, symbols and relocations
+///
+/// ```
+/// use write_x86_64::*;
+/// use write_x86_64::object::{Object, RelocationKind};
+///
+/// let file = file::File {
+///     globl: Some(new_label("main")),
+///     text_ss: Segment::label(new_label("main")) + call(reg::Label::printf()) + ret(),
+///     ..Default::default()
+/// };
+/// let object = Object::new(&file).unwrap();
+/// let text = object.section(".text").unwrap();
+/// assert_eq!(text.bytes, [0xe8, 0, 0, 0, 0, 0xc3]);
+/// assert_eq!(text.relocations[0].label, reg::Label::printf());
+/// assert_eq!(text.relocations[0].kind, RelocationKind::Plt32);
+/// assert!(object.symbol(&reg::Label::printf()).is_some());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Object {
+    /// Sections in the order of the file (`.text` and `.data` first)
+    pub sections: Vec<ObjectSection>,
+    /// Symbols defined in the file followed by undefined symbols
+    pub symbols: Vec<ObjectSymbol>,
+}
+
+impl Object {
+    /// Assemble a file in memory, files with line information (`.file`, `.loc`) are an error
+    pub fn new(file: &File) -> Result<Self, EncodeError> {
+        let mut object = Self::default();
+        let mut sets = Vec::new();
+        // references to global symbols are not resolved (they can be overridden)
+        let mut globals: Vec<&Label> = file.globl.iter().collect();
+        for symbol in &file.symbols {
+            if symbol.binding != Binding::Local {
+                globals.push(&symbol.label)
+            }
+        }
+        let index = object.section_index(".text", None, None);
+        object.add_text(index, &file.text_ss, &globals, &mut sets)?;
+        let index = object.section_index(".data", None, None);
+        object.add_data(index, &file.data_ss, &mut sets)?;
+        for section in &file.sections {
+            match section {
+                Section::Text(text) => {
+                    let index = object.section_index(text.name(), text.flags(), text.kind());
+                    object.add_text(index, text.segment(), &globals, &mut sets)?
+                }
+                Section::Data(data) => {
+                    let index = object.section_index(data.name(), data.flags(), data.kind());
+                    object.add_data(index, data.segment(), &mut sets)?
+                }
+            }
+        }
+        for (label, expr) in sets {
+            let value = object.eval(expr)?;
+            object.define(ObjectSymbol::new(
+                label.clone(),
+                SymbolSection::Absolute,
+                value,
+            ))?;
+        }
+        if let Some(main) = &file.globl {
+            object.symbol_mut(main).binding = Binding::Global;
+        }
+        for symbol in &file.symbols {
+            let size = match &symbol.size {
+                None => None,
+                Some(SymbolSize::Const(size)) => Some(*size),
+                Some(SymbolSize::End(end)) => Some(object.distance(&symbol.label, end)?),
+            };
+            let object_symbol = object.symbol_mut(&symbol.label);
+            object_symbol.binding = symbol.binding;
+            object_symbol.visibility = symbol.visibility;
+            object_symbol.kind = symbol.kind;
+            object_symbol.size = size;
+        }
+        for label in file.external_labels() {
+            object.symbol_mut(label);
+        }
+        Ok(object)
+    }
+
+    /// Section with the given name
+    pub fn section(&self, name: &str) -> Option<&ObjectSection> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// Symbol of a label
+    pub fn symbol(&self, label: &Label) -> Option<&ObjectSymbol> {
+        self.symbols.iter().find(|symbol| &symbol.label == label)
+    }
+
+    /// Symbol of a label, undefined global symbols are added for unknown labels
+    fn symbol_mut(&mut self, label: &Label) -> &mut ObjectSymbol {
+        let index = match self
+            .symbols
+            .iter()
+            .position(|symbol| &symbol.label == label)
+        {
+            Some(index) => index,
+            None => {
+                let mut symbol = ObjectSymbol::new(label.clone(), SymbolSection::Undefined, 0);
+                symbol.binding = Binding::Global;
+                self.symbols.push(symbol);
+                self.symbols.len() - 1
+            }
+        };
+        &mut self.symbols[index]
+    }
+
+    fn define(&mut self, symbol: ObjectSymbol) -> Result<(), EncodeError> {
+        if self.symbol(&symbol.label).is_some() {
+            return Err(EncodeError::new(format!(
+                "label {} is defined twice",
+                symbol.label.name()
+            )));
+        }
+        self.symbols.push(symbol);
+        Ok(())
+    }
+
+    fn define_label(&mut self, label: &Label, section: usize) -> Result<(), EncodeError> {
+        let value = self.sections[section].size as u64;
+        self.define(ObjectSymbol::new(
+            label.clone(),
+            SymbolSection::Section(section),
+            value,
+        ))
+    }
+
+    /// Section of the given name (created if it does not exist)
+    fn section_index(
+        &mut self,
+        name: &str,
+        flags: Option<SectionFlags>,
+        kind: Option<SectionType>,
+    ) -> usize {
+        match self
+            .sections
+            .iter()
+            .position(|section| section.name == name)
+        {
+            Some(index) => index,
+            None => {
+                let (default_flags, default_kind) = default_attributes(name);
+                self.sections.push(ObjectSection::new(
+                    name,
+                    flags.unwrap_or(default_flags),
+                    kind.unwrap_or(default_kind),
+                ));
+                self.sections.len() - 1
+            }
+        }
+    }
+
+    fn add_text<'a>(
+        &mut self,
+        index: usize,
+        text: &'a Text,
+        globals: &[&Label],
+        sets: &mut Vec<(&'a Label, &'a Expr)>,
+    ) -> Result<(), EncodeError> {
+        let assembly = assemble_with_globals(text, globals)?;
+        // the segment is assembled from offset 0 so its alignments must be kept
+        let align = text
+            .data
+            .iter()
+            .filter_map(|el| match &el.el {
+                SegmentEL::Directive(Directive::P2Align(pow, _, _)) => Some(1 << pow),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(1);
+        let section = &mut self.sections[index];
+        section.align = section.align.max(align);
+        section.push(&nops(section.padding(align)))?;
+        let base = section.size;
+        for el in &text.data {
+            match &el.el {
+                SegmentEL::Label(label) => self.define(ObjectSymbol::new(
+                    label.clone(),
+                    SymbolSection::Section(index),
+                    (base + assembly.labels[label]) as u64,
+                ))?,
+                SegmentEL::Directive(Directive::Set(label, expr)) => sets.push((label, expr)),
+                _ => (),
+            }
+        }
+        let section = &mut self.sections[index];
+        section.push(&assembly.code.bytes)?;
+        for fixup in assembly.code.fixups {
+            section.relocations.push(Relocation {
+                offset: base + fixup.offset,
+                label: fixup.label,
+                addend: fixup.addend,
+                kind: RelocationKind::of_fixup(fixup.kind),
+            })
+        }
+        Ok(())
+    }
+
+    fn add_data<'a>(
+        &mut self,
+        index: usize,
+        data: &'a Data,
+        sets: &mut Vec<(&'a Label, &'a Expr)>,
+    ) -> Result<(), EncodeError> {
+        for el in &data.data {
+            match &el.el {
+                SegmentEL::Label(label) => self.define_label(label, index)?,
+                SegmentEL::Data(data) => self.add_data_el(index, data)?,
+                SegmentEL::Directive(Directive::P2Align(pow, fill, max)) => {
+                    let section = &mut self.sections[index];
+                    let align = 1 << pow;
+                    let padding = section.padding(align);
+                    if max.map_or(true, |max| padding <= max) {
+                        section.align = section.align.max(align);
+                        section.fill(padding, fill.unwrap_or(0) as u8)
+                    }
+                }
+                SegmentEL::Directive(Directive::Set(label, expr)) => sets.push((label, expr)),
+                SegmentEL::Directive(
+                    Directive::File(..) | Directive::Loc(..) | Directive::LocMarkBlocks(_),
+                ) => {
+                    return Err(EncodeError::new(
+                        "line information (.file, .loc) cannot be assembled as .debug_line is not generated"
+                            .to_string(),
+                    ))
+                }
+                SegmentEL::Comment(_) => (),
+                SegmentEL::Inline(_) => {
+                    return Err(EncodeError::new(
+                        "inlined assembly cannot be assembled".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_data_el(&mut self, index: usize, data: &DataEL) -> Result<(), EncodeError> {
+        let section = &mut self.sections[index];
+        match data {
+            DataEL::Byte(i) => section.push(&i.to_le_bytes()),
+            DataEL::ByteU(i) => section.push(&i.to_le_bytes()),
+            DataEL::Word(i) => section.push(&i.to_le_bytes()),
+            DataEL::ShortU(i) => section.push(&i.to_le_bytes()),
+            DataEL::Long(i) => section.push(&i.to_le_bytes()),
+            DataEL::LongU(i) => section.push(&i.to_le_bytes()),
+            DataEL::Quad(i) => section.push(&i.to_le_bytes()),
+            DataEL::Space(size) => {
+                section.fill(*size, 0);
+                Ok(())
+            }
+            DataEL::AddressLong(label) => section.push_relocation(label, RelocationKind::Abs32),
+            DataEL::AddressQuad(label) => section.push_relocation(label, RelocationKind::Abs64),
+            DataEL::Ascii(str) => section.push(&unescape(str)?),
+            DataEL::Asciz(str) => {
+                let mut bytes = unescape(str)?;
+                bytes.push(0);
+                section.push(&bytes)
+            }
+        }
+    }
+
+    /// Distance between two labels of the same section
+    fn distance(&self, start: &Label, end: &Label) -> Result<usize, EncodeError> {
+        match (self.symbol(start), self.symbol(end)) {
+            (Some(start), Some(end))
+                if start.section == end.section && start.value <= end.value =>
+            {
+                Ok((end.value - start.value) as usize)
+            }
+            _ => Err(EncodeError::new(format!(
+                "{} and {} are not in the same section",
+                start.name(),
+                end.name()
+            ))),
+        }
+    }
+
+    /// Value of a `.set` expression
+    fn eval(&self, expr: &Expr) -> Result<u64, EncodeError> {
+        match expr {
+            Expr::UConst(i) => Ok(*i as u64),
+            Expr::SConst(i) => Ok(*i as u64),
+            Expr::Sub(lab1, lab2) => match (self.symbol(lab1), self.symbol(lab2)) {
+                (Some(sym1), Some(sym2)) if sym1.section == sym2.section => {
+                    Ok(sym1.value.wrapping_sub(sym2.value))
+                }
+                _ => Err(EncodeError::new(format!(
+                    "{} and {} are not in the same section",
+                    lab1.name(),
+                    lab2.name()
+                ))),
+            },
+            Expr::Add(_, _) => Err(EncodeError::new(format!(
+                "cannot add the addresses of {:?}",
+                expr.used_labels()
+            ))),
+        }
+    }
+}
+
+/// Flags and type given by GNU as to sections without attributes
+fn default_attributes(name: &str) -> (SectionFlags, SectionType) {
+    if name == ".bss" || name.starts_with(".bss.") {
+        (SectionFlags::writable(), SectionType::Nobits)
+    } else if name == ".text" || name.starts_with(".text.") {
+        (SectionFlags::executable(), SectionType::Progbits)
+    } else if name == ".rodata" || name.starts_with(".rodata.") {
+        (SectionFlags::read_only(), SectionType::Progbits)
+    } else if name.starts_with(".data") {
+        (SectionFlags::writable(), SectionType::Progbits)
+    } else if name == ".init_array" {
+        (SectionFlags::writable(), SectionType::InitArray)
+    } else if name == ".fini_array" {
+        (SectionFlags::writable(), SectionType::FiniArray)
+    } else if name.starts_with(".note") {
+        (SectionFlags::default(), SectionType::Note)
+    } else {
+        (SectionFlags::default(), SectionType::Progbits)
+    }
+}
+
+/// Bytes of a string written as in `.ascii` (with C escapes)
+fn unescape(str: &str) -> Result<Vec<u8>, EncodeError> {
+    let mut bytes = Vec::new();
+    let mut chars = str.bytes().peekable();
+    while let Some(c) = chars.next() {
+        if c != b'\\' {
+            bytes.push(c);
+            continue;
+        }
+        let c = chars
+            .next()
+            .ok_or_else(|| EncodeError::new(format!("invalid escape at the end of {:?}", str)))?;
+        match c {
+            b'n' => bytes.push(b'\n'),
+            b't' => bytes.push(b'\t'),
+            b'r' => bytes.push(b'\r'),
+            b'b' => bytes.push(8),
+            b'f' => bytes.push(12),
+            b'0'..=b'7' => {
+                let mut value = (c - b'0') as u32;
+                for _ in 0..2 {
+                    match chars.peek() {
+                        Some(d @ b'0'..=b'7') => {
+                            value = value * 8 + (d - b'0') as u32;
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+                bytes.push(value as u8)
+            }
+            b'x' => {
+                let mut value = 0u32;
+                while let Some(d) = chars.peek().and_then(|d| (*d as char).to_digit(16)) {
+                    value = value * 16 + d;
+                    chars.next();
+                }
+                bytes.push(value as u8)
+            }
+            c => bytes.push(c),
+        }
+    }
+    Ok(bytes)
+}
@@ -1,4 +1,5 @@
 use crate::traits::Reg;
+use crate::writer::{Syntax, Writer};
 use std::io::prelude::*;
 
 /// Different operand sizes
@@ -12,6 +13,10 @@ pub enum Sizes {
     Long,
     /// 8 bytes
     Quad,
+    /// 16 bytes (XMM registers)
+    Xmm,
+    /// 32 bytes (YMM registers)
+    Ymm,
     /// Invalid size
     Invalid,
 }
@@ -24,9 +29,43 @@ impl Sizes {
             Self::Word => 'w',
             Self::Long => 'l',
             Self::Quad => 'q',
+            Self::Xmm | Self::Ymm | Self::Invalid => panic!("Internal error"),
+        }
+    }
+
+    /// Get size keyword of memory operands in Intel syntax
+    pub fn to_intel_ptr(&self) -> &'static str {
+        match self {
+            Self::Byte => "BYTE PTR",
+            Self::Word => "WORD PTR",
+            Self::Long => "DWORD PTR",
+            Self::Quad => "QWORD PTR",
+            Self::Xmm => "XMMWORD PTR",
+            Self::Ymm => "YMMWORD PTR",
             Self::Invalid => panic!("Internal error"),
         }
     }
+
+    /// Get size keyword of memory operands in NASM syntax
+    pub fn to_nasm_size(&self) -> &'static str {
+        match self {
+            Self::Byte => "byte",
+            Self::Word => "word",
+            Self::Long => "dword",
+            Self::Quad => "qword",
+            Self::Xmm => "oword",
+            Self::Ymm => "yword",
+            Self::Invalid => panic!("Internal error"),
+        }
+    }
+}
+
+/// Write register name (stored in AT&T syntax) using the syntax of the writer
+fn write_reg_name(name: &str, file: &mut Writer) -> std::io::Result<()> {
+    match file.syntax() {
+        Syntax::Att => file.write_all(name.as_bytes()),
+        Syntax::Intel | Syntax::Nasm => file.write_all(&name.as_bytes()[1..]),
+    }
 }
 
 #[allow(missing_docs)]
@@ -52,7 +91,7 @@ pub enum RegQ {
 }
 
 impl RegQ {
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
             Self::Rax => "%rax",
             Self::Rbx => "%rbx",
@@ -75,8 +114,8 @@ impl RegQ {
 }
 
 impl Reg for RegQ {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        file.write_all(self.to_str().as_bytes())
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        write_reg_name(self.to_str(), file)
     }
 
     fn to_bits(&self) -> (bool, u8) {
@@ -126,7 +165,7 @@ pub enum RegL {
 }
 
 impl RegL {
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
             Self::Eax => "%eax",
             Self::Ebx => "%ebx",
@@ -149,8 +188,8 @@ impl RegL {
 }
 
 impl Reg for RegL {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        file.write_all(self.to_str().as_bytes())
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        write_reg_name(self.to_str(), file)
     }
 
     fn to_bits(&self) -> (bool, u8) {
@@ -200,7 +239,7 @@ pub enum RegW {
 }
 
 impl RegW {
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
             Self::Ax => "%ax",
             Self::Bx => "%bx",
@@ -223,8 +262,8 @@ impl RegW {
 }
 
 impl Reg for RegW {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        file.write_all(self.to_str().as_bytes())
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        write_reg_name(self.to_str(), file)
     }
 
     fn to_bits(&self) -> (bool, u8) {
@@ -236,7 +275,7 @@ impl Reg for RegW {
             RegW::Sp => (false, 0b100),
             RegW::Bp => (false, 0b101),
             RegW::Si => (false, 0b110),
-            RegW::Di => (false, 0b101),
+            RegW::Di => (false, 0b111),
             RegW::R8w => (true, 0b000),
             RegW::R9w => (true, 0b001),
             RegW::R10w => (true, 0b010),
@@ -278,7 +317,7 @@ pub enum RegB {
 }
 
 impl RegB {
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
             Self::Al => "%al",
             Self::Ah => "%ah",
@@ -305,8 +344,8 @@ impl RegB {
 }
 
 impl Reg for RegB {
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        file.write_all(self.to_str().as_bytes())
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        write_reg_name(self.to_str(), file)
     }
 
     fn to_bits(&self) -> (bool, u8) {
@@ -315,14 +354,14 @@ impl Reg for RegB {
             RegB::Cl => (false, 0b001),
             RegB::Dl => (false, 0b010),
             RegB::Bl => (false, 0b011),
-            RegB::Ah => todo!(),
-            RegB::Spl => todo!(),
-            RegB::Ch => todo!(),
-            RegB::Bpl => todo!(),
-            RegB::Dh => todo!(),
-            RegB::Sil => todo!(),
-            RegB::Bh => todo!(),
-            RegB::Dil => todo!(),
+            RegB::Ah => (false, 0b100),
+            RegB::Spl => (false, 0b100),
+            RegB::Ch => (false, 0b101),
+            RegB::Bpl => (false, 0b101),
+            RegB::Dh => (false, 0b110),
+            RegB::Sil => (false, 0b110),
+            RegB::Bh => (false, 0b111),
+            RegB::Dil => (false, 0b111),
             RegB::R8b => (true, 0b000),
             RegB::R9b => (true, 0b001),
             RegB::R10b => (true, 0b010),
@@ -334,6 +373,14 @@ impl Reg for RegB {
         }
     }
 
+    fn requires_rex(&self) -> bool {
+        matches!(self, RegB::Spl | RegB::Bpl | RegB::Sil | RegB::Dil)
+    }
+
+    fn forbids_rex(&self) -> bool {
+        matches!(self, RegB::Ah | RegB::Bh | RegB::Ch | RegB::Dh)
+    }
+
     const SIZE: Sizes = Sizes::Byte;
 }
 
@@ -342,6 +389,8 @@ impl Reg for RegB {
 pub enum Operand<T: Reg> {
     /// Address at 1 + 2 + 3 * 4
     Addr(i64, RegQ, Option<RegQ>, u8),
+    /// Address at 1 + 2 * 3 (without base register)
+    Index(i64, RegQ, u8),
     /// Direct access to register
     Reg(T),
     /// Label relative to RIP
@@ -358,43 +407,205 @@ pub enum Operand<T: Reg> {
 impl<T: Reg> Operand<T> {
     /// test if operand is register of memory
     pub fn is_rm(&self) -> bool {
-        match self {
-            Self::Addr(_, _, _, _) | Self::Reg(_) => true,
-            _ => false,
+        matches!(
+            self,
+            Self::Addr(_, _, _, _) | Self::Index(_, _, _) | Self::Reg(_)
+        )
+    }
+
+    /// test if operand is in memory
+    pub fn is_memory(&self) -> bool {
+        matches!(
+            self,
+            Self::Addr(_, _, _, _)
+                | Self::Index(_, _, _)
+                | Self::LabRelAddr(_)
+                | Self::LabAbsAddr(_)
+        )
+    }
+
+    /// Write operand in file (AT&T syntax)
+    pub fn write_in(&self, file: &mut dyn Write) -> std::io::Result<()> {
+        self.write_with(&mut Writer::new(file))
+    }
+
+    /// Write operand using the syntax of the writer
+    pub fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        match file.syntax() {
+            Syntax::Att => self.write_att(file),
+            Syntax::Intel | Syntax::Nasm => self.write_intel(file, true, None),
         }
     }
 
-    /// Write operand in file
-    pub fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_att(&self, file: &mut Writer) -> std::io::Result<()> {
         match self {
-            Self::Reg(reg) => reg.write_in(file),
+            Self::Reg(reg) => reg.write_with(file),
             Self::Addr(offset, reg, index, scale) => {
                 file.write_all(format!("{}(", offset).as_bytes())?;
-                reg.write_in(file)?;
+                reg.write_with(file)?;
                 match index {
                     None => file.write_all(b")"),
                     Some(index) => {
                         file.write_all(b", ")?;
-                        index.write_in(file)?;
+                        index.write_with(file)?;
                         file.write_all(format!(", {})", scale).as_bytes())
                     }
                 }
             }
+            Self::Index(offset, index, scale) => {
+                file.write_all(format!("{}(, ", offset).as_bytes())?;
+                index.write_with(file)?;
+                file.write_all(format!(", {})", scale).as_bytes())
+            }
             Self::LabRelAddr(label) => {
-                label.write_in(file)?;
+                label.write_with(file)?;
                 file.write_all(b"(%rip)")
             }
-            Self::LabAbsAddr(label) => label.write_in(file),
+            Self::LabAbsAddr(label) => label.write_with(file),
             Self::LabVal(label) => {
                 file.write_all(b"$")?;
-                label.write_in(file)
+                label.write_with(file)
             }
             Self::Imm(id) => file.write_all(format!("${}", id).as_bytes()),
         }
     }
+
+    /// Write memory size keyword of Intel syntaxes
+    fn write_size(file: &mut Writer) -> std::io::Result<()> {
+        match file.syntax() {
+            Syntax::Nasm => {
+                file.write_all(T::SIZE.to_nasm_size().as_bytes())?;
+                file.write_all(b" ")
+            }
+            _ => {
+                file.write_all(T::SIZE.to_intel_ptr().as_bytes())?;
+                file.write_all(b" ")
+            }
+        }
+    }
+
+    /// Opening bracket of a memory operand with its segment (`fs:[` in Intel syntax, `[fs:` in NASM)
+    fn write_bracket(file: &mut Writer, segment: Option<&str>) -> std::io::Result<()> {
+        match (segment, file.syntax()) {
+            (None, _) => file.write_all(b"["),
+            (Some(segment), Syntax::Nasm) => file.write_all(format!("[{}:", segment).as_bytes()),
+            (Some(segment), _) => file.write_all(format!("{}:[", segment).as_bytes()),
+        }
+    }
+
+    /// Write operand in Intel or NASM syntax, `size` tells if the size of memory operands is written
+    /// and `segment` is the segment override of memory operands
+    pub(crate) fn write_intel(
+        &self,
+        file: &mut Writer,
+        size: bool,
+        segment: Option<&str>,
+    ) -> std::io::Result<()> {
+        // the size of memory operands of XMM and YMM registers is given by the instruction
+        if size && !matches!(T::SIZE, Sizes::Xmm | Sizes::Ymm) && self.is_memory() {
+            Self::write_size(file)?;
+        }
+        match self {
+            Self::Reg(reg) => reg.write_with(file),
+            Self::Addr(offset, reg, index, scale) => {
+                Self::write_bracket(file, segment)?;
+                reg.write_with(file)?;
+                if let Some(index) = index {
+                    file.write_all(b"+")?;
+                    index.write_with(file)?;
+                    file.write_all(format!("*{}", scale).as_bytes())?;
+                }
+                if *offset != 0 {
+                    file.write_all(format!("{:+}", offset).as_bytes())?;
+                }
+                file.write_all(b"]")
+            }
+            Self::Index(offset, index, scale) => {
+                Self::write_bracket(file, segment)?;
+                index.write_with(file)?;
+                file.write_all(format!("*{}", scale).as_bytes())?;
+                if *offset != 0 {
+                    file.write_all(format!("{:+}", offset).as_bytes())?;
+                }
+                file.write_all(b"]")
+            }
+            Self::LabRelAddr(label) => {
+                Self::write_bracket(file, segment)?;
+                if file.syntax() == Syntax::Nasm {
+                    file.write_all(b"rel ")?;
+                } else {
+                    file.write_all(b"rip+")?;
+                }
+                label.write_with(file)?;
+                file.write_all(b"]")
+            }
+            Self::LabAbsAddr(label) => {
+                Self::write_bracket(file, segment)?;
+                label.write_with(file)?;
+                file.write_all(b"]")
+            }
+            Self::LabVal(label) => {
+                if file.syntax() == Syntax::Intel {
+                    file.write_all(b"OFFSET ")?;
+                }
+                label.write_with(file)
+            }
+            Self::Imm(id) => file.write_all(format!("{}", id).as_bytes()),
+        }
+    }
+
+    /// Label used by the operand
+    pub fn label(&self) -> Option<&Label> {
+        match self {
+            Self::LabRelAddr(label) | Self::LabAbsAddr(label) | Self::LabVal(label) => Some(label),
+            Self::Addr(_, _, _, _) | Self::Index(_, _, _) | Self::Reg(_) | Self::Imm(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Memory operand of any size, it is the only destination of atomic instructions
+pub enum Memory {
+    /// Address at 1 + 2 + 3 * 4
+    Addr(i64, RegQ, Option<RegQ>, u8),
+    /// Address at 1 + 2 * 3 (without base register)
+    Index(i64, RegQ, u8),
+    /// Label relative to RIP
+    LabRelAddr(Label),
+    /// Absolute label address
+    LabAbsAddr(Label),
+}
+
+impl<T: Reg> From<Memory> for Operand<T> {
+    fn from(mem: Memory) -> Self {
+        match mem {
+            Memory::Addr(offset, reg, index, scale) => Self::Addr(offset, reg, index, scale),
+            Memory::Index(offset, index, scale) => Self::Index(offset, index, scale),
+            Memory::LabRelAddr(label) => Self::LabRelAddr(label),
+            Memory::LabAbsAddr(label) => Self::LabAbsAddr(label),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
+/// Register or immediate operand, it is the only source of atomic instructions
+pub enum RegImm<T: Reg> {
+    /// Direct access to register
+    Reg(T),
+    /// Immediate value
+    Imm(i64),
+}
+
+impl<T: Reg> From<RegImm<T>> for Operand<T> {
+    fn from(src: RegImm<T>) -> Self {
+        match src {
+            RegImm::Reg(reg) => Self::Reg(reg),
+            RegImm::Imm(imm) => Self::Imm(imm),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// Label
 pub struct Label {
     name: String,
@@ -402,10 +613,16 @@ pub struct Label {
 
 impl Label {
     /// Create label from string
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(name: String) -> Self {
         Self { name }
     }
 
+    /// Name of the label (without the prefix added on some targets)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     /// Printf function label
     pub fn printf() -> Self {
         Self {
@@ -441,18 +658,143 @@ impl Label {
         }
     }
 
-    #[cfg(target_os = "macos")]
-    /// Write label in file (implementation differs on linux and mac)
-    pub fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        file.write_all(b"_")?;
-        file.write_all(self.name.as_bytes())
+    /// Write label in file (for the host target)
+    pub fn write_in(&self, file: &mut dyn Write) -> std::io::Result<()> {
+        self.write_with(&mut Writer::new(file))
+    }
+
+    /// Write label using the writer (symbols are prefixed on some targets)
+    ///
+    /// NASM attaches a label starting with `.` to the previous label, they are written as `..@` labels
+    /// (`.Lend` is `..@Lend`) which are not
+    pub fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        match self.name.strip_prefix('.') {
+            Some(name) if file.syntax() == Syntax::Nasm => {
+                file.write_all(b"..@")?;
+                file.write_all(name.as_bytes())
+            }
+            _ => {
+                file.write_all(file.target().symbol_prefix().as_bytes())?;
+                file.write_all(self.name.as_bytes())
+            }
+        }
+    }
+}
+
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// 16 bytes registers of floating point instructions
+pub enum RegX {
+    Xmm0,
+    Xmm1,
+    Xmm2,
+    Xmm3,
+    Xmm4,
+    Xmm5,
+    Xmm6,
+    Xmm7,
+    Xmm8,
+    Xmm9,
+    Xmm10,
+    Xmm11,
+    Xmm12,
+    Xmm13,
+    Xmm14,
+    Xmm15,
+}
+
+impl RegX {
+    fn to_str(self) -> &'static str {
+        match self {
+            Self::Xmm0 => "%xmm0",
+            Self::Xmm1 => "%xmm1",
+            Self::Xmm2 => "%xmm2",
+            Self::Xmm3 => "%xmm3",
+            Self::Xmm4 => "%xmm4",
+            Self::Xmm5 => "%xmm5",
+            Self::Xmm6 => "%xmm6",
+            Self::Xmm7 => "%xmm7",
+            Self::Xmm8 => "%xmm8",
+            Self::Xmm9 => "%xmm9",
+            Self::Xmm10 => "%xmm10",
+            Self::Xmm11 => "%xmm11",
+            Self::Xmm12 => "%xmm12",
+            Self::Xmm13 => "%xmm13",
+            Self::Xmm14 => "%xmm14",
+            Self::Xmm15 => "%xmm15",
+        }
+    }
+}
+
+impl Reg for RegX {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        write_reg_name(self.to_str(), file)
     }
 
-    #[cfg(target_os = "linux")]
-    /// Write label in file (implementation differs on linux and mac)
-    pub fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()> {
-        file.write_all(self.name.as_bytes())
+    fn to_bits(&self) -> (bool, u8) {
+        let num = *self as u8;
+        (num >= 8, num & 0b111)
     }
+
+    const SIZE: Sizes = Sizes::Xmm;
+}
+
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// 32 bytes registers of AVX instructions, their lowest 16 bytes are the XMM registers
+pub enum RegY {
+    Ymm0,
+    Ymm1,
+    Ymm2,
+    Ymm3,
+    Ymm4,
+    Ymm5,
+    Ymm6,
+    Ymm7,
+    Ymm8,
+    Ymm9,
+    Ymm10,
+    Ymm11,
+    Ymm12,
+    Ymm13,
+    Ymm14,
+    Ymm15,
+}
+
+impl RegY {
+    fn to_str(self) -> &'static str {
+        match self {
+            Self::Ymm0 => "%ymm0",
+            Self::Ymm1 => "%ymm1",
+            Self::Ymm2 => "%ymm2",
+            Self::Ymm3 => "%ymm3",
+            Self::Ymm4 => "%ymm4",
+            Self::Ymm5 => "%ymm5",
+            Self::Ymm6 => "%ymm6",
+            Self::Ymm7 => "%ymm7",
+            Self::Ymm8 => "%ymm8",
+            Self::Ymm9 => "%ymm9",
+            Self::Ymm10 => "%ymm10",
+            Self::Ymm11 => "%ymm11",
+            Self::Ymm12 => "%ymm12",
+            Self::Ymm13 => "%ymm13",
+            Self::Ymm14 => "%ymm14",
+            Self::Ymm15 => "%ymm15",
+        }
+    }
+}
+
+impl Reg for RegY {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        write_reg_name(self.to_str(), file)
+    }
+
+    fn to_bits(&self) -> (bool, u8) {
+        let num = *self as u8;
+        (num >= 8, num & 0b111)
+    }
+
+    const SIZE: Sizes = Sizes::Ymm;
 }
 
 /// Type representing a register that can never occur
@@ -463,7 +805,7 @@ pub enum RegInv {}
 impl Reg for RegInv {
     const SIZE: Sizes = Sizes::Invalid;
 
-    fn write_in(&self, _: &mut std::fs::File) -> std::io::Result<()> {
+    fn write_with(&self, _: &mut Writer) -> std::io::Result<()> {
         panic!("Internal error")
     }
 
@@ -471,3 +813,51 @@ impl Reg for RegInv {
         panic!("Should not happen")
     }
 }
+
+/// Registers used implicitly by string instructions and widening multiplications, they are
+/// not written in the instruction and only show in the signature of the constructors which
+/// registers are used
+///
+/// `rep_movsq(implicit::Rcx, implicit::Rsi, implicit::Rdi)` copies %rcx quads from (%rsi) to (%rdi),
+/// `mulq(reg!(RBX), implicit::Rax, implicit::RdxRax)` stores %rax * %rbx in %rdx:%rax
+pub mod implicit {
+    /// `%rcx`, number of repetitions of the `rep` prefixes
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct Rcx;
+
+    /// `(%rsi)`, source of string instructions, %rsi is advanced
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct Rsi;
+
+    /// `(%rdi)`, destination of string instructions, %rdi is advanced
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct Rdi;
+
+    /// `%al`, accumulator of 1-byte elements
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct Al;
+
+    /// `%ax`, accumulator of 2-bytes elements and result of 1-byte multiplications
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct Ax;
+
+    /// `%eax`, accumulator of 4-bytes elements
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct Eax;
+
+    /// `%rax`, accumulator of 8-bytes elements
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct Rax;
+
+    /// `%dx:%ax`, result of 2-bytes multiplications
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct DxAx;
+
+    /// `%edx:%eax`, result of 4-bytes multiplications
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct EdxEax;
+
+    /// `%rdx:%rax`, result of 8-bytes multiplications
+    #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+    pub struct RdxRax;
+}
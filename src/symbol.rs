@@ -0,0 +1,267 @@
+use crate::reg::Label;
+use crate::target::Os;
+use crate::traits::{UsesLabels, Writable};
+use crate::writer::{unsupported, Syntax, Writer};
+use std::io::Write;
+
+/// Binding of a symbol
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    /// Visible from other files (.globl)
+    Global,
+    /// Only visible in this file (.local)
+    Local,
+    /// Visible from other files and can be overriden by a global symbol (.weak)
+    Weak,
+}
+
+/// Visibility of a global symbol once linked in a shared object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    /// Exported from the shared object
+    Default,
+    /// Not exported from the shared object (.hidden)
+    Hidden,
+    /// Exported but cannot be overriden (.protected)
+    Protected,
+}
+
+/// Type of a symbol (`@function` in `.type main,@function`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolType {
+    /// Symbol is a function
+    Function,
+    /// Symbol is a data object
+    Object,
+}
+
+/// Size of a symbol
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolSize {
+    /// Size in bytes
+    Const(usize),
+    /// Size computed from the label marking the end of the symbol
+    End(Label),
+}
+
+/// Attributes of a symbol defined (or used) in a file
+///
+/// ```
+/// use write_x86_64::*;
+/// use write_x86_64::symbol::Symbol;
+///
+/// let symbol = Symbol::global(new_label("f"))
+///     .function()
+///     .with_end(new_label(".Lf_end"));
+/// assert_eq!(
+///     symbol.to_string(),
+///     "\t.globl f\n\t.type f, @function\n\t.size f, .Lf_end-f\n"
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    /// Label of the symbol
+    pub label: Label,
+    /// Binding of the symbol
+    pub binding: Binding,
+    /// Visibility of the symbol
+    pub visibility: Visibility,
+    /// Type of the symbol
+    pub kind: Option<SymbolType>,
+    /// Size of the symbol
+    pub size: Option<SymbolSize>,
+}
+
+impl Symbol {
+    fn new(label: Label, binding: Binding) -> Self {
+        Self {
+            label,
+            binding,
+            visibility: Visibility::Default,
+            kind: None,
+            size: None,
+        }
+    }
+
+    /// Global symbol
+    pub fn global(label: Label) -> Self {
+        Self::new(label, Binding::Global)
+    }
+
+    /// Local symbol
+    pub fn local(label: Label) -> Self {
+        Self::new(label, Binding::Local)
+    }
+
+    /// Weak symbol
+    pub fn weak(label: Label) -> Self {
+        Self::new(label, Binding::Weak)
+    }
+
+    /// Hide symbol outside of the shared object
+    pub fn hidden(mut self) -> Self {
+        self.visibility = Visibility::Hidden;
+        self
+    }
+
+    /// Make symbol protected
+    pub fn protected(mut self) -> Self {
+        self.visibility = Visibility::Protected;
+        self
+    }
+
+    /// Mark symbol as a function
+    pub fn function(mut self) -> Self {
+        self.kind = Some(SymbolType::Function);
+        self
+    }
+
+    /// Mark symbol as a data object
+    pub fn object(mut self) -> Self {
+        self.kind = Some(SymbolType::Object);
+        self
+    }
+
+    /// Set size of symbol in bytes
+    pub fn with_size(mut self, size: usize) -> Self {
+        self.size = Some(SymbolSize::Const(size));
+        self
+    }
+
+    /// Set size of symbol as the distance between its label and `end`
+    pub fn with_end(mut self, end: Label) -> Self {
+        self.size = Some(SymbolSize::End(end));
+        self
+    }
+
+    fn write_size(&self, file: &mut Writer) -> std::io::Result<()> {
+        match &self.size {
+            None => Ok(()),
+            Some(SymbolSize::Const(size)) => file.write_all(format!("{}", size).as_bytes()),
+            Some(SymbolSize::End(end)) => {
+                end.write_with(file)?;
+                file.write_all(b"-")?;
+                self.label.write_with(file)
+            }
+        }
+    }
+
+    /// Write binding, visibility and type (the size is written by [`Symbol::write_size_directive`])
+    pub fn write_attributes(&self, file: &mut Writer) -> std::io::Result<()> {
+        match file.syntax() {
+            Syntax::Nasm => self.write_nasm(file),
+            Syntax::Att | Syntax::Intel => match file.target().os {
+                Os::Linux => self.write_elf(file),
+                Os::MacOs => self.write_macho(file),
+            },
+        }
+    }
+
+    /// Write `.size` directive, it is usually placed after the end of the symbol
+    ///
+    /// Nothing is written for NASM (the size is part of the `global` directive) and on macOS
+    pub fn write_size_directive(&self, file: &mut Writer) -> std::io::Result<()> {
+        if self.size.is_none() || file.syntax() == Syntax::Nasm || file.target().os == Os::MacOs {
+            return Ok(());
+        }
+        file.write_all(b"\t.size ")?;
+        self.label.write_with(file)?;
+        file.write_all(b", ")?;
+        self.write_size(file)?;
+        file.write_all(b"\n")
+    }
+
+    fn write_directive(&self, file: &mut Writer, directive: &[u8]) -> std::io::Result<()> {
+        file.write_all(b"\t")?;
+        file.write_all(directive)?;
+        file.write_all(b" ")?;
+        self.label.write_with(file)?;
+        file.write_all(b"\n")
+    }
+
+    fn write_elf(&self, file: &mut Writer) -> std::io::Result<()> {
+        match self.binding {
+            Binding::Global => self.write_directive(file, b".globl")?,
+            Binding::Local => self.write_directive(file, b".local")?,
+            Binding::Weak => self.write_directive(file, b".weak")?,
+        }
+        match self.visibility {
+            Visibility::Default => (),
+            Visibility::Hidden => self.write_directive(file, b".hidden")?,
+            Visibility::Protected => self.write_directive(file, b".protected")?,
+        }
+        if let Some(kind) = self.kind {
+            file.write_all(b"\t.type ")?;
+            self.label.write_with(file)?;
+            file.write_all(match kind {
+                SymbolType::Function => b", @function\n",
+                SymbolType::Object => b", @object\n",
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_macho(&self, file: &mut Writer) -> std::io::Result<()> {
+        match self.binding {
+            Binding::Global => self.write_directive(file, b".globl")?,
+            Binding::Local => (),
+            Binding::Weak => {
+                self.write_directive(file, b".globl")?;
+                self.write_directive(file, b".weak_definition")?
+            }
+        }
+        match self.visibility {
+            Visibility::Default => Ok(()),
+            Visibility::Hidden => self.write_directive(file, b".private_extern"),
+            Visibility::Protected => Err(unsupported("protected visibility", file.syntax())),
+        }
+    }
+
+    fn write_nasm(&self, file: &mut Writer) -> std::io::Result<()> {
+        match self.binding {
+            Binding::Local => return Ok(()),
+            Binding::Weak => return Err(unsupported(".weak", file.syntax())),
+            Binding::Global => (),
+        }
+        file.write_all(b"\tglobal ")?;
+        self.label.write_with(file)?;
+        match self.kind {
+            None => (),
+            Some(SymbolType::Function) => file.write_all(b":function")?,
+            Some(SymbolType::Object) => file.write_all(b":data")?,
+        }
+        match self.visibility {
+            Visibility::Default => (),
+            Visibility::Hidden => file.write_all(b" hidden")?,
+            Visibility::Protected => file.write_all(b" protected")?,
+        }
+        if self.size.is_some() {
+            file.write_all(b" (")?;
+            self.write_size(file)?;
+            file.write_all(b")")?;
+        }
+        file.write_all(b"\n")
+    }
+}
+
+impl Writable for Symbol {
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()> {
+        self.write_attributes(file)?;
+        self.write_size_directive(file)
+    }
+}
+
+impl UsesLabels for Symbol {
+    fn used_labels(&self) -> Vec<&Label> {
+        match &self.size {
+            Some(SymbolSize::End(end)) => vec![end],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::traits::fmt_writable(self, f)
+    }
+}
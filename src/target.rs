@@ -0,0 +1,121 @@
+use crate::reg::{Label, Operand, RegQ};
+use crate::traits::Reg;
+use crate::{instr, Text};
+
+/// Operating systems (and object formats) that can be targeted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Os {
+    /// Linux, ELF object files
+    Linux,
+    /// macOS, Mach-O object files
+    MacOs,
+}
+
+/// Target of the generated assembly
+///
+/// It decides how symbols are named and how labels are addressed, so that
+/// assembly for any target can be produced from any host.
+///
+/// ```
+/// use write_x86_64::*;
+/// use write_x86_64::target::Target;
+///
+/// let target = Target::linux().with_pie(false);
+/// let counter: reg::Operand<reg::RegQ> = lab!(target, new_label("counter"));
+/// assert!(matches!(counter, reg::Operand::LabAbsAddr(_)));
+/// let counter: reg::Operand<reg::RegQ> = lab!(Target::linux(), new_label("counter"));
+/// assert!(matches!(counter, reg::Operand::LabRelAddr(_)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// Operating system
+    pub os: Os,
+    /// Position independent executable (labels can only be accessed relatively to %rip)
+    pub pie: bool,
+}
+
+impl Target {
+    /// Linux target producing position independent code (default of modern toolchains)
+    pub const fn linux() -> Self {
+        Self {
+            os: Os::Linux,
+            pie: true,
+        }
+    }
+
+    /// macOS target (always position independent)
+    pub const fn macos() -> Self {
+        Self {
+            os: Os::MacOs,
+            pie: true,
+        }
+    }
+
+    /// Target of the machine compiling the crate
+    pub const fn host() -> Self {
+        if cfg!(target_os = "macos") {
+            Self::macos()
+        } else {
+            Self::linux()
+        }
+    }
+
+    /// Change whether code is position independent (ignored on macOS)
+    pub const fn with_pie(mut self, pie: bool) -> Self {
+        self.pie = pie || matches!(self.os, Os::MacOs);
+        self
+    }
+
+    /// Prefix added to every symbol name
+    pub fn symbol_prefix(&self) -> &'static str {
+        match self.os {
+            Os::Linux => "",
+            Os::MacOs => "_",
+        }
+    }
+
+    /// Tests if labels must be accessed relatively to %rip
+    pub fn rip_relative(&self) -> bool {
+        self.pie || self.os == Os::MacOs
+    }
+
+    /// Access memory at label (lab operator from <https://www.lri.fr/~filliatr/ens/compil/lib/x86_64.ml.html>)
+    pub fn lab<T: Reg>(&self, label: Label) -> Operand<T> {
+        if self.rip_relative() {
+            Operand::LabRelAddr(label)
+        } else {
+            Operand::LabAbsAddr(label)
+        }
+    }
+
+    /// Move address of label in register
+    ///
+    /// Usefull to get address to string before calling printf
+    pub fn deplq(&self, l: Label, reg: RegQ) -> Text {
+        if self.rip_relative() {
+            Text::new(Box::new(instr::Instruction::<RegQ, RegQ> {
+                instr: instr::InstrName::Lea,
+                reg1: Some(Operand::LabRelAddr(l)),
+                reg2: Some(Operand::Reg(reg)),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        } else {
+            Text::new(Box::new(instr::Instruction::<RegQ, RegQ> {
+                instr: instr::InstrName::Move,
+                reg1: Some(Operand::LabVal(l)),
+                reg2: Some(Operand::Reg(reg)),
+                reg3: None,
+                lock_rep: None,
+                segment: None,
+            }))
+        }
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self::host()
+    }
+}
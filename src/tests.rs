@@ -25,6 +25,7 @@ fn hello_world() {
         globl: Some(new_label("main")),
         text_ss,
         data_ss,
+        ..Default::default()
     };
 
     file.print_in(file_name).unwrap();
@@ -72,6 +73,7 @@ fn hello_world() {
         globl: Some(new_label("main")),
         text_ss,
         data_ss,
+        ..Default::default()
     };
 
     file.print_in(file_name).unwrap();
@@ -89,3 +91,2594 @@ fn hello_world() {
     std::fs::remove_file("a.out").unwrap();
     assert_eq!(&output.stdout, b"Hello World\n");
 }
+
+#[test]
+// instructions are written as bytes with gen_binary
+#[cfg(not(feature = "gen_binary"))]
+fn write_to_string() {
+    let text_ss = Segment::comment("entry".to_string())
+        + pushq(reg!(RBP))
+        + movq(immq(42), reg!(RAX))
+        + addq(addr!(-8, RBP), reg!(RAX))
+        + popq(RBP)
+        + ret();
+
+    assert_eq!(
+        text_ss.to_string(),
+        "## entry\n\tpushq %rbp\n\tmovq $42, %rax\n\taddq -8(%rbp), %rax\n\tpopq %rbp\n\tret\n"
+    );
+
+    let file = file::File {
+        globl: None,
+        text_ss: nop(),
+        data_ss: data::dquad(1),
+        ..Default::default()
+    };
+
+    let mut buffer = Vec::new();
+    traits::Writable::write_in(&file, &mut buffer).unwrap();
+    assert_eq!(buffer, file.to_string().into_bytes());
+    assert_eq!(
+        file.to_string(),
+        "\t.text\n\tnop\n\t.data\n\t\t.quad  1         \n"
+    );
+}
+
+#[test]
+#[cfg(feature = "gen_binary")]
+fn write_to_string_gen_binary() {
+    let text_ss = Segment::comment("entry".to_string())
+        + pushq(reg!(RBP))
+        + Segment::label(new_label("l"))
+        + jmp(new_label("l"));
+
+    assert_eq!(
+        text_ss.to_string(),
+        "## entry\n\t## pushq %rbp\n\t.byte 0x55\nl:\n\t## jmp l\n\t.byte 0xe9\n\t.long l-4-.\n"
+    );
+
+    let file = file::File {
+        globl: None,
+        text_ss: nop(),
+        data_ss: data::dquad(1),
+        ..Default::default()
+    };
+
+    let mut buffer = Vec::new();
+    traits::Writable::write_in(&file, &mut buffer).unwrap();
+    assert_eq!(buffer, file.to_string().into_bytes());
+    assert_eq!(
+        file.to_string(),
+        "\t.text\n\t## nop\n\t.byte 0x90\n\t.data\n\t\t.quad  1         \n"
+    );
+}
+
+/// Write `file` as an object file (without `as`), link it with gcc and return the output of the program
+#[cfg(target_os = "linux")]
+fn run_object(file: &file::File, name: &str) -> Vec<u8> {
+    let object_name = format!("{name}.o");
+    let exec_name = format!("./{name}.out");
+    file.print_object_in(&object_name).unwrap();
+    let output = Command::new("gcc")
+        .args(["-o", &exec_name, &object_name])
+        .output()
+        .expect("failed linking");
+    std::fs::remove_file(&object_name).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = Command::new(&exec_name).output().expect("failed running");
+    std::fs::remove_file(&exec_name).unwrap();
+    output.stdout
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn hello_world_object() {
+    let text_ss = Segment::label(new_label("main"))
+        + pushq(reg!(RBP))
+        + leaq(lab!(new_label("my_string")), RDI)
+        + call(reg::Label::printf())
+        + leaq(lab!(new_label("my_string2")), RDI)
+        + call(reg::Label::printf())
+        + xorq(reg!(RAX), reg!(RAX))
+        + popq(RBP)
+        + ret();
+
+    let data_ss = Data::label(new_label("my_string"))
+        + data::dasciz("Hello".to_string())
+        + Data::label(new_label("my_string2"))
+        + data::dasciz(" World\\n".to_string());
+
+    let file = file::File {
+        globl: Some(new_label("main")),
+        text_ss,
+        data_ss,
+        ..Default::default()
+    };
+
+    assert_eq!(run_object(&file, "object_hello_world"), b"Hello World\n");
+}
+
+/// Assemble `asm` with the system assembler and call `f` on the object file
+#[cfg(target_os = "linux")]
+fn with_gas_object<T>(name: &str, asm: &str, f: impl FnOnce(&std::path::Path) -> T) -> T {
+    let dir = std::env::temp_dir();
+    let src = dir.join(format!("write_x86_64_{name}.s"));
+    let obj = dir.join(format!("write_x86_64_{name}.o"));
+    std::fs::write(&src, asm).unwrap();
+    let output = Command::new("as")
+        .arg(&src)
+        .arg("-o")
+        .arg(&obj)
+        .output()
+        .expect("failed assembling");
+    assert!(
+        output.status.success(),
+        "{}\n{asm}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let res = f(&obj);
+    for file in [src, obj] {
+        std::fs::remove_file(file).unwrap();
+    }
+    res
+}
+
+/// Content of a section of an object file
+#[cfg(target_os = "linux")]
+fn section_bytes(obj: &std::path::Path, section: &str) -> Vec<u8> {
+    let bin = obj.with_extension(format!("{}.bin", section.trim_start_matches('.')));
+    Command::new("objcopy")
+        .args(["-O", "binary", "--set-section-flags"])
+        .arg(format!("{section}=alloc"))
+        .arg(format!("--only-section={section}"))
+        .arg(obj)
+        .arg(&bin)
+        .output()
+        .expect("failed extracting");
+    let bytes = std::fs::read(&bin).unwrap();
+    std::fs::remove_file(bin).unwrap();
+    bytes
+}
+
+/// Section, offset and type of the relocations of an object file
+#[cfg(target_os = "linux")]
+fn gas_relocations(obj: &std::path::Path) -> Vec<(String, usize, String)> {
+    let output = Command::new("readelf")
+        .arg("-rW")
+        .arg(obj)
+        .output()
+        .expect("failed reading relocations");
+    let mut relocations = Vec::new();
+    let mut section = String::new();
+    for line in String::from_utf8(output.stdout).unwrap().lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if line.starts_with("Relocation section") {
+            section = words[2]
+                .trim_matches('\'')
+                .trim_start_matches(".rela")
+                .to_string();
+        } else if words.len() > 2 && words[2].starts_with("R_X86_64") {
+            let offset = usize::from_str_radix(words[0], 16).unwrap();
+            relocations.push((section.clone(), offset, words[2].to_string()));
+        }
+    }
+    relocations
+}
+
+/// Assemble `asm` with the system assembler and return the content of `.text`
+#[cfg(target_os = "linux")]
+fn assemble_text(name: &str, asm: &str) -> Vec<u8> {
+    with_gas_object(name, asm, |obj| section_bytes(obj, ".text"))
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "gen_binary")))]
+fn intel_syntax() {
+    use reg::implicit::{Al, Rax, Rcx, Rdi, RdxRax, Rsi};
+    use writer::{Syntax, Writer};
+
+    let build = || {
+        Segment::label(new_label("f"))
+            + pushq(reg!(RBP))
+            + movq(reg!(RSP), reg!(RBP))
+            + jrcxz(new_label("f"))
+            + loopne(new_label("f"))
+            + movl(imml(3), addr!(-4, RBP))
+            + movq(addr!(8, RBP, RCX, 8), reg!(RAX))
+            + movsbq(addr!(RDI), RAX)
+            + movslq(reg!(EAX), RDX)
+            + movzwl(reg!(CX), EAX)
+            + leaq(addr!(16, RSP), RSI)
+            + leaq(reg::Operand::LabRelAddr(new_label("f")), RDI)
+            + movq(ilab!(new_label("f")), reg!(RCX))
+            + movq(immq(1 << 40), reg!(R8))
+            + movabsq(ilab!(new_label("f")), reg!(RDX))
+            + movabsq(reg!(RAX), reg::Operand::LabAbsAddr(new_label("f")))
+            + subq(immq(-16), reg!(RSP))
+            + shlq_reg(reg!(RDX))
+            + sarq_reg(reg!(RAX))
+            + rolw(immw(3), addr!(RDI))
+            + shldq(immq(3), RAX, reg!(RBX))
+            + shrdl_reg(EAX, addr!(RDI))
+            + sarb(immb(1), addr!(-1, RBP))
+            + cmovq(instr::Cond::GE, addr!(-8, RBP), reg!(RAX))
+            + set(instr::Cond::NE, reg!(AL))
+            + cmovnaeq(reg!(RCX), reg!(RAX))
+            + setpo(reg!(DL))
+            + jo(new_label("f"))
+            + cqto()
+            + idivq(reg!(RCX))
+            + call(new_label("f"))
+            + call_star(reg!(RAX))
+            + jmp_star(addr!(R11))
+            + jz(new_label("f"))
+            + movsd(addr!(-8, RBP), reg!(XMM0))
+            + cvtsi2sdl(addr!(-4, RBP), XMM1)
+            + cvttsd2siq(reg!(XMM1), RAX)
+            + movq_to_xmm(RAX, XMM2)
+            + btsq(immq(5), addr!(-8, RBP))
+            + popcntl(addr!(-4, RBP), EAX)
+            + shlxq(RCX, addr!(RDI), RAX)
+            + rorxl(imml(7), reg!(EAX), EBX)
+            + mulxq(addr!(RDI), RBX, RAX)
+            + mulq(addr!(-8, RBP), Rax, RdxRax)
+            + imulq_imm(immq(3), reg!(RAX), RBX)
+            + rep_movsl(Rcx, Rsi, Rdi)
+            + repne_scasb(Rcx, Rdi, Al)
+            + lock_addq(reg::RegImm::Reg(RAX), mem!(RDI))
+            + lock_cmpxchg16b(mem!(RDI))
+            + fs(movq(addr!(8, RAX), reg!(RDX)))
+            + gs(rep_movsq(Rcx, Rsi, Rdi))
+            + xchgq(reg!(RAX), addr!(RDI))
+            + vmovups(addr!(RDI), reg!(YMM0))
+            + vfmadd231ps(addr!(32, RDI), YMM1, YMM0)
+            + vpmovmskb(YMM0, EAX)
+            + vbroadcastss(addr!(RSI), YMM2)
+            + vzeroupper()
+            + leave()
+            + ret()
+    };
+    let file = |syntax| {
+        let file = file::File {
+            globl: None,
+            text_ss: build(),
+            data_ss: Data::empty(),
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        traits::Writable::write_with(&file, &mut Writer::new(&mut buffer).with_syntax(syntax))
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    };
+
+    let intel = file(Syntax::Intel);
+    assert!(intel.contains("\tmov rax, QWORD PTR [rbp+rcx*8+8]\n"));
+    assert!(intel.contains("\tmovsxd rdx, eax\n"));
+    assert!(intel.contains("\tmovabs r8, 1099511627776\n"));
+    assert!(intel.contains("\tcvtsi2sd xmm1, DWORD PTR [rbp-4]\n"));
+    assert!(intel.contains("\tvfmadd231ps ymm0, ymm1, [rdi+32]\n"));
+    assert!(intel.contains("\tshlx rax, QWORD PTR [rdi], rcx\n"));
+    assert!(intel.contains("\trep movsd\n"));
+    assert!(intel.contains("\tsar rax, cl\n"));
+    assert!(intel.contains("\tshld rbx, rax, 3\n"));
+    assert!(intel.contains("\tlock add QWORD PTR [rdi], rax\n"));
+    assert!(intel.contains("\tlock cmpxchg16b [rdi]\n"));
+    assert!(intel.contains("\tmov rdx, QWORD PTR fs:[rax+8]\n"));
+    assert!(file(Syntax::Att).contains("\tmovq %fs:8(%rax), %rdx\n"));
+    assert!(intel.contains("\tgs rep movsq\n"));
+    assert!(intel.contains("\tcmovnae rax, rcx\n"));
+    assert!(intel.contains("\tloopne f\n"));
+    assert!(intel.contains("\tmul QWORD PTR [rbp-8]\n"));
+    assert!(intel.contains("\timul rbx, rax, 3\n"));
+    assert!(intel.contains("\tmulx rax, rbx, QWORD PTR [rdi]\n"));
+    assert_eq!(
+        assemble_text("intel_syntax", &intel),
+        assemble_text("att_syntax", &file(Syntax::Att))
+    );
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "gen_binary")))]
+fn nasm_syntax() {
+    use writer::{Syntax, Writer};
+
+    let text_ss = Segment::label(new_label("main"))
+        + pushq(reg!(RBP))
+        + leaq(reg::Operand::LabRelAddr(new_label("my_string")), RDI)
+        + call(reg::Label::printf()).add_comment("print".to_string())
+        + movzbl(addr!(-1, RBP, RAX, 4), EAX)
+        + movq(ilab!(new_label("my_string")), reg!(RCX))
+        + Text::directive(directives::Directive::P2Align(4, None, None))
+        + xorq(reg!(RAX), reg!(RAX))
+        + popq(RBP)
+        + ret();
+
+    let data_ss = Data::label(new_label("my_string"))
+        + data::dasciz("Hello `World`\\n".to_string())
+        + data::daddress(new_label("main"))
+        + data::dlong(-3)
+        + data::space(8);
+
+    let file = file::File {
+        globl: Some(new_label("main")),
+        text_ss,
+        data_ss,
+        ..Default::default()
+    };
+
+    let mut buffer = Vec::new();
+    traits::Writable::write_with(
+        &file,
+        &mut Writer::new(&mut buffer).with_syntax(Syntax::Nasm),
+    )
+    .unwrap();
+    let nasm = String::from_utf8(buffer).unwrap();
+    assemble_nasm("nasm_syntax", &nasm);
+    assert_eq!(
+        nasm,
+        "\tsection .text
+\tglobal main
+\textern printf
+main:
+\tpush rbp
+\tlea rdi, [rel my_string]
+\tcall printf ; print
+\tmovzx eax, byte [rbp+rax*4-1]
+\tmov rcx, my_string
+\talign 16
+\txor rax, rax
+\tpop rbp
+\tret
+\tsection .data
+my_string:
+\tdb `Hello \\`World\\`\\n`, 0
+\tdq main
+\tdd -3
+\ttimes 8 db 0
+"
+    );
+
+    let loc = Text::directive(directives::Directive::Loc(1, 2, None, Vec::new()));
+    let mut buffer = Vec::new();
+    let error = traits::Writable::write_with(
+        &loc,
+        &mut Writer::new(&mut buffer).with_syntax(Syntax::Nasm),
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+
+    // labels starting with a dot are not attached to the previous label
+    let file = file::File {
+        globl: Some(new_label("main")),
+        text_ss: Segment::label(new_label("main"))
+            + ret()
+            + Segment::label(new_label(".Lmain_end"))
+            + Segment::label(new_label("g"))
+            + jmp(new_label(".Lmain_end")),
+        ..Default::default()
+    };
+    let mut buffer = Vec::new();
+    traits::Writable::write_with(
+        &file,
+        &mut Writer::new(&mut buffer).with_syntax(Syntax::Nasm),
+    )
+    .unwrap();
+    let nasm = String::from_utf8(buffer).unwrap();
+    assert!(nasm.contains("\n..@Lmain_end:\ng:\n\tjmp ..@Lmain_end\n"));
+    assemble_nasm("nasm_local_labels", &nasm);
+}
+
+/// Assemble `asm` with `program`, nothing is checked when it is not on the PATH
+#[cfg(target_os = "linux")]
+fn assemble_with(program: &str, args: &[&str], name: &str, asm: &str) {
+    if Command::new(program).arg("--version").output().is_err() {
+        return;
+    }
+    let dir = std::env::temp_dir();
+    let src = dir.join(format!("write_x86_64_{name}.asm"));
+    let obj = dir.join(format!("write_x86_64_{name}.o"));
+    std::fs::write(&src, asm).unwrap();
+    let output = Command::new(program)
+        .args(args)
+        .arg("-o")
+        .arg(&obj)
+        .arg(&src)
+        .output()
+        .expect("failed assembling");
+    for file in [src, obj] {
+        let _ = std::fs::remove_file(file);
+    }
+    assert!(
+        output.status.success(),
+        "{}\n{asm}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Assemble NASM source, nothing is checked when nasm is not on the PATH
+#[cfg(target_os = "linux")]
+fn assemble_nasm(name: &str, asm: &str) {
+    assemble_with("nasm", &["-f", "elf64"], name, asm)
+}
+
+/// Assemble macOS source, nothing is checked when llvm-mc is not on the PATH
+#[cfg(target_os = "linux")]
+fn assemble_macho(name: &str, asm: &str) {
+    assemble_with(
+        "llvm-mc",
+        &["-triple", "x86_64-apple-macos", "-filetype=obj"],
+        name,
+        asm,
+    )
+}
+
+#[test]
+// instructions are written as bytes with gen_binary
+#[cfg(not(feature = "gen_binary"))]
+fn cross_target() {
+    use target::Target;
+    use writer::Writer;
+
+    let build = |target: Target| {
+        let text_ss = Segment::label(new_label("main"))
+            + target.deplq(new_label("my_string"), RDI)
+            + movq(lab!(target, new_label("my_string")), reg!(RAX))
+            + call(reg::Label::printf());
+        file::File {
+            globl: Some(new_label("main")),
+            text_ss,
+            data_ss: Data::empty(),
+            ..Default::default()
+        }
+    };
+    let write = |target: Target| {
+        let mut buffer = Vec::new();
+        traits::Writable::write_with(
+            &build(target),
+            &mut Writer::new(&mut buffer).with_target(target),
+        )
+        .unwrap();
+        String::from_utf8(buffer).unwrap()
+    };
+
+    assert_eq!(
+        write(Target::macos()),
+        "\t.text\n\t.globl\t_main\n_main:\n\tleaq _my_string(%rip), %rdi\n\tmovq _my_string(%rip), %rax\n\tcall _printf\n\t.data\n"
+    );
+    assert_eq!(
+        write(Target::linux()),
+        "\t.text\n\t.globl\tmain\nmain:\n\tleaq my_string(%rip), %rdi\n\tmovq my_string(%rip), %rax\n\tcall printf\n\t.data\n"
+    );
+    assert_eq!(
+        write(Target::linux().with_pie(false)),
+        "\t.text\n\t.globl\tmain\nmain:\n\tmovq $my_string, %rdi\n\tmovq my_string, %rax\n\tcall printf\n\t.data\n"
+    );
+    assert!(Target::macos().with_pie(false).rip_relative());
+}
+
+#[test]
+fn host_target() {
+    use target::Target;
+
+    // Linux executables are position independent by default, lab! and deplq use
+    // addresses relative to %rip (previous versions used absolute addresses on Linux)
+    let host = if cfg!(target_os = "macos") {
+        Target::macos()
+    } else {
+        Target::linux()
+    };
+    assert_eq!(Target::host(), host);
+    let label: reg::Operand<reg::RegQ> = lab!(new_label("counter"));
+    assert!(matches!(label, reg::Operand::LabRelAddr(_)));
+    assert_eq!(
+        deplq(new_label("counter"), RDI).to_string(),
+        host.deplq(new_label("counter"), RDI).to_string()
+    );
+    // previous behaviour on Linux
+    let label: reg::Operand<reg::RegQ> =
+        lab!(Target::linux().with_pie(false), new_label("counter"));
+    assert!(matches!(label, reg::Operand::LabAbsAddr(_)));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn hello_world_no_pie() {
+    let target = target::Target::linux().with_pie(false);
+    let file_name = "asm_file_no_pie.s";
+    let exec_name = "./a_no_pie.out";
+
+    let text_ss = Segment::label(new_label("main"))
+        + pushq(reg!(RBP))
+        + target.deplq(new_label("my_string"), RDI)
+        + call(reg::Label::printf())
+        + xorq(reg!(RAX), reg!(RAX))
+        + popq(RBP)
+        + ret();
+
+    let data_ss = Data::label(new_label("my_string")) + data::dasciz("Hello World\\n".to_string());
+
+    let file = file::File {
+        globl: Some(new_label("main")),
+        text_ss,
+        data_ss,
+        ..Default::default()
+    };
+
+    let mut out = std::fs::File::create(file_name).unwrap();
+    traits::Writable::write_with(
+        &file,
+        &mut writer::Writer::new(&mut out).with_target(target),
+    )
+    .unwrap();
+
+    Command::new("gcc")
+        .args(["-no-pie", "-o", exec_name, file_name])
+        .output()
+        .expect("failed linking");
+    std::fs::remove_file(file_name).unwrap();
+
+    let output = Command::new(exec_name).output().expect("failed running");
+    std::fs::remove_file(exec_name).unwrap();
+    assert_eq!(&output.stdout, b"Hello World\n");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn sections() {
+    use file::{Section, SectionFlags, SectionType};
+
+    let file_name = "asm_file_sections.s";
+    let exec_name = "./a_sections.out";
+
+    // init sets counter to 41, main increments it and prints it
+    let text_ss = Segment::label(new_label("main"))
+        + pushq(reg!(RBP))
+        + incq(lab!(new_label("counter")))
+        + leaq(lab!(new_label("format")), RDI)
+        + movq(lab!(new_label("counter")), reg!(RSI))
+        + xorq(reg!(RAX), reg!(RAX))
+        + call(reg::Label::printf())
+        + xorq(reg!(RAX), reg!(RAX))
+        + popq(RBP)
+        + ret()
+        + Segment::label(new_label(".Lmain_end"));
+
+    let init =
+        Segment::label(new_label("init")) + movq(immq(41), lab!(new_label("counter"))) + ret();
+
+    let file = file::File {
+        globl: Some(new_label("main")),
+        text_ss,
+        sections: vec![
+            Section::text(".text.startup", init),
+            Section::rodata(Data::label(new_label("format")) + data::dasciz("%ld\\n".to_string())),
+            Section::bss(Data::label(new_label("counter")) + data::space(8)),
+            Section::init_array(data::daddress(new_label("init"))),
+            Section::debug(
+                ".debug_str",
+                Data::label(new_label("name")) + data::dasciz("main".to_string()),
+            ),
+            Section::debug(
+                ".debug_info",
+                Segment::directive(directives::set_sub(
+                    new_label("main_size"),
+                    new_label(".Lmain_end"),
+                    new_label("main"),
+                )) + data::dlong_label(new_label("name"))
+                    + data::dlong_label(new_label("main_size")),
+            ),
+            Data::label(new_label("merged"))
+                .name(".rodata.cst8".to_string())
+                .with_flags(SectionFlags {
+                    merge: Some(8),
+                    ..SectionFlags::read_only()
+                })
+                .into(),
+        ],
+        ..Default::default()
+    };
+
+    let text = file.to_string();
+    assert!(text.contains("\t.section .text.startup,\"ax\",@progbits\ninit:\n"));
+    assert!(text.contains("\t.section .bss,\"aw\",@nobits\ncounter:\n"));
+    assert!(text.contains("\t.section .init_array,\"aw\",@init_array\n"));
+    assert!(text.contains("\t.section .rodata.cst8,\"aM\",@progbits,8\n"));
+
+    let mut buffer = Vec::new();
+    traits::Writable::write_with(
+        &file,
+        &mut writer::Writer::new(&mut buffer).with_syntax(writer::Syntax::Nasm),
+    )
+    .unwrap();
+    let nasm = String::from_utf8(buffer).unwrap();
+    assert!(nasm.contains("\tsection .bss nobits alloc noexec write\ncounter:\n\tresb 8\n"));
+    assert!(nasm.contains("\n..@Lmain_end:\n"));
+    assert!(nasm.contains("main_size equ ..@Lmain_end-main\n"));
+    assemble_nasm("sections", &nasm);
+
+    let macho = |section: &Section| {
+        let mut buffer = Vec::new();
+        traits::Writable::write_with(
+            section,
+            &mut writer::Writer::new(&mut buffer).with_target(target::Target::macos()),
+        )
+        .map(|()| String::from_utf8(buffer).unwrap())
+    };
+    let header = |section: &Section| macho(section).unwrap().lines().next().unwrap().to_string();
+    assert!(macho(&file.sections[2])
+        .unwrap()
+        .starts_with("\t.zerofill __DATA,__bss\n\t.section __DATA,__bss\n_counter:\n"));
+    assert_eq!(
+        header(&file.sections[3]),
+        "\t.section __DATA,__mod_init_func,mod_init_funcs"
+    );
+    assert_eq!(
+        header(&file.sections[4]),
+        "\t.section __DWARF,__debug_str,regular,debug"
+    );
+    let custom = Section::text(
+        "__TEXT,__startup",
+        Segment::label(new_label("init")) + ret(),
+    );
+    assert_eq!(
+        header(&custom),
+        "\t.section __TEXT,__startup,regular,pure_instructions"
+    );
+    let zeros = Data::label(new_label("zeros"))
+        .name("__DATA,__zeros".to_string())
+        .with_flags(SectionFlags::writable())
+        .with_type(SectionType::Nobits)
+        .into();
+    assert_eq!(header(&zeros), "\t.zerofill __DATA,__zeros");
+    let mapped = [1, 2, 3, 4]
+        .iter()
+        .map(|&i| macho(&file.sections[i]).unwrap());
+    let mapped: String = mapped
+        .chain([macho(&custom).unwrap(), macho(&zeros).unwrap()])
+        .collect();
+    assemble_macho("sections", &mapped);
+    for unmapped in [&file.sections[0], &file.sections[6]] {
+        assert_eq!(
+            macho(unmapped).unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+    }
+    assert_eq!(
+        SectionType::macho_name(".rodata").as_deref(),
+        Some("__TEXT,__const")
+    );
+    assert_eq!(SectionType::macho_name(".debug_str_offsets"), None);
+
+    assert_eq!(run_object(&file, "object_sections"), b"42\n");
+
+    file.print_in(file_name).unwrap();
+    Command::new("gcc")
+        .args(["-o", exec_name, file_name])
+        .output()
+        .expect("failed linking");
+    std::fs::remove_file(file_name).unwrap();
+
+    let output = Command::new(exec_name).output().expect("failed running");
+    std::fs::remove_file(exec_name).unwrap();
+    assert_eq!(&output.stdout, b"42\n");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn executable() {
+    use file::Section;
+
+    let exec_name = "./static_executable.out";
+
+    // write(1, message, 6) then exit(counter + 41) without libc
+    let text_ss = Segment::label(new_label("_start"))
+        + incq(reg::Operand::LabAbsAddr(new_label("counter")))
+        + movq(immq(1), reg!(RAX))
+        + movq(immq(1), reg!(RDI))
+        + movq(
+            reg::Operand::LabRelAddr(new_label("message_ptr")),
+            reg!(RSI),
+        )
+        + movq(immq(6), reg!(RDX))
+        + syscall()
+        + movq(immq(60), reg!(RAX))
+        + movq(reg::Operand::LabRelAddr(new_label("counter")), reg!(RDI))
+        + addq(immq(41), reg!(RDI))
+        + syscall();
+
+    let file = file::File {
+        globl: Some(new_label("_start")),
+        text_ss,
+        data_ss: Data::label(new_label("message_ptr")) + data::daddress(new_label("message")),
+        sections: vec![
+            Section::rodata(
+                Data::label(new_label("message")) + data::dascii("Hello\\n".to_string()),
+            ),
+            Section::bss(Data::label(new_label("counter")) + data::space(8)),
+        ],
+        ..Default::default()
+    };
+
+    file.print_executable_in(exec_name).unwrap();
+    let output = Command::new(exec_name).output().expect("failed running");
+    std::fs::remove_file(exec_name).unwrap();
+    assert_eq!(&output.stdout, b"Hello\n");
+    assert_eq!(output.status.code(), Some(42));
+
+    let file = file::File {
+        globl: Some(new_label("_start")),
+        text_ss: call(new_label("puts")),
+        ..Default::default()
+    };
+    assert!(file.print_executable_in(exec_name).is_err());
+    let _ = std::fs::remove_file(exec_name);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn symbols() {
+    use symbol::Symbol;
+
+    let file_name = "asm_file_symbols.s";
+    let object_name = "asm_file_symbols.o";
+
+    let build = |g: Symbol| file::File {
+        text_ss: Segment::label(new_label("f"))
+            + movq(lab!(new_label("counter")), reg!(RAX))
+            + ret()
+            + Segment::label(new_label(".Lf_end"))
+            + Segment::label(new_label("g"))
+            + ret()
+            + Segment::label(new_label(".Lg_end")),
+        data_ss: Data::label(new_label("counter")) + data::dquad(1),
+        symbols: vec![
+            Symbol::global(new_label("f"))
+                .hidden()
+                .function()
+                .with_end(new_label(".Lf_end")),
+            g.function().with_end(new_label(".Lg_end")),
+            Symbol::global(new_label("counter")).object().with_size(8),
+        ],
+        ..Default::default()
+    };
+    let file = build(Symbol::weak(new_label("g")));
+
+    let text = file.to_string();
+    assert!(text.starts_with(
+        "\t.text\n\t.globl f\n\t.hidden f\n\t.type f, @function\n\t.weak g\n\t.type g, @function\n"
+    ));
+    assert!(text.ends_with("\t.size f, .Lf_end-f\n\t.size g, .Lg_end-g\n\t.size counter, 8\n"));
+
+    let mut buffer = Vec::new();
+    traits::Writable::write_with(
+        &file,
+        &mut writer::Writer::new(&mut buffer).with_target(target::Target::macos()),
+    )
+    .unwrap();
+    let macho = String::from_utf8(buffer).unwrap();
+    assert!(macho.starts_with(
+        "\t.text\n\t.globl _f\n\t.private_extern _f\n\t.globl _g\n\t.weak_definition _g\n\t.globl _counter\n"
+    ));
+    assert!(!macho.contains(".size"));
+
+    let mut buffer = Vec::new();
+    traits::Writable::write_with(
+        &file.symbols[0],
+        &mut writer::Writer::new(&mut buffer).with_syntax(writer::Syntax::Nasm),
+    )
+    .unwrap();
+    assert_eq!(buffer, b"\tglobal f:function hidden (..@Lf_end-f)\n");
+
+    // NASM has no weak symbols
+    let mut buffer = Vec::new();
+    traits::Writable::write_with(
+        &build(Symbol::global(new_label("g"))),
+        &mut writer::Writer::new(&mut buffer).with_syntax(writer::Syntax::Nasm),
+    )
+    .unwrap();
+    assemble_nasm("symbols", &String::from_utf8(buffer).unwrap());
+
+    std::fs::write(file_name, file.to_string()).unwrap();
+    let output = Command::new("as")
+        .args(["-o", object_name, file_name])
+        .output()
+        .expect("failed assembling");
+    std::fs::remove_file(file_name).unwrap();
+    assert!(output.status.success());
+    let gas_symbols = read_symbols(object_name);
+
+    file.print_object_in(object_name).unwrap();
+    for symbols in [gas_symbols, read_symbols(object_name)] {
+        let find = |name: &str| {
+            symbols
+                .lines()
+                .find(|line| line.ends_with(&format!(" {}", name)))
+                .unwrap()
+                .split_whitespace()
+                .skip(2)
+                .take(4)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find("f"), ["8", "FUNC", "GLOBAL", "HIDDEN"]);
+        assert_eq!(find("g"), ["1", "FUNC", "WEAK", "DEFAULT"]);
+        assert_eq!(find("counter"), ["8", "OBJECT", "GLOBAL", "DEFAULT"]);
+        assert!(!symbols.contains(".Lf_end"));
+    }
+}
+
+/// Symbol table of an object file (and remove the file)
+#[cfg(target_os = "linux")]
+fn read_symbols(object_name: &str) -> String {
+    let output = Command::new("readelf")
+        .args(["-sW", object_name])
+        .output()
+        .expect("failed reading symbols");
+    std::fs::remove_file(object_name).unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Machine code of the instructions of a segment
+fn encode_text(text: &Text) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for el in &text.data {
+        if let SegmentEL::Data(instr) = &el.el {
+            bytes.append(&mut instr.encode().unwrap().bytes);
+        }
+    }
+    bytes
+}
+
+fn encode_fails(text: Text) -> bool {
+    text.data.iter().any(|el| match &el.el {
+        SegmentEL::Data(instr) => instr.encode().is_err(),
+        _ => false,
+    })
+}
+
+/// One instruction of each encoding
+fn encoded_instructions() -> Text {
+    use reg::implicit::{Al, Ax, DxAx, Eax, EdxEax, Rax, Rcx, Rdi, RdxRax, Rsi};
+
+    let ext = || new_label("ext");
+    movb(immb(5), reg!(AL))
+        + movb(immb(-5), addr!(3, RAX))
+        + movb(reg!(R8B), reg!(AL))
+        + movw(immw(5), reg!(AX))
+        + movw(reg!(R8W), addr!(2, RSI))
+        + movl(imml(-1), reg!(R9D))
+        + movl(addr!(-4, RBP), reg!(EAX))
+        + movl(imml(1), reg::Operand::LabRelAddr(ext()))
+        + movq(immq(-1), reg!(RAX))
+        + movq(immq(0x1_2345_6789), reg!(R10))
+        + movq(immq(7), addr!(1000, RBX))
+        + movq(reg!(R8), reg!(R9))
+        + movq(reg::Operand::LabRelAddr(ext()), reg!(RAX))
+        + movq(reg::Operand::LabAbsAddr(ext()), reg!(RCX))
+        + movq(ilab!(ext()), reg!(RCX))
+        + movl(reg::Operand::LabVal(ext()), reg!(ECX))
+        + movabsq(immq(5), reg!(R11))
+        + movabsq(ilab!(ext()), reg!(RDX))
+        + movabsq(reg::Operand::LabAbsAddr(ext()), reg!(RAX))
+        + movabsq(reg!(RAX), reg::Operand::LabAbsAddr(ext()))
+        + addb(immb(5), reg!(AL))
+        + addq(immq(1), reg!(RAX))
+        + addq(reg::Operand::LabVal(ext()), reg!(RAX))
+        + andq(immq(255), reg!(RAX))
+        + xorw(immw(1000), reg!(AX))
+        + subw(immw(1), reg!(BX))
+        + cmpb(immb(5), addr!(1, RDI))
+        + orl(imml(100_000), reg!(EDX))
+        + addl(reg!(EAX), reg!(EBX))
+        + subq(addr!(8, RAX), reg!(RBX))
+        + addq(reg!(RBX), addr!(8, RSP))
+        + subq(reg!(R11), addr!(1000, RBP))
+        + xorq(reg!(RAX), reg!(RAX))
+        + cmpq(reg::Operand::LabRelAddr(ext()), reg!(R12))
+        + shlb(immb(3), reg!(AL))
+        + shlq(immq(1), reg!(RAX))
+        + shrw_reg(reg!(BX))
+        + shrq_reg(addr!(8, R13))
+        + rolb(immb(1), reg!(AL))
+        + rorq(immq(13), reg!(RDX))
+        + rcll(imml(1), addr!(4, RSI))
+        + rcrw(immw(3), reg!(CX))
+        + sarq_reg(reg!(RAX))
+        + roll_reg(reg!(R9D))
+        + rorb_reg(addr!(RDI))
+        + rclq_reg(reg!(RBX))
+        + rcrl_reg(reg!(EAX))
+        + shldq(immq(3), RAX, reg!(RBX))
+        + shldl_reg(ECX, addr!(RDI))
+        + shrdw(immw(4), DX, reg!(AX))
+        + shrdq_reg(R8, reg!(R9))
+        + sarl(imml(1), addr!(4, RAX))
+        + sarq(immq(63), reg!(RDX))
+        + testb(immb(1), reg!(AL))
+        + testl(imml(256), reg!(EBX))
+        + testq(reg!(RAX), reg!(RAX))
+        + testw(reg!(CX), addr!(2, RBX))
+        + testq(addr!(8, RBX), reg!(R14))
+        + leaw(addr!(4, RAX), BX)
+        + leal(addr!(4, RAX), EBX)
+        + leaq(reg::Operand::LabRelAddr(ext()), RDI)
+        + imulw(reg!(CX), reg!(DX))
+        + imull(imml(5), reg!(EAX))
+        + imulq(immq(1000), reg!(R9))
+        + imulq(addr!(8, RBP), reg!(RAX))
+        + imulq_imm(immq(3), reg!(RAX), RBX)
+        + imull_imm(imml(1000), addr!(RDI), R8D)
+        + imulw_imm(immw(-2), reg!(CX), DX)
+        + mulq(reg!(RCX), Rax, RdxRax)
+        + mulb(addr!(RDI), Al, Ax)
+        + mulw(reg!(R9W), Ax, DxAx)
+        + imulq_wide(reg!(R9), Rax, RdxRax)
+        + imull_wide(addr!(8, RSP), Eax, EdxEax)
+        + imulb_wide(reg!(AH), Al, Ax)
+        + movsbw(addr!(RDI), AX)
+        + movsbl(reg!(AL), EAX)
+        + movsbq(reg!(R9B), R10)
+        + movswl(reg!(AX), ECX)
+        + movswq(addr!(2, RAX), RDX)
+        + movslq(reg!(EAX), RDX)
+        + movzbw(reg!(AL), AX)
+        + movzbl(addr!(-1, RBP), EAX)
+        + movzbq(reg!(CL), R15)
+        + movzwl(reg!(CX), EAX)
+        + movzwq(reg!(CX), RAX)
+        + incb(reg!(AL))
+        + decw(addr!(RBX))
+        + incl(reg!(R8D))
+        + decq(reg::Operand::LabRelAddr(ext()))
+        + negl(reg!(EAX))
+        + notq(reg!(RAX))
+        + notb(addr!(1, RAX))
+        + idivl(reg!(ECX))
+        + divq(addr!(8, RSP))
+        + pushq(immq(1))
+        + pushq(immq(1000))
+        + pushq(ilab!(ext()))
+        + pushq(reg!(R12))
+        + pushq(addr!(8, RBP))
+        + popq(R15)
+        + popq(RAX)
+        + movq(addr!(RSP), reg!(RAX))
+        + movq(reg!(RAX), addr!(R12))
+        + movl(addr!(R13), reg!(EAX))
+        + movl(addr!(RBP), reg!(ECX))
+        + movq(addr!(8, RBP, RCX, 8), reg!(RDX))
+        + movq(addr!(0, RAX, R9), reg!(RDX))
+        + leaq(addr!(-16, R12, R15, 2), R8)
+        + movl(addr!(0, , RAX, 4), reg!(EBX))
+        + leaq(addr!(100, , R10, 8), RAX)
+        + movb(reg!(SIL), reg!(AL))
+        + movb(reg!(AH), addr!(RBX))
+        + movb(immb(1), reg!(DIL))
+        + addb(reg!(AH), reg!(BL))
+        + cmovw(instr::Cond::E, reg!(AX), reg!(BX))
+        + cmovq(instr::Cond::GE, addr!(8, RBP), reg!(RAX))
+        + set(instr::Cond::E, reg!(AL))
+        + set(instr::Cond::B, addr!(1, RDI))
+        + call(ext())
+        + call_star(reg!(RAX))
+        + call_star(addr!(8, RAX))
+        + jmp(ext())
+        + jmp_star(reg!(R11))
+        + jcc(instr::Cond::LE, ext())
+        + jz(ext())
+        + jo(ext())
+        + jnp(ext())
+        + jrcxz(ext())
+        + loop_(ext())
+        + loope(ext())
+        + loopne(ext())
+        + seto(reg!(CL))
+        + setp(addr!(RDI))
+        + cmovnol(reg!(EAX), reg!(EBX))
+        + cmovpq(addr!(RDI), reg!(RAX))
+        + btq(reg!(RAX), reg!(RBX))
+        + btw(reg!(R8W), addr!(2, RDI))
+        + btsl(imml(3), addr!(RDI))
+        + btrq(immq(63), reg!(R9))
+        + btcl(reg!(ECX), reg!(EDX))
+        + bsfq(addr!(RDI), RAX)
+        + bsrl(reg!(EAX), EBX)
+        + popcntw(reg!(AX), BX)
+        + popcntq(reg!(R10), RAX)
+        + lzcntl(reg!(EAX), EBX)
+        + tzcntq(addr!(8, RSP), R11)
+        + andnq(reg!(RCX), RBX, RAX)
+        + andnl(addr!(RDI), R9D, EAX)
+        + blsiq(reg!(RAX), RBX)
+        + blsrl(addr!(RDI), EBX)
+        + bzhiq(RBX, reg!(RCX), RAX)
+        + bzhil(EBX, addr!(RDI), R8D)
+        + shlxq(RCX, reg!(RAX), RBX)
+        + shrxl(ECX, reg!(EAX), EBX)
+        + sarxq(R12, addr!(RDI), RBX)
+        + pdepq(reg!(RCX), RBX, RAX)
+        + pextl(reg!(ECX), EBX, EAX)
+        + rorxq(immq(3), reg!(R13), RBX)
+        + rorxl(imml(31), addr!(RDI), EBX)
+        + mulxq(reg!(RCX), RBX, RAX)
+        + mulxl(addr!(RDI), EBX, R9D)
+        + movsb(Rsi, Rdi)
+        + rep_movsw(Rcx, Rsi, Rdi)
+        + rep_movsq(Rcx, Rsi, Rdi)
+        + stosl(Eax, Rdi)
+        + rep_stosb(Rcx, Al, Rdi)
+        + lodsq(Rsi, Rax)
+        + rep_lodsb(Rcx, Rsi, Al)
+        + cmpsw(Rdi, Rsi)
+        + repe_cmpsl(Rcx, Rdi, Rsi)
+        + repne_cmpsb(Rcx, Rdi, Rsi)
+        + repe_scasw(Rcx, Rdi, Ax)
+        + repne_scasb(Rcx, Rdi, Al)
+        + scasq(Rdi, Rax)
+        + xchgq(reg!(RAX), reg!(RBX))
+        + xchgl(reg!(EAX), reg!(R8D))
+        + xchgw(reg!(AX), reg!(BX))
+        + xchgb(reg!(AL), reg!(BL))
+        + xchgl(reg!(ECX), reg!(EDX))
+        + xchgq(reg!(RCX), addr!(RDI))
+        + cmpxchgq(RCX, addr!(RDI))
+        + cmpxchgb(BL, reg!(CL))
+        + xaddl(EAX, addr!(8, RSI))
+        + cmpxchg8b(mem!(RDI))
+        + cmpxchg16b(mem!(8, RSP))
+        + lock_addw(reg::RegImm::Reg(AX), mem!(RDI))
+        + lock_addq(reg::RegImm::Imm(1), mem!(8, RDI))
+        + lock_addq(reg::RegImm::Reg(RAX), reg::Memory::LabRelAddr(ext()))
+        + lock_subb(reg::RegImm::Imm(1), mem!(RDI))
+        + lock_andl(reg::RegImm::Imm(0xff), mem!(0, RDI, RAX, 4))
+        + lock_orq(reg::RegImm::Reg(R8), mem!(RSI))
+        + lock_xorb(reg::RegImm::Reg(AL), mem!(-1, RBP))
+        + lock_incl(mem!(RDI))
+        + lock_decq(mem!(16, R12))
+        + lock_adcl(reg::RegImm::Imm(1), mem!(RDI))
+        + lock_sbbq(reg::RegImm::Reg(RAX), mem!(8, RSI))
+        + lock_negw(mem!(RDI))
+        + lock_notb(mem!(RDI))
+        + lock_btsq(reg::RegImm::Imm(3), mem!(RDI))
+        + lock_btrl(reg::RegImm::Reg(EAX), mem!(RDI))
+        + lock_btcw(reg::RegImm::Reg(CX), mem!(RBX))
+        + lock_cmpxchgq(RCX, mem!(RDI))
+        + lock_cmpxchgw(R9W, mem!(RDI))
+        + lock_xaddq(RAX, mem!(RDI))
+        + lock_cmpxchg8b(mem!(RSI))
+        + lock_cmpxchg16b(mem!(RSI))
+        + fs(movq(addr!(8, RAX), reg!(RDX)))
+        + gs(movq(reg!(RAX), addr!(8, R12)))
+        + fs(lock_addw(reg::RegImm::Reg(AX), mem!(RBX)))
+        + gs(lock_addq(
+            reg::RegImm::Imm(1),
+            reg::Memory::LabRelAddr(ext()),
+        ))
+        + fs(movsb(Rsi, Rdi))
+        + gs(rep_movsq(Rcx, Rsi, Rdi))
+        + fs(lodsl(Rsi, Eax))
+        + gs(repe_cmpsw(Rcx, Rdi, Rsi))
+        + mfence()
+        + lfence()
+        + sfence()
+        + movss(addr!(4, RAX), reg!(XMM1))
+        + movsd(reg!(XMM8), addr!(-8, RBP))
+        + movsd(reg!(XMM1), reg!(XMM0))
+        + movaps(addr!(RSP), reg!(XMM15))
+        + movapd(reg!(XMM2), addr!(16, RSP))
+        + movups(reg!(XMM3), reg!(XMM4))
+        + movupd(reg::Operand::LabRelAddr(ext()), reg!(XMM0))
+        + addss(reg!(XMM1), XMM2)
+        + addsd(addr!(8, RDI), XMM9)
+        + subsd(reg!(XMM10), XMM0)
+        + mulsd(reg!(XMM0), XMM1)
+        + divss(reg::Operand::LabRelAddr(ext()), XMM3)
+        + divsd(reg!(XMM2), XMM3)
+        + sqrtsd(reg!(XMM4), XMM4)
+        + ucomisd(reg!(XMM1), XMM0)
+        + comiss(addr!(RAX), XMM7)
+        + cvtsi2sdl(reg!(EAX), XMM0)
+        + cvtsi2ssq(addr!(8, RSP), XMM11)
+        + cvtsi2sdq(reg!(R9), XMM1)
+        + cvttsd2sil(reg!(XMM0), EAX)
+        + cvttss2siq(addr!(RBX), R12)
+        + cvtss2sd(reg!(XMM1), XMM2)
+        + cvtsd2ss(addr!(8, RBP), XMM12)
+        + andpd(reg!(XMM1), XMM0)
+        + xorps(reg!(XMM5), XMM5)
+        + xorpd(addr!(RSI), XMM14)
+        + movd_to_xmm(EAX, XMM0)
+        + movd_from_xmm(XMM9, R8D)
+        + movq_to_xmm(RAX, XMM13)
+        + movq_from_xmm(XMM0, RDX)
+        + vmovaps(addr!(RDI), reg!(YMM0))
+        + vmovupd(reg!(YMM9), addr!(32, RSI, RCX, 8))
+        + vmovups(reg!(YMM8), reg!(YMM1))
+        + vmovdqa(reg!(YMM1), reg!(YMM10))
+        + vmovdqu(reg::Operand::LabRelAddr(ext()), reg!(YMM2))
+        + vaddps(reg!(YMM2), YMM1, YMM0)
+        + vaddpd(addr!(RAX), YMM12, YMM13)
+        + vsubps(reg!(YMM8), YMM1, YMM0)
+        + vmulpd(reg!(YMM3), YMM4, YMM5)
+        + vdivps(addr!(-32, RBP), YMM6, YMM7)
+        + vxorpd(reg!(YMM15), YMM15, YMM15)
+        + vfmadd132ps(reg!(YMM1), YMM2, YMM3)
+        + vfmadd213pd(addr!(8, RDI), YMM2, YMM3)
+        + vfmadd231pd(reg!(YMM2), YMM1, YMM0)
+        + vpaddb(reg!(YMM1), YMM2, YMM3)
+        + vpaddd(addr!(0, RDI, RAX, 4), YMM0, YMM0)
+        + vpaddq(reg!(YMM11), YMM2, YMM3)
+        + vpsubw(reg!(YMM1), YMM2, YMM3)
+        + vpcmpeqb(addr!(RSI), YMM0, YMM1)
+        + vpcmpeqq(reg!(YMM1), YMM2, YMM3)
+        + vpand(reg!(YMM1), YMM2, YMM3)
+        + vpor(reg!(YMM1), YMM9, YMM3)
+        + vpxor(reg!(YMM0), YMM0, YMM0)
+        + vpmovmskb(YMM1, EAX)
+        + vpmovmskb(YMM9, R10D)
+        + vbroadcastss(addr!(4, RSP), YMM0)
+        + vbroadcastss(reg!(XMM1), YMM14)
+        + vbroadcastsd(reg!(XMM9), YMM2)
+        + vzeroupper()
+        + cltd()
+        + cqto()
+        + leave()
+        + syscall()
+        + nop()
+        + ret()
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn encode() {
+    let text = encoded_instructions();
+    assert_eq!(
+        encode_text(&text),
+        assemble_text("encode", &text.to_string())
+    );
+    assert!(encode_fails(movb(reg!(AH), reg!(SIL))));
+    assert!(encode_fails(movb(reg!(AH), reg!(R8B))));
+    assert!(encode_fails(movq(addr!(0, RAX, RSP), reg!(RAX))));
+    assert!(encode_fails(movq(addr!(0, RAX, RBX, 3), reg!(RAX))));
+    assert!(encode_fails(movabsq(
+        reg::Operand::LabAbsAddr(new_label("ext")),
+        reg!(RCX)
+    )));
+    assert!(encode_fails(movabsq(immq(1), addr!(RAX))));
+    assert!(encode_fails(vmovaps(addr!(RDI), addr!(RSI))));
+    assert!(encode_fails(btq(addr!(RDI), reg!(RAX))));
+    assert!(encode_fails(rorxq(reg!(RCX), reg!(RAX), RBX)));
+    assert!(encode_fails(Segment::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::Add,
+        reg1: Some(reg!(RAX)),
+        reg2: Some(reg!(RBX)),
+        reg3: None,
+        lock_rep: Some(instr::Prefix::Rep),
+        segment: None,
+    }))));
+    assert!(encode_fails(Segment::new(Box::new(instr::Instruction {
+        instr: instr::InstrName::Add,
+        reg1: Some(reg!(RAX)),
+        reg2: Some(reg!(RBX)),
+        reg3: None,
+        lock_rep: Some(instr::Prefix::Lock),
+        segment: None,
+    }))));
+    assert!(encode_fails(cmpxchgq(RCX, immq(1))));
+    // segment overrides apply to a memory operand or to the (%rsi) source of string instructions
+    assert!(encode_fails(Segment::new(Box::new(instr::Instruction::<
+        reg::RegQ,
+        reg::RegQ,
+    > {
+        instr: instr::InstrName::Move,
+        reg1: Some(reg!(RAX)),
+        reg2: Some(reg!(RBX)),
+        reg3: None,
+        lock_rep: None,
+        segment: Some(instr::SegmentOverride::Fs),
+    }))));
+    assert!(encode_fails(Segment::new(Box::new(instr::Instruction::<
+        reg::RegB,
+        reg::RegInv,
+    > {
+        instr: instr::InstrName::StoreString,
+        reg1: None,
+        reg2: None,
+        reg3: None,
+        lock_rep: None,
+        segment: Some(instr::SegmentOverride::Fs),
+    }))));
+}
+
+#[test]
+fn conditions() {
+    use instr::{Comparison, Cond};
+
+    assert_eq!(Cond::signed(Comparison::Lt), Cond::L);
+    assert_eq!(Cond::unsigned(Comparison::Ge), Cond::AE);
+    assert_eq!(Cond::G.negate(), Cond::LE);
+    assert_eq!(Cond::NAE.negate(), Cond::AE);
+    assert_eq!(Cond::PE.negate().negate(), Cond::PE);
+    assert_eq!(Cond::B.swap_operands(), Some(Cond::A));
+    assert_eq!(Cond::NGE.swap_operands(), Some(Cond::NLE));
+    assert_eq!(Cond::O.swap_operands(), None);
+    // aliases have the same encoding
+    assert_eq!(
+        encode_text(&jnae(new_label("l"))),
+        encode_text(&jc(new_label("l")))
+    );
+    assert_eq!(
+        encode_text(&cmovnlq(reg!(RAX), reg!(RBX))),
+        encode_text(&cmovgeq(reg!(RAX), reg!(RBX)))
+    );
+}
+
+#[test]
+fn movabs() {
+    // immediates on more than 32 bits can only be moved to registers
+    let movabs = movq(immq(1 << 40), reg!(RAX));
+    assert!(movabs.to_string().contains("movabsq $1099511627776, %rax"));
+    assert_eq!(encode_text(&movabs), [0x48, 0xb8, 0, 0, 0, 0, 0, 1, 0, 0]);
+    assert!(std::panic::catch_unwind(|| movq(immq(1 << 40), addr!(RAX))).is_err());
+}
+
+#[test]
+fn encoding_size() {
+    use instr::encode::Encoding;
+
+    let encode = |text: Text, encoding| match &text.data[0].el {
+        SegmentEL::Data(instr) => instr.encode_with(encoding).unwrap().bytes,
+        _ => panic!("expected an instruction"),
+    };
+    // 4-bytes moves clear the upper half of 8-bytes registers
+    assert_eq!(
+        encode(movq(immq(5), reg!(R9)), Encoding::Shortest),
+        [0x41, 0xb9, 5, 0, 0, 0]
+    );
+    assert_eq!(
+        encode(movq(immq(0xffff_ffff), reg!(RAX)), Encoding::Shortest),
+        [0xb8, 0xff, 0xff, 0xff, 0xff]
+    );
+    assert_eq!(
+        encode(movq(immq(-1), reg!(RAX)), Encoding::Shortest),
+        [0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff]
+    );
+    assert_eq!(
+        encode(addq(immq(8), reg!(RSP)), Encoding::Shortest),
+        [0x48, 0x83, 0xc4, 8]
+    );
+
+    // the size of fixed encodings does not depend on the values
+    assert_eq!(
+        encode(movq(immq(5), reg!(R9)), Encoding::Fixed),
+        [0x49, 0xb9, 5, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        encode(addq(immq(8), reg!(RSP)), Encoding::Fixed),
+        [0x48, 0x81, 0xc4, 8, 0, 0, 0]
+    );
+    assert_eq!(
+        encode(addl(imml(8), reg!(EAX)), Encoding::Fixed),
+        [0x05, 8, 0, 0, 0]
+    );
+    assert_eq!(
+        encode(movq(addr!(RBP), reg!(RAX)), Encoding::Fixed),
+        [0x48, 0x8b, 0x85, 0, 0, 0, 0]
+    );
+    assert_eq!(encode(pushq(immq(1)), Encoding::Fixed), [0x68, 1, 0, 0, 0]);
+    assert_eq!(
+        encode(shlq(immq(1), reg!(RAX)), Encoding::Fixed),
+        [0x48, 0xc1, 0xe0, 1]
+    );
+    assert_eq!(
+        encode(imulq(immq(3), reg!(RCX)), Encoding::Fixed),
+        [0x48, 0x69, 0xc9, 3, 0, 0, 0]
+    );
+}
+
+#[test]
+fn decode() {
+    use instr::decode::{decode, decode_instr, disassemble};
+
+    let first = |text: &Text| match &text.data[0].el {
+        SegmentEL::Data(instr) => instr.encode().unwrap(),
+        _ => panic!("expected an instruction"),
+    };
+    for el in &encoded_instructions().data {
+        if let SegmentEL::Data(instr) = &el.el {
+            let code = instr.encode().unwrap();
+            let (decoded, len) = decode_instr(&code, 0).unwrap();
+            assert_eq!(len, code.bytes.len());
+            // the operands of test can be in any order
+            if cfg!(not(feature = "gen_binary"))
+                && !matches!(decoded.instr(), instr::InstrName::Test)
+            {
+                let mut original = Vec::new();
+                traits::Writable::write_in(instr, &mut original).unwrap();
+                // condition codes have several names
+                let original = String::from_utf8(original).unwrap().replace("jz ", "je ");
+                assert_eq!(decoded.to_string(), original);
+            }
+            assert_eq!(first(&decoded.into_text()), code);
+        }
+    }
+
+    // labels of jumps are named after their target
+    let text = Segment::label(new_label("start"))
+        + movl(imml(10), reg!(ECX))
+        + Segment::label(new_label("loop"))
+        + addq(reg::Operand::LabRelAddr(new_label("start")), reg!(RAX))
+        + decl(reg!(ECX))
+        + jnz(new_label("loop"))
+        + Segment::directive(directives::Directive::P2Align(5, None, None))
+        + call(new_label("start"))
+        + ret();
+    let code = instr::assembler::assemble(&text).unwrap().code;
+    let decoded = decode(&code).unwrap();
+    assert_eq!(decoded[1].0, 5);
+    assert_eq!(decoded[3].1.to_string(), "jne .L5");
+    assert!(matches!(decoded[4].1.instr(), instr::InstrName::Nop));
+    let disassembled = disassemble(&code.bytes).unwrap();
+    if cfg!(not(feature = "gen_binary")) {
+        assert!(disassembled
+            .to_string()
+            .contains(".L0:\n\tmovl $10, %ecx\n.L5:\n\taddq .L0(%rip), %rax\n"));
+    }
+    // padding is decoded as nops of any length
+    let loop_code = &code.bytes[..16];
+    assert_eq!(
+        instr::assembler::assemble(&disassemble(loop_code).unwrap())
+            .unwrap()
+            .code
+            .bytes,
+        loop_code
+    );
+
+    // undecodable bytes are errors
+    for bytes in [
+        &[0x0f, 0x0b][..],
+        &[0x48],
+        &[0xf0, 0x90],
+        &[0x8d, 0xc0],
+        &[0xc7, 0x00, 0x01],
+        &[0x90, 0xd1, 0xf0],
+    ] {
+        assert!(disassemble(bytes).is_err());
+    }
+    assert_eq!(disassemble(&[0x90, 0xd1, 0xf0]).err().unwrap().offset(), 1);
+}
+
+/// Random instructions encoded by us and by GNU as
+#[cfg(target_os = "linux")]
+mod differential {
+    use super::*;
+
+    /// Pseudo-random generator (xorshift64*) of the differential test
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<T: Copy>(&mut self, values: &[T]) -> T {
+            values[self.below(values.len())]
+        }
+
+        /// Signed value of `bits` bits, often close to the limits of shorter encodings
+        fn imm(&mut self, bits: u32) -> i64 {
+            let value = match self.below(4) {
+                0 => self.pick(&[0, 1, -1, 127, -128, 128, -129]),
+                1 => self.below(256) as i64 - 128,
+                _ => self.next() as i64,
+            };
+            // sign extend the lowest bits
+            value << (64 - bits) >> (64 - bits)
+        }
+
+        /// Register number (%rsp is not valid as an index)
+        fn reg(&mut self, index: bool) -> usize {
+            loop {
+                let num = self.below(16);
+                if !index || num != 4 {
+                    return num;
+                }
+            }
+        }
+
+        fn mem(&mut self) -> Arg {
+            let base = if self.below(5) == 0 {
+                None
+            } else {
+                Some(self.reg(false))
+            };
+            let index = if base.is_none() || self.below(2) == 0 {
+                Some((self.reg(true), self.pick(&[1, 2, 4, 8])))
+            } else {
+                None
+            };
+            let disp = match self.below(3) {
+                0 => 0,
+                1 => self.imm(8),
+                _ => self.imm(32),
+            };
+            Arg::Mem(base, index, disp)
+        }
+
+        fn rm(&mut self) -> Arg {
+            if self.below(2) == 0 {
+                Arg::Reg(self.reg(false))
+            } else {
+                self.mem()
+            }
+        }
+
+        /// Operands of add, sub, and, or, xor, cmp, test and mov
+        fn binary(&mut self, size: usize) -> Vec<Arg> {
+            match self.below(3) {
+                0 => vec![Arg::Imm(self.imm([8, 16, 32, 32][size])), self.rm()],
+                1 => vec![Arg::Reg(self.reg(false)), self.rm()],
+                _ => vec![self.mem(), Arg::Reg(self.reg(false))],
+            }
+        }
+
+        fn case(&mut self) -> Case {
+            let op = self.below(57);
+            let mut size = self.below(4);
+            let mut extra = 0;
+            let args = match op {
+                0..=5 | 7 => self.binary(size),
+                6 => {
+                    let mut args = self.binary(size);
+                    if size == 3 && matches!(args[1], Arg::Reg(_)) && self.below(2) == 0 {
+                        args[0] = Arg::Imm(self.imm(64));
+                    }
+                    args
+                }
+                8..=10 | 29..=32 => vec![Arg::Imm(self.below(256) as i64), self.rm()],
+                11 | 12 | 14..=17 | 22 | 27 | 28 | 33..=36 => vec![self.rm()],
+                37 => {
+                    size = 1 + self.below(3);
+                    vec![
+                        Arg::Imm(self.imm([8, 16, 32, 32][size])),
+                        self.rm(),
+                        Arg::Reg(self.reg(false)),
+                    ]
+                }
+                13 => {
+                    size = 1 + self.below(3);
+                    if self.below(2) == 0 {
+                        vec![
+                            Arg::Imm(self.imm([8, 16, 32, 32][size])),
+                            Arg::Reg(self.reg(false)),
+                        ]
+                    } else {
+                        vec![self.rm(), Arg::Reg(self.reg(false))]
+                    }
+                }
+                18 | 19 => {
+                    size = 2 + self.below(2);
+                    vec![self.rm()]
+                }
+                20 => {
+                    size = 1 + self.below(3);
+                    vec![self.mem(), Arg::Reg(self.reg(false))]
+                }
+                21 => {
+                    size = 1 + self.below(3);
+                    extra = self.below(CONDS.len());
+                    vec![self.rm(), Arg::Reg(self.reg(false))]
+                }
+                23 => match self.below(3) {
+                    0 => vec![Arg::Imm(self.imm(32))],
+                    _ => vec![self.rm()],
+                },
+                24 => vec![Arg::Reg(self.reg(false))],
+                25 | 26 => {
+                    // (source, destination) sizes of movs and movz
+                    extra = self.below(if op == 25 { 6 } else { 5 });
+                    vec![self.rm(), Arg::Reg(self.reg(false))]
+                }
+                // shld and shrd (extra gives the direction), by an immediate or by %cl
+                38 | 39 => {
+                    size = 1 + self.below(3);
+                    extra = self.below(2);
+                    let mut args = vec![Arg::Reg(self.reg(false)), self.rm()];
+                    if op == 38 {
+                        args.insert(0, Arg::Imm(self.below(256) as i64));
+                    }
+                    args
+                }
+                // andn, pdep, pext and mulx or bzhi, shlx, shrx and sarx
+                40 | 41 => {
+                    size = 2 + self.below(2);
+                    extra = self.below(4);
+                    let (rm, reg) = (self.rm(), Arg::Reg(self.reg(false)));
+                    if op == 40 {
+                        vec![rm, reg, Arg::Reg(self.reg(false))]
+                    } else {
+                        vec![reg, rm, Arg::Reg(self.reg(false))]
+                    }
+                }
+                // bsf, bsr, popcnt, lzcnt, tzcnt, blsi and blsr
+                42 => {
+                    extra = self.below(7);
+                    size =
+                        if extra < 5 { 1 } else { 2 } + self.below(if extra < 5 { 3 } else { 2 });
+                    vec![self.rm(), Arg::Reg(self.reg(false))]
+                }
+                // bt, bts, btr and btc
+                43 => {
+                    size = 1 + self.below(3);
+                    extra = self.below(4);
+                    let index = if self.below(2) == 0 {
+                        Arg::Imm(self.below(256) as i64)
+                    } else {
+                        Arg::Reg(self.reg(false))
+                    };
+                    vec![index, self.rm()]
+                }
+                // rorx
+                44 => {
+                    size = 2 + self.below(2);
+                    vec![
+                        Arg::Imm(self.below(256) as i64),
+                        self.rm(),
+                        Arg::Reg(self.reg(false)),
+                    ]
+                }
+                // string instructions (extra 0 to 4), their rep forms (5 to 11) and fences
+                45 => {
+                    extra = self.below(15);
+                    vec![]
+                }
+                // xchg, cmpxchg and xadd
+                46 => {
+                    extra = self.below(3);
+                    vec![Arg::Reg(self.reg(false)), self.rm()]
+                }
+                // locked add, sub, and, or, xor, adc, sbb, cmpxchg, xadd, inc, dec, neg, not,
+                // bts, btr and btc
+                47 => {
+                    extra = self.below(16);
+                    if extra >= 13 {
+                        size = 1 + self.below(3);
+                    }
+                    match extra {
+                        0..=6 if self.below(2) == 0 => {
+                            vec![Arg::Imm(self.imm([8, 16, 32, 32][size])), self.mem()]
+                        }
+                        13..=15 if self.below(2) == 0 => {
+                            vec![Arg::Imm(self.below(256) as i64), self.mem()]
+                        }
+                        9..=12 => vec![self.mem()],
+                        _ => vec![Arg::Reg(self.reg(false)), self.mem()],
+                    }
+                }
+                // cmpxchg8b and cmpxchg16b, with or without lock
+                48 => {
+                    extra = self.below(4);
+                    vec![self.mem()]
+                }
+                // SSE operations and moves (size 0 for single precision, 1 for double)
+                49 | 50 => {
+                    size = self.below(2);
+                    extra = self.below(if op == 49 { 10 } else { 3 });
+                    if op == 50 && self.below(2) == 0 {
+                        vec![Arg::Reg(self.reg(false)), self.mem()]
+                    } else {
+                        vec![self.rm(), Arg::Reg(self.reg(false))]
+                    }
+                }
+                // conversions between integers and floats (extra 0 and 1 to single and
+                // double precision, 2 and 3 from them) and movd or movq with XMM registers
+                51 | 52 => {
+                    size = 2 + self.below(2);
+                    extra = self.below(if op == 51 { 4 } else { 2 });
+                    let src = if op == 51 {
+                        self.rm()
+                    } else {
+                        Arg::Reg(self.reg(false))
+                    };
+                    vec![src, Arg::Reg(self.reg(false))]
+                }
+                // AVX floats (size 0 for ps, 1 for pd) and integers
+                53 | 54 => {
+                    if op == 53 {
+                        size = self.below(2);
+                    }
+                    extra = self.below(if op == 53 { 8 } else { 6 });
+                    vec![
+                        self.rm(),
+                        Arg::Reg(self.reg(false)),
+                        Arg::Reg(self.reg(false)),
+                    ]
+                }
+                // AVX moves
+                55 => {
+                    extra = self.below(6);
+                    if self.below(2) == 0 {
+                        vec![Arg::Reg(self.reg(false)), self.mem()]
+                    } else {
+                        vec![self.rm(), Arg::Reg(self.reg(false))]
+                    }
+                }
+                // vpmovmskb, vbroadcastss and vbroadcastsd
+                _ => {
+                    extra = self.below(3);
+                    let src = if extra == 0 {
+                        Arg::Reg(self.reg(false))
+                    } else {
+                        self.rm()
+                    };
+                    vec![src, Arg::Reg(self.reg(false))]
+                }
+            };
+            if op == 22 {
+                size = 0;
+                extra = self.below(CONDS.len());
+            }
+            if matches!(op, 23 | 24 | 27 | 28) {
+                size = 3;
+            }
+            Case {
+                op,
+                size,
+                extra,
+                args,
+            }
+        }
+    }
+
+    /// Operand of a randomly generated instruction, registers are numbers
+    #[derive(Clone, Debug)]
+    enum Arg {
+        Reg(usize),
+        Mem(Option<usize>, Option<(usize, u8)>, i64),
+        Imm(i64),
+    }
+
+    /// Randomly generated instruction: constructor, size of the operands (0 to 3 for b, w, l and q)
+    #[derive(Clone, Debug)]
+    struct Case {
+        op: usize,
+        size: usize,
+        // condition of cmov and set, sizes of movs and movz, instruction of a family
+        extra: usize,
+        args: Vec<Arg>,
+    }
+
+    const REGS_B: [reg::RegB; 16] = [
+        AL, CL, DL, BL, SPL, BPL, SIL, DIL, R8B, R9B, R10B, R11B, R12B, R13B, R14B, R15B,
+    ];
+    const REGS_W: [reg::RegW; 16] = [
+        AX, CX, DX, BX, SP, BP, SI, DI, R8W, R9W, R10W, R11W, R12W, R13W, R14W, R15W,
+    ];
+    const REGS_L: [reg::RegL; 16] = [
+        EAX, ECX, EDX, EBX, ESP, EBP, ESI, EDI, R8D, R9D, R10D, R11D, R12D, R13D, R14D, R15D,
+    ];
+    const REGS_Q: [reg::RegQ; 16] = [
+        RAX, RCX, RDX, RBX, RSP, RBP, RSI, RDI, R8, R9, R10, R11, R12, R13, R14, R15,
+    ];
+    const REGS_X: [reg::RegX; 16] = [
+        XMM0, XMM1, XMM2, XMM3, XMM4, XMM5, XMM6, XMM7, XMM8, XMM9, XMM10, XMM11, XMM12, XMM13,
+        XMM14, XMM15,
+    ];
+    const REGS_Y: [reg::RegY; 16] = [
+        YMM0, YMM1, YMM2, YMM3, YMM4, YMM5, YMM6, YMM7, YMM8, YMM9, YMM10, YMM11, YMM12, YMM13,
+        YMM14, YMM15,
+    ];
+    const CONDS: [instr::Cond; 16] = [
+        instr::Cond::O,
+        instr::Cond::NO,
+        instr::Cond::P,
+        instr::Cond::NP,
+        instr::Cond::E,
+        instr::Cond::NE,
+        instr::Cond::S,
+        instr::Cond::NS,
+        instr::Cond::G,
+        instr::Cond::GE,
+        instr::Cond::L,
+        instr::Cond::LE,
+        instr::Cond::A,
+        instr::Cond::AE,
+        instr::Cond::B,
+        instr::Cond::BE,
+    ];
+
+    fn arg<R: traits::Reg + Copy>(arg: &Arg, regs: &[R; 16]) -> reg::Operand<R> {
+        match arg {
+            Arg::Reg(num) => reg::Operand::Reg(regs[*num]),
+            Arg::Mem(Some(base), None, disp) => addr!(*disp, REGS_Q[*base]),
+            Arg::Mem(Some(base), Some((index, scale)), disp) => {
+                addr!(*disp, REGS_Q[*base], REGS_Q[*index], *scale)
+            }
+            Arg::Mem(None, Some((index, scale)), disp) => addr!(*disp, , REGS_Q[*index], *scale),
+            Arg::Mem(None, None, _) => panic!("memory operand without registers"),
+            Arg::Imm(imm) => reg::Operand::Imm(*imm),
+        }
+    }
+
+    fn reg_of<R: Copy>(arg: &Arg, regs: &[R; 16]) -> R {
+        match arg {
+            Arg::Reg(num) => regs[*num],
+            _ => panic!("expected a register"),
+        }
+    }
+
+    fn reg_imm<R: traits::Reg + Copy>(arg: &Arg, regs: &[R; 16]) -> reg::RegImm<R> {
+        match arg {
+            Arg::Reg(num) => reg::RegImm::Reg(regs[*num]),
+            Arg::Imm(imm) => reg::RegImm::Imm(*imm),
+            _ => panic!("expected a register or an immediate"),
+        }
+    }
+
+    fn mem_of(arg: &Arg) -> reg::Memory {
+        match arg {
+            Arg::Mem(Some(base), None, disp) => mem!(*disp, REGS_Q[*base]),
+            Arg::Mem(Some(base), Some((index, scale)), disp) => {
+                mem!(*disp, REGS_Q[*base], REGS_Q[*index], *scale)
+            }
+            Arg::Mem(None, Some((index, scale)), disp) => mem!(*disp, , REGS_Q[*index], *scale),
+            _ => panic!("expected a memory operand"),
+        }
+    }
+
+    /// Call `$name` with the arguments of the case, `op`, `reg`, `src` (register or immediate) or `mem`
+    /// operands of the register table
+    macro_rules! call {
+        (@op, $arg:expr, $regs:expr) => {
+            arg($arg, &$regs)
+        };
+        (@reg, $arg:expr, $regs:expr) => {
+            reg_of($arg, &$regs)
+        };
+        (@src, $arg:expr, $regs:expr) => {
+            reg_imm($arg, &$regs)
+        };
+        (@mem, $arg:expr, $regs:expr) => {
+            mem_of($arg)
+        };
+        ($case:expr, $name:ident($($kind:ident $regs:ident),*)) => {{
+            let mut args = $case.args.iter();
+            $name($(call!(@$kind, args.next().unwrap(), $regs)),*)
+        }};
+    }
+
+    /// Call the constructor of the size of the case with operands of kinds `op`, `reg`, `src` or `mem`
+    macro_rules! bwlq {
+        ($case:expr, [$b:ident, $w:ident, $l:ident, $q:ident], $($kind:ident),*) => {
+            match $case.size {
+                0 => call!($case, $b($($kind REGS_B),*)),
+                1 => call!($case, $w($($kind REGS_W),*)),
+                2 => call!($case, $l($($kind REGS_L),*)),
+                _ => call!($case, $q($($kind REGS_Q),*)),
+            }
+        };
+        ($case:expr, [$w:ident, $l:ident, $q:ident], $($kind:ident),*) => {
+            match $case.size {
+                1 => call!($case, $w($($kind REGS_W),*)),
+                2 => call!($case, $l($($kind REGS_L),*)),
+                _ => call!($case, $q($($kind REGS_Q),*)),
+            }
+        };
+        ($case:expr, [$l:ident, $q:ident], $($kind:ident),*) => {
+            match $case.size {
+                2 => call!($case, $l($($kind REGS_L),*)),
+                _ => call!($case, $q($($kind REGS_Q),*)),
+            }
+        };
+    }
+
+    /// Call the string instruction of the size of the case with its implicit operands
+    macro_rules! string {
+        (@acc, $acc:ident) => {
+            $acc
+        };
+        (@$implicit:ident, $acc:ident) => {
+            $implicit
+        };
+        ($case:expr, $b:ident, $w:ident, $l:ident, $q:ident, $($implicit:ident),*) => {{
+            use reg::implicit::*;
+            match $case.size {
+                0 => $b($(string!(@$implicit, Al)),*),
+                1 => $w($(string!(@$implicit, Ax)),*),
+                2 => $l($(string!(@$implicit, Eax)),*),
+                _ => $q($(string!(@$implicit, Rax)),*),
+            }
+        }};
+    }
+
+    /// Call the constructor of the size of the case
+    macro_rules! sized {
+        ($case:expr, $b:ident, $w:ident, $l:ident, $q:ident, 1) => {
+            match $case.size {
+                0 => $b(arg(&$case.args[0], &REGS_B)),
+                1 => $w(arg(&$case.args[0], &REGS_W)),
+                2 => $l(arg(&$case.args[0], &REGS_L)),
+                _ => $q(arg(&$case.args[0], &REGS_Q)),
+            }
+        };
+        ($case:expr, $b:ident, $w:ident, $l:ident, $q:ident, 2) => {
+            match $case.size {
+                0 => $b(arg(&$case.args[0], &REGS_B), arg(&$case.args[1], &REGS_B)),
+                1 => $w(arg(&$case.args[0], &REGS_W), arg(&$case.args[1], &REGS_W)),
+                2 => $l(arg(&$case.args[0], &REGS_L), arg(&$case.args[1], &REGS_L)),
+                _ => $q(arg(&$case.args[0], &REGS_Q), arg(&$case.args[1], &REGS_Q)),
+            }
+        };
+        ($case:expr, $b:ident, $w:ident, $l:ident, $q:ident, widening) => {{
+            use reg::implicit::{Al, Ax, DxAx, Eax, EdxEax, Rax, RdxRax};
+            match $case.size {
+                0 => $b(arg(&$case.args[0], &REGS_B), Al, Ax),
+                1 => $w(arg(&$case.args[0], &REGS_W), Ax, DxAx),
+                2 => $l(arg(&$case.args[0], &REGS_L), Eax, EdxEax),
+                _ => $q(arg(&$case.args[0], &REGS_Q), Rax, RdxRax),
+            }
+        }};
+    }
+
+    type SseOp = fn(reg::Operand<reg::RegX>, reg::RegX) -> Text;
+    type SseMove = fn(reg::Operand<reg::RegX>, reg::Operand<reg::RegX>) -> Text;
+    type AvxOp = fn(reg::Operand<reg::RegY>, reg::RegY, reg::RegY) -> Text;
+    type AvxMove = fn(reg::Operand<reg::RegY>, reg::Operand<reg::RegY>) -> Text;
+
+    const SSE_OPS: [[SseOp; 2]; 10] = [
+        [addss, addsd],
+        [subss, subsd],
+        [mulss, mulsd],
+        [divss, divsd],
+        [sqrtss, sqrtsd],
+        [ucomiss, ucomisd],
+        [comiss, comisd],
+        [andps, andpd],
+        [xorps, xorpd],
+        [cvtss2sd, cvtsd2ss],
+    ];
+    const SSE_MOVES: [[SseMove; 2]; 3] = [[movss, movsd], [movaps, movapd], [movups, movupd]];
+    const AVX_FLOAT_OPS: [[AvxOp; 2]; 8] = [
+        [vaddps, vaddpd],
+        [vsubps, vsubpd],
+        [vmulps, vmulpd],
+        [vdivps, vdivpd],
+        [vxorps, vxorpd],
+        [vfmadd132ps, vfmadd132pd],
+        [vfmadd213ps, vfmadd213pd],
+        [vfmadd231ps, vfmadd231pd],
+    ];
+    const AVX_INT_OPS: [[AvxOp; 4]; 3] = [
+        [vpaddb, vpaddw, vpaddd, vpaddq],
+        [vpsubb, vpsubw, vpsubd, vpsubq],
+        [vpcmpeqb, vpcmpeqw, vpcmpeqd, vpcmpeqq],
+    ];
+    const AVX_LOGIC_OPS: [AvxOp; 3] = [vpand, vpor, vpxor];
+    const AVX_MOVES: [AvxMove; 6] = [vmovaps, vmovapd, vmovups, vmovupd, vmovdqa, vmovdqu];
+
+    impl Case {
+        /// Instructions without explicit operands
+        fn nullary_text(&self) -> Text {
+            match self.extra {
+                0 => string!(self, movsb, movsw, movsl, movsq, Rsi, Rdi),
+                1 => string!(self, stosb, stosw, stosl, stosq, acc, Rdi),
+                2 => string!(self, lodsb, lodsw, lodsl, lodsq, Rsi, acc),
+                3 => string!(self, cmpsb, cmpsw, cmpsl, cmpsq, Rdi, Rsi),
+                4 => string!(self, scasb, scasw, scasl, scasq, Rdi, acc),
+                5 => string!(self, rep_movsb, rep_movsw, rep_movsl, rep_movsq, Rcx, Rsi, Rdi),
+                6 => string!(self, rep_stosb, rep_stosw, rep_stosl, rep_stosq, Rcx, acc, Rdi),
+                7 => string!(self, rep_lodsb, rep_lodsw, rep_lodsl, rep_lodsq, Rcx, Rsi, acc),
+                8 => string!(self, repe_cmpsb, repe_cmpsw, repe_cmpsl, repe_cmpsq, Rcx, Rdi, Rsi),
+                9 => string!(
+                    self,
+                    repne_cmpsb,
+                    repne_cmpsw,
+                    repne_cmpsl,
+                    repne_cmpsq,
+                    Rcx,
+                    Rdi,
+                    Rsi
+                ),
+                10 => string!(self, repe_scasb, repe_scasw, repe_scasl, repe_scasq, Rcx, Rdi, acc),
+                11 => string!(
+                    self,
+                    repne_scasb,
+                    repne_scasw,
+                    repne_scasl,
+                    repne_scasq,
+                    Rcx,
+                    Rdi,
+                    acc
+                ),
+                12 => mfence(),
+                13 => lfence(),
+                _ => sfence(),
+            }
+        }
+
+        fn text(&self) -> Text {
+            if self.args.is_empty() {
+                return self.nullary_text();
+            }
+            let src = &self.args[0];
+            let dst = self.args.last().unwrap();
+            let cond = CONDS[self.extra];
+            match self.op {
+                0 => sized!(self, addb, addw, addl, addq, 2),
+                1 => sized!(self, subb, subw, subl, subq, 2),
+                2 => sized!(self, andb, andw, andl, andq, 2),
+                3 => sized!(self, orb, orw, orl, orq, 2),
+                4 => sized!(self, xorb, xorw, xorl, xorq, 2),
+                5 => sized!(self, cmpb, cmpw, cmpl, cmpq, 2),
+                6 => sized!(self, movb, movw, movl, movq, 2),
+                7 => sized!(self, testb, testw, testl, testq, 2),
+                8 => sized!(self, shlb, shlw, shll, shlq, 2),
+                9 => sized!(self, shrb, shrw, shrl, shrq, 2),
+                10 => sized!(self, sarb, sarw, sarl, sarq, 2),
+                11 => sized!(self, shlb_reg, shlw_reg, shll_reg, shlq_reg, 1),
+                12 => sized!(self, shrb_reg, shrw_reg, shrl_reg, shrq_reg, 1),
+                13 => match self.size {
+                    1 => imulw(arg(src, &REGS_W), arg(dst, &REGS_W)),
+                    2 => imull(arg(src, &REGS_L), arg(dst, &REGS_L)),
+                    _ => imulq(arg(src, &REGS_Q), arg(dst, &REGS_Q)),
+                },
+                14 => sized!(self, incb, incw, incl, incq, 1),
+                15 => sized!(self, decb, decw, decl, decq, 1),
+                16 => sized!(self, negb, negw, negl, negq, 1),
+                17 => sized!(self, notb, notw, notl, notq, 1),
+                18 if self.size == 2 => divl(arg(src, &REGS_L)),
+                18 => divq(arg(src, &REGS_Q)),
+                19 if self.size == 2 => idivl(arg(src, &REGS_L)),
+                19 => idivq(arg(src, &REGS_Q)),
+                20 => match self.size {
+                    1 => leaw(arg(src, &REGS_W), reg_of(dst, &REGS_W)),
+                    2 => leal(arg(src, &REGS_L), reg_of(dst, &REGS_L)),
+                    _ => leaq(arg(src, &REGS_Q), reg_of(dst, &REGS_Q)),
+                },
+                21 => match self.size {
+                    1 => cmovw(cond, arg(src, &REGS_W), arg(dst, &REGS_W)),
+                    2 => cmovl(cond, arg(src, &REGS_L), arg(dst, &REGS_L)),
+                    _ => cmovq(cond, arg(src, &REGS_Q), arg(dst, &REGS_Q)),
+                },
+                22 => set(cond, arg(src, &REGS_B)),
+                23 => pushq(arg(src, &REGS_Q)),
+                24 => popq(reg_of(src, &REGS_Q)),
+                25 => match self.extra {
+                    0 => movsbw(arg(src, &REGS_B), reg_of(dst, &REGS_W)),
+                    1 => movsbl(arg(src, &REGS_B), reg_of(dst, &REGS_L)),
+                    2 => movsbq(arg(src, &REGS_B), reg_of(dst, &REGS_Q)),
+                    3 => movswl(arg(src, &REGS_W), reg_of(dst, &REGS_L)),
+                    4 => movswq(arg(src, &REGS_W), reg_of(dst, &REGS_Q)),
+                    _ => movslq(arg(src, &REGS_L), reg_of(dst, &REGS_Q)),
+                },
+                26 => match self.extra {
+                    0 => movzbw(arg(src, &REGS_B), reg_of(dst, &REGS_W)),
+                    1 => movzbl(arg(src, &REGS_B), reg_of(dst, &REGS_L)),
+                    2 => movzbq(arg(src, &REGS_B), reg_of(dst, &REGS_Q)),
+                    3 => movzwl(arg(src, &REGS_W), reg_of(dst, &REGS_L)),
+                    _ => movzwq(arg(src, &REGS_W), reg_of(dst, &REGS_Q)),
+                },
+                27 => call_star(arg(src, &REGS_Q)),
+                28 => jmp_star(arg(src, &REGS_Q)),
+                29 => sized!(self, rolb, rolw, roll, rolq, 2),
+                30 => sized!(self, rorb, rorw, rorl, rorq, 2),
+                31 => sized!(self, rclb, rclw, rcll, rclq, 2),
+                32 => sized!(self, rcrb, rcrw, rcrl, rcrq, 2),
+                33 => sized!(self, sarb_reg, sarw_reg, sarl_reg, sarq_reg, 1),
+                34 => sized!(self, rolb_reg, rolw_reg, roll_reg, rolq_reg, 1),
+                35 => sized!(self, mulb, mulw, mull, mulq, widening),
+                36 => sized!(self, imulb_wide, imulw_wide, imull_wide, imulq_wide, widening),
+                37 => bwlq!(self, [imulw_imm, imull_imm, imulq_imm], op, op, reg),
+                38 if self.extra == 0 => bwlq!(self, [shldw, shldl, shldq], op, reg, op),
+                38 => bwlq!(self, [shrdw, shrdl, shrdq], op, reg, op),
+                39 if self.extra == 0 => bwlq!(self, [shldw_reg, shldl_reg, shldq_reg], reg, op),
+                39 => bwlq!(self, [shrdw_reg, shrdl_reg, shrdq_reg], reg, op),
+                40 => match self.extra {
+                    0 => bwlq!(self, [andnl, andnq], op, reg, reg),
+                    1 => bwlq!(self, [pdepl, pdepq], op, reg, reg),
+                    2 => bwlq!(self, [pextl, pextq], op, reg, reg),
+                    _ => bwlq!(self, [mulxl, mulxq], op, reg, reg),
+                },
+                41 => match self.extra {
+                    0 => bwlq!(self, [bzhil, bzhiq], reg, op, reg),
+                    1 => bwlq!(self, [shlxl, shlxq], reg, op, reg),
+                    2 => bwlq!(self, [shrxl, shrxq], reg, op, reg),
+                    _ => bwlq!(self, [sarxl, sarxq], reg, op, reg),
+                },
+                42 => match self.extra {
+                    0 => bwlq!(self, [bsfw, bsfl, bsfq], op, reg),
+                    1 => bwlq!(self, [bsrw, bsrl, bsrq], op, reg),
+                    2 => bwlq!(self, [popcntw, popcntl, popcntq], op, reg),
+                    3 => bwlq!(self, [lzcntw, lzcntl, lzcntq], op, reg),
+                    4 => bwlq!(self, [tzcntw, tzcntl, tzcntq], op, reg),
+                    5 => bwlq!(self, [blsil, blsiq], op, reg),
+                    _ => bwlq!(self, [blsrl, blsrq], op, reg),
+                },
+                43 => match self.extra {
+                    0 => bwlq!(self, [btw, btl, btq], op, op),
+                    1 => bwlq!(self, [btsw, btsl, btsq], op, op),
+                    2 => bwlq!(self, [btrw, btrl, btrq], op, op),
+                    _ => bwlq!(self, [btcw, btcl, btcq], op, op),
+                },
+                44 => bwlq!(self, [rorxl, rorxq], op, op, reg),
+                46 => match self.extra {
+                    0 => bwlq!(self, [xchgb, xchgw, xchgl, xchgq], op, op),
+                    1 => bwlq!(self, [cmpxchgb, cmpxchgw, cmpxchgl, cmpxchgq], reg, op),
+                    _ => bwlq!(self, [xaddb, xaddw, xaddl, xaddq], reg, op),
+                },
+                47 => match self.extra {
+                    0 => bwlq!(self, [lock_addb, lock_addw, lock_addl, lock_addq], src, mem),
+                    1 => bwlq!(self, [lock_subb, lock_subw, lock_subl, lock_subq], src, mem),
+                    2 => bwlq!(self, [lock_andb, lock_andw, lock_andl, lock_andq], src, mem),
+                    3 => bwlq!(self, [lock_orb, lock_orw, lock_orl, lock_orq], src, mem),
+                    4 => bwlq!(self, [lock_xorb, lock_xorw, lock_xorl, lock_xorq], src, mem),
+                    5 => bwlq!(self, [lock_adcb, lock_adcw, lock_adcl, lock_adcq], src, mem),
+                    6 => bwlq!(self, [lock_sbbb, lock_sbbw, lock_sbbl, lock_sbbq], src, mem),
+                    7 => bwlq!(
+                        self,
+                        [lock_cmpxchgb, lock_cmpxchgw, lock_cmpxchgl, lock_cmpxchgq],
+                        reg,
+                        mem
+                    ),
+                    8 => bwlq!(
+                        self,
+                        [lock_xaddb, lock_xaddw, lock_xaddl, lock_xaddq],
+                        reg,
+                        mem
+                    ),
+                    9 => bwlq!(self, [lock_incb, lock_incw, lock_incl, lock_incq], mem),
+                    10 => bwlq!(self, [lock_decb, lock_decw, lock_decl, lock_decq], mem),
+                    11 => bwlq!(self, [lock_negb, lock_negw, lock_negl, lock_negq], mem),
+                    12 => bwlq!(self, [lock_notb, lock_notw, lock_notl, lock_notq], mem),
+                    13 => bwlq!(self, [lock_btsw, lock_btsl, lock_btsq], src, mem),
+                    14 => bwlq!(self, [lock_btrw, lock_btrl, lock_btrq], src, mem),
+                    _ => bwlq!(self, [lock_btcw, lock_btcl, lock_btcq], src, mem),
+                },
+                48 => match self.extra {
+                    0 => cmpxchg8b(mem_of(src)),
+                    1 => cmpxchg16b(mem_of(src)),
+                    2 => lock_cmpxchg8b(mem_of(src)),
+                    _ => lock_cmpxchg16b(mem_of(src)),
+                },
+                49 => SSE_OPS[self.extra][self.size](arg(src, &REGS_X), reg_of(dst, &REGS_X)),
+                50 => SSE_MOVES[self.extra][self.size](arg(src, &REGS_X), arg(dst, &REGS_X)),
+                51 => match (self.extra, self.size) {
+                    (0, 2) => cvtsi2ssl(arg(src, &REGS_L), reg_of(dst, &REGS_X)),
+                    (0, _) => cvtsi2ssq(arg(src, &REGS_Q), reg_of(dst, &REGS_X)),
+                    (1, 2) => cvtsi2sdl(arg(src, &REGS_L), reg_of(dst, &REGS_X)),
+                    (1, _) => cvtsi2sdq(arg(src, &REGS_Q), reg_of(dst, &REGS_X)),
+                    (2, 2) => cvttss2sil(arg(src, &REGS_X), reg_of(dst, &REGS_L)),
+                    (2, _) => cvttss2siq(arg(src, &REGS_X), reg_of(dst, &REGS_Q)),
+                    (_, 2) => cvttsd2sil(arg(src, &REGS_X), reg_of(dst, &REGS_L)),
+                    _ => cvttsd2siq(arg(src, &REGS_X), reg_of(dst, &REGS_Q)),
+                },
+                52 => match (self.extra, self.size) {
+                    (0, 2) => movd_to_xmm(reg_of(src, &REGS_L), reg_of(dst, &REGS_X)),
+                    (0, _) => movq_to_xmm(reg_of(src, &REGS_Q), reg_of(dst, &REGS_X)),
+                    (_, 2) => movd_from_xmm(reg_of(src, &REGS_X), reg_of(dst, &REGS_L)),
+                    _ => movq_from_xmm(reg_of(src, &REGS_X), reg_of(dst, &REGS_Q)),
+                },
+                53 | 54 => {
+                    let op = match (self.op, self.extra) {
+                        (53, extra) => AVX_FLOAT_OPS[extra][self.size],
+                        (_, extra @ 0..=2) => AVX_INT_OPS[extra][self.size],
+                        (_, extra) => AVX_LOGIC_OPS[extra - 3],
+                    };
+                    op(
+                        arg(src, &REGS_Y),
+                        reg_of(&self.args[1], &REGS_Y),
+                        reg_of(dst, &REGS_Y),
+                    )
+                }
+                55 => AVX_MOVES[self.extra](arg(src, &REGS_Y), arg(dst, &REGS_Y)),
+                _ => match self.extra {
+                    0 => vpmovmskb(reg_of(src, &REGS_Y), reg_of(dst, &REGS_L)),
+                    1 => vbroadcastss(arg(src, &REGS_X), reg_of(dst, &REGS_Y)),
+                    _ => vbroadcastsd(arg(src, &REGS_X), reg_of(dst, &REGS_Y)),
+                },
+            }
+        }
+
+        /// Instruction given to GNU as, which only uses a 4-bytes move for `movq $imm, %reg` with `-O`
+        fn gas_text(&self) -> Text {
+            match (self.op, self.size, &self.args[..]) {
+                (6, 3, [Arg::Imm(imm), dst @ Arg::Reg(_)])
+                    if 0 <= *imm && *imm <= u32::MAX as i64 =>
+                {
+                    movl(reg::Operand::Imm(*imm), reg!(reg_of(dst, &REGS_L)))
+                }
+                _ => self.text(),
+            }
+        }
+
+        /// Simpler cases, each operand is made simpler in turn
+        fn shrink(&self) -> Vec<Case> {
+            let mut cases = Vec::new();
+            for (i, arg) in self.args.iter().enumerate() {
+                let simpler = match arg {
+                    // immediates only get closer to 0 so that shrinking terminates
+                    Arg::Imm(imm) => [0, 1, imm / 2]
+                        .iter()
+                        .filter(|simpler| simpler.abs() < imm.abs())
+                        .map(|simpler| Arg::Imm(*simpler))
+                        .collect(),
+                    Arg::Reg(_) => vec![Arg::Reg(0)],
+                    Arg::Mem(base, index, disp) => {
+                        let mut simpler = vec![
+                            Arg::Mem(*base, *index, 0),
+                            Arg::Mem(*base, *index, disp / 2),
+                        ];
+                        if base.is_some() {
+                            simpler.push(Arg::Mem(*base, None, *disp));
+                            simpler.push(Arg::Mem(Some(0), *index, *disp));
+                        }
+                        if let Some((index, _)) = index {
+                            simpler.push(Arg::Mem(*base, Some((*index, 1)), *disp));
+                            simpler.push(Arg::Mem(*base, Some((0, 1)), *disp));
+                        }
+                        simpler
+                    }
+                };
+                for arg in simpler {
+                    let mut case = self.clone();
+                    case.args[i] = arg;
+                    if format!("{:?}", case) != format!("{:?}", self) {
+                        cases.push(case)
+                    }
+                }
+            }
+            cases
+        }
+    }
+
+    /// Instructions in AT&T syntax, with gen_binary they are in the comments before their bytes
+    fn att_text(text: &Text) -> String {
+        let asm = text.to_string();
+        if cfg!(feature = "gen_binary") {
+            asm.lines()
+                .filter_map(|line| line.strip_prefix("\t## "))
+                .map(|instr| format!("\t{}\n", instr))
+                .collect()
+        } else {
+            asm
+        }
+    }
+
+    /// Tests if the encoding of the cases differs from the one of GNU as
+    fn differs(cases: &[Case]) -> bool {
+        let text = cases
+            .iter()
+            .fold(Segment::empty(), |text, case| text + case.text());
+        let gas_text = cases
+            .iter()
+            .fold(Segment::empty(), |text, case| text + case.gas_text());
+        let mut bytes = Vec::new();
+        for el in &text.data {
+            if let SegmentEL::Data(instr) = &el.el {
+                match instr.encode() {
+                    Ok(mut code) => bytes.append(&mut code.bytes),
+                    Err(_) => return true,
+                }
+            }
+        }
+        bytes != assemble_text("differential", &att_text(&gas_text))
+    }
+
+    /// Smallest failing instruction among the cases
+    fn shrink_failure(cases: &[Case]) -> Option<Vec<Case>> {
+        if !differs(cases) {
+            return None;
+        }
+        // bisect the failing instructions, then simplify their operands
+        let mut cases = cases.to_vec();
+        while cases.len() > 1 {
+            let half = cases.len() / 2;
+            if differs(&cases[..half]) {
+                cases.truncate(half)
+            } else if differs(&cases[half..]) {
+                cases.drain(..half);
+            } else {
+                return Some(cases);
+            }
+        }
+        'shrink: loop {
+            for case in cases[0].shrink() {
+                if differs(std::slice::from_ref(&case)) {
+                    cases[0] = case;
+                    continue 'shrink;
+                }
+            }
+            return Some(cases);
+        }
+    }
+
+    #[test]
+    fn random_instructions() {
+        // WRITE_X86_64_SEED=<n> cargo test random_instructions to try other instructions
+        let seed = std::env::var("WRITE_X86_64_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or(0x5eed_u64);
+        let mut rng = Rng(seed.max(1));
+        let cases: Vec<Case> = (0..2000).map(|_| rng.case()).collect();
+        if let Some(cases) = shrink_failure(&cases) {
+            let text = cases
+                .iter()
+                .fold(Segment::empty(), |text, case| text + case.text());
+            let ours: Vec<_> = text
+                .data
+                .iter()
+                .filter_map(|el| match &el.el {
+                    SegmentEL::Data(instr) => Some(instr.encode()),
+                    _ => None,
+                })
+                .collect();
+            let gas_text = cases
+                .iter()
+                .fold(Segment::empty(), |text, case| text + case.gas_text());
+            panic!(
+                "encoding differs from GNU as (seed {seed}):\n{text}ours: {:02x?}\ngas: {:02x?}",
+                ours,
+                assemble_text("differential", &att_text(&gas_text))
+            );
+        }
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "gen_binary")))]
+fn assembler() {
+    let lab = |name: &str| new_label(name);
+    let nops = |n| (0..n).fold(Segment::empty(), |text, _| text + nop());
+    let text = Segment::label(lab("start"))
+        + jmp(lab("near"))
+        + jz(lab("far"))
+        + nops(10)
+        + Segment::label(lab("near"))
+        + nops(120)
+        + jcc(instr::Cond::NZ, lab("start"))
+        + Segment::directive(directives::Directive::P2Align(4, Some(0x90), None))
+        + Segment::label(lab("far"))
+        + jcc(instr::Cond::L, lab("near"))
+        + call(lab("far"))
+        + call(lab("ext"))
+        + leaq(reg::Operand::LabRelAddr(lab("start")), RAX)
+        + movl(reg::Operand::LabVal(lab("start")), reg!(EAX))
+        + Segment::directive(directives::Directive::P2Align(3, Some(0xcc), Some(2)))
+        + jmp(lab("start"))
+        + ret();
+    let assembly = instr::assembler::assemble(&text).unwrap();
+    assert_eq!(
+        assembly.code.bytes,
+        assemble_text("assembler", &text.to_string())
+    );
+    assert_eq!(assembly.labels[&lab("near")], 18);
+    assert_eq!(assembly.labels[&lab("far")], 144);
+    let fixups: Vec<_> = assembly
+        .code
+        .fixups
+        .iter()
+        .map(|fixup| (fixup.offset, fixup.label.clone()))
+        .collect();
+    assert_eq!(fixups, [(152, lab("ext")), (164, lab("start"))]);
+
+    let twice = Segment::label(lab("a")) + Segment::label(lab("a"));
+    assert!(instr::assembler::assemble(&twice).is_err());
+    // loop has no encoding with a 32-bits displacement
+    let far_loop = Segment::label(lab("a")) + nops(130) + loop_(lab("a"));
+    assert!(instr::assembler::assemble(&far_loop).is_err());
+    let close_loop = Segment::label(lab("a")) + nops(120) + loop_(lab("a"));
+    assert_eq!(
+        instr::assembler::assemble(&close_loop).unwrap().code.bytes[120..],
+        [0xe2, -122i8 as u8]
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn object() {
+    use object::{Object, RelocationKind, SymbolSection};
+    use symbol::{Binding, Symbol};
+
+    let text_ss = Segment::label(new_label("main"))
+        + pushq(reg!(RBP))
+        + leaq(lab!(new_label("message")), RDI)
+        + call(reg::Label::printf())
+        + movq(reg::Operand::LabRelAddr(new_label("counter")), reg!(RAX))
+        + movabsq(ilab!(new_label("table")), reg!(RCX))
+        + call(new_label("f"))
+        + popq(RBP)
+        + ret()
+        + Segment::directive(directives::Directive::P2Align(4, None, None))
+        + Segment::label(new_label("f"))
+        + movl(reg::Operand::LabVal(new_label("table")), reg!(EAX))
+        + ret()
+        + Segment::label(new_label("f_end"));
+    let data_ss = Data::label(new_label("table"))
+        + data::daddress(new_label("main"))
+        + data::dlong(-2)
+        + data::dlong_label(new_label("ext"))
+        + data::dword(3);
+    let file = file::File {
+        globl: Some(new_label("main")),
+        text_ss,
+        data_ss,
+        sections: vec![
+            file::Section::rodata(
+                Data::label(new_label("message"))
+                    + data::dasciz("tab\\t \\\"quoted\\\" \\101\\x42\\n".to_string()),
+            ),
+            file::Section::bss(Data::label(new_label("counter")) + data::space(16)),
+        ],
+        symbols: vec![Symbol::global(new_label("f"))
+            .function()
+            .with_end(new_label("f_end"))],
+    };
+    let object = Object::new(&file).unwrap();
+    let asm = file.to_string();
+
+    let sections = [".text", ".data", ".rodata", ".bss"];
+    let names: Vec<&str> = object.sections.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, sections);
+    let relocations = with_gas_object("object", &asm, |obj| {
+        for section in [".text", ".data", ".rodata"] {
+            assert_eq!(
+                object.section(section).unwrap().bytes,
+                section_bytes(obj, section),
+                "{section}"
+            );
+        }
+        gas_relocations(obj)
+    });
+    let mut expected = Vec::new();
+    for section in &object.sections {
+        for relocation in &section.relocations {
+            let kind = match relocation.kind {
+                RelocationKind::Pc8 => "R_X86_64_PC8",
+                RelocationKind::Pc32 => "R_X86_64_PC32",
+                RelocationKind::Plt32 => "R_X86_64_PLT32",
+                RelocationKind::Abs32 => "R_X86_64_32",
+                RelocationKind::Abs32S => "R_X86_64_32S",
+                RelocationKind::Abs64 => "R_X86_64_64",
+            };
+            expected.push((section.name.clone(), relocation.offset, kind.to_string()));
+        }
+    }
+    assert_eq!(expected, relocations);
+
+    let bss = object.section(".bss").unwrap();
+    assert_eq!((bss.size, bss.bytes.len()), (16, 0));
+    assert_eq!(object.section(".text").unwrap().align, 16);
+    let f = object.symbol(&new_label("f")).unwrap();
+    assert_eq!(f.section, SymbolSection::Section(0));
+    assert_eq!((f.value, f.size, f.binding), (48, Some(6), Binding::Global));
+    let printf = object.symbol(&reg::Label::printf()).unwrap();
+    assert_eq!(printf.section, SymbolSection::Undefined);
+    assert_eq!(
+        object.symbol(&new_label("main")).unwrap().binding,
+        Binding::Global
+    );
+    assert_eq!(
+        object.symbol(&new_label("message")).unwrap().binding,
+        Binding::Local
+    );
+
+    // gas generates .debug_line from the line information, which is not done here
+    let loc = Segment::directive(directives::Directive::File(1, "main.c".to_string()))
+        + Segment::label(new_label("main"))
+        + Segment::directive(directives::Directive::Loc(1, 2, None, Vec::new()))
+        + ret();
+    assert!(instr::assembler::assemble(&loc).is_err());
+    let file = file::File {
+        text_ss: loc,
+        ..Default::default()
+    };
+    assert!(Object::new(&file).is_err());
+    let file = file::File {
+        sections: vec![file::Section::rodata(
+            Segment::directive(directives::Directive::LocMarkBlocks(true)) + data::dlong(1),
+        )],
+        ..Default::default()
+    };
+    assert!(Object::new(&file).is_err());
+}
+
+#[test]
+#[cfg(all(unix, target_arch = "x86_64"))]
+fn jit() {
+    use jit::Jit;
+    use reg::implicit::{Rcx, Rdi, Rsi};
+
+    // sum of 1..=n with a loop
+    let sum = Segment::label(new_label("sum"))
+        + xorq(reg!(RAX), reg!(RAX))
+        + Segment::label(new_label(".Lloop"))
+        + addq(reg!(RDI), reg!(RAX))
+        + decq(reg!(RDI))
+        + jnz(new_label(".Lloop"))
+        + ret();
+    let mut jit = Jit::new();
+    let sum_fn = jit.compile(&sum, &new_label("sum")).unwrap();
+    let f: extern "C" fn(i64) -> i64 = unsafe { sum_fn.as_fn() };
+    assert_eq!(f(10), 55);
+    let code = instr::decode::disassemble(sum_fn.code()).unwrap();
+    assert_eq!(
+        instr::assembler::assemble(&code).unwrap().code.bytes,
+        sum_fn.code()
+    );
+
+    // calls a compiled function and a function of libc
+    jit.define(new_label("sum"), sum_fn.entry());
+    let text = Segment::label(new_label("main"))
+        + pushq(reg!(RBX))
+        + call(new_label("strlen"))
+        + movq(reg!(RAX), reg!(RDI))
+        + call(new_label("sum"))
+        + popq(RBX)
+        + ret();
+    let main_fn = jit.compile(&text, &new_label("main")).unwrap();
+    let f: extern "C" fn(*const u8) -> i64 = unsafe { main_fn.as_fn() };
+    assert_eq!(f(b"four\0".as_ptr()), 10);
+    assert_eq!(main_fn.address(&new_label("main")), Some(main_fn.entry()));
+    drop(main_fn);
+
+    // compile again after freeing
+    let main_fn = jit.compile(&text, &new_label("main")).unwrap();
+    let f: extern "C" fn(*const u8) -> i64 = unsafe { main_fn.as_fn() };
+    assert_eq!(f(b"three\0".as_ptr()), 15);
+
+    // variables defined by the user are accessed relatively to %rip, a large allocation
+    // is mapped like the code so it is less than 2 GiB away
+    let mut variables = vec![0u64; 1 << 16];
+    variables[0] = 42;
+    let mut jit_vars = Jit::new();
+    jit_vars.define(new_label("counter"), variables.as_mut_ptr() as usize);
+    let counter = || reg::Operand::LabRelAddr(new_label("counter"));
+    let swap = Segment::label(new_label("swap"))
+        + movq(counter(), reg!(RAX))
+        + movq(reg!(RDI), counter())
+        + ret();
+    let swap_fn = jit_vars.compile(&swap, &new_label("swap")).unwrap();
+    let f: extern "C" fn(u64) -> u64 = unsafe { swap_fn.as_fn() };
+    assert_eq!(f(7), 42);
+    assert_eq!(variables[0], 7);
+    drop(swap_fn);
+    jit_vars.define(new_label("counter"), 8);
+    assert!(jit_vars.compile(&swap, &new_label("swap")).is_err());
+
+    // floats are passed in XMM registers
+    let hypot = Segment::label(new_label("hypot"))
+        + mulsd(reg!(XMM0), XMM0)
+        + mulsd(reg!(XMM1), XMM1)
+        + addsd(reg!(XMM1), XMM0)
+        + sqrtsd(reg!(XMM0), XMM0)
+        + ret();
+    let hypot_fn = jit.compile(&hypot, &new_label("hypot")).unwrap();
+    let f: extern "C" fn(f64, f64) -> f64 = unsafe { hypot_fn.as_fn() };
+    assert_eq!(f(3.0, 4.0), 5.0);
+
+    // extract the bits of the mask
+    if std::is_x86_feature_detected!("bmi2") {
+        let pext = Segment::label(new_label("pext")) + pextq(reg!(RSI), RDI, RAX) + ret();
+        let pext_fn = jit.compile(&pext, &new_label("pext")).unwrap();
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { pext_fn.as_fn() };
+        assert_eq!(f(0b1010_1100, 0b1111_0000), 0b1010);
+
+        let mulx = Segment::label(new_label("mulx"))
+            + movq(reg!(RSI), reg!(RDX))
+            + mulxq(reg!(RDI), RCX, RAX)
+            + ret();
+        let mulx_fn = jit.compile(&mulx, &new_label("mulx")).unwrap();
+        let f: extern "C" fn(u64, u64) -> u64 = unsafe { mulx_fn.as_fn() };
+        assert_eq!(f(1 << 63, 4), 2);
+    }
+
+    // memcpy
+    let copy = Segment::label(new_label("copy"))
+        + movq(reg!(RDX), reg!(RCX))
+        + rep_movsb(Rcx, Rsi, Rdi)
+        + ret();
+    let copy_fn = jit.compile(&copy, &new_label("copy")).unwrap();
+    let f: extern "C" fn(*mut u8, *const u8, u64) = unsafe { copy_fn.as_fn() };
+    let (src, mut dst) = ([1u8, 2, 3, 4, 5], [0u8; 5]);
+    f(dst.as_mut_ptr(), src.as_ptr(), 4);
+    assert_eq!(dst, [1, 2, 3, 4, 0]);
+
+    // left shift of a 128-bits integer
+    let shift = Segment::label(new_label("shift"))
+        + movq(reg!(RSI), reg!(RAX))
+        + movq(reg!(RDX), reg!(RCX))
+        + shldq_reg(RDI, reg!(RAX))
+        + ret();
+    let shift_fn = jit.compile(&shift, &new_label("shift")).unwrap();
+    let f: extern "C" fn(u64, u64, u64) -> u64 = unsafe { shift_fn.as_fn() };
+    assert_eq!(f(0xf000_0000_0000_0000, 1, 4), 0x1f);
+
+    // sum of 2 for each iteration of loop, %rcx = 0 is skipped
+    let twice = Segment::label(new_label("twice"))
+        + movq(reg!(RDI), reg!(RCX))
+        + xorl(reg!(EAX), reg!(EAX))
+        + jrcxz(new_label(".Ldone"))
+        + Segment::label(new_label(".Ltwice"))
+        + addq(immq(2), reg!(RAX))
+        + loop_(new_label(".Ltwice"))
+        + Segment::label(new_label(".Ldone"))
+        + ret();
+    let twice_fn = jit.compile(&twice, &new_label("twice")).unwrap();
+    let f: extern "C" fn(u64) -> u64 = unsafe { twice_fn.as_fn() };
+    assert_eq!(f(0), 0);
+    assert_eq!(f(21), 42);
+
+    // overflow of a signed addition
+    let overflows = Segment::label(new_label("overflows"))
+        + addq(reg!(RSI), reg!(RDI))
+        + seto(reg!(AL))
+        + movzbl(reg!(AL), EAX)
+        + ret();
+    let overflows_fn = jit.compile(&overflows, &new_label("overflows")).unwrap();
+    let f: extern "C" fn(i64, i64) -> u32 = unsafe { overflows_fn.as_fn() };
+    assert_eq!(f(i64::MAX, 1), 1);
+    assert_eq!(f(i64::MAX, -1), 0);
+
+    // high half of a 128-bits product
+    let mul_high = Segment::label(new_label("mul_high"))
+        + movq(reg!(RDI), reg!(RAX))
+        + mulq(reg!(RSI), reg::implicit::Rax, reg::implicit::RdxRax)
+        + movq(reg!(RDX), reg!(RAX))
+        + ret();
+    let mul_high_fn = jit.compile(&mul_high, &new_label("mul_high")).unwrap();
+    let f: extern "C" fn(u64, u64) -> u64 = unsafe { mul_high_fn.as_fn() };
+    assert_eq!(f(u64::MAX, 3), 2);
+    let imul_high = Segment::label(new_label("imul_high"))
+        + movq(reg!(RDI), reg!(RAX))
+        + imulq_wide(reg!(RSI), reg::implicit::Rax, reg::implicit::RdxRax)
+        + movq(reg!(RDX), reg!(RAX))
+        + ret();
+    let imul_high_fn = jit.compile(&imul_high, &new_label("imul_high")).unwrap();
+    let f: extern "C" fn(i64, i64) -> i64 = unsafe { imul_high_fn.as_fn() };
+    assert_eq!(f(-1, 3), -1);
+    let scale = Segment::label(new_label("scale")) + imulq_imm(immq(24), reg!(RDI), RAX) + ret();
+    let scale_fn = jit.compile(&scale, &new_label("scale")).unwrap();
+    let f: extern "C" fn(i64) -> i64 = unsafe { scale_fn.as_fn() };
+    assert_eq!(f(-2), -48);
+
+    // atomic counter
+    let incr = Segment::label(new_label("incr"))
+        + movq(immq(1), reg!(RAX))
+        + lock_xaddq(RAX, mem!(RDI))
+        + ret();
+    let incr_fn = jit.compile(&incr, &new_label("incr")).unwrap();
+    let f: extern "C" fn(*mut u64) -> u64 = unsafe { incr_fn.as_fn() };
+    let mut counter = 41;
+    assert_eq!(f(&mut counter), 41);
+    assert_eq!(counter, 42);
+
+    // sum of vectors of 8 floats
+    if std::is_x86_feature_detected!("avx") {
+        let add = Segment::label(new_label("add"))
+            + vmovups(addr!(RDI), reg!(YMM0))
+            + vaddps(addr!(RSI), YMM0, YMM0)
+            + vmovups(reg!(YMM0), addr!(RDX))
+            + vzeroupper()
+            + ret();
+        let add_fn = jit.compile(&add, &new_label("add")).unwrap();
+        let f: extern "C" fn(*const f32, *const f32, *mut f32) = unsafe { add_fn.as_fn() };
+        let (x, y, mut z) = ([1.0f32; 8], [0.5f32; 8], [0.0f32; 8]);
+        f(x.as_ptr(), y.as_ptr(), z.as_mut_ptr());
+        assert_eq!(z, [1.5; 8]);
+    }
+
+    let text = Segment::label(new_label("main")) + call(new_label("not_a_symbol"));
+    assert!(jit.compile(&text, &new_label("main")).is_err());
+    assert!(Jit::new().compile(&nop(), &new_label("main")).is_err());
+}
@@ -1,20 +1,57 @@
-use crate::reg::Sizes;
+use crate::reg::{Label, Sizes};
+use crate::writer::Writer;
 use std::fmt::Debug;
 
 /// Trait representing registers (used by Operand<R>)
 pub trait Reg: Debug + Clone {
-    /// Write register in file
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()>;
+    /// Write register using the syntax of the writer
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()>;
+
+    /// Write register in file (AT&T syntax)
+    fn write_in(&self, file: &mut dyn std::io::Write) -> std::io::Result<()> {
+        self.write_with(&mut Writer::new(file))
+    }
 
     /// Convert to 3 bits (+ flag)
     fn to_bits(&self) -> (bool, u8);
 
+    /// Tests if the register can only be encoded with a REX prefix (%sil, %dil, %spl, %bpl)
+    fn requires_rex(&self) -> bool {
+        false
+    }
+
+    /// Tests if the register cannot be encoded with a REX prefix (%ah, %bh, %ch, %dh)
+    fn forbids_rex(&self) -> bool {
+        false
+    }
+
     /// Register size
     const SIZE: Sizes;
 }
 
 /// Trait for structures that can be written
 pub trait Writable {
-    /// Write structure in the file
-    fn write_in(&self, file: &mut std::fs::File) -> std::io::Result<()>;
+    /// Write structure using the syntax of the writer
+    fn write_with(&self, file: &mut Writer) -> std::io::Result<()>;
+
+    /// Write structure in the file (AT&T syntax)
+    fn write_in(&self, file: &mut dyn std::io::Write) -> std::io::Result<()> {
+        self.write_with(&mut Writer::new(file))
+    }
+}
+
+/// Trait for structures that can refer to labels
+pub trait UsesLabels {
+    /// Labels used by the structure
+    fn used_labels(&self) -> Vec<&Label>;
+}
+
+/// Helper to implement [`std::fmt::Display`] for [`Writable`] structures
+pub(crate) fn fmt_writable<W: Writable>(
+    el: &W,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    let mut buffer = Vec::new();
+    el.write_in(&mut buffer).map_err(|_| std::fmt::Error)?;
+    f.write_str(&String::from_utf8_lossy(&buffer))
 }
@@ -0,0 +1,120 @@
+use crate::target::Target;
+use std::io::Write;
+
+/// Assembly syntaxes that can be produced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// AT&T syntax (`movq $1, -8(%rbp)`), default of GNU as
+    Att,
+    /// GNU as Intel syntax (`mov QWORD PTR [rbp-8], 1`), enabled with `.intel_syntax noprefix`
+    Intel,
+    /// NASM/YASM source (`mov qword [rbp-8], 1`)
+    Nasm,
+}
+
+impl Syntax {
+    /// Tests if operands are written destination first, without `%` and `$`
+    pub fn is_intel_like(self) -> bool {
+        matches!(self, Self::Intel | Self::Nasm)
+    }
+
+    /// Characters starting a comment
+    pub fn comment_marker(self) -> &'static str {
+        match self {
+            Self::Att | Self::Intel => "##",
+            Self::Nasm => ";",
+        }
+    }
+}
+
+impl Default for Syntax {
+    fn default() -> Self {
+        Self::Att
+    }
+}
+
+/// Output stream used to write assembly, it stores the formatting options
+///
+/// ```
+/// use write_x86_64::*;
+/// use write_x86_64::traits::Writable;
+/// use write_x86_64::writer::{Syntax, Writer};
+///
+/// let text = movq(immq(1), addr!(-8, RBP));
+/// let mut buffer = Vec::new();
+/// text.write_with(&mut Writer::new(&mut buffer).with_syntax(Syntax::Intel)).unwrap();
+/// // instructions are written as bytes with the feature gen_binary
+/// #[cfg(not(feature = "gen_binary"))]
+/// assert_eq!(buffer, b"\tmov QWORD PTR [rbp-8], 1\n");
+/// ```
+pub struct Writer<'a> {
+    out: &'a mut dyn Write,
+    syntax: Syntax,
+    target: Target,
+    nobits: bool,
+}
+
+impl<'a> Writer<'a> {
+    /// Create a writer producing AT&T syntax for the host target
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        Self {
+            out,
+            syntax: Syntax::default(),
+            target: Target::host(),
+            nobits: false,
+        }
+    }
+
+    /// Change the syntax produced by the writer
+    pub fn with_syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Syntax produced by the writer
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    /// Change the target of the writer
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Target of the writer
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// Tests if the writer is inside a section without content (like `.bss`)
+    pub fn in_nobits(&self) -> bool {
+        self.nobits
+    }
+
+    pub(crate) fn set_nobits(&mut self, nobits: bool) {
+        self.nobits = nobits
+    }
+}
+
+impl Write for Writer<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.out.write_all(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Error returned when an element cannot be expressed in the syntax of the writer
+pub(crate) fn unsupported(what: &str, syntax: Syntax) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{} has no equivalent in {:?} syntax", what, syntax),
+    )
+}